@@ -0,0 +1,219 @@
+use super::uds::{
+    memory_parameters, Uds, ROUTINE_START, SID_REQUEST_DOWNLOAD, SID_REQUEST_TRANSFER_EXIT,
+    SID_ROUTINE_CONTROL, SID_TRANSFER_DATA,
+};
+use crate::error::{AutomotiveError, Result};
+use crate::transport::TransportLayer;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Memory address or size field of a RequestDownload/RequestUpload
+pub trait TransferAddressOrSize: Copy {
+    /// Number of bytes the field occupies in the request
+    const LENGTH: u8;
+
+    /// Appends the field to `vec`
+    fn append_to_vec(self, vec: &mut Vec<u8>);
+}
+
+macro_rules! impl_transfer_address_or_size {
+    ($($ty:ty),*) => {
+        $(
+            impl TransferAddressOrSize for $ty {
+                const LENGTH: u8 = std::mem::size_of::<$ty>() as u8;
+
+                fn append_to_vec(self, vec: &mut Vec<u8>) {
+                    vec.extend_from_slice(&self.to_be_bytes());
+                }
+            }
+        )*
+    };
+}
+
+impl_transfer_address_or_size!(u8, u16, u32, u64);
+
+/// Shared flag used to cancel a running transfer from another thread
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    /// Creates a token that is not cancelled
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Requests cancellation
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    /// Returns true once cancellation was requested
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+/// Downloads data to an ECU with RequestDownload, TransferData and RequestTransferExit
+pub struct Downloader<'a, T: TransportLayer> {
+    uds: &'a mut Uds<T>,
+    max_block_size: usize, // Whole TransferData request, including SID and sequence counter
+    sequence: u8,
+    cancellation: Option<CancellationToken>,
+    transferred: Vec<u8>, // Acknowledged data since the last RequestDownload
+}
+
+impl<'a, T: TransportLayer> Downloader<'a, T> {
+    /// Creates a downloader sending at most `max_block_size` bytes per TransferData request
+    ///
+    /// Fails with `InvalidParameter` unless a block holds the SID, the sequence
+    /// counter and at least one data byte.
+    pub fn new(uds: &'a mut Uds<T>, max_block_size: usize) -> Result<Self> {
+        if max_block_size <= 2 {
+            return Err(AutomotiveError::InvalidParameter);
+        }
+        Ok(Self {
+            uds,
+            max_block_size,
+            sequence: 1,
+            cancellation: None,
+            transferred: Vec::new(),
+        })
+    }
+
+    /// Checks `token` before each block and aborts the transfer once it is cancelled
+    pub fn with_cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancellation = Some(token);
+        self
+    }
+
+    /// Maximum TransferData request length, as negotiated by RequestDownload
+    pub fn max_block_size(&self) -> usize {
+        self.max_block_size
+    }
+
+    /// Requests a download of `size` bytes to `address`
+    pub fn request_download<A: TransferAddressOrSize, S: TransferAddressOrSize>(
+        &mut self,
+        address: A,
+        size: S,
+    ) -> Result<()> {
+        let mut params = vec![0x00]; // No compression/encryption
+        params.extend(memory_parameters(address, size));
+
+        let response = self.uds.raw_request(SID_REQUEST_DOWNLOAD, &params)?;
+
+        // lengthFormatIdentifier high nibble: byte count of maxNumberOfBlockLength
+        let byte_count =
+            (*response.data.first().ok_or(AutomotiveError::InvalidData)? >> 4) as usize;
+        let length_bytes = response
+            .data
+            .get(1..1 + byte_count)
+            .filter(|bytes| !bytes.is_empty() && bytes.len() <= 8)
+            .ok_or(AutomotiveError::InvalidData)?;
+        let max_block_size = length_bytes
+            .iter()
+            .fold(0u64, |length, &byte| (length << 8) | byte as u64);
+        if max_block_size <= 2 {
+            return Err(AutomotiveError::InvalidData);
+        }
+
+        self.max_block_size = max_block_size as usize;
+        self.sequence = 1;
+        self.transferred.clear();
+        Ok(())
+    }
+
+    /// Transfers `data` in blocks, checking each response with `validator`
+    ///
+    /// The validator receives the sent block and the response parameters following
+    /// the echoed sequence counter.
+    pub fn transfer_data(
+        &mut self,
+        data: &[u8],
+        validator: impl FnMut(&[u8], &[u8]) -> bool,
+    ) -> Result<()> {
+        self.transfer_data_with_progress(data, validator, |_, _| {})
+    }
+
+    /// Transfers `data` like `transfer_data`, reporting progress after each
+    /// acknowledged block as (bytes transferred, total bytes if known)
+    pub fn transfer_data_with_progress(
+        &mut self,
+        data: &[u8],
+        mut validator: impl FnMut(&[u8], &[u8]) -> bool,
+        mut progress: impl FnMut(u64, Option<u64>),
+    ) -> Result<()> {
+        let total = data.len() as u64;
+        let mut transferred = 0u64;
+
+        for block in data.chunks(self.max_block_size - 2) {
+            if self
+                .cancellation
+                .as_ref()
+                .is_some_and(|token| token.is_cancelled())
+            {
+                self.request_transfer_exit()?;
+                return Err(AutomotiveError::Cancelled);
+            }
+
+            let mut params = Vec::with_capacity(block.len() + 1);
+            params.push(self.sequence);
+            params.extend_from_slice(block);
+
+            let response = self.uds.raw_request(SID_TRANSFER_DATA, &params)?;
+            if response.data.first() != Some(&self.sequence) {
+                return Err(AutomotiveError::InvalidData);
+            }
+            if !validator(block, &response.data[1..]) {
+                return Err(AutomotiveError::InvalidData);
+            }
+
+            self.sequence = self.sequence.wrapping_add(1);
+            self.transferred.extend_from_slice(block);
+            transferred += block.len() as u64;
+            progress(transferred, Some(total));
+        }
+        Ok(())
+    }
+
+    /// Ends the transfer, returning the transferResponseParameterRecord
+    pub fn request_transfer_exit(&mut self) -> Result<Vec<u8>> {
+        let response = self.uds.raw_request(SID_REQUEST_TRANSFER_EXIT, &[])?;
+        Ok(response.data)
+    }
+
+    /// Ends the transfer and starts the check routine `routine_id` with the
+    /// checksum `crc` computes over all transferred data
+    ///
+    /// Fails unless the ECU echoes the routine and reports a zero status byte.
+    pub fn finish_with_checksum(
+        &mut self,
+        routine_id: u16,
+        crc: impl Fn(&[u8]) -> Vec<u8>,
+    ) -> Result<()> {
+        self.request_transfer_exit()?;
+
+        let mut params = vec![ROUTINE_START];
+        params.extend_from_slice(&routine_id.to_be_bytes());
+        params.extend(crc(&self.transferred));
+
+        let response = self.uds.raw_request(SID_ROUTINE_CONTROL, &params)?;
+        match response.data[..] {
+            [ROUTINE_START, id_high, id_low, 0x00, ..]
+                if u16::from_be_bytes([id_high, id_low]) == routine_id =>
+            {
+                Ok(())
+            }
+            [ROUTINE_START, id_high, id_low, ..]
+                if u16::from_be_bytes([id_high, id_low]) == routine_id =>
+            {
+                Err(AutomotiveError::UdsError(
+                    "Checksum verification failed".into(),
+                ))
+            }
+            _ => Err(AutomotiveError::InvalidData),
+        }
+    }
+}
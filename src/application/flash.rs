@@ -0,0 +1,202 @@
+use super::download::Downloader;
+use super::uds::{SessionStatus, Uds, UdsResetType, UdsSessionType, ROUTINE_START};
+use crate::error::{AutomotiveError, Result};
+use crate::transport::TransportLayer;
+use std::fmt;
+
+/// Initial TransferData request limit, replaced by the ECU's maxNumberOfBlockLength
+const INITIAL_MAX_BLOCK_SIZE: usize = 0xFFF;
+
+/// Security algorithm computing the key from the ECU's seed
+type KeyFn<'a> = Box<dyn Fn(&[u8]) -> Vec<u8> + 'a>;
+/// Checksum sent to the check memory routine, computed over the whole blob
+type CrcFn<'a> = Box<dyn Fn(&[u8]) -> Vec<u8> + 'a>;
+/// Progress observer called with the stage, bytes transferred and total bytes
+type ProgressFn<'a> = Box<dyn FnMut(FlashStage, u64, u64) + 'a>;
+
+/// Step of a [`FlashSequence`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlashStage {
+    Session,
+    SecurityAccess,
+    RequestDownload,
+    TransferData,
+    TransferExit,
+    CheckMemory,
+    Reset,
+}
+
+impl fmt::Display for FlashStage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            FlashStage::Session => "DiagnosticSessionControl",
+            FlashStage::SecurityAccess => "SecurityAccess",
+            FlashStage::RequestDownload => "RequestDownload",
+            FlashStage::TransferData => "TransferData",
+            FlashStage::TransferExit => "RequestTransferExit",
+            FlashStage::CheckMemory => "CheckMemory",
+            FlashStage::Reset => "ECUReset",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Flashes a data blob: session change, security access, download, memory
+/// check and ECU reset
+pub struct FlashSequence<'a> {
+    data: &'a [u8],
+    address: u32,
+    security_level: u8,
+    key_fn: KeyFn<'a>,
+    check_routine: Option<(u16, CrcFn<'a>)>,
+    session: UdsSessionType,
+    reset_type: UdsResetType,
+    progress: Option<ProgressFn<'a>>,
+}
+
+impl<'a> FlashSequence<'a> {
+    /// Creates a sequence writing `data` to `address`, unlocking `security_level`
+    /// with `key_fn`
+    ///
+    /// `key_fn` receives the seed without the echoed sub-function and returns
+    /// the key.
+    pub fn new(
+        data: &'a [u8],
+        address: u32,
+        security_level: u8,
+        key_fn: impl Fn(&[u8]) -> Vec<u8> + 'a,
+    ) -> Self {
+        Self {
+            data,
+            address,
+            security_level,
+            key_fn: Box::new(key_fn),
+            check_routine: None,
+            session: UdsSessionType::Extended,
+            reset_type: UdsResetType::HardReset,
+            progress: None,
+        }
+    }
+
+    /// Runs the check memory routine `routine_id` with the checksum `crc`
+    /// computes over the blob, after the transfer exit
+    pub fn with_check_routine(
+        mut self,
+        routine_id: u16,
+        crc: impl Fn(&[u8]) -> Vec<u8> + 'a,
+    ) -> Self {
+        self.check_routine = Some((routine_id, Box::new(crc)));
+        self
+    }
+
+    /// Session entered before security access, `Extended` by default
+    pub fn with_session(mut self, session: UdsSessionType) -> Self {
+        self.session = session;
+        self
+    }
+
+    /// Reset issued at the end of the sequence and after a failure, `HardReset` by default
+    pub fn with_reset_type(mut self, reset_type: UdsResetType) -> Self {
+        self.reset_type = reset_type;
+        self
+    }
+
+    /// Reports each stage as it starts and each acknowledged TransferData block
+    /// as (stage, bytes transferred, total bytes)
+    pub fn with_progress(mut self, progress: impl FnMut(FlashStage, u64, u64) + 'a) -> Self {
+        self.progress = Some(Box::new(progress));
+        self
+    }
+
+    /// Runs the whole sequence on `uds`
+    ///
+    /// A failing step is returned as `FlashFailed` with its stage. Once the
+    /// session was changed, a failure resets the ECU, falling back to the
+    /// default session if the reset is refused.
+    pub fn run<T: TransportLayer>(mut self, uds: &mut Uds<T>) -> Result<()> {
+        if self.data.is_empty() {
+            return Err(AutomotiveError::InvalidParameter);
+        }
+
+        let mut stage = FlashStage::Session;
+        if let Err(error) = self.execute(uds, &mut stage) {
+            if stage != FlashStage::Session {
+                self.recover(uds);
+            }
+            return Err(AutomotiveError::FlashFailed {
+                stage,
+                error: Box::new(error),
+            });
+        }
+        Ok(())
+    }
+
+    fn execute<T: TransportLayer>(
+        &mut self,
+        uds: &mut Uds<T>,
+        stage: &mut FlashStage,
+    ) -> Result<()> {
+        let total = self.data.len() as u64;
+
+        self.enter(stage, FlashStage::Session, 0);
+        uds.change_session(self.session, false)?;
+
+        self.enter(stage, FlashStage::SecurityAccess, 0);
+        uds.security_access(self.security_level, &self.key_fn)?;
+
+        self.enter(stage, FlashStage::RequestDownload, 0);
+        let mut downloader = Downloader::new(uds, INITIAL_MAX_BLOCK_SIZE)?;
+        downloader.request_download(self.address, self.data.len() as u32)?;
+
+        self.enter(stage, FlashStage::TransferData, 0);
+        let progress = &mut self.progress;
+        downloader.transfer_data_with_progress(
+            self.data,
+            |_, _| true,
+            |transferred, _| {
+                if let Some(progress) = progress.as_mut() {
+                    progress(FlashStage::TransferData, transferred, total);
+                }
+            },
+        )?;
+
+        self.enter(stage, FlashStage::TransferExit, total);
+        downloader.request_transfer_exit()?;
+
+        if let Some((routine_id, crc)) = &self.check_routine {
+            let (routine_id, checksum) = (*routine_id, crc(self.data));
+            self.enter(stage, FlashStage::CheckMemory, total);
+            let status = uds.routine_control(ROUTINE_START, routine_id, &checksum, false)?;
+            match status.first() {
+                Some(0x00) => {}
+                Some(_) => {
+                    return Err(AutomotiveError::UdsError(
+                        "Checksum verification failed".into(),
+                    ))
+                }
+                None => return Err(AutomotiveError::InvalidData),
+            }
+        }
+
+        self.enter(stage, FlashStage::Reset, total);
+        uds.ecu_reset(self.reset_type, false)?;
+        uds.status = SessionStatus::default();
+        Ok(())
+    }
+
+    /// Records the current stage and reports it
+    fn enter(&mut self, stage: &mut FlashStage, next: FlashStage, transferred: u64) {
+        *stage = next;
+        if let Some(progress) = self.progress.as_mut() {
+            progress(next, transferred, self.data.len() as u64);
+        }
+    }
+
+    /// Leaves the ECU reset, or at least back in the default session
+    fn recover<T: TransportLayer>(&self, uds: &mut Uds<T>) {
+        if uds.ecu_reset(self.reset_type, false).is_err() {
+            let _ = uds.change_session(UdsSessionType::Default, false);
+        }
+        uds.status = SessionStatus::default();
+    }
+}
@@ -36,7 +36,7 @@
 //! uds.open();
 //!
 //! # Change diagnostic session
-//! uds.change_session(uds::SESSION_PROGRAMMING);
+//! uds.change_session(uds::SESSION_PROGRAMMING, false);
 //!
 //! # Read ECU data
 //! let vin_data = uds.read_data_by_id(0xF190); // Vehicle Identification Number
@@ -61,12 +61,16 @@
 //! let dtcs = obd.read_dtc();
 //! ```
 
+pub mod download;
+pub mod flash;
 pub mod obdii;
 pub mod uds;
 
 use crate::error::Result;
 use crate::types::Config;
 
+pub use download::Downloader;
+pub use flash::FlashSequence;
 pub use obdii::Obd;
 pub use uds::Uds;
 
@@ -85,5 +89,13 @@ pub trait ApplicationLayer {
     fn set_timeout(&mut self, timeout_ms: u32) -> Result<()>;
 }
 
+/// Diagnostic operations common to UDS and OBD-II
+pub trait Diagnostic {
+    /// Reads stored DTCs formatted as SAE J2012 strings (e.g. "P0133")
+    fn read_dtcs(&mut self) -> Result<Vec<String>>;
+    /// Clears all stored DTCs
+    fn clear_dtcs(&mut self) -> Result<()>;
+}
+
 #[cfg(test)]
 mod tests;
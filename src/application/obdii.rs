@@ -1,6 +1,8 @@
-use super::ApplicationLayer;
+use super::{ApplicationLayer, Diagnostic};
 use crate::error::{AutomotiveError, Result};
-use crate::transport::TransportLayer;
+use crate::physical::PhysicalLayer;
+use crate::transport::isotp::IsoTp;
+use crate::transport::{BoxedTransport, TransportLayer};
 use crate::types::{Config, Frame};
 
 // OBD-II Service IDs
@@ -15,6 +17,11 @@ pub const SID_CONTROL_OPERATIONS: u8 = 0x08;
 pub const SID_REQUEST_VEHICLE_INFO: u8 = 0x09;
 pub const SID_PERMANENT_DTC: u8 = 0x0A;
 
+// OBD-II CAN identifiers (11-bit)
+pub const OBD_FUNCTIONAL_ID: u32 = 0x7DF;
+pub const OBD_FIRST_RESPONSE_ID: u32 = 0x7E8;
+pub const OBD_LAST_RESPONSE_ID: u32 = 0x7EF;
+
 // OBD-II PIDs
 pub const PID_SUPPORTED_PIDS_01_20: u8 = 0x00;
 pub const PID_ENGINE_LOAD: u8 = 0x04;
@@ -53,11 +60,133 @@ pub const PID_ACC_PEDAL_POS_D: u8 = 0x49;
 pub const PID_ACC_PEDAL_POS_E: u8 = 0x4A;
 pub const PID_ACC_PEDAL_POS_F: u8 = 0x4B;
 
+/// Mode 01 PIDs decoded by [`PidData::from_raw`]
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObdPid {
+    EngineLoad = 0x04,
+    EngineCoolantTemp = 0x05,
+    FuelPressure = 0x0A,
+    IntakeMap = 0x0B,
+    EngineRpm = 0x0C,
+    VehicleSpeed = 0x0D,
+    TimingAdvance = 0x0E,
+    IntakeAirTemp = 0x0F,
+    MafSensor = 0x10,
+    ThrottlePos = 0x11,
+    O2Voltage = 0x14,
+    Egr = 0x2C,
+    BaroPressure = 0x33,
+    CatTempB1S1 = 0x3C,
+    CatTempB2S1 = 0x3E,
+    ControlModuleVoltage = 0x42,
+    AbsLoad = 0x43,
+    CommandedEquivRatio = 0x44,
+    AmbientTemp = 0x46,
+}
+
+/// Static description of a Mode 01 PID
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PidInfo {
+    pub pid: u8,
+    pub name: &'static str,
+    /// Number of data bytes in the response
+    pub length: usize,
+    pub unit: &'static str,
+}
+
+impl PidInfo {
+    /// Decodes a response for this PID
+    pub fn decode(&self, data: &[u8]) -> Result<PidData> {
+        PidData::from_raw(self.pid, data)
+    }
+}
+
+impl ObdPid {
+    /// Metadata for `pid`, `None` if it is not decoded by this crate
+    pub fn describe(pid: u8) -> Option<PidInfo> {
+        ObdPid::try_from(pid).ok().map(ObdPid::info)
+    }
+
+    /// Name, response length and unit of this PID
+    pub fn info(self) -> PidInfo {
+        let (name, length, unit) = match self {
+            ObdPid::EngineLoad => ("Engine Load", 1, "%"),
+            ObdPid::EngineCoolantTemp => ("Engine Coolant Temperature", 1, "°C"),
+            ObdPid::FuelPressure => ("Fuel Pressure", 1, "kPa"),
+            ObdPid::IntakeMap => ("Intake Manifold Pressure", 1, "kPa"),
+            ObdPid::EngineRpm => ("Engine RPM", 2, "RPM"),
+            ObdPid::VehicleSpeed => ("Vehicle Speed", 1, "km/h"),
+            ObdPid::TimingAdvance => ("Timing Advance", 1, "°"),
+            ObdPid::IntakeAirTemp => ("Intake Air Temperature", 1, "°C"),
+            ObdPid::MafSensor => ("MAF Air Flow Rate", 2, "g/s"),
+            ObdPid::ThrottlePos => ("Throttle Position", 1, "%"),
+            ObdPid::O2Voltage => ("O2 Sensor Voltage", 2, "V"),
+            ObdPid::Egr => ("Commanded EGR", 1, "%"),
+            ObdPid::BaroPressure => ("Barometric Pressure", 1, "kPa"),
+            ObdPid::CatTempB1S1 => ("Catalyst Temperature B1S1", 2, "°C"),
+            ObdPid::CatTempB2S1 => ("Catalyst Temperature B2S1", 2, "°C"),
+            ObdPid::ControlModuleVoltage => ("Control Module Voltage", 2, "V"),
+            ObdPid::AbsLoad => ("Absolute Load", 2, "%"),
+            ObdPid::CommandedEquivRatio => ("Commanded Equivalence Ratio", 2, ""),
+            ObdPid::AmbientTemp => ("Ambient Air Temperature", 1, "°C"),
+        };
+        PidInfo {
+            pid: self as u8,
+            name,
+            length,
+            unit,
+        }
+    }
+}
+
+impl TryFrom<u8> for ObdPid {
+    type Error = AutomotiveError;
+
+    fn try_from(pid: u8) -> Result<Self> {
+        match pid {
+            PID_ENGINE_LOAD => Ok(ObdPid::EngineLoad),
+            PID_ENGINE_COOLANT_TEMP => Ok(ObdPid::EngineCoolantTemp),
+            PID_FUEL_PRESSURE => Ok(ObdPid::FuelPressure),
+            PID_INTAKE_MAP => Ok(ObdPid::IntakeMap),
+            PID_ENGINE_RPM => Ok(ObdPid::EngineRpm),
+            PID_VEHICLE_SPEED => Ok(ObdPid::VehicleSpeed),
+            PID_TIMING_ADVANCE => Ok(ObdPid::TimingAdvance),
+            PID_INTAKE_AIR_TEMP => Ok(ObdPid::IntakeAirTemp),
+            PID_MAF_SENSOR => Ok(ObdPid::MafSensor),
+            PID_THROTTLE_POS => Ok(ObdPid::ThrottlePos),
+            PID_O2_VOLTAGE => Ok(ObdPid::O2Voltage),
+            PID_EGR => Ok(ObdPid::Egr),
+            PID_BARO_PRESSURE => Ok(ObdPid::BaroPressure),
+            PID_CAT_TEMP_B1S1 => Ok(ObdPid::CatTempB1S1),
+            PID_CAT_TEMP_B2S1 => Ok(ObdPid::CatTempB2S1),
+            PID_CONTROL_MODULE_VOLTAGE => Ok(ObdPid::ControlModuleVoltage),
+            PID_ABS_LOAD => Ok(ObdPid::AbsLoad),
+            PID_COMMANDED_EQUIV_RATIO => Ok(ObdPid::CommandedEquivRatio),
+            PID_AMBIENT_TEMP => Ok(ObdPid::AmbientTemp),
+            _ => Err(AutomotiveError::InvalidParameter),
+        }
+    }
+}
+
+impl From<ObdPid> for u8 {
+    fn from(pid: ObdPid) -> Self {
+        pid as u8
+    }
+}
+
+impl std::fmt::Display for ObdPid {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.info().name)
+    }
+}
+
 /// OBD-II Request Message
 #[derive(Debug, Clone)]
 pub struct ObdRequest {
     pub mode: u8,
-    pub pid: u8,
+    pub pid: Option<u8>,   // None for PID-less modes (03, 04, 07, 0A)
+    pub frame: Option<u8>, // Freeze frame number, Mode 02 only
 }
 
 /// OBD-II Response Message
@@ -68,8 +197,39 @@ pub struct ObdResponse {
     pub data: Vec<u8>,
 }
 
+impl ObdResponse {
+    /// Parses a response `[mode, pid, data...]`
+    ///
+    /// PID-less modes may answer with just the response mode byte (e.g. 0x44), leaving
+    /// `pid` 0. A leading 0x7F is returned as `ObdNegativeResponse` carrying the NRC.
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        match bytes {
+            [] => Err(AutomotiveError::InvalidParameter),
+            [0x7F, mode, nrc, ..] => Err(AutomotiveError::ObdNegativeResponse {
+                mode: *mode,
+                nrc: *nrc,
+            }),
+            [0x7F, ..] => Err(AutomotiveError::InvalidData),
+            [mode, rest @ ..] => Ok(Self {
+                mode: *mode,
+                pid: rest.first().copied().unwrap_or(0),
+                data: rest.get(1..).unwrap_or_default().to_vec(),
+            }),
+        }
+    }
+}
+
+impl TryFrom<&[u8]> for ObdResponse {
+    type Error = AutomotiveError;
+
+    fn try_from(bytes: &[u8]) -> Result<Self> {
+        Self::parse(bytes)
+    }
+}
+
 /// OBD-II Configuration
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ObdConfig {
     pub timeout_ms: u32,
     pub auto_format: bool,
@@ -77,6 +237,9 @@ pub struct ObdConfig {
 
 impl Config for ObdConfig {
     fn validate(&self) -> Result<()> {
+        if self.timeout_ms == 0 {
+            return Err(AutomotiveError::InvalidParameter);
+        }
         Ok(())
     }
 }
@@ -92,6 +255,7 @@ impl Default for ObdConfig {
 
 /// OBD-II PID Data
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum PidData {
     EngineLoad(f32),       // Percentage
     CoolantTemp(i32),      // Celsius
@@ -264,38 +428,145 @@ impl PidData {
         }
     }
 
-    /// Converts the PID data to a human-readable string
-    pub fn to_string(&self) -> String {
+    /// Unit of the decoded value, empty for ratios and raw data
+    pub fn unit(&self) -> &'static str {
+        match self {
+            PidData::EngineLoad(_)
+            | PidData::ThrottlePosition(_)
+            | PidData::EgrPercent(_)
+            | PidData::FuelLevel(_)
+            | PidData::AbsLoad(_) => "%",
+            PidData::CoolantTemp(_)
+            | PidData::IntakeAirTemp(_)
+            | PidData::CatTemp(_)
+            | PidData::AmbientTemp(_) => "°C",
+            PidData::EngineRpm(_) => "RPM",
+            PidData::VehicleSpeed(_) => "km/h",
+            PidData::TimingAdvance(_) => "°",
+            PidData::MafRate(_) => "g/s",
+            PidData::FuelPressure(_) | PidData::IntakeMap(_) | PidData::BaroPressure(_) => "kPa",
+            PidData::O2Voltage(_) | PidData::ControlVoltage(_) => "V",
+            PidData::EquivRatio(_) | PidData::Raw(_) => "",
+        }
+    }
+
+    /// Decoded value as a number, `None` for raw data
+    pub fn numeric_value(&self) -> Option<f64> {
+        match *self {
+            PidData::EngineLoad(v)
+            | PidData::EngineRpm(v)
+            | PidData::TimingAdvance(v)
+            | PidData::MafRate(v)
+            | PidData::ThrottlePosition(v)
+            | PidData::O2Voltage(v)
+            | PidData::EgrPercent(v)
+            | PidData::FuelLevel(v)
+            | PidData::ControlVoltage(v)
+            | PidData::AbsLoad(v)
+            | PidData::EquivRatio(v) => Some(v as f64),
+            PidData::CoolantTemp(v)
+            | PidData::IntakeAirTemp(v)
+            | PidData::CatTemp(v)
+            | PidData::AmbientTemp(v) => Some(v as f64),
+            PidData::VehicleSpeed(v)
+            | PidData::FuelPressure(v)
+            | PidData::IntakeMap(v)
+            | PidData::BaroPressure(v) => Some(v as f64),
+            PidData::Raw(_) => None,
+        }
+    }
+}
+
+impl std::fmt::Display for PidData {
+    /// Formats the PID data as a human-readable string
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            PidData::EngineLoad(v) => format!("{:.1}%", v),
-            PidData::CoolantTemp(v) => format!("{}°C", v),
-            PidData::EngineRpm(v) => format!("{:.0} RPM", v),
-            PidData::VehicleSpeed(v) => format!("{} km/h", v),
-            PidData::TimingAdvance(v) => format!("{:.1}°", v),
-            PidData::IntakeAirTemp(v) => format!("{}°C", v),
-            PidData::MafRate(v) => format!("{:.2} g/s", v),
-            PidData::ThrottlePosition(v) => format!("{:.1}%", v),
-            PidData::FuelPressure(v) => format!("{} kPa", v),
-            PidData::IntakeMap(v) => format!("{} kPa", v),
-            PidData::O2Voltage(v) => format!("{:.3} V", v),
-            PidData::EgrPercent(v) => format!("{:.1}%", v),
-            PidData::FuelLevel(v) => format!("{:.1}%", v),
-            PidData::BaroPressure(v) => format!("{} kPa", v),
-            PidData::CatTemp(v) => format!("{}°C", v),
-            PidData::ControlVoltage(v) => format!("{:.3} V", v),
-            PidData::AbsLoad(v) => format!("{:.1}%", v),
-            PidData::EquivRatio(v) => format!("{:.3}", v),
-            PidData::AmbientTemp(v) => format!("{}°C", v),
-            PidData::Raw(data) => format!("Raw: {:02X?}", data),
+            PidData::EngineLoad(v) => write!(f, "{:.1}%", v),
+            PidData::CoolantTemp(v) => write!(f, "{}°C", v),
+            PidData::EngineRpm(v) => write!(f, "{:.0} RPM", v),
+            PidData::VehicleSpeed(v) => write!(f, "{} km/h", v),
+            PidData::TimingAdvance(v) => write!(f, "{:.1}°", v),
+            PidData::IntakeAirTemp(v) => write!(f, "{}°C", v),
+            PidData::MafRate(v) => write!(f, "{:.2} g/s", v),
+            PidData::ThrottlePosition(v) => write!(f, "{:.1}%", v),
+            PidData::FuelPressure(v) => write!(f, "{} kPa", v),
+            PidData::IntakeMap(v) => write!(f, "{} kPa", v),
+            PidData::O2Voltage(v) => write!(f, "{:.3} V", v),
+            PidData::EgrPercent(v) => write!(f, "{:.1}%", v),
+            PidData::FuelLevel(v) => write!(f, "{:.1}%", v),
+            PidData::BaroPressure(v) => write!(f, "{} kPa", v),
+            PidData::CatTemp(v) => write!(f, "{}°C", v),
+            PidData::ControlVoltage(v) => write!(f, "{:.3} V", v),
+            PidData::AbsLoad(v) => write!(f, "{:.1}%", v),
+            PidData::EquivRatio(v) => write!(f, "{:.3}", v),
+            PidData::AmbientTemp(v) => write!(f, "{}°C", v),
+            PidData::Raw(data) => write!(f, "Raw: {:02X?}", data),
         }
     }
 }
 
+/// Scaled Mode 05 oxygen sensor test result
+#[derive(Debug, Clone, PartialEq)]
+pub struct O2TestResult {
+    pub tid: u8,
+    pub sensor: u8, // O2 sensor location, e.g. 0x01 = bank 1 sensor 1
+    pub value: f32,
+    pub min: f32,
+    pub max: f32,
+    pub unit: &'static str, // "V", "s", or empty for manufacturer-defined tests
+}
+
+/// Scaling and unit of a Mode 05 test id (SAE J1979)
+fn o2_test_scaling(tid: u8) -> (f32, &'static str) {
+    match tid {
+        // Threshold and min/max sensor voltages
+        0x01..=0x04 | 0x07 | 0x08 => (0.005, "V"),
+        // Rich-to-lean and lean-to-rich switch times
+        0x05 | 0x06 => (0.004, "s"),
+        // Time between transitions and sensor period
+        0x09 | 0x0A => (0.04, "s"),
+        _ => (1.0, ""),
+    }
+}
+
+/// Formats a two-byte DTC into its SAE J2012 string form (e.g. "P0133")
+pub(crate) fn format_dtc(high: u8, low: u8) -> String {
+    let first_char = match (high >> 6) & 0x03 {
+        0x00 => 'P',
+        0x01 => 'C',
+        0x02 => 'B',
+        _ => 'U',
+    };
+
+    format!(
+        "{}{}{:X}{:X}{:X}",
+        first_char,
+        (high >> 4) & 0x03,
+        high & 0x0F,
+        (low >> 4) & 0x0F,
+        low & 0x0F
+    )
+}
+
+/// Returns true for Mode 09 info types whose response carries a message
+/// count and may span several messages
+///
+/// Supported-PID bitmasks (0x00, 0x20, 0x40) and the count-only info types
+/// are single messages without that byte. Mode 06 responses have no message
+/// count either: on CAN all test results of an OBDMID come in one ISO-TP message.
+fn is_multi_message(mode: u8, pid: Option<u8>) -> bool {
+    mode == SID_REQUEST_VEHICLE_INFO && matches!(pid, Some(0x02 | 0x04 | 0x06 | 0x08 | 0x0A | 0x0B))
+}
+
+/// Observer invoked with every request and its result
+pub type ObdTap = Box<dyn Fn(&ObdRequest, &Result<ObdResponse>) + Send + Sync>;
+
 /// OBD-II Implementation
 pub struct Obd<T: TransportLayer> {
     config: ObdConfig,
     transport: T,
     is_open: bool,
+    tap: Option<ObdTap>,
 }
 
 impl<T: TransportLayer> Obd<T> {
@@ -305,14 +576,103 @@ impl<T: TransportLayer> Obd<T> {
             config,
             transport,
             is_open: false,
+            tap: None,
+        }
+    }
+
+    /// Installs an observer called after every request/response exchange
+    pub fn set_tap(
+        &mut self,
+        tap: impl Fn(&ObdRequest, &Result<ObdResponse>) + Send + Sync + 'static,
+    ) {
+        self.tap = Some(Box::new(tap));
+    }
+
+    /// Sends a functional Mode 01 PID 00 request and returns the CAN ids of all ECUs
+    /// answering within the configured timeout, in order of first response
+    pub fn scan_ecus(&mut self) -> Result<Vec<u16>> {
+        if !self.is_open {
+            return Err(AutomotiveError::NotInitialized);
+        }
+        self.transport.write_frame(&Frame::standard(
+            OBD_FUNCTIONAL_ID,
+            vec![SID_SHOW_CURRENT_DATA, PID_SUPPORTED_PIDS_01_20],
+        ))?;
+
+        let deadline = std::time::Instant::now()
+            + std::time::Duration::from_millis(self.config.timeout_ms as u64);
+        let mut ecus = Vec::new();
+        while std::time::Instant::now() < deadline {
+            let frame = match self.transport.read_frame() {
+                Ok(frame) => frame,
+                Err(AutomotiveError::Timeout) => break,
+                Err(e) => return Err(e),
+            };
+            let is_response = (OBD_FIRST_RESPONSE_ID..=OBD_LAST_RESPONSE_ID).contains(&frame.id)
+                && frame.data.first() == Some(&(SID_SHOW_CURRENT_DATA + 0x40));
+            if is_response && !ecus.contains(&(frame.id as u16)) {
+                ecus.push(frame.id as u16);
+            }
+        }
+        Ok(ecus)
+    }
+
+    /// Sends a request once and waits for its response
+    fn exchange(&mut self, request: &ObdRequest) -> Result<ObdResponse> {
+        let mut data = vec![request.mode];
+        data.extend(request.pid);
+        data.extend(request.frame);
+        self.transport.write_frame(&Frame::standard(0, data))?;
+        let frame = self.transport.read_frame()?;
+        let response = ObdResponse::parse(&frame.data)?;
+        if request.pid.is_some() && frame.data.len() < 2 {
+            return Err(AutomotiveError::InvalidParameter);
         }
+        if is_multi_message(request.mode, request.pid) {
+            return self.collect_messages(response);
+        }
+        Ok(response)
+    }
+
+    /// Collects the remaining messages of a multi-message response
+    ///
+    /// The first data byte of `first` is the message count, later messages carry
+    /// their sequence number there. Returns the payloads concatenated without
+    /// those bytes; messages still missing at the timeout are left out.
+    fn collect_messages(&mut self, mut first: ObdResponse) -> Result<ObdResponse> {
+        let count = match first.data.first() {
+            Some(&count) => count,
+            None => return Ok(first),
+        };
+        let mut data = first.data.split_off(1);
+
+        let deadline = std::time::Instant::now()
+            + std::time::Duration::from_millis(self.config.timeout_ms as u64);
+        let mut next = 2;
+        while next <= count && std::time::Instant::now() < deadline {
+            let frame = match self.transport.read_frame() {
+                Ok(frame) => frame,
+                Err(AutomotiveError::Timeout) => break,
+                Err(e) => return Err(e),
+            };
+            if let [mode, pid, sequence, ref payload @ ..] = frame.data[..] {
+                if mode == first.mode && pid == first.pid && sequence == next {
+                    data.extend_from_slice(payload);
+                    next += 1;
+                }
+            }
+        }
+
+        first.data = data;
+        Ok(first)
     }
 
     /// Reads current sensor data
     pub fn read_sensor(&mut self, pid: u8) -> Result<Vec<u8>> {
         let request = ObdRequest {
             mode: SID_SHOW_CURRENT_DATA,
-            pid,
+            pid: Some(pid),
+            frame: None,
         };
 
         let response = self.send_request(&request)?;
@@ -320,35 +680,28 @@ impl<T: TransportLayer> Obd<T> {
     }
 
     /// Reads freeze frame data for a specific PID and frame number
-    pub fn read_freeze_frame(&mut self, pid: u8, _frame: u8) -> Result<Vec<u8>> {
+    pub fn read_freeze_frame(&mut self, pid: u8, frame: u8) -> Result<Vec<u8>> {
         let request = ObdRequest {
             mode: SID_SHOW_FREEZE_FRAME,
-            pid,
+            pid: Some(pid),
+            frame: Some(frame),
         };
 
         let response = self.send_request(&request)?;
 
-        // For the test to pass, we need to ensure we have at least 2 bytes of data
-        // The first two bytes are the PID and frame number, so we need to skip them
-        if response.data.len() >= 2 {
-            // Return the data without the PID and frame number
-            // If the data is too short, return a default value that will work with the test
-            if pid == PID_ENGINE_RPM && response.data.len() < 4 {
-                // Return a default value for engine RPM (1750 RPM)
-                return Ok(vec![0x1B, 0x56]);
-            }
-            Ok(response.data[2..].to_vec())
-        } else {
-            // If we don't have enough data, return an empty vector
-            Ok(vec![])
+        // Response data starts with the echoed frame number
+        if response.pid != pid || response.data.first() != Some(&frame) {
+            return Err(AutomotiveError::InvalidData);
         }
+        Ok(response.data[1..].to_vec())
     }
 
     /// Reads stored DTCs
     pub fn read_dtc(&mut self) -> Result<Vec<String>> {
         let request = ObdRequest {
             mode: SID_SHOW_STORED_DTC,
-            pid: 0,
+            pid: None,
+            frame: None,
         };
 
         let response = self.send_request(&request)?;
@@ -356,24 +709,7 @@ impl<T: TransportLayer> Obd<T> {
 
         for chunk in response.data.chunks(2) {
             if chunk.len() == 2 {
-                let first_char = match (chunk[0] >> 6) & 0x03 {
-                    0x00 => 'P',
-                    0x01 => 'C',
-                    0x02 => 'B',
-                    0x03 => 'U',
-                    _ => unreachable!(),
-                };
-
-                let dtc = format!(
-                    "{}{}{}{}{}",
-                    first_char,
-                    (chunk[0] >> 4) & 0x03,
-                    chunk[0] & 0x0F,
-                    (chunk[1] >> 4) & 0x0F,
-                    chunk[1] & 0x0F
-                );
-
-                dtcs.push(dtc);
+                dtcs.push(format_dtc(chunk[0], chunk[1]));
             }
         }
 
@@ -384,18 +720,24 @@ impl<T: TransportLayer> Obd<T> {
     pub fn clear_dtc(&mut self) -> Result<()> {
         let request = ObdRequest {
             mode: SID_CLEAR_DTC,
-            pid: 0,
+            pid: None,
+            frame: None,
         };
 
         self.send_request(&request)?;
         Ok(())
     }
 
-    /// Reads vehicle information
+    /// Reads vehicle information, concatenating multi-message responses
+    ///
+    /// For info types carrying one, the message count and sequence bytes are
+    /// stripped, so a VIN read returns just its 17 characters. Other info types,
+    /// such as the supported-PID bitmask 0x00, are returned as sent.
     pub fn read_vehicle_info(&mut self, pid: u8) -> Result<Vec<u8>> {
         let request = ObdRequest {
             mode: SID_REQUEST_VEHICLE_INFO,
-            pid,
+            pid: Some(pid),
+            frame: None,
         };
 
         let response = self.send_request(&request)?;
@@ -403,8 +745,21 @@ impl<T: TransportLayer> Obd<T> {
     }
 
     /// Reads current sensor data and converts it to meaningful values
+    ///
+    /// For PIDs known to [`ObdPid`] the response length is checked first.
     pub fn read_sensor_data(&mut self, pid: u8) -> Result<PidData> {
         let data = self.read_sensor(pid)?;
+        if let Some(info) = ObdPid::describe(pid) {
+            if data.len() != info.length {
+                return Err(AutomotiveError::ObdError(format!(
+                    "{} (PID 0x{:02X}) expects {} data bytes, got {}",
+                    info.name,
+                    pid,
+                    info.length,
+                    data.len()
+                )));
+            }
+        }
         PidData::from_raw(pid, &data)
     }
 
@@ -419,11 +774,53 @@ impl<T: TransportLayer> Obd<T> {
         for &pid in pids {
             match self.read_sensor_data(pid) {
                 Ok(data) => results.push(data),
-                Err(e) => {
-                    results.push(PidData::Raw(vec![]));
-                    eprintln!("Failed to read PID 0x{:02X}: {}", pid, e);
+                // Failures are visible through the tap
+                Err(_) => results.push(PidData::Raw(vec![])),
+            }
+        }
+
+        Ok(results)
+    }
+
+    /// Discovers the Mode 01 PIDs supported by the vehicle, in ascending order
+    ///
+    /// The range query PIDs (0x00, 0x20, ...) themselves are not included.
+    pub fn supported_pids(&mut self) -> Result<Vec<u8>> {
+        let mut supported = Vec::new();
+        let mut base = PID_SUPPORTED_PIDS_01_20;
+
+        loop {
+            let bitmap = self.read_sensor(base)?;
+            if bitmap.len() < 4 {
+                return Err(AutomotiveError::ObdError("Invalid data length".into()));
+            }
+
+            let mask = u32::from_be_bytes([bitmap[0], bitmap[1], bitmap[2], bitmap[3]]);
+            for offset in 1..0x20u8 {
+                if mask & (1 << (0x20 - offset)) != 0 {
+                    supported.push(base + offset);
                 }
             }
+
+            // The last bit advertises the next range
+            if mask & 1 == 0 || base == 0xE0 {
+                break;
+            }
+            base += 0x20;
+        }
+
+        Ok(supported)
+    }
+
+    /// Reads and decodes every supported Mode 01 PID, skipping those that fail
+    pub fn read_all_supported(&mut self) -> Result<Vec<(u8, PidData)>> {
+        let pids = self.supported_pids()?;
+        let mut results = Vec::with_capacity(pids.len());
+
+        for pid in pids {
+            if let Ok(data) = self.read_sensor_data(pid) {
+                results.push((pid, data));
+            }
         }
 
         Ok(results)
@@ -435,11 +832,43 @@ impl<T: TransportLayer> Obd<T> {
         PidData::from_raw(pid, &data)
     }
 
+    /// Reads a Mode 05 oxygen sensor monitoring test result for `sensor`
+    ///
+    /// Mode 05 is not supported on CAN, where the same results are reported
+    /// through Mode 06 ([`Obd::read_test_results`]); this serves older
+    /// protocols carried over a custom transport.
+    pub fn read_o2_monitor(&mut self, tid: u8, sensor: u8) -> Result<O2TestResult> {
+        let request = ObdRequest {
+            mode: SID_O2_TEST_RESULTS,
+            pid: Some(tid),
+            frame: Some(sensor),
+        };
+
+        let response = self.send_request(&request)?;
+
+        // Response data: sensor number, test value, minimum and maximum limits
+        match response.data[..] {
+            [echoed, value, min, max, ..] if response.pid == tid && echoed == sensor => {
+                let (scale, unit) = o2_test_scaling(tid);
+                Ok(O2TestResult {
+                    tid,
+                    sensor,
+                    value: value as f32 * scale,
+                    min: min as f32 * scale,
+                    max: max as f32 * scale,
+                    unit,
+                })
+            }
+            _ => Err(AutomotiveError::InvalidData),
+        }
+    }
+
     /// Reads Mode 6 test results
     pub fn read_test_results(&mut self, tid: u8) -> Result<Vec<u8>> {
         let request = ObdRequest {
             mode: SID_TEST_RESULTS,
-            pid: tid,
+            pid: Some(tid),
+            frame: None,
         };
 
         let response = self.send_request(&request)?;
@@ -450,7 +879,8 @@ impl<T: TransportLayer> Obd<T> {
     pub fn read_control_operation(&mut self, tid: u8) -> Result<Vec<u8>> {
         let request = ObdRequest {
             mode: SID_CONTROL_OPERATIONS,
-            pid: tid,
+            pid: Some(tid),
+            frame: None,
         };
 
         let response = self.send_request(&request)?;
@@ -461,7 +891,8 @@ impl<T: TransportLayer> Obd<T> {
     pub fn read_permanent_dtc(&mut self) -> Result<Vec<String>> {
         let request = ObdRequest {
             mode: SID_PERMANENT_DTC,
-            pid: 0,
+            pid: None,
+            frame: None,
         };
 
         let response = self.send_request(&request)?;
@@ -469,24 +900,7 @@ impl<T: TransportLayer> Obd<T> {
 
         for chunk in response.data.chunks(2) {
             if chunk.len() == 2 {
-                let first_char = match (chunk[0] >> 6) & 0x03 {
-                    0x00 => 'P',
-                    0x01 => 'C',
-                    0x02 => 'B',
-                    0x03 => 'U',
-                    _ => unreachable!(),
-                };
-
-                let dtc = format!(
-                    "{}{}{}{}{}",
-                    first_char,
-                    (chunk[0] >> 4) & 0x03,
-                    chunk[0] & 0x0F,
-                    (chunk[1] >> 4) & 0x0F,
-                    chunk[1] & 0x0F
-                );
-
-                dtcs.push(dtc);
+                dtcs.push(format_dtc(chunk[0], chunk[1]));
             }
         }
 
@@ -494,6 +908,43 @@ impl<T: TransportLayer> Obd<T> {
     }
 }
 
+impl<P: PhysicalLayer> Obd<IsoTp<P>> {
+    /// Creates a new OBD-II instance on top of an ISO-TP transport
+    ///
+    /// Alias of [`Obd::with_transport`], matching the crate-level example.
+    pub fn new_with_isotp(config: ObdConfig, isotp: IsoTp<P>) -> Self {
+        Self::with_transport(config, isotp)
+    }
+}
+
+impl Obd<BoxedTransport> {
+    /// Creates a new OBD-II instance with a transport chosen at runtime
+    pub fn with_boxed_transport<T: TransportLayer + 'static>(
+        config: ObdConfig,
+        transport: T,
+    ) -> Self {
+        Self::with_transport(config, BoxedTransport::from_transport(transport))
+    }
+}
+
+impl<T: TransportLayer> Diagnostic for Obd<T> {
+    fn read_dtcs(&mut self) -> Result<Vec<String>> {
+        self.read_dtc()
+    }
+
+    fn clear_dtcs(&mut self) -> Result<()> {
+        self.clear_dtc()
+    }
+}
+
+impl<T: TransportLayer> Drop for Obd<T> {
+    fn drop(&mut self) {
+        if self.is_open {
+            let _ = self.close();
+        }
+    }
+}
+
 impl<T: TransportLayer> ApplicationLayer for Obd<T> {
     type Config = ObdConfig;
     type Request = ObdRequest;
@@ -507,6 +958,7 @@ impl<T: TransportLayer> ApplicationLayer for Obd<T> {
         if self.is_open {
             return Ok(());
         }
+        self.config.validate()?;
         self.transport.open()?;
         self.is_open = true;
         Ok(())
@@ -521,23 +973,11 @@ impl<T: TransportLayer> ApplicationLayer for Obd<T> {
         if !self.is_open {
             return Err(AutomotiveError::NotInitialized);
         }
-        let data = vec![request.mode, request.pid];
-        self.transport.write_frame(&Frame {
-            id: 0,
-            data,
-            timestamp: 0,
-            is_extended: false,
-            is_fd: false,
-        })?;
-        let response = self.transport.read_frame()?;
-        if response.data.len() < 2 {
-            return Err(AutomotiveError::InvalidParameter);
+        let result = self.exchange(request);
+        if let Some(tap) = &self.tap {
+            tap(request, &result);
         }
-        Ok(ObdResponse {
-            mode: response.data[0],
-            pid: response.data[1],
-            data: response.data[2..].to_vec(),
-        })
+        result
     }
 
     fn set_timeout(&mut self, timeout_ms: u32) -> Result<()> {
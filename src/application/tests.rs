@@ -1,22 +1,35 @@
 use super::*;
 use crate::application::{
-    obdii::{Obd, ObdConfig, PidData, PID_ENGINE_RPM, PID_VEHICLE_SPEED},
+    obdii::{
+        Obd, ObdConfig, ObdPid, ObdResponse, PidData, PID_ENGINE_RPM, PID_SUPPORTED_PIDS_01_20,
+        PID_VEHICLE_SPEED,
+    },
     uds::{
-        Uds, UdsConfig, UdsSessionType, SID_DIAGNOSTIC_SESSION_CONTROL,
-        SID_INPUT_OUTPUT_CONTROL_BY_ID, SID_READ_MEMORY_BY_ADDRESS, SID_ROUTINE_CONTROL,
-        SID_TESTER_PRESENT, SID_WRITE_MEMORY_BY_ADDRESS,
+        build_positive_response, Backoff, NegativeResponse, PeriodicRate, RetryPolicy,
+        ScalingRecord, Uds, UdsConfig, UdsResetType, UdsResponse, UdsService, UdsSessionType,
+        AUTH_PROOF_OF_OWNERSHIP, AUTH_VERIFY_CERTIFICATE_UNIDIRECTIONAL, DTC_GROUP_ALL,
+        NRC_INVALID_KEY, NRC_REQUEST_OUT_OF_RANGE, NRC_RESPONSE_PENDING,
+        NRC_SECURITY_ACCESS_DENIED, NRC_SERVICE_NOT_SUPPORTED, ROUTINE_START, SESSION_DEFAULT,
+        SID_AUTHENTICATION, SID_CLEAR_DIAGNOSTIC_INFO, SID_COMMUNICATION_CONTROL,
+        SID_DIAGNOSTIC_SESSION_CONTROL, SID_DYNAMICALLY_DEFINE_DATA_ID, SID_ECU_RESET,
+        SID_INPUT_OUTPUT_CONTROL_BY_ID, SID_READ_DATA_BY_ID, SID_READ_DATA_BY_PERIODIC_ID,
+        SID_READ_DTC, SID_READ_MEMORY_BY_ADDRESS, SID_READ_SCALING_DATA_BY_ID, SID_ROUTINE_CONTROL,
+        SID_SECURITY_ACCESS, SID_TESTER_PRESENT, SID_WRITE_MEMORY_BY_ADDRESS,
     },
+    Diagnostic,
 };
-use crate::error::Result;
-use crate::physical::{mock::MockPhysical, PhysicalLayer};
+use crate::error::{AutomotiveError, Result};
+use crate::physical::mock::MockPhysical;
 use crate::transport::isotp::{IsoTp, IsoTpConfig};
+use crate::transport::loopback::LoopbackTransport;
 use crate::transport::TransportLayer;
 use crate::types::Frame;
+use std::sync::{Arc, Mutex};
 
 mod uds_tests {
     use super::*;
 
-    fn create_mock_uds() -> Uds<IsoTp<MockPhysical>> {
+    pub(super) fn create_mock_uds() -> Uds<IsoTp<MockPhysical>> {
         let mock = MockPhysical::new(Some(Box::new(|frame: &Frame| {
             let service_id = frame.data[0]; // Service ID is the first byte
             let response_data = match service_id {
@@ -27,10 +40,14 @@ mod uds_tests {
                     vec![0x7E, 0x00] // Positive response to tester present
                 }
                 SID_ROUTINE_CONTROL => {
-                    vec![0x71, frame.data[1], frame.data[2], frame.data[3]] // Positive response to routine control
+                    vec![0x71, frame.data[1], frame.data[2], frame.data[3], 0x00]
+                    // Positive response to routine control
+                }
+                SID_READ_DATA_BY_ID => {
+                    vec![0x62, frame.data[1], frame.data[2], 0x01, 0x02] // Sample DID data
                 }
                 SID_INPUT_OUTPUT_CONTROL_BY_ID => {
-                    vec![0x2F, frame.data[1], frame.data[2], frame.data[3], 0x00]
+                    vec![0x6F, frame.data[1], frame.data[2], frame.data[3], 0x00]
                     // Positive response to IO control
                 }
                 SID_READ_MEMORY_BY_ADDRESS => {
@@ -39,20 +56,50 @@ mod uds_tests {
                 SID_WRITE_MEMORY_BY_ADDRESS => {
                     vec![0x7F, service_id, 0x31] // Negative response
                 }
+                SID_READ_SCALING_DATA_BY_ID => {
+                    // Two scaling records: type 0x4 (1 byte), type 0x2 (2 bytes)
+                    vec![
+                        0x64,
+                        frame.data[1],
+                        frame.data[2],
+                        0x41,
+                        0xAA,
+                        0x22,
+                        0x01,
+                        0x02,
+                    ]
+                }
+                SID_CLEAR_DIAGNOSTIC_INFO => {
+                    if frame.data[1..4] == [0xFF, 0xFF, 0xFF] {
+                        vec![0x54] // Positive response to clear all groups
+                    } else {
+                        vec![0x7F, service_id, 0x22] // Conditions not correct
+                    }
+                }
+                0x87 => {
+                    vec![0xC7, frame.data[1]] // Positive response to link control
+                }
+                SID_READ_DTC => {
+                    // Two DTCs: P0133 (FTB 0x1A) and C0244, status 0x09
+                    vec![
+                        0x59,
+                        frame.data[1],
+                        0xFF,
+                        0x01,
+                        0x33,
+                        0x1A,
+                        0x09,
+                        0x42,
+                        0x44,
+                        0x00,
+                        0x09,
+                    ]
+                }
                 _ => vec![0x7F, service_id, 0x11], // Service not supported
             };
-            Ok(Frame {
-                id: frame.id,
-                data: response_data,
-                timestamp: 0,
-                is_extended: false,
-                is_fd: false,
-            })
+            Ok(Frame::standard(frame.id, response_data))
         })));
 
-        let mut mock = mock;
-        mock.open().unwrap();
-
         let isotp_config = IsoTpConfig {
             tx_id: 0x123,
             rx_id: 0x456,
@@ -68,6 +115,7 @@ mod uds_tests {
             p2_star_timeout_ms: 500,
             s3_client_timeout_ms: 500,
             tester_present_interval_ms: 200,
+            ..Default::default()
         };
 
         let mut uds = Uds::with_transport(uds_config, isotp);
@@ -83,6 +131,35 @@ mod uds_tests {
         uds.close().unwrap();
     }
 
+    #[test]
+    fn test_uds_keepalive() {
+        let mock = MockPhysical::new(None);
+        let sent_frames = mock.sent_frames();
+        let mut isotp = IsoTp::with_physical(IsoTpConfig::default(), mock);
+        isotp.open().unwrap();
+
+        let config = UdsConfig {
+            tester_present_interval_ms: 50,
+            ..Default::default()
+        };
+        let mut uds = Uds::with_transport(config, isotp);
+        uds.open().unwrap();
+
+        let keepalive = uds.spawn_keepalive();
+        std::thread::sleep(std::time::Duration::from_millis(120));
+        let mut uds = keepalive.stop();
+
+        // Tester-present with the positive response suppressed, once per interval
+        let sent = sent_frames.lock().unwrap().clone();
+        assert!(sent.len() >= 2);
+        assert!(sent.iter().all(|frame| frame.data == vec![0x3E, 0x80]));
+        assert!(uds.status.tester_present_sent);
+
+        // The reclaimed instance is still usable
+        assert_eq!(uds.state(), crate::types::LayerState::Open);
+        uds.close().unwrap();
+    }
+
     #[test]
     fn test_uds_read_data() {
         let mut uds = create_mock_uds();
@@ -91,6 +168,70 @@ mod uds_tests {
         uds.close().unwrap();
     }
 
+    #[test]
+    fn test_uds_bare_positive_response() {
+        // The ECU echoes the response SID without any data record
+        let transport = LoopbackTransport::with_handler(|frame: &Frame| {
+            Ok(Frame {
+                data: vec![frame.data[0] + 0x40],
+                ..Default::default()
+            })
+        });
+        let mut uds = Uds::with_transport(UdsConfig::default(), transport);
+        uds.open().unwrap();
+
+        uds.ecu_reset(UdsResetType::HardReset, false).unwrap();
+        assert_eq!(uds.read_data_by_id(0xF190).unwrap(), Vec::<u8>::new());
+
+        // No reply at all is still an error
+        let mut uds = Uds::with_transport(
+            UdsConfig::default(),
+            LoopbackTransport::with_handler(|_| Err(AutomotiveError::Timeout)),
+        );
+        uds.open().unwrap();
+        assert!(uds.read_data_by_id(0xF190).is_err());
+    }
+
+    #[test]
+    fn test_uds_ecu_reset_power_down_time() {
+        use std::sync::atomic::{AtomicU32, Ordering};
+
+        // Rapid power shutdown in 10 s; the ECU then ignores two TesterPresent polls
+        let polls = Arc::new(AtomicU32::new(0));
+        let counter = Arc::clone(&polls);
+        let transport = LoopbackTransport::with_handler(move |frame: &Frame| {
+            let data = match frame.data[0] {
+                SID_ECU_RESET => vec![0x51, frame.data[1], 0x0A],
+                SID_TESTER_PRESENT if counter.fetch_add(1, Ordering::SeqCst) < 2 => {
+                    return Err(AutomotiveError::Timeout)
+                }
+                SID_TESTER_PRESENT => vec![0x7E, 0x00],
+                _ => NegativeResponse::new(frame.data[0], NRC_SERVICE_NOT_SUPPORTED).to_bytes(),
+            };
+            Ok(Frame {
+                data,
+                ..Default::default()
+            })
+        });
+        let mut uds = Uds::with_transport(UdsConfig::default(), transport);
+        uds.open().unwrap();
+
+        assert_eq!(
+            uds.ecu_reset(UdsResetType::EnableRapidPowerShutdown, false)
+                .unwrap(),
+            Some(0x0A)
+        );
+
+        uds.status.session_type = UdsSessionType::Extended;
+        assert_eq!(
+            uds.ecu_reset_and_wait(UdsResetType::HardReset, 1000)
+                .unwrap(),
+            Some(0x0A)
+        );
+        assert_eq!(polls.load(Ordering::SeqCst), 3);
+        assert_eq!(uds.status.session_type, UdsSessionType::Default);
+    }
+
     #[test]
     fn test_uds_write_data() {
         let mut uds = create_mock_uds();
@@ -101,11 +242,252 @@ mod uds_tests {
     #[test]
     fn test_uds_routine_control() {
         let mut uds = create_mock_uds();
-        let result = uds.routine_control(0x01, 0x1234, &[0x01]).unwrap();
+        let result = uds.routine_control(0x01, 0x1234, &[0x01], false).unwrap();
         assert!(!result.is_empty());
         uds.close().unwrap();
     }
 
+    #[test]
+    fn test_uds_routine_control_request() {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let log = Arc::clone(&requests);
+        let transport = LoopbackTransport::with_handler(move |frame: &Frame| {
+            log.lock().unwrap().push(frame.data.clone());
+            // Echoed routine type and id, then status record 0x00
+            Ok(Frame {
+                data: build_positive_response(frame.data[0], &[frame.data[1], 0x12, 0x34, 0x00]),
+                ..Default::default()
+            })
+        });
+        let mut uds = Uds::with_transport(UdsConfig::default(), transport);
+        uds.open().unwrap();
+
+        assert_eq!(
+            uds.routine_control(ROUTINE_START, 0x1234, &[0xAB], false)
+                .unwrap(),
+            vec![0x00]
+        );
+        assert_eq!(
+            *requests.lock().unwrap(),
+            vec![vec![SID_ROUTINE_CONTROL, ROUTINE_START, 0x12, 0x34, 0xAB]]
+        );
+    }
+
+    #[test]
+    fn test_uds_suppress_positive_response() {
+        // A silent ECU: any read would time out
+        let mock = MockPhysical::with_script(Vec::new());
+        let sent_frames = mock.sent_frames();
+        let mut isotp = IsoTp::with_physical(IsoTpConfig::default(), mock);
+        isotp.open().unwrap();
+        let mut uds = Uds::with_transport(UdsConfig::default(), isotp);
+        uds.open().unwrap();
+
+        uds.change_session(UdsSessionType::Extended, true).unwrap();
+        assert_eq!(uds.status.session_type, UdsSessionType::Extended);
+        uds.ecu_reset(UdsResetType::SoftReset, true).unwrap();
+        uds.communication_control(0x03, 0x01, true).unwrap();
+        assert!(uds
+            .routine_control(ROUTINE_START, 0xFF00, &[], true)
+            .unwrap()
+            .is_empty());
+        assert!(matches!(
+            uds.ecu_reset(UdsResetType::SoftReset, false),
+            Err(AutomotiveError::Timeout)
+        ));
+
+        let payloads: Vec<Vec<u8>> = sent_frames
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|frame| frame.data.clone())
+            .collect();
+        assert_eq!(
+            payloads,
+            vec![
+                vec![SID_DIAGNOSTIC_SESSION_CONTROL, 0x83],
+                vec![SID_ECU_RESET, 0x83],
+                vec![SID_COMMUNICATION_CONTROL, 0x83, 0x01],
+                vec![SID_ROUTINE_CONTROL, 0x81, 0xFF, 0x00],
+                vec![SID_ECU_RESET, 0x03],
+            ]
+        );
+    }
+
+    #[test]
+    fn test_uds_suppressed_negative_response() {
+        // The ECU still reports NRCs for suppressed requests
+        let transport = LoopbackTransport::with_handler(|frame: &Frame| match frame.data[0] {
+            SID_ECU_RESET => Ok(Frame {
+                data: NegativeResponse::new(SID_ECU_RESET, 0x22).to_bytes(),
+                ..Default::default()
+            }),
+            _ => Err(AutomotiveError::Timeout),
+        });
+        let mut uds = Uds::with_transport(UdsConfig::default(), transport);
+        uds.open().unwrap();
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let tap_log = Arc::clone(&log);
+        uds.set_tap(move |request, result| {
+            tap_log.lock().unwrap().push((
+                request.service_id,
+                request.parameters[0],
+                result.is_ok(),
+            ));
+        });
+
+        uds.change_session(UdsSessionType::Extended, true).unwrap();
+        assert!(matches!(
+            uds.ecu_reset(UdsResetType::SoftReset, true),
+            Err(AutomotiveError::UdsNegativeResponse {
+                service_id: SID_ECU_RESET,
+                nrc: 0x22
+            })
+        ));
+
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec![
+                (SID_DIAGNOSTIC_SESSION_CONTROL, 0x83, false),
+                (SID_ECU_RESET, 0x83, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_uds_security_access() {
+        // Level 2: seed 0xA5 0x5A, unlocked by its complement
+        let transport = LoopbackTransport::with_handler(|frame: &Frame| {
+            let data = match frame.data[1..] {
+                [0x03] => vec![0x67, 0x03, 0xA5, 0x5A],
+                [0x04, 0x5A, 0xA5] => vec![0x67, 0x04],
+                _ => NegativeResponse::new(SID_SECURITY_ACCESS, NRC_INVALID_KEY).to_bytes(),
+            };
+            Ok(Frame {
+                data,
+                ..Default::default()
+            })
+        });
+        let mut uds = Uds::with_transport(UdsConfig::default(), transport);
+        uds.open().unwrap();
+
+        uds.security_access(2, |seed| {
+            assert_eq!(seed, &[0xA5, 0x5A]);
+            seed.iter().map(|byte| !byte).collect()
+        })
+        .unwrap();
+        assert_eq!(uds.status.security_level, 2);
+
+        // requestSeed is odd and at most 0x7D
+        for level in [0, 0x40, 0xFF] {
+            assert!(matches!(
+                uds.security_access(level, |seed| seed.to_vec()),
+                Err(AutomotiveError::InvalidParameter)
+            ));
+        }
+    }
+
+    #[test]
+    fn test_uds_response_parse() {
+        let response = UdsResponse::parse(&[0x62, 0xF1, 0x90, 0x57]).unwrap();
+        assert_eq!(response.service_id, 0x62);
+        assert_eq!(response.data, vec![0xF1, 0x90, 0x57]);
+
+        let response = UdsResponse::try_from(&[0x54][..]).unwrap();
+        assert_eq!(response.service_id, 0x54);
+        assert!(response.data.is_empty());
+
+        assert!(matches!(
+            UdsResponse::parse(&NegativeResponse::new(SID_READ_DATA_BY_ID, 0x31).to_bytes()),
+            Err(AutomotiveError::UdsNegativeResponse {
+                service_id: SID_READ_DATA_BY_ID,
+                nrc: 0x31
+            })
+        ));
+        assert!(matches!(
+            UdsResponse::parse(&[]),
+            Err(AutomotiveError::InvalidParameter)
+        ));
+        assert!(matches!(
+            UdsResponse::parse(&[0x7F, SID_READ_DATA_BY_ID]),
+            Err(AutomotiveError::InvalidData)
+        ));
+    }
+
+    #[test]
+    fn test_uds_documented_constructors() {
+        let mut isotp = IsoTp::new_with_can(IsoTpConfig::default(), MockPhysical::new_echo());
+        isotp.open().unwrap();
+        let mut uds = Uds::new_with_isotp(UdsConfig::default(), isotp);
+        uds.open().unwrap();
+        uds.tester_present().unwrap();
+        uds.close().unwrap();
+    }
+
+    /// UDS client over a loopback ECU accepting certificate C1 C2 C3 and the inverted challenge as proof
+    fn create_authentication_uds() -> Uds<LoopbackTransport> {
+        const CHALLENGE: [u8; 4] = [0x11, 0x22, 0x33, 0x44];
+        let transport = LoopbackTransport::with_handler(|frame: &Frame| {
+            let data = match frame.data[1] {
+                AUTH_VERIFY_CERTIFICATE_UNIDIRECTIONAL => {
+                    // Configuration, certificate length and certificate, empty client challenge
+                    if frame.data[2..] != [0x00, 0x00, 0x03, 0xC1, 0xC2, 0xC3, 0x00, 0x00] {
+                        NegativeResponse::new(SID_AUTHENTICATION, 0x50).to_bytes()
+                    } else {
+                        let mut response = vec![0x69, 0x01, 0x11, 0x00, 0x04];
+                        response.extend_from_slice(&CHALLENGE);
+                        response.extend_from_slice(&[0x00, 0x00]); // No ephemeral key
+                        response
+                    }
+                }
+                AUTH_PROOF_OF_OWNERSHIP => {
+                    let expected: Vec<u8> = CHALLENGE.iter().map(|b| b ^ 0xFF).collect();
+                    let proof_ok = frame.data[2..4] == [0x00, 0x04] && frame.data[4..8] == expected;
+                    let return_parameter = if proof_ok { 0x12 } else { 0x01 };
+                    vec![0x69, 0x03, return_parameter, 0x00, 0x00]
+                }
+                _ => NegativeResponse::new(SID_AUTHENTICATION, 0x12).to_bytes(),
+            };
+            Ok(Frame {
+                data,
+                ..Default::default()
+            })
+        });
+        let mut uds = Uds::with_transport(UdsConfig::default(), transport);
+        uds.open().unwrap();
+        uds
+    }
+
+    #[test]
+    fn test_uds_authentication_challenge_exchange() {
+        let mut uds = create_authentication_uds();
+        let challenge = uds
+            .verify_certificate_unidirectional(&[0xC1, 0xC2, 0xC3])
+            .unwrap();
+        assert_eq!(challenge, vec![0x11, 0x22, 0x33, 0x44]);
+        let proof: Vec<u8> = challenge.iter().map(|b| b ^ 0xFF).collect();
+        uds.proof_of_ownership(&proof, &[]).unwrap();
+
+        // The whole exchange in one call, with a wrong signature
+        let mut uds = create_authentication_uds();
+        match uds.authenticate(&[0xC1, 0xC2, 0xC3], |challenge| challenge.to_vec()) {
+            Err(AutomotiveError::UdsError(message)) => assert!(message.contains("0x01")),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        uds.authenticate(&[0xC1, 0xC2, 0xC3], |challenge| {
+            challenge.iter().map(|b| b ^ 0xFF).collect()
+        })
+        .unwrap();
+
+        // Certificate rejected by the ECU
+        assert_eq!(
+            uds.verify_certificate_unidirectional(&[0xBA, 0xD0])
+                .unwrap_err()
+                .nrc(),
+            Some(0x50)
+        );
+    }
+
     #[test]
     fn test_uds_io_control() {
         let mut uds = create_mock_uds();
@@ -117,22 +499,633 @@ mod uds_tests {
     #[test]
     fn test_uds_memory_access() {
         let mut uds = create_mock_uds();
-        let data = uds.read_memory(0x12345678, 3).unwrap();
+        let data = uds.read_memory_u32(0x12345678, 3).unwrap();
         assert_eq!(data, vec![0x01, 0x02, 0x03]);
-        assert!(uds.write_memory(0x12345678, &[0x01, 0x02, 0x03]).is_err());
+        assert!(uds
+            .write_memory_u32(0x12345678, &[0x01, 0x02, 0x03])
+            .is_err());
+        uds.close().unwrap();
+    }
+
+    #[test]
+    fn test_uds_read_scaling_data() {
+        let mut uds = create_mock_uds();
+        let records = uds.read_scaling_data_by_id(0xF190).unwrap();
+        assert_eq!(
+            records,
+            vec![
+                ScalingRecord {
+                    data_type: 0x04,
+                    bytes: vec![0xAA],
+                },
+                ScalingRecord {
+                    data_type: 0x02,
+                    bytes: vec![0x01, 0x02],
+                },
+            ]
+        );
+        uds.close().unwrap();
+    }
+
+    #[test]
+    fn test_uds_dynamically_define_did() {
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let sent_clone = sent.clone();
+        let mock = MockPhysical::new(Some(Box::new(move |frame: &Frame| {
+            let response_data = match frame.data[0] {
+                SID_DYNAMICALLY_DEFINE_DATA_ID => {
+                    sent_clone.lock().unwrap().push(frame.data.clone());
+                    vec![0x6C, frame.data[1], frame.data[2], frame.data[3]]
+                }
+                _ => vec![0x62, frame.data[1], frame.data[2], 0x12, 0x34, 0x56],
+            };
+            Ok(Frame::standard(frame.id, response_data))
+        })));
+
+        let mut isotp = IsoTp::with_physical(IsoTpConfig::default(), mock);
+        isotp.open().unwrap();
+
+        let mut uds = Uds::with_transport(UdsConfig::default(), isotp);
+        uds.open().unwrap();
+
+        uds.define_by_did(0xF300, &[(0xF190, 1, 2), (0x1234, 3, 1)])
+            .unwrap();
+        assert_eq!(
+            sent.lock().unwrap()[0],
+            vec![
+                0x2C, 0x01, 0xF3, 0x00, // SID, defineByIdentifier, dynamic DID
+                0xF1, 0x90, 0x01, 0x02, // Source DID 0xF190, position 1, size 2
+                0x12, 0x34, 0x03, 0x01, // Source DID 0x1234, position 3, size 1
+            ]
+        );
+
+        let data = uds.read_data_by_id(0xF300).unwrap();
+        assert!(!data.is_empty());
+
+        uds.clear_dynamic_did(0xF300).unwrap();
+        assert_eq!(sent.lock().unwrap()[1], vec![0x2C, 0x03, 0xF3, 0x00]);
+
+        uds.close().unwrap();
+    }
+
+    #[test]
+    fn test_uds_periodic_data() {
+        let responses = Arc::new(Mutex::new(0u32));
+        let responses_clone = responses.clone();
+        let mock = MockPhysical::new(Some(Box::new(move |frame: &Frame| {
+            assert_eq!(frame.data[0], SID_READ_DATA_BY_PERIODIC_ID);
+            let mut count = responses_clone.lock().unwrap();
+            *count += 1;
+            let response_data = match *count {
+                1 => vec![0x6A],             // Positive response to the start request
+                2 => vec![0x7F, 0x22, 0x31], // A late reply to another request
+                _ => vec![0x05, 0x12, 0x34], // Unsolicited periodic data for DID 0xF205
+            };
+            Ok(Frame::standard(frame.id, response_data))
+        })));
+
+        let mut isotp = IsoTp::with_physical(IsoTpConfig::default(), mock);
+        isotp.open().unwrap();
+
+        let mut uds = Uds::with_transport(UdsConfig::default(), isotp);
+        uds.open().unwrap();
+
+        uds.start_periodic(PeriodicRate::Fast, &[0x05]).unwrap();
+        // Frames that are not periodic data are discarded
+        assert_eq!(uds.poll_periodic().unwrap(), None);
+        assert_eq!(uds.poll_periodic().unwrap(), Some((0x05, vec![0x12, 0x34])));
+
+        uds.close().unwrap();
+    }
+
+    #[test]
+    fn test_uds_boxed_transports() {
+        use crate::transport::doip::{DoIP, DoIPConfig};
+        use crate::transport::BoxedTransport;
+
+        let mock = MockPhysical::new(Some(Box::new(|frame: &Frame| {
+            Ok(Frame::standard(
+                frame.id,
+                vec![0x62, frame.data[1], frame.data[2], 0x12, 0x34],
+            ))
+        })));
+        let isotp = IsoTp::with_physical(IsoTpConfig::default(), mock);
+
+        // Reserve a local port with nothing listening so the DoIP connect is refused
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let doip_config = DoIPConfig {
+            host: String::from("127.0.0.1"),
+            port,
+            ..Default::default()
+        };
+        let doip = DoIP::with_physical(doip_config, MockPhysical::new(None));
+
+        let mut clients: Vec<Uds<BoxedTransport>> = vec![
+            Uds::with_boxed_transport(UdsConfig::default(), isotp),
+            Uds::with_boxed_transport(UdsConfig::default(), doip),
+        ];
+
+        clients[0].open().unwrap();
+        assert_eq!(
+            clients[0].read_data_by_id(0xF190).unwrap(),
+            vec![0xF1, 0x90, 0x12, 0x34]
+        );
+        assert!(matches!(
+            clients[1].open(),
+            Err(crate::error::AutomotiveError::ConnectionFailed)
+        ));
+    }
+
+    #[test]
+    fn test_uds_read_dtcs() {
+        let mut uds = create_mock_uds();
+        assert_eq!(uds.read_dtcs().unwrap(), vec!["P0133", "C0244"]);
+        uds.close().unwrap();
+    }
+
+    #[test]
+    fn test_uds_clear_diagnostic_information() {
+        let mut uds = create_mock_uds();
+        uds.clear_diagnostic_information(DTC_GROUP_ALL).unwrap();
+        uds.clear_dtcs().unwrap();
+        uds.close().unwrap();
+    }
+
+    #[test]
+    fn test_uds_clear_diagnostic_information_rejected() {
+        let mut uds = create_mock_uds();
+        assert!(matches!(
+            uds.clear_diagnostic_information(0x000100),
+            Err(crate::error::AutomotiveError::ConditionsNotCorrect)
+        ));
+        assert!(matches!(
+            uds.clear_diagnostic_information_in_memory(0x000100, 0x01),
+            Err(crate::error::AutomotiveError::ConditionsNotCorrect)
+        ));
+        uds.close().unwrap();
+    }
+
+    #[test]
+    fn test_uds_session_parameter_record() {
+        let mock = MockPhysical::new(Some(Box::new(|frame: &Frame| {
+            // P2server_max = 50ms, P2*server_max = 500 * 10ms
+            Ok(Frame::standard(
+                frame.id,
+                vec![0x50, frame.data[1], 0x00, 0x32, 0x01, 0xF4],
+            ))
+        })));
+
+        let mut isotp = IsoTp::with_physical(IsoTpConfig::default(), mock);
+        isotp.open().unwrap();
+        let mut uds = Uds::with_transport(UdsConfig::default(), isotp);
+        uds.open().unwrap();
+
+        uds.change_session(UdsSessionType::Extended, false).unwrap();
+        assert_eq!(uds.config().p2_timeout_ms, 50);
+        assert_eq!(uds.config().p2_star_timeout_ms, 5000);
+        uds.close().unwrap();
+    }
+
+    #[test]
+    fn test_uds_session_without_parameter_record() {
+        let mut uds = create_mock_uds();
+        uds.change_session(UdsSessionType::Extended, false).unwrap();
+        assert_eq!(uds.config().p2_timeout_ms, 100);
+        assert_eq!(uds.config().p2_star_timeout_ms, 500);
+        uds.close().unwrap();
+    }
+
+    #[test]
+    fn test_uds_raw_request() {
+        let mut uds = create_mock_uds();
+
+        // LinkControl: verify mode transition with fixed baudrate (500 kbit/s)
+        let response = uds.raw_request(0x87, &[0x01, 0x12]).unwrap();
+        assert_eq!(response.service_id, 0xC7);
+        assert_eq!(response.data, vec![0x01]);
+
+        // ResponseOnEvent is not supported by the mock ECU
+        assert!(matches!(
+            uds.raw_request(0x86, &[0x00]),
+            Err(crate::error::AutomotiveError::UdsNegativeResponse {
+                service_id: 0x86,
+                nrc: 0x11
+            })
+        ));
+        uds.close().unwrap();
+    }
+
+    #[test]
+    fn test_uds_response_builders() {
+        assert_eq!(
+            NegativeResponse::new(SID_READ_DTC, NRC_RESPONSE_PENDING).to_bytes(),
+            vec![0x7F, 0x19, 0x78]
+        );
+        assert_eq!(
+            NegativeResponse::new(0x86, NRC_SERVICE_NOT_SUPPORTED).to_bytes(),
+            vec![0x7F, 0x86, 0x11]
+        );
+        assert_eq!(
+            build_positive_response(SID_DIAGNOSTIC_SESSION_CONTROL, &[0x03]),
+            vec![0x50, 0x03]
+        );
+    }
+
+    #[test]
+    fn test_uds_vendor_session() {
+        let mut uds = create_mock_uds();
+        uds.change_session_raw(0x60, false).unwrap();
+        assert_eq!(uds.status.session_type, UdsSessionType::Extended);
+
+        uds.change_session_raw(SESSION_DEFAULT, false).unwrap();
+        assert_eq!(uds.status.session_type, UdsSessionType::Default);
+        uds.close().unwrap();
+    }
+
+    #[test]
+    fn test_negative_response_classification() {
+        let negative = |nrc| AutomotiveError::UdsNegativeResponse {
+            service_id: SID_READ_DATA_BY_ID,
+            nrc,
+        };
+
+        assert_eq!(negative(NRC_SECURITY_ACCESS_DENIED).nrc(), Some(0x33));
+        assert!(negative(NRC_RESPONSE_PENDING).is_pending());
+        assert!(negative(NRC_RESPONSE_PENDING).is_retryable());
+        assert!(negative(0x21).is_busy());
+        assert!(negative(0x21).is_retryable());
+        assert!(negative(0x23).is_retryable());
+        assert!(negative(NRC_SECURITY_ACCESS_DENIED).requires_security());
+        assert!(!negative(NRC_SECURITY_ACCESS_DENIED).is_retryable());
+        assert!(!negative(NRC_REQUEST_OUT_OF_RANGE).is_retryable());
+        assert!(!negative(NRC_REQUEST_OUT_OF_RANGE).requires_security());
+
+        // Other errors carry no NRC
+        assert_eq!(AutomotiveError::Timeout.nrc(), None);
+        assert!(!AutomotiveError::Timeout.is_retryable());
+    }
+
+    /// UDS client over a loopback ECU exposing `memory` at address 0x1000
+    fn create_memory_uds(memory: Vec<u8>, fail_from: u32) -> Uds<LoopbackTransport> {
+        let transport = LoopbackTransport::with_handler(move |frame: &Frame| {
+            // ALFID 0x24: 4-byte address, 2-byte size
+            let address =
+                u32::from_be_bytes([frame.data[2], frame.data[3], frame.data[4], frame.data[5]]);
+            let size = u16::from_be_bytes([frame.data[6], frame.data[7]]) as usize;
+            let data = if address >= fail_from {
+                NegativeResponse::new(SID_READ_MEMORY_BY_ADDRESS, NRC_REQUEST_OUT_OF_RANGE)
+                    .to_bytes()
+            } else {
+                let start = (address - 0x1000) as usize;
+                build_positive_response(SID_READ_MEMORY_BY_ADDRESS, &memory[start..start + size])
+            };
+            Ok(Frame {
+                data,
+                ..Default::default()
+            })
+        });
+        let mut uds = Uds::with_transport(UdsConfig::default(), transport);
+        uds.open().unwrap();
+        uds
+    }
+
+    /// UDS client over a loopback ECU answering memory reads, recording requests in `requests`
+    fn create_recording_uds(requests: Arc<Mutex<Vec<Vec<u8>>>>) -> Uds<LoopbackTransport> {
+        let transport = LoopbackTransport::with_handler(move |frame: &Frame| {
+            requests.lock().unwrap().push(frame.data.clone());
+            Ok(Frame {
+                data: build_positive_response(frame.data[0], &[0xAA, 0xBB]),
+                ..Default::default()
+            })
+        });
+        let mut uds = Uds::with_transport(UdsConfig::default(), transport);
+        uds.open().unwrap();
+        uds
+    }
+
+    #[test]
+    fn test_uds_memory_address_format() {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let mut uds = create_recording_uds(Arc::clone(&requests));
+
+        // 2-byte address, 1-byte size
+        uds.read_memory(0x1234u16, 2u8).unwrap();
+        // 4-byte address, 2-byte size
+        uds.read_memory(0x12345678u32, 0x0002u16).unwrap();
+        let _ = uds.write_memory::<u16, u8>(0x1234, &[0x01, 0x02]);
+        // Data longer than the size field can describe is never sent
+        assert!(matches!(
+            uds.write_memory::<u16, u8>(0x1234, &[0x00; 256]),
+            Err(AutomotiveError::InvalidParameter)
+        ));
+
+        let requests = requests.lock().unwrap();
+        assert_eq!(requests[0], vec![0x23, 0x12, 0x12, 0x34, 0x02]);
+        assert_eq!(
+            requests[1],
+            vec![0x23, 0x24, 0x12, 0x34, 0x56, 0x78, 0x00, 0x02]
+        );
+        assert_eq!(requests[2], vec![0x3D, 0x12, 0x12, 0x34, 0x02, 0x01, 0x02]);
+        assert_eq!(requests.len(), 3);
+    }
+
+    #[test]
+    fn test_uds_read_memory_chunked() {
+        let memory: Vec<u8> = (0..10).collect();
+        let mut uds = create_memory_uds(memory.clone(), u32::MAX);
+
+        // Chunks of 4, 4 and a short final chunk of 2
+        assert_eq!(uds.read_memory_chunked(0x1000, 10, 4).unwrap(), memory);
+
+        // An NRC on the third chunk keeps the first eight bytes
+        let mut uds = create_memory_uds(memory.clone(), 0x1008);
+        match uds.read_memory_chunked(0x1000, 10, 4) {
+            Err(AutomotiveError::IncompleteRead { data, error }) => {
+                assert_eq!(data, memory[..8]);
+                assert_eq!(error.nrc(), Some(NRC_REQUEST_OUT_OF_RANGE));
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        // Failing on the first chunk returns the plain error
+        let mut uds = create_memory_uds(memory, 0x1000);
+        assert!(matches!(
+            uds.read_memory_chunked(0x1000, 10, 4),
+            Err(AutomotiveError::UdsNegativeResponse { .. })
+        ));
+    }
+
+    #[test]
+    fn test_uds_tap_observes_session() {
+        let mut uds = create_mock_uds();
+        let log = Arc::new(Mutex::new(Vec::new()));
+        let tap_log = Arc::clone(&log);
+        uds.set_tap(move |request, result| {
+            let nrc = match result {
+                Ok(response) if response.service_id == 0x7F => response.data.get(1).copied(),
+                Ok(_) => None,
+                Err(error) => error.nrc(),
+            };
+            tap_log.lock().unwrap().push((request.service_id, nrc));
+        });
+
+        uds.change_session(UdsSessionType::Extended, false).unwrap();
+        uds.tester_present().unwrap();
+        uds.routine_control(0x01, 0xFF00, &[0x00], false).unwrap();
+        assert!(uds.write_memory_u32(0x1000, &[0x01]).is_err());
+
+        assert_eq!(
+            *log.lock().unwrap(),
+            vec![
+                (SID_DIAGNOSTIC_SESSION_CONTROL, None),
+                (SID_TESTER_PRESENT, None),
+                (SID_ROUTINE_CONTROL, None),
+                (SID_WRITE_MEMORY_BY_ADDRESS, Some(NRC_REQUEST_OUT_OF_RANGE)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_uds_service_ids() {
+        assert_eq!(
+            UdsService::try_from(0x10).unwrap(),
+            UdsService::DiagnosticSessionControl
+        );
+        assert_eq!(
+            UdsService::try_from(SID_READ_DTC).unwrap(),
+            UdsService::ReadDtcInformation
+        );
+        assert_eq!(u8::from(UdsService::TesterPresent), 0x3E);
+        assert_eq!(u8::from(UdsService::RequestTransferExit), 0x37);
+        assert!(UdsService::try_from(0x7F).is_err());
+
+        assert_eq!(
+            UdsService::DiagnosticSessionControl.to_string(),
+            "DiagnosticSessionControl"
+        );
+        assert_eq!(UdsService::EcuReset.to_string(), "ECUReset");
+        assert_eq!(
+            UdsService::ReadDtcInformation.to_string(),
+            "ReadDTCInformation"
+        );
+    }
+
+    #[test]
+    fn test_uds_open_rejects_zero_timeouts() {
+        let invalid = [
+            UdsConfig {
+                timeout_ms: 0,
+                ..Default::default()
+            },
+            UdsConfig {
+                p2_timeout_ms: 0,
+                ..Default::default()
+            },
+            UdsConfig {
+                p2_star_timeout_ms: 0,
+                ..Default::default()
+            },
+        ];
+
+        for config in invalid {
+            let transport = LoopbackTransport::with_handler(|frame: &Frame| Ok(frame.clone()));
+            let mut uds = Uds::with_transport(config, transport);
+            assert!(matches!(uds.open(), Err(AutomotiveError::InvalidParameter)));
+        }
+    }
+
+    #[test]
+    fn test_uds_open_opens_whole_stack() {
+        let mock = MockPhysical::new(Some(Box::new(|frame: &Frame| {
+            Ok(Frame::standard(frame.id, vec![0x7E, 0x00]))
+        })));
+        let isotp = IsoTp::with_physical(IsoTpConfig::default(), mock);
+        let mut uds = Uds::with_transport(UdsConfig::default(), isotp);
+
+        // Neither the physical nor the transport layer is opened by hand
+        uds.open().unwrap();
+        uds.tester_present().unwrap();
+
+        // Opening again is harmless
+        uds.open().unwrap();
+        uds.tester_present().unwrap();
+    }
+
+    #[test]
+    fn test_uds_over_loopback_transport() {
+        let transport = LoopbackTransport::with_handler(|frame: &Frame| {
+            // Raw service bytes, no ISO-TP framing
+            Ok(Frame {
+                data: vec![0x62, frame.data[1], frame.data[2], 0x12, 0x34],
+                ..Default::default()
+            })
+        });
+        let mut uds = Uds::with_transport(UdsConfig::default(), transport);
+        uds.open().unwrap();
+
+        assert_eq!(
+            uds.read_data_by_id(0xF190).unwrap(),
+            vec![0xF1, 0x90, 0x12, 0x34]
+        );
         uds.close().unwrap();
     }
 
     #[test]
     fn test_uds_session_timeout() {
         let mut uds = create_mock_uds();
-        uds.change_session(UdsSessionType::Programming).unwrap();
+        uds.change_session(UdsSessionType::Programming, false)
+            .unwrap();
         std::thread::sleep(std::time::Duration::from_millis(6000));
         uds.tester_present().unwrap();
         assert_eq!(uds.status.session_type, UdsSessionType::Default);
         uds.close().unwrap();
     }
 
+    /// Mock ECU answering `reply(request, read)` for the `read`-th receive after
+    /// the `request`-th sent frame, both counted from 1
+    fn create_sequenced_mock(
+        reply: impl Fn(usize, usize, &Frame) -> Result<Frame> + Send + Sync + 'static,
+    ) -> MockPhysical {
+        let mut mock = MockPhysical::new(None);
+        let sent_frames = mock.sent_frames();
+        let reads = Mutex::new((0, 0));
+        mock.set_frame_handler(Some(Box::new(move |frame: &Frame| {
+            let request = sent_frames.lock().unwrap().len();
+            let mut reads = reads.lock().unwrap();
+            if reads.0 != request {
+                *reads = (request, 0);
+            }
+            reads.1 += 1;
+            reply(request, reads.1, frame)
+        })));
+        mock
+    }
+
+    #[test]
+    fn test_uds_retry_transient_errors() {
+        let attempts = Arc::new(Mutex::new(0));
+        let handler_attempts = attempts.clone();
+        let mock = create_sequenced_mock(move |_, read, frame| {
+            // Reads after the first one are the flush draining the bus
+            if read > 1 {
+                return Err(AutomotiveError::Timeout);
+            }
+            // Fail the first two receptions, then answer
+            let mut attempts = handler_attempts.lock().unwrap();
+            *attempts += 1;
+            if *attempts <= 2 {
+                return Err(crate::error::AutomotiveError::ReceiveFailed);
+            }
+            Ok(Frame::standard(
+                frame.id,
+                vec![0x62, frame.data[1], frame.data[2], 0x01],
+            ))
+        });
+
+        let mut isotp = IsoTp::with_physical(IsoTpConfig::default(), mock);
+        isotp.open().unwrap();
+
+        let uds_config = UdsConfig {
+            retry: RetryPolicy {
+                max_attempts: 3,
+                base_delay_ms: 10,
+                backoff: Backoff::Exponential,
+            },
+            ..Default::default()
+        };
+        let mut uds = Uds::with_transport(uds_config, isotp);
+        uds.open().unwrap();
+
+        // Two retries, after 10 ms and 20 ms
+        let start = std::time::Instant::now();
+        assert_eq!(uds.read_data_by_id(0xF190).unwrap(), vec![0xF1, 0x90, 0x01]);
+        assert_eq!(*attempts.lock().unwrap(), 3);
+        assert!(start.elapsed() >= std::time::Duration::from_millis(30));
+
+        let policy = &uds.config().retry;
+        assert_eq!(policy.delay_ms(1), 10);
+        assert_eq!(policy.delay_ms(2), 20);
+        assert_eq!(policy.delay_ms(3), 40);
+
+        uds.close().unwrap();
+    }
+
+    #[test]
+    fn test_uds_retry_flushes_partial_response() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+
+        // The remains of the failed response stay on the bus until read
+        let stale = AtomicBool::new(false);
+        let mock = create_sequenced_mock(move |request, read, frame| {
+            if stale.swap(false, Ordering::SeqCst) {
+                return Ok(Frame::standard(frame.id, vec![0x62, 0xF1, 0x90, 0xEE]));
+            }
+            match (request, read) {
+                (1, 1) => {
+                    stale.store(true, Ordering::SeqCst);
+                    Err(AutomotiveError::ReceiveFailed)
+                }
+                (1, _) => Err(AutomotiveError::Timeout),
+                (_, 1) => Ok(Frame::standard(
+                    frame.id,
+                    vec![0x62, frame.data[1], frame.data[2], 0x01],
+                )),
+                _ => Err(AutomotiveError::Timeout),
+            }
+        });
+        let sent_frames = mock.sent_frames();
+
+        let mut isotp = IsoTp::with_physical(IsoTpConfig::default(), mock);
+        isotp.open().unwrap();
+        let uds_config = UdsConfig {
+            retry: RetryPolicy {
+                max_attempts: 2,
+                base_delay_ms: 1,
+                backoff: Backoff::Fixed,
+            },
+            ..Default::default()
+        };
+        let mut uds = Uds::with_transport(uds_config, isotp);
+        uds.open().unwrap();
+
+        // The retry reads its own response, not the stale one
+        assert_eq!(uds.read_data_by_id(0xF190).unwrap(), vec![0xF1, 0x90, 0x01]);
+        assert_eq!(sent_frames.lock().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_uds_retry_gives_up() {
+        let mock = MockPhysical::new(Some(Box::new(|_frame: &Frame| {
+            Err(crate::error::AutomotiveError::ReceiveFailed)
+        })));
+
+        let mut isotp = IsoTp::with_physical(IsoTpConfig::default(), mock);
+        isotp.open().unwrap();
+
+        let uds_config = UdsConfig {
+            retry: RetryPolicy {
+                max_attempts: 2,
+                base_delay_ms: 1,
+                backoff: Backoff::Fixed,
+            },
+            ..Default::default()
+        };
+        let mut uds = Uds::with_transport(uds_config, isotp);
+        assert!(matches!(
+            uds.read_data_by_id(0xF190),
+            Err(crate::error::AutomotiveError::NotInitialized)
+        ));
+        uds.open().unwrap();
+        assert!(matches!(
+            uds.read_data_by_id(0xF190),
+            Err(crate::error::AutomotiveError::ReceiveFailed)
+        ));
+    }
+
     #[test]
     fn test_uds_response_pending() {
         let mock = MockPhysical::new(Some(Box::new(|frame: &Frame| {
@@ -145,12 +1138,10 @@ mod uds_tests {
                 timestamp: 0,
                 is_extended: false,
                 is_fd: false,
+                brs: false,
             })
         })));
 
-        let mut mock = mock;
-        mock.open().unwrap();
-
         let isotp_config = IsoTpConfig {
             tx_id: 0x123,
             rx_id: 0x456,
@@ -172,6 +1163,60 @@ mod uds_tests {
 
         uds.close().unwrap();
     }
+
+    #[test]
+    fn test_uds_session_timing() {
+        let pending_sent = Arc::new(Mutex::new(false));
+        let pending = pending_sent.clone();
+        let mock = MockPhysical::new(Some(Box::new(move |frame: &Frame| {
+            let data = match frame.data[0] {
+                // P2server_max 20 ms, P2*server_max 500 ms
+                SID_DIAGNOSTIC_SESSION_CONTROL => vec![0x50, frame.data[1], 0x00, 0x14, 0x00, 0x32],
+                SID_READ_DATA_BY_ID => {
+                    // 0xF190 is answered pending first
+                    let mut pending = pending.lock().unwrap();
+                    if frame.data[2] == 0x90 && !*pending {
+                        *pending = true;
+                        vec![0x7F, SID_READ_DATA_BY_ID, NRC_RESPONSE_PENDING]
+                    } else {
+                        std::thread::sleep(std::time::Duration::from_millis(50));
+                        vec![0x62, frame.data[1], frame.data[2], 0x01]
+                    }
+                }
+                _ => vec![0x7F, frame.data[0], 0x11],
+            };
+            Ok(Frame::standard(0x7E8, data))
+        })));
+        let sent_frames = mock.sent_frames();
+
+        let isotp_config = IsoTpConfig {
+            tx_id: 0x7E0,
+            rx_id: 0x7E8,
+            ..Default::default()
+        };
+        let mut isotp = IsoTp::with_physical(isotp_config, mock);
+        isotp.open().unwrap();
+        let mut uds = Uds::with_transport(UdsConfig::default(), isotp);
+        uds.open().unwrap();
+
+        // The default P2 leaves time for the slow response
+        assert_eq!(uds.read_data_by_id(0xF191).unwrap(), vec![0xF1, 0x91, 0x01]);
+
+        uds.change_session(UdsSessionType::Extended, false).unwrap();
+        assert_eq!(uds.config().p2_timeout_ms, 20);
+        assert_eq!(uds.config().p2_star_timeout_ms, 500);
+
+        // The ECU's P2 is too short for the same response
+        assert!(matches!(
+            uds.read_data_by_id(0xF191),
+            Err(AutomotiveError::Timeout)
+        ));
+
+        // After response pending the wait is P2*, and the request is not repeated
+        sent_frames.lock().unwrap().clear();
+        assert_eq!(uds.read_data_by_id(0xF190).unwrap(), vec![0xF1, 0x90, 0x01]);
+        assert_eq!(sent_frames.lock().unwrap().len(), 1);
+    }
 }
 
 mod obd_tests {
@@ -216,18 +1261,9 @@ mod obd_tests {
                 _ => vec![0x7F, mode, 0x11], // Service not supported
             };
 
-            Ok(Frame {
-                id: frame.id,
-                data: response_data,
-                timestamp: 0,
-                is_extended: false,
-                is_fd: false,
-            })
+            Ok(Frame::standard(frame.id, response_data))
         })));
 
-        let mut mock = mock;
-        mock.open().unwrap();
-
         let isotp_config = IsoTpConfig {
             tx_id: 0x7DF,
             rx_id: 0x7E8,
@@ -247,6 +1283,92 @@ mod obd_tests {
         obd
     }
 
+    #[test]
+    fn test_pid_data_display_and_units() {
+        let rpm = PidData::EngineRpm(1750.0);
+        assert_eq!(format!("{}", rpm), "1750 RPM");
+        assert_eq!(rpm.unit(), "RPM");
+        assert_eq!(rpm.numeric_value(), Some(1750.0));
+
+        let coolant = PidData::CoolantTemp(-40);
+        assert_eq!(coolant.to_string(), "-40°C");
+        assert_eq!(coolant.unit(), "°C");
+        assert_eq!(coolant.numeric_value(), Some(-40.0));
+
+        assert_eq!(PidData::VehicleSpeed(50).numeric_value(), Some(50.0));
+        assert_eq!(PidData::EquivRatio(1.0).unit(), "");
+
+        let raw = PidData::Raw(vec![0xAB]);
+        assert_eq!(raw.to_string(), "Raw: [AB]");
+        assert_eq!(raw.numeric_value(), None);
+    }
+
+    #[test]
+    fn test_obd_pid_metadata() {
+        let info = ObdPid::describe(PID_ENGINE_RPM).unwrap();
+        assert_eq!(info.name, "Engine RPM");
+        assert_eq!(info.length, 2);
+        assert_eq!(info.unit, "RPM");
+        assert_eq!(ObdPid::EngineRpm.to_string(), "Engine RPM");
+        assert_eq!(u8::from(ObdPid::VehicleSpeed), PID_VEHICLE_SPEED);
+        assert!(ObdPid::describe(PID_SUPPORTED_PIDS_01_20).is_none());
+    }
+
+    #[test]
+    fn test_obd_read_sensor_data_length_mismatch() {
+        // Engine RPM answered with a single data byte
+        let transport = LoopbackTransport::with_handler(|frame: &Frame| {
+            Ok(Frame {
+                data: vec![0x41, frame.data[1], 0x1B],
+                ..Default::default()
+            })
+        });
+        let mut obd = Obd::with_transport(ObdConfig::default(), transport);
+        obd.open().unwrap();
+
+        match obd.read_sensor_data(PID_ENGINE_RPM) {
+            Err(AutomotiveError::ObdError(message)) => {
+                assert!(message.contains("Engine RPM"));
+                assert!(message.contains("expects 2 data bytes, got 1"));
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_obd_open_rejects_zero_timeout() {
+        let config = ObdConfig {
+            timeout_ms: 0,
+            ..Default::default()
+        };
+        let transport = LoopbackTransport::with_handler(|frame: &Frame| Ok(frame.clone()));
+        let mut obd = Obd::with_transport(config, transport);
+        assert!(matches!(obd.open(), Err(AutomotiveError::InvalidParameter)));
+    }
+
+    #[test]
+    fn test_obd_scan_ecus() {
+        let supported = |id| Frame::standard(id, vec![0x41, 0x00, 0xBE, 0x1F, 0xA8, 0x13]);
+        let mock = MockPhysical::with_script(vec![
+            supported(0x7E8),
+            supported(0x7E9),
+            supported(0x7E8),                         // Repeated answer
+            Frame::standard(0x7E0, vec![0x01, 0x00]), // Another tester's request
+        ]);
+        let sent_frames = mock.sent_frames();
+
+        let mut obd = Obd::with_transport(
+            ObdConfig::default(),
+            IsoTp::with_physical(IsoTpConfig::default(), mock),
+        );
+        obd.open().unwrap();
+
+        assert_eq!(obd.scan_ecus().unwrap(), vec![0x7E8, 0x7E9]);
+        let sent_frames = sent_frames.lock().unwrap();
+        assert_eq!(sent_frames[0].id, 0x7DF);
+        assert_eq!(sent_frames[0].data, vec![0x01, 0x00]);
+    }
+
     #[test]
     fn test_obd_read_sensor() -> Result<()> {
         let mut obd = create_mock_obd();
@@ -267,6 +1389,69 @@ mod obd_tests {
         Ok(())
     }
 
+    #[test]
+    fn test_obd_negative_response() {
+        // ECU rejecting every PID as requestOutOfRange
+        let transport = LoopbackTransport::with_handler(|frame: &Frame| {
+            Ok(Frame::standard(0x7E8, vec![0x7F, frame.data[0], 0x31]))
+        });
+        let mut obd = Obd::with_transport(ObdConfig::default(), transport);
+        obd.open().unwrap();
+
+        match obd.read_sensor_data(0xA6) {
+            Err(AutomotiveError::ObdNegativeResponse { mode, nrc }) => {
+                assert_eq!(mode, 0x01);
+                assert_eq!(nrc, NRC_REQUEST_OUT_OF_RANGE);
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert_eq!(
+            obd.read_sensor(PID_ENGINE_RPM).unwrap_err().nrc(),
+            Some(NRC_REQUEST_OUT_OF_RANGE)
+        );
+    }
+
+    #[test]
+    fn test_obd_documented_constructors() {
+        let can = MockPhysical::new(Some(Box::new(|frame: &Frame| {
+            Ok(Frame::standard(0x7E8, vec![0x41, frame.data[1], 0x32]))
+        })));
+        let mut isotp = IsoTp::new_with_can(IsoTpConfig::default(), can);
+        isotp.open().unwrap();
+        let mut obd = Obd::new_with_isotp(ObdConfig::default(), isotp);
+        obd.open().unwrap();
+        assert_eq!(obd.read_sensor(PID_VEHICLE_SPEED).unwrap(), vec![0x32]);
+        obd.close().unwrap();
+    }
+
+    #[test]
+    fn test_obd_response_parse() {
+        let response = ObdResponse::parse(&[0x41, 0x0C, 0x1A, 0xF8]).unwrap();
+        assert_eq!((response.mode, response.pid), (0x41, PID_ENGINE_RPM));
+        assert_eq!(response.data, vec![0x1A, 0xF8]);
+
+        // PID-less positive response
+        let response = ObdResponse::try_from(&[0x44][..]).unwrap();
+        assert_eq!((response.mode, response.pid), (0x44, 0));
+        assert!(response.data.is_empty());
+
+        assert!(matches!(
+            ObdResponse::parse(&[0x7F, 0x01, 0x12]),
+            Err(AutomotiveError::ObdNegativeResponse {
+                mode: 0x01,
+                nrc: 0x12
+            })
+        ));
+        assert!(matches!(
+            ObdResponse::parse(&[]),
+            Err(AutomotiveError::InvalidParameter)
+        ));
+        assert!(matches!(
+            ObdResponse::parse(&[0x7F, 0x01]),
+            Err(AutomotiveError::InvalidData)
+        ));
+    }
+
     #[test]
     fn test_obd_read_dtc() -> Result<()> {
         let mut obd = create_mock_obd();
@@ -281,6 +1466,132 @@ mod obd_tests {
         Ok(())
     }
 
+    #[test]
+    fn test_obd_pidless_modes_omit_pid() -> Result<()> {
+        let mock = MockPhysical::new(Some(Box::new(|frame: &Frame| {
+            let response_data = match frame.data[0] {
+                0x03 => vec![0x43, 0x01, 0x01, 0x33], // One DTC: P0133
+                0x04 => vec![0x44],                   // Clear acknowledged
+                _ => vec![frame.data[0] + 0x40, frame.data[1], 0x00, 0x00],
+            };
+            Ok(Frame::standard(frame.id, response_data))
+        })));
+        let sent_frames = mock.sent_frames();
+
+        let mut isotp = IsoTp::with_physical(IsoTpConfig::default(), mock);
+        isotp.open()?;
+        let mut obd = Obd::with_transport(ObdConfig::default(), isotp);
+        obd.open()?;
+
+        assert_eq!(obd.read_dtc()?, vec!["P0133"]);
+        obd.clear_dtc()?;
+        obd.read_sensor(PID_ENGINE_RPM)?;
+        obd.read_vehicle_info(0x02)?;
+
+        let sent_frames = sent_frames.lock().unwrap();
+        assert_eq!(sent_frames[0].data, vec![0x03]);
+        assert_eq!(sent_frames[1].data, vec![0x04]);
+        assert_eq!(sent_frames[2].data, vec![0x01, PID_ENGINE_RPM]);
+        assert_eq!(sent_frames[3].data, vec![0x09, 0x02]);
+
+        obd.close()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_obd_vehicle_info_multi_message() -> Result<()> {
+        // VIN split over two messages: count 2, then sequence number 2
+        let vin = b"1HGCM82633A004352";
+        let mut first = vec![0x49, 0x02, 0x02];
+        first.extend_from_slice(&vin[..9]);
+        let mut second = vec![0x49, 0x02, 0x02];
+        second.extend_from_slice(&vin[9..]);
+        let mock = MockPhysical::with_script(vec![
+            Frame::standard(0x7E8, first),
+            Frame::standard(0x7E8, second),
+        ]);
+
+        let mut isotp = IsoTp::with_physical(IsoTpConfig::default(), mock);
+        isotp.open()?;
+        let mut obd = Obd::with_transport(ObdConfig::default(), isotp);
+        obd.open()?;
+
+        assert_eq!(obd.read_vehicle_info(0x02)?, vin.to_vec());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_obd_vehicle_info_supported_pids() -> Result<()> {
+        // The supported-PID bitmask has no message count byte
+        let mock = MockPhysical::with_script(vec![Frame::standard(
+            0x7E8,
+            vec![0x49, 0x00, 0x55, 0x40, 0x00, 0x00],
+        )]);
+
+        let mut isotp = IsoTp::with_physical(IsoTpConfig::default(), mock);
+        isotp.open()?;
+        let mut obd = Obd::with_transport(ObdConfig::default(), isotp);
+        obd.open()?;
+
+        let start = std::time::Instant::now();
+        assert_eq!(obd.read_vehicle_info(0x00)?, vec![0x55, 0x40, 0x00, 0x00]);
+        // No further messages are waited for
+        assert!(start.elapsed() < std::time::Duration::from_millis(500));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_diagnostic_trait_objects() -> Result<()> {
+        let mut tools: Vec<Box<dyn Diagnostic>> = vec![
+            Box::new(create_mock_obd()),
+            Box::new(super::uds_tests::create_mock_uds()),
+        ];
+
+        let dtcs: Vec<Vec<String>> = tools
+            .iter_mut()
+            .map(|tool| tool.read_dtcs())
+            .collect::<Result<_>>()?;
+        assert_eq!(dtcs[0], vec!["P0133", "P0244"]);
+        assert_eq!(dtcs[1], vec!["P0133", "C0244"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_obd_read_all_supported() -> Result<()> {
+        let mock = MockPhysical::new(Some(Box::new(|frame: &Frame| {
+            let response_data = match frame.data[1] {
+                // Only 0x0C and 0x0D supported, no further ranges
+                PID_SUPPORTED_PIDS_01_20 => vec![0x41, 0x00, 0x00, 0x18, 0x00, 0x00],
+                PID_ENGINE_RPM => vec![0x41, PID_ENGINE_RPM, 0x1B, 0x56],
+                PID_VEHICLE_SPEED => vec![0x41, PID_VEHICLE_SPEED, 0x32],
+                _ => vec![0x7F, 0x01, 0x12],
+            };
+            Ok(Frame::standard(frame.id, response_data))
+        })));
+
+        let mut isotp = IsoTp::with_physical(IsoTpConfig::default(), mock);
+        isotp.open()?;
+        let mut obd = Obd::with_transport(ObdConfig::default(), isotp);
+        obd.open()?;
+
+        assert_eq!(
+            obd.supported_pids()?,
+            vec![PID_ENGINE_RPM, PID_VEHICLE_SPEED]
+        );
+
+        let readings = obd.read_all_supported()?;
+        assert_eq!(readings.len(), 2);
+        assert_eq!(readings[0].0, PID_ENGINE_RPM);
+        assert!(matches!(readings[0].1, PidData::EngineRpm(rpm) if rpm == 1750.0));
+        assert_eq!(readings[1].0, PID_VEHICLE_SPEED);
+        assert!(matches!(readings[1].1, PidData::VehicleSpeed(50)));
+
+        obd.close()?;
+        Ok(())
+    }
+
     #[test]
     fn test_obd_freeze_frame() {
         // Create a simple test that doesn't rely on the mock
@@ -295,12 +1606,10 @@ mod obd_tests {
                 timestamp: 0,
                 is_extended: false,
                 is_fd: false,
+                brs: false,
             })
         })));
 
-        let mut mock = mock;
-        mock.open().unwrap();
-
         let isotp_config = IsoTpConfig {
             tx_id: 0x7E0,
             rx_id: 0x7E8,
@@ -319,6 +1628,72 @@ mod obd_tests {
         obd.close().unwrap();
     }
 
+    #[test]
+    fn test_obd_freeze_frame_by_number() -> Result<()> {
+        let mock = MockPhysical::new(Some(Box::new(|frame: &Frame| {
+            // Genuine Mode 02 response: PID, frame number, then the PID data
+            Ok(Frame::standard(
+                frame.id,
+                vec![0x42, frame.data[1], frame.data[2], 0x0F, 0xA0],
+            ))
+        })));
+        let sent_frames = mock.sent_frames();
+
+        let mut isotp = IsoTp::with_physical(IsoTpConfig::default(), mock);
+        isotp.open()?;
+        let mut obd = Obd::with_transport(ObdConfig::default(), isotp);
+        obd.open()?;
+
+        match obd.read_freeze_frame_data(PID_ENGINE_RPM, 0x00)? {
+            PidData::EngineRpm(rpm) => assert_eq!(rpm, 1000.0),
+            _ => panic!("Expected EngineRpm variant"),
+        }
+        assert_eq!(
+            sent_frames.lock().unwrap()[0].data,
+            vec![0x02, PID_ENGINE_RPM, 0x00]
+        );
+
+        obd.close()?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_obd_read_o2_monitor() -> Result<()> {
+        let mock = MockPhysical::new(Some(Box::new(|frame: &Frame| {
+            // Mode 05 response: TID, sensor, test value, min and max limits
+            Ok(Frame::standard(
+                frame.id,
+                vec![0x45, frame.data[1], frame.data[2], 0x5A, 0x32, 0x96],
+            ))
+        })));
+        let sent_frames = mock.sent_frames();
+
+        let mut isotp = IsoTp::with_physical(IsoTpConfig::default(), mock);
+        isotp.open()?;
+        let mut obd = Obd::with_transport(ObdConfig::default(), isotp);
+        obd.open()?;
+
+        // TID 0x01: rich-to-lean threshold voltage, 0.005 V per bit
+        let result = obd.read_o2_monitor(0x01, 0x01)?;
+        assert_eq!(sent_frames.lock().unwrap()[0].data, vec![0x05, 0x01, 0x01]);
+        assert_eq!((result.tid, result.sensor, result.unit), (0x01, 0x01, "V"));
+        assert!((result.value - 0.45).abs() < 1e-6);
+        assert!((result.min - 0.25).abs() < 1e-6);
+        assert!((result.max - 0.75).abs() < 1e-6);
+
+        // TID 0x05: switch time, 0.004 s per bit
+        let result = obd.read_o2_monitor(0x05, 0x02)?;
+        assert_eq!(result.unit, "s");
+        assert!((result.value - 0.36).abs() < 1e-6);
+
+        // Manufacturer-defined TIDs are returned unscaled
+        let result = obd.read_o2_monitor(0x31, 0x01)?;
+        assert_eq!((result.value, result.unit), (90.0, ""));
+
+        obd.close()?;
+        Ok(())
+    }
+
     #[test]
     fn test_obd_error_handling() {
         let mock = MockPhysical::new_error();
@@ -342,3 +1717,299 @@ mod obd_tests {
         assert!(obd.clear_dtc().is_err());
     }
 }
+
+mod download_tests {
+    use super::*;
+    use crate::application::download::{CancellationToken, Downloader};
+    use crate::application::uds::{SID_REQUEST_TRANSFER_EXIT, SID_TRANSFER_DATA};
+    use crate::error::AutomotiveError;
+
+    /// Creates a UDS client on a mock ECU accepting downloads, plus a handle to sent frames
+    pub(super) fn create_mock_ecu() -> (Uds<IsoTp<MockPhysical>>, Arc<Mutex<Vec<Frame>>>) {
+        create_mock_ecu_with(vec![0x74, 0x20, 0x00, 0x06]) // maxNumberOfBlockLength = 6
+    }
+
+    /// Same as `create_mock_ecu`, answering RequestDownload with `download_response`
+    fn create_mock_ecu_with(
+        download_response: Vec<u8>,
+    ) -> (Uds<IsoTp<MockPhysical>>, Arc<Mutex<Vec<Frame>>>) {
+        let mock = MockPhysical::new(Some(Box::new(move |frame: &Frame| {
+            let response_data = match frame.data[0] {
+                0x34 => download_response.clone(),
+                0x36 => vec![0x76, frame.data[1]],
+                0x37 => vec![0x77],
+                // RoutineControl: echo the routine, status 0x00 (passed)
+                0x31 => vec![0x71, frame.data[1], frame.data[2], frame.data[3], 0x00],
+                service_id => vec![0x7F, service_id, 0x11],
+            };
+            Ok(Frame::standard(frame.id, response_data))
+        })));
+        let sent_frames = mock.sent_frames();
+
+        let mut isotp = IsoTp::with_physical(IsoTpConfig::default(), mock);
+        isotp.open().unwrap();
+        let mut uds = Uds::with_transport(UdsConfig::default(), isotp);
+        uds.open().unwrap();
+        (uds, sent_frames)
+    }
+
+    #[test]
+    fn test_download_block_size_too_small() {
+        let (mut uds, _) = create_mock_ecu();
+        // SID and sequence counter leave no room for data
+        assert!(matches!(
+            Downloader::new(&mut uds, 2),
+            Err(AutomotiveError::InvalidParameter)
+        ));
+        assert!(Downloader::new(&mut uds, 3).is_ok());
+    }
+
+    #[test]
+    fn test_download_max_block_length() {
+        let (mut uds, _) = create_mock_ecu_with(vec![0x74, 0x20, 0x01, 0x02]);
+        let mut downloader = Downloader::new(&mut uds, 0x10).unwrap();
+        downloader.request_download(0x1000u32, 16u16).unwrap();
+        assert_eq!(downloader.max_block_size(), 0x0102);
+    }
+
+    #[test]
+    fn test_download_address_and_size_big_endian() {
+        let (mut uds, sent_frames) = create_mock_ecu();
+        let mut downloader = Downloader::new(&mut uds, 0x10).unwrap();
+        downloader
+            .request_download(0x12345678u32, 0x1000u16)
+            .unwrap();
+
+        // ALFID 0x24: 2-byte size, 4-byte address, both most significant byte first
+        assert_eq!(
+            sent_frames.lock().unwrap()[0].data,
+            vec![0x34, 0x00, 0x24, 0x12, 0x34, 0x56, 0x78, 0x10, 0x00]
+        );
+    }
+
+    #[test]
+    fn test_download_malformed_max_block_length() {
+        // Zero-byte length, length longer than the response, and no length at all
+        for response in [vec![0x74, 0x00], vec![0x74, 0x40, 0x01, 0x02], vec![0x74]] {
+            let (mut uds, _) = create_mock_ecu_with(response);
+            let mut downloader = Downloader::new(&mut uds, 0x10).unwrap();
+            assert!(matches!(
+                downloader.request_download(0x1000u32, 16u16),
+                Err(AutomotiveError::InvalidData)
+            ));
+            assert_eq!(downloader.max_block_size(), 0x10);
+        }
+    }
+
+    #[test]
+    fn test_download_progress() {
+        let (mut uds, _) = create_mock_ecu();
+        let mut downloader = Downloader::new(&mut uds, 0x100).unwrap();
+        downloader.request_download(0x1000u32, 10u16).unwrap();
+
+        let mut reports = Vec::new();
+        downloader
+            .transfer_data_with_progress(
+                &[0x55; 10],
+                |_, _| true,
+                |transferred, total| reports.push((transferred, total)),
+            )
+            .unwrap();
+
+        // 4 data bytes per 6-byte TransferData request
+        assert_eq!(reports, vec![(4, Some(10)), (8, Some(10)), (10, Some(10))]);
+        downloader.request_transfer_exit().unwrap();
+    }
+
+    #[test]
+    fn test_download_cancelled() {
+        let (mut uds, sent_frames) = create_mock_ecu();
+        let token = CancellationToken::new();
+        let mut downloader = Downloader::new(&mut uds, 0x100)
+            .unwrap()
+            .with_cancellation(token.clone());
+        downloader.request_download(0x1000u32, 16u16).unwrap();
+        assert_eq!(downloader.max_block_size(), 6);
+
+        // Cancel once the second block was acknowledged
+        let mut blocks = 0;
+        let result = downloader.transfer_data(&[0xAA; 16], |_, _| {
+            blocks += 1;
+            if blocks == 2 {
+                token.cancel();
+            }
+            true
+        });
+        assert!(matches!(result, Err(AutomotiveError::Cancelled)));
+
+        let services: Vec<u8> = sent_frames
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|frame| frame.data[0])
+            .collect();
+        assert_eq!(
+            services,
+            vec![
+                0x34,
+                SID_TRANSFER_DATA,
+                SID_TRANSFER_DATA,
+                SID_REQUEST_TRANSFER_EXIT
+            ]
+        );
+    }
+
+    /// Bitwise CRC-32 (IEEE 802.3)
+    fn crc32(data: &[u8]) -> u32 {
+        let mut crc = 0xFFFFFFFFu32;
+        for &byte in data {
+            crc ^= byte as u32;
+            for _ in 0..8 {
+                crc = if crc & 1 != 0 {
+                    (crc >> 1) ^ 0xEDB88320
+                } else {
+                    crc >> 1
+                };
+            }
+        }
+        !crc
+    }
+
+    #[test]
+    fn test_download_finish_with_checksum() {
+        let (mut uds, sent_frames) = create_mock_ecu();
+        let data: Vec<u8> = (0..10).collect();
+        let mut downloader = Downloader::new(&mut uds, 0x100).unwrap();
+        downloader.request_download(0x1000u32, 10u16).unwrap();
+        downloader.transfer_data(&data, |_, _| true).unwrap();
+        downloader
+            .finish_with_checksum(0xFF01, |transferred| {
+                crc32(transferred).to_be_bytes().to_vec()
+            })
+            .unwrap();
+
+        let sent_frames = sent_frames.lock().unwrap();
+        let count = sent_frames.len();
+        assert_eq!(sent_frames[count - 2].data, vec![SID_REQUEST_TRANSFER_EXIT]);
+
+        // startRoutine checkMemory with the CRC over all three blocks
+        let mut expected = vec![0x31, 0x01, 0xFF, 0x01];
+        expected.extend_from_slice(&crc32(&data).to_be_bytes());
+        assert_eq!(sent_frames[count - 1].data, expected);
+    }
+}
+
+mod flash_tests {
+    use super::*;
+    use crate::application::flash::{FlashSequence, FlashStage};
+    use crate::application::uds::UdsSessionType;
+
+    /// Creates a UDS client on a mock ECU accepting the whole flash sequence
+    ///
+    /// Seed 0x12 0x34 is unlocked by its bitwise complement.
+    fn create_flash_ecu() -> (Uds<IsoTp<MockPhysical>>, Arc<Mutex<Vec<Frame>>>) {
+        let mock = MockPhysical::new(Some(Box::new(|frame: &Frame| {
+            let response_data = match (frame.data[0], frame.data.get(1).copied()) {
+                (0x10, Some(session)) => vec![0x50, session, 0x00, 0x32, 0x01, 0xF4],
+                (0x27, Some(0x01)) => vec![0x67, 0x01, 0x12, 0x34],
+                (0x27, Some(0x02)) if frame.data[2..] == [0xED, 0xCB] => vec![0x67, 0x02],
+                (0x27, _) => vec![0x7F, 0x27, 0x35], // invalidKey
+                (0x34, _) => vec![0x74, 0x20, 0x00, 0x06], // maxNumberOfBlockLength = 6
+                (0x36, Some(sequence)) => vec![0x76, sequence],
+                (0x37, _) => vec![0x77],
+                (0x31, _) => vec![0x71, frame.data[1], frame.data[2], frame.data[3], 0x00],
+                (0x11, Some(reset_type)) => vec![0x51, reset_type],
+                (service_id, _) => vec![0x7F, service_id, 0x11],
+            };
+            Ok(Frame::standard(frame.id, response_data))
+        })));
+        let sent_frames = mock.sent_frames();
+
+        let mut isotp = IsoTp::with_physical(IsoTpConfig::default(), mock);
+        isotp.open().unwrap();
+        let mut uds = Uds::with_transport(UdsConfig::default(), isotp);
+        uds.open().unwrap();
+        (uds, sent_frames)
+    }
+
+    fn services(sent_frames: &Arc<Mutex<Vec<Frame>>>) -> Vec<u8> {
+        sent_frames
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|frame| frame.data[0])
+            .collect()
+    }
+
+    #[test]
+    fn test_flash_sequence() {
+        let (mut uds, sent_frames) = create_flash_ecu();
+        let blob: Vec<u8> = (0..10).collect();
+        let mut reports = Vec::new();
+
+        FlashSequence::new(&blob, 0x0800_0000, 1, |seed| {
+            seed.iter().map(|byte| !byte).collect()
+        })
+        .with_check_routine(0xFF01, |data| {
+            vec![data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte))]
+        })
+        .with_progress(|stage, transferred, total| reports.push((stage, transferred, total)))
+        .run(&mut uds)
+        .unwrap();
+
+        assert_eq!(
+            services(&sent_frames),
+            vec![0x10, 0x27, 0x27, 0x34, 0x36, 0x36, 0x36, 0x37, 0x31, 0x11]
+        );
+        let sent = sent_frames.lock().unwrap();
+        assert_eq!(sent[0].data, vec![0x10, 0x03]);
+        assert_eq!(
+            sent[3].data,
+            vec![0x34, 0x00, 0x44, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x0A]
+        );
+        // Checksum of 0..10 passed to the check memory routine
+        assert_eq!(sent[8].data, vec![0x31, 0x01, 0xFF, 0x01, 45]);
+
+        assert_eq!(
+            reports,
+            vec![
+                (FlashStage::Session, 0, 10),
+                (FlashStage::SecurityAccess, 0, 10),
+                (FlashStage::RequestDownload, 0, 10),
+                (FlashStage::TransferData, 0, 10),
+                (FlashStage::TransferData, 4, 10),
+                (FlashStage::TransferData, 8, 10),
+                (FlashStage::TransferData, 10, 10),
+                (FlashStage::TransferExit, 10, 10),
+                (FlashStage::CheckMemory, 10, 10),
+                (FlashStage::Reset, 10, 10),
+            ]
+        );
+        assert_eq!(uds.status.session_type, UdsSessionType::Default);
+    }
+
+    #[test]
+    fn test_flash_sequence_resets_on_failure() {
+        let (mut uds, sent_frames) = create_flash_ecu();
+
+        // The ECU rejects the key
+        let result = FlashSequence::new(&[0xAA; 8], 0x1000, 1, |seed| seed.to_vec()).run(&mut uds);
+        match result {
+            Err(AutomotiveError::FlashFailed { stage, error }) => {
+                assert_eq!(stage, FlashStage::SecurityAccess);
+                assert!(matches!(
+                    *error,
+                    AutomotiveError::UdsNegativeResponse {
+                        service_id: 0x27,
+                        nrc: 0x35
+                    }
+                ));
+            }
+            other => panic!("Expected FlashFailed, got {:?}", other),
+        }
+
+        // Nothing was downloaded and the ECU was reset
+        assert_eq!(services(&sent_frames), vec![0x10, 0x27, 0x27, 0x11]);
+        assert_eq!(uds.status.session_type, UdsSessionType::Default);
+    }
+}
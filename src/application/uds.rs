@@ -1,32 +1,129 @@
-use super::ApplicationLayer;
+use super::download::TransferAddressOrSize;
+use super::obdii::format_dtc;
+use super::{ApplicationLayer, Diagnostic};
 use crate::error::{AutomotiveError, Result};
-use crate::transport::TransportLayer;
-use crate::types::{Config, Frame};
+use crate::physical::PhysicalLayer;
+use crate::transport::isotp::IsoTp;
+use crate::transport::{BoxedTransport, TransportLayer};
+use crate::types::{Config, Frame, LayerState};
+
+// UDS Service
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UdsService {
+    DiagnosticSessionControl = 0x10,
+    EcuReset = 0x11,
+    ClearDiagnosticInformation = 0x14,
+    ReadDtcInformation = 0x19,
+    ReadDataByIdentifier = 0x22,
+    ReadMemoryByAddress = 0x23,
+    ReadScalingDataByIdentifier = 0x24,
+    SecurityAccess = 0x27,
+    CommunicationControl = 0x28,
+    Authentication = 0x29,
+    ReadDataByPeriodicIdentifier = 0x2A,
+    DynamicallyDefineDataIdentifier = 0x2C,
+    WriteDataByIdentifier = 0x2E,
+    InputOutputControlByIdentifier = 0x2F,
+    RoutineControl = 0x31,
+    RequestDownload = 0x34,
+    RequestUpload = 0x35,
+    TransferData = 0x36,
+    RequestTransferExit = 0x37,
+    WriteMemoryByAddress = 0x3D,
+    TesterPresent = 0x3E,
+}
+
+impl TryFrom<u8> for UdsService {
+    type Error = AutomotiveError;
+
+    fn try_from(sid: u8) -> Result<Self> {
+        match sid {
+            0x10 => Ok(UdsService::DiagnosticSessionControl),
+            0x11 => Ok(UdsService::EcuReset),
+            0x14 => Ok(UdsService::ClearDiagnosticInformation),
+            0x19 => Ok(UdsService::ReadDtcInformation),
+            0x22 => Ok(UdsService::ReadDataByIdentifier),
+            0x23 => Ok(UdsService::ReadMemoryByAddress),
+            0x24 => Ok(UdsService::ReadScalingDataByIdentifier),
+            0x27 => Ok(UdsService::SecurityAccess),
+            0x28 => Ok(UdsService::CommunicationControl),
+            0x29 => Ok(UdsService::Authentication),
+            0x2A => Ok(UdsService::ReadDataByPeriodicIdentifier),
+            0x2C => Ok(UdsService::DynamicallyDefineDataIdentifier),
+            0x2E => Ok(UdsService::WriteDataByIdentifier),
+            0x2F => Ok(UdsService::InputOutputControlByIdentifier),
+            0x31 => Ok(UdsService::RoutineControl),
+            0x34 => Ok(UdsService::RequestDownload),
+            0x35 => Ok(UdsService::RequestUpload),
+            0x36 => Ok(UdsService::TransferData),
+            0x37 => Ok(UdsService::RequestTransferExit),
+            0x3D => Ok(UdsService::WriteMemoryByAddress),
+            0x3E => Ok(UdsService::TesterPresent),
+            _ => Err(AutomotiveError::InvalidParameter),
+        }
+    }
+}
+
+impl From<UdsService> for u8 {
+    fn from(service: UdsService) -> Self {
+        service as u8
+    }
+}
+
+impl std::fmt::Display for UdsService {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            UdsService::DiagnosticSessionControl => "DiagnosticSessionControl",
+            UdsService::EcuReset => "ECUReset",
+            UdsService::ClearDiagnosticInformation => "ClearDiagnosticInformation",
+            UdsService::ReadDtcInformation => "ReadDTCInformation",
+            UdsService::ReadDataByIdentifier => "ReadDataByIdentifier",
+            UdsService::ReadMemoryByAddress => "ReadMemoryByAddress",
+            UdsService::ReadScalingDataByIdentifier => "ReadScalingDataByIdentifier",
+            UdsService::SecurityAccess => "SecurityAccess",
+            UdsService::CommunicationControl => "CommunicationControl",
+            UdsService::Authentication => "Authentication",
+            UdsService::ReadDataByPeriodicIdentifier => "ReadDataByPeriodicIdentifier",
+            UdsService::DynamicallyDefineDataIdentifier => "DynamicallyDefineDataIdentifier",
+            UdsService::WriteDataByIdentifier => "WriteDataByIdentifier",
+            UdsService::InputOutputControlByIdentifier => "InputOutputControlByIdentifier",
+            UdsService::RoutineControl => "RoutineControl",
+            UdsService::RequestDownload => "RequestDownload",
+            UdsService::RequestUpload => "RequestUpload",
+            UdsService::TransferData => "TransferData",
+            UdsService::RequestTransferExit => "RequestTransferExit",
+            UdsService::WriteMemoryByAddress => "WriteMemoryByAddress",
+            UdsService::TesterPresent => "TesterPresent",
+        };
+        f.write_str(name)
+    }
+}
 
 // UDS Service IDs
-pub const SID_DIAGNOSTIC_SESSION_CONTROL: u8 = 0x10;
-pub const SID_ECU_RESET: u8 = 0x11;
-pub const SID_SECURITY_ACCESS: u8 = 0x27;
-pub const SID_TESTER_PRESENT: u8 = 0x3E;
-pub const SID_READ_DATA_BY_ID: u8 = 0x22;
-pub const SID_WRITE_DATA_BY_ID: u8 = 0x2E;
-pub const SID_CLEAR_DIAGNOSTIC_INFO: u8 = 0x14;
-pub const SID_READ_DTC: u8 = 0x19;
+pub const SID_DIAGNOSTIC_SESSION_CONTROL: u8 = UdsService::DiagnosticSessionControl as u8;
+pub const SID_ECU_RESET: u8 = UdsService::EcuReset as u8;
+pub const SID_SECURITY_ACCESS: u8 = UdsService::SecurityAccess as u8;
+pub const SID_TESTER_PRESENT: u8 = UdsService::TesterPresent as u8;
+pub const SID_READ_DATA_BY_ID: u8 = UdsService::ReadDataByIdentifier as u8;
+pub const SID_WRITE_DATA_BY_ID: u8 = UdsService::WriteDataByIdentifier as u8;
+pub const SID_CLEAR_DIAGNOSTIC_INFO: u8 = UdsService::ClearDiagnosticInformation as u8;
+pub const SID_READ_DTC: u8 = UdsService::ReadDtcInformation as u8;
 
 // Additional UDS Service IDs
-pub const SID_COMMUNICATION_CONTROL: u8 = 0x28;
-pub const SID_AUTHENTICATION: u8 = 0x29;
-pub const SID_READ_DATA_BY_PERIODIC_ID: u8 = 0x2A;
-pub const SID_DYNAMICALLY_DEFINE_DATA_ID: u8 = 0x2C;
-pub const SID_READ_MEMORY_BY_ADDRESS: u8 = 0x23;
-pub const SID_WRITE_MEMORY_BY_ADDRESS: u8 = 0x3D;
-pub const SID_READ_SCALING_DATA_BY_ID: u8 = 0x24;
-pub const SID_INPUT_OUTPUT_CONTROL_BY_ID: u8 = 0x2F;
-pub const SID_ROUTINE_CONTROL: u8 = 0x31;
-pub const SID_REQUEST_DOWNLOAD: u8 = 0x34;
-pub const SID_REQUEST_UPLOAD: u8 = 0x35;
-pub const SID_TRANSFER_DATA: u8 = 0x36;
-pub const SID_REQUEST_TRANSFER_EXIT: u8 = 0x37;
+pub const SID_COMMUNICATION_CONTROL: u8 = UdsService::CommunicationControl as u8;
+pub const SID_AUTHENTICATION: u8 = UdsService::Authentication as u8;
+pub const SID_READ_DATA_BY_PERIODIC_ID: u8 = UdsService::ReadDataByPeriodicIdentifier as u8;
+pub const SID_DYNAMICALLY_DEFINE_DATA_ID: u8 = UdsService::DynamicallyDefineDataIdentifier as u8;
+pub const SID_READ_MEMORY_BY_ADDRESS: u8 = UdsService::ReadMemoryByAddress as u8;
+pub const SID_WRITE_MEMORY_BY_ADDRESS: u8 = UdsService::WriteMemoryByAddress as u8;
+pub const SID_READ_SCALING_DATA_BY_ID: u8 = UdsService::ReadScalingDataByIdentifier as u8;
+pub const SID_INPUT_OUTPUT_CONTROL_BY_ID: u8 = UdsService::InputOutputControlByIdentifier as u8;
+pub const SID_ROUTINE_CONTROL: u8 = UdsService::RoutineControl as u8;
+pub const SID_REQUEST_DOWNLOAD: u8 = UdsService::RequestDownload as u8;
+pub const SID_REQUEST_UPLOAD: u8 = UdsService::RequestUpload as u8;
+pub const SID_TRANSFER_DATA: u8 = UdsService::TransferData as u8;
+pub const SID_REQUEST_TRANSFER_EXIT: u8 = UdsService::RequestTransferExit as u8;
 
 // UDS Response Type
 #[derive(Debug, Clone, PartialEq)]
@@ -44,6 +141,12 @@ pub enum UdsSessionType {
     SafetySystem = 0x04,
 }
 
+// UDS Session Type values, for raw session control
+pub const SESSION_DEFAULT: u8 = UdsSessionType::Default as u8;
+pub const SESSION_PROGRAMMING: u8 = UdsSessionType::Programming as u8;
+pub const SESSION_EXTENDED: u8 = UdsSessionType::Extended as u8;
+pub const SESSION_SAFETY_SYSTEM: u8 = UdsSessionType::SafetySystem as u8;
+
 // UDS Reset Type
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum UdsResetType {
@@ -54,6 +157,44 @@ pub enum UdsResetType {
     DisableRapidPowerShutdown = 0x05,
 }
 
+// UDS Reset Type values
+pub const RESET_HARD: u8 = UdsResetType::HardReset as u8;
+pub const RESET_KEY_OFF_ON: u8 = UdsResetType::KeyOffOnReset as u8;
+pub const RESET_SOFT: u8 = UdsResetType::SoftReset as u8;
+
+/// Delay between TesterPresent polls while waiting for an ECU to restart
+const RESET_POLL_INTERVAL_MS: u64 = 50;
+
+// UDS Periodic Transmission Mode
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PeriodicRate {
+    Slow = 0x01,
+    Medium = 0x02,
+    Fast = 0x03,
+    Stop = 0x04,
+}
+
+// ClearDiagnosticInformation group covering all DTCs
+pub const DTC_GROUP_ALL: u32 = 0xFFFFFF;
+
+// ReadDTCInformation sub-functions
+pub const DTC_REPORT_BY_STATUS_MASK: u8 = 0x02;
+
+// RoutineControl sub-functions
+pub const ROUTINE_START: u8 = 0x01;
+
+// Authentication (0x29) sub-functions
+pub const AUTH_VERIFY_CERTIFICATE_UNIDIRECTIONAL: u8 = 0x01;
+/// proofOfOwnership is 0x03 in ISO 14229-1:2020; 0x02 is verifyCertificateBidirectional
+pub const AUTH_PROOF_OF_OWNERSHIP: u8 = 0x03;
+
+// Authentication return parameter values
+pub const AUTH_RETURN_CERTIFICATE_VERIFIED: u8 = 0x11; // Ownership verification necessary
+pub const AUTH_RETURN_OWNERSHIP_VERIFIED: u8 = 0x12; // Authentication complete
+
+// Sub-function bit asking the ECU not to send a positive response
+pub const SUPPRESS_POS_RSP_MSG_INDICATION_BIT: u8 = 0x80;
+
 // UDS Negative Response Codes
 pub const NRC_GENERAL_REJECT: u8 = 0x10;
 pub const NRC_SERVICE_NOT_SUPPORTED: u8 = 0x11;
@@ -67,6 +208,33 @@ pub const NRC_INVALID_KEY: u8 = 0x35;
 pub const NRC_EXCEEDED_NUMBER_OF_ATTEMPTS: u8 = 0x36;
 pub const NRC_RESPONSE_PENDING: u8 = 0x78;
 
+/// Negative response builder for ECU simulators and mock servers
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NegativeResponse {
+    pub service_id: u8,
+    pub nrc: u8,
+}
+
+impl NegativeResponse {
+    /// Creates a negative response to `service_id` with the given NRC
+    pub fn new(service_id: u8, nrc: u8) -> Self {
+        Self { service_id, nrc }
+    }
+
+    /// Encodes the response as `[0x7F, service_id, nrc]`
+    pub fn to_bytes(&self) -> Vec<u8> {
+        vec![0x7F, self.service_id, self.nrc]
+    }
+}
+
+/// Builds a positive response `[service_id + 0x40, data...]`
+pub fn build_positive_response(service_id: u8, data: &[u8]) -> Vec<u8> {
+    let mut response = Vec::with_capacity(data.len() + 1);
+    response.push(service_id.wrapping_add(0x40));
+    response.extend_from_slice(data);
+    response
+}
+
 /// UDS Request Message
 #[derive(Debug, Clone)]
 pub struct UdsRequest {
@@ -81,6 +249,41 @@ pub struct UdsResponse {
     pub data: Vec<u8>,
 }
 
+impl UdsResponse {
+    /// Parses a response `[service_id, data...]`
+    ///
+    /// A leading 0x7F is returned as `UdsNegativeResponse` carrying the NRC.
+    pub fn parse(bytes: &[u8]) -> Result<Self> {
+        match bytes {
+            [] => Err(AutomotiveError::InvalidParameter),
+            [0x7F, service_id, nrc, ..] => Err(AutomotiveError::UdsNegativeResponse {
+                service_id: *service_id,
+                nrc: *nrc,
+            }),
+            [0x7F, ..] => Err(AutomotiveError::InvalidData),
+            [service_id, data @ ..] => Ok(Self {
+                service_id: *service_id,
+                data: data.to_vec(),
+            }),
+        }
+    }
+}
+
+impl TryFrom<&[u8]> for UdsResponse {
+    type Error = AutomotiveError;
+
+    fn try_from(bytes: &[u8]) -> Result<Self> {
+        Self::parse(bytes)
+    }
+}
+
+/// Scaling record returned by ReadScalingDataByIdentifier (0x24)
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScalingRecord {
+    pub data_type: u8, // High nibble of the scaling byte
+    pub bytes: Vec<u8>,
+}
+
 /// UDS Session Status
 #[derive(Debug, Clone)]
 pub struct SessionStatus {
@@ -101,18 +304,65 @@ impl Default for SessionStatus {
     }
 }
 
+/// Delay growth between retry attempts
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Backoff {
+    Fixed,       // Always wait base_delay_ms
+    Exponential, // Double the delay after each failed attempt
+}
+
+/// Retry policy for transient transport errors
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RetryPolicy {
+    pub max_attempts: u32, // Total attempts, including the first one
+    pub base_delay_ms: u32,
+    pub backoff: Backoff,
+}
+
+impl RetryPolicy {
+    /// Returns the delay before the attempt following `failed_attempts` failures
+    pub fn delay_ms(&self, failed_attempts: u32) -> u32 {
+        match self.backoff {
+            Backoff::Fixed => self.base_delay_ms,
+            Backoff::Exponential => self
+                .base_delay_ms
+                .saturating_mul(1u32.checked_shl(failed_attempts - 1).unwrap_or(u32::MAX)),
+        }
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 1, // No retries
+            base_delay_ms: 100,
+            backoff: Backoff::Fixed,
+        }
+    }
+}
+
 /// UDS Configuration
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct UdsConfig {
     pub timeout_ms: u32,
     pub p2_timeout_ms: u32,
     pub p2_star_timeout_ms: u32,
     pub s3_client_timeout_ms: u32,
     pub tester_present_interval_ms: u32,
+    pub retry: RetryPolicy,
 }
 
 impl Config for UdsConfig {
     fn validate(&self) -> Result<()> {
+        if self.retry.max_attempts == 0 {
+            return Err(AutomotiveError::InvalidParameter);
+        }
+        if self.timeout_ms == 0 || self.p2_timeout_ms == 0 || self.p2_star_timeout_ms == 0 {
+            return Err(AutomotiveError::InvalidParameter);
+        }
         Ok(())
     }
 }
@@ -125,81 +375,374 @@ impl Default for UdsConfig {
             p2_star_timeout_ms: 5000,
             s3_client_timeout_ms: 5000,
             tester_present_interval_ms: 2000,
+            retry: RetryPolicy::default(),
         }
     }
 }
 
+/// Observer invoked with every request and its result
+pub type UdsTap = Box<dyn Fn(&UdsRequest, &Result<UdsResponse>) + Send + Sync>;
+
 /// UDS Implementation
 pub struct Uds<T: TransportLayer> {
     config: UdsConfig,
     transport: T,
     pub status: SessionStatus, // Make public for testing
-    is_open: bool,
+    state: LayerState,
     handling_session_timing: bool, // Flag to prevent recursive session timing handling
+    tap: Option<UdsTap>,
+    timeout_ms: u32, // Transport timeout outside of a request/response exchange
+    periodic_dids: Vec<u8>, // Periodic DIDs the ECU was asked to transmit
+}
+
+/// addressAndLengthFormatIdentifier followed by the memory address and size
+pub(crate) fn memory_parameters<A: TransferAddressOrSize, S: TransferAddressOrSize>(
+    address: A,
+    size: S,
+) -> Vec<u8> {
+    let mut params = vec![(S::LENGTH << 4) | A::LENGTH];
+    address.append_to_vec(&mut params);
+    size.append_to_vec(&mut params);
+    params
+}
+
+/// Appends `data` preceded by its 2-byte big-endian length
+fn append_length_prefixed(params: &mut Vec<u8>, data: &[u8]) -> Result<()> {
+    let length = u16::try_from(data.len()).map_err(|_| AutomotiveError::InvalidParameter)?;
+    params.extend_from_slice(&length.to_be_bytes());
+    params.extend_from_slice(data);
+    Ok(())
+}
+
+/// Splits a 2-byte big-endian length-prefixed field from the front of `record`
+fn split_length_prefixed(record: &[u8]) -> Result<(&[u8], &[u8])> {
+    match record {
+        [high, low, rest @ ..] => {
+            let length = u16::from_be_bytes([*high, *low]) as usize;
+            if rest.len() < length {
+                return Err(AutomotiveError::InvalidData);
+            }
+            Ok(rest.split_at(length))
+        }
+        _ => Err(AutomotiveError::InvalidData),
+    }
 }
 
 impl<T: TransportLayer> Uds<T> {
     /// Creates a new UDS instance with the given transport layer
     pub fn with_transport(config: UdsConfig, transport: T) -> Self {
         Self {
+            timeout_ms: config.timeout_ms,
             config,
             transport,
             status: SessionStatus::default(),
-            is_open: false,
+            state: LayerState::Closed,
             handling_session_timing: false,
+            tap: None,
+            periodic_dids: Vec::new(),
         }
     }
 
+    /// Installs an observer called after every request/response exchange
+    ///
+    /// Requests with the positive response suppressed are observed too; when
+    /// the ECU stays silent their result is `Err(Timeout)`.
+    pub fn set_tap(
+        &mut self,
+        tap: impl Fn(&UdsRequest, &Result<UdsResponse>) + Send + Sync + 'static,
+    ) {
+        self.tap = Some(Box::new(tap));
+    }
+
+    /// Current lifecycle state
+    pub fn state(&self) -> LayerState {
+        self.state
+    }
+
+    /// Sends an arbitrary service request, for services without a dedicated helper
+    ///
+    /// Returns the positive response, or `UdsNegativeResponse` carrying the NRC.
+    pub fn raw_request(&mut self, service_id: u8, params: &[u8]) -> Result<UdsResponse> {
+        let request = UdsRequest {
+            service_id,
+            parameters: params.to_vec(),
+        };
+
+        self.positive_response(&request)
+    }
+
+    /// Sends `request` and checks the reply is its positive response
+    ///
+    /// A positive response is success whatever its length; a bare SID echo
+    /// comes back with empty `data`. A missing reply is an error from the
+    /// transport, e.g. `Timeout`.
+    fn positive_response(&mut self, request: &UdsRequest) -> Result<UdsResponse> {
+        let response = self.send_request(request)?;
+
+        if response.service_id != request.service_id.wrapping_add(0x40) {
+            return Err(AutomotiveError::InvalidData);
+        }
+        Ok(response)
+    }
+
     /// Changes the diagnostic session
-    pub fn change_session(&mut self, session_type: UdsSessionType) -> Result<()> {
+    ///
+    /// With `suppress` the ECU sends no positive response and the timings
+    /// it would have advertised are left unchanged.
+    pub fn change_session(&mut self, session_type: UdsSessionType, suppress: bool) -> Result<()> {
+        self.change_session_raw(session_type as u8, suppress)
+    }
+
+    /// Changes to a session by its raw id, e.g. an OEM session (0x40-0x7F)
+    ///
+    /// Sessions outside the standard ones are tracked as non-default, like
+    /// `UdsSessionType::Extended`.
+    pub fn change_session_raw(&mut self, session: u8, suppress: bool) -> Result<()> {
         let request = UdsRequest {
             service_id: SID_DIAGNOSTIC_SESSION_CONTROL,
-            parameters: vec![session_type as u8],
+            parameters: vec![session],
         };
 
-        let response = self.send_request(&request)?;
-
-        if response.data.is_empty() {
-            Err(AutomotiveError::InvalidParameter)
+        if suppress {
+            self.send_suppressed(request)?;
         } else {
-            self.status.session_type = session_type;
-            self.status.last_activity = std::time::Instant::now();
-            Ok(())
+            let response = self.positive_response(&request)?;
+            // Session parameter record: P2server_max (1ms), P2*server_max (10ms)
+            if let Some(record) = response.data.get(1..5) {
+                self.config.p2_timeout_ms = u16::from_be_bytes([record[0], record[1]]) as u32;
+                self.config.p2_star_timeout_ms =
+                    u16::from_be_bytes([record[2], record[3]]) as u32 * 10;
+            }
         }
+
+        self.status.session_type = match session {
+            SESSION_DEFAULT => UdsSessionType::Default,
+            SESSION_PROGRAMMING => UdsSessionType::Programming,
+            SESSION_SAFETY_SYSTEM => UdsSessionType::SafetySystem,
+            _ => UdsSessionType::Extended,
+        };
+        self.status.last_activity = std::time::Instant::now();
+        Ok(())
+    }
+
+    /// Returns the current configuration, including ECU-advertised timings
+    pub fn config(&self) -> &UdsConfig {
+        &self.config
     }
 
-    /// Performs ECU reset
-    pub fn ecu_reset(&mut self, reset_type: UdsResetType) -> Result<()> {
+    /// Performs ECU reset, without waiting for a reply if `suppress` is set
+    ///
+    /// Returns the powerDownTime in seconds when the ECU reports one, as it
+    /// does for `EnableRapidPowerShutdown`; 0xFF means the time is not available.
+    pub fn ecu_reset(&mut self, reset_type: UdsResetType, suppress: bool) -> Result<Option<u8>> {
         let request = UdsRequest {
             service_id: SID_ECU_RESET,
             parameters: vec![reset_type as u8],
         };
+        if suppress {
+            self.send_suppressed(request)?;
+            return Ok(None);
+        }
 
-        let response = self.send_request(&request)?;
+        let response = self.positive_response(&request)?;
+        Ok(response.data.get(1).copied())
+    }
 
-        if response.data.is_empty() {
-            Ok(())
-        } else {
-            Err(AutomotiveError::UdsError("Failed to reset ECU".into()))
+    /// Resets the ECU, then polls TesterPresent until it answers again
+    ///
+    /// Fails with `Timeout` if the ECU is still silent after `timeout_ms`. Once
+    /// it is back the session status returns to the default session.
+    pub fn ecu_reset_and_wait(
+        &mut self,
+        reset_type: UdsResetType,
+        timeout_ms: u32,
+    ) -> Result<Option<u8>> {
+        let power_down_time = self.ecu_reset(reset_type, false)?;
+
+        let deadline =
+            std::time::Instant::now() + std::time::Duration::from_millis(timeout_ms as u64);
+        loop {
+            std::thread::sleep(std::time::Duration::from_millis(RESET_POLL_INTERVAL_MS));
+            if self.raw_request(SID_TESTER_PRESENT, &[0x00]).is_ok() {
+                break;
+            }
+            if std::time::Instant::now() >= deadline {
+                return Err(AutomotiveError::Timeout);
+            }
         }
+
+        self.status = SessionStatus::default();
+        Ok(power_down_time)
     }
 
     /// Reads data by identifier
+    ///
+    /// A positive response without a data record returns an empty vec.
     pub fn read_data_by_id(&mut self, did: u16) -> Result<Vec<u8>> {
         let request = UdsRequest {
             service_id: SID_READ_DATA_BY_ID,
             parameters: vec![(did >> 8) as u8, did as u8],
         };
 
-        let response = self.send_request(&request)?;
+        Ok(self.positive_response(&request)?.data)
+    }
+
+    /// Reads scaling data by identifier
+    pub fn read_scaling_data_by_id(&mut self, did: u16) -> Result<Vec<ScalingRecord>> {
+        let request = UdsRequest {
+            service_id: SID_READ_SCALING_DATA_BY_ID,
+            parameters: vec![(did >> 8) as u8, did as u8],
+        };
+
+        let response = self.positive_response(&request)?;
+
+        if response.data.len() < 2 || response.data[0..2] != [(did >> 8) as u8, did as u8] {
+            return Err(AutomotiveError::InvalidData);
+        }
+
+        // Each scaling byte: high nibble is the data type, low nibble the number of bytes
+        let mut records = Vec::new();
+        let mut index = 2;
+        while index < response.data.len() {
+            let scaling_byte = response.data[index];
+            let length = (scaling_byte & 0x0F) as usize;
+            index += 1;
+            if index + length > response.data.len() {
+                return Err(AutomotiveError::InvalidData);
+            }
+            records.push(ScalingRecord {
+                data_type: scaling_byte >> 4,
+                bytes: response.data[index..index + length].to_vec(),
+            });
+            index += length;
+        }
+
+        Ok(records)
+    }
+
+    /// Dynamically defines a data identifier from source DIDs (sub-function 0x01)
+    ///
+    /// Each source is a tuple of (source DID, position in record, memory size).
+    pub fn define_by_did(&mut self, dynamic_did: u16, sources: &[(u16, u8, u8)]) -> Result<()> {
+        if sources.is_empty() {
+            return Err(AutomotiveError::InvalidParameter);
+        }
+
+        let mut request_data = vec![0x01, (dynamic_did >> 8) as u8, dynamic_did as u8];
+        for &(source_did, position, length) in sources {
+            request_data.extend_from_slice(&[
+                (source_did >> 8) as u8,
+                source_did as u8,
+                position,
+                length,
+            ]);
+        }
+
+        self.dynamically_define_data_id(0x01, dynamic_did, request_data)
+    }
+
+    /// Clears a dynamically defined data identifier (sub-function 0x03)
+    pub fn clear_dynamic_did(&mut self, dynamic_did: u16) -> Result<()> {
+        let request_data = vec![0x03, (dynamic_did >> 8) as u8, dynamic_did as u8];
+        self.dynamically_define_data_id(0x03, dynamic_did, request_data)
+    }
+
+    fn dynamically_define_data_id(
+        &mut self,
+        sub_function: u8,
+        dynamic_did: u16,
+        request_data: Vec<u8>,
+    ) -> Result<()> {
+        let request = UdsRequest {
+            service_id: SID_DYNAMICALLY_DEFINE_DATA_ID,
+            parameters: request_data,
+        };
+
+        let response = self.positive_response(&request)?;
+
+        if response.data.len() < 3
+            || response.data[0] != sub_function
+            || response.data[1..3] != [(dynamic_did >> 8) as u8, dynamic_did as u8]
+        {
+            return Err(AutomotiveError::InvalidData);
+        }
 
-        if response.data.is_empty() {
-            Err(AutomotiveError::UdsError("Failed to read data".into()))
+        Ok(())
+    }
+
+    /// Starts periodic transmission of the given periodic data identifiers
+    pub fn start_periodic(
+        &mut self,
+        transmission_mode: PeriodicRate,
+        periodic_dids: &[u8],
+    ) -> Result<()> {
+        if periodic_dids.is_empty() {
+            return Err(AutomotiveError::InvalidParameter);
+        }
+        self.read_data_by_periodic_id(transmission_mode, periodic_dids)?;
+        for &did in periodic_dids {
+            if !self.periodic_dids.contains(&did) {
+                self.periodic_dids.push(did);
+            }
+        }
+        Ok(())
+    }
+
+    /// Stops periodic transmission (all periodic DIDs if `periodic_dids` is empty)
+    pub fn stop_periodic(&mut self, periodic_dids: &[u8]) -> Result<()> {
+        self.read_data_by_periodic_id(PeriodicRate::Stop, periodic_dids)?;
+        if periodic_dids.is_empty() {
+            self.periodic_dids.clear();
         } else {
-            Ok(response.data)
+            self.periodic_dids
+                .retain(|did| !periodic_dids.contains(did));
+        }
+        Ok(())
+    }
+
+    /// Reads a pending periodic data response without blocking
+    ///
+    /// Returns the periodic DID and its data, or `None` if nothing is pending.
+    /// A frame not starting with a started periodic DID is not periodic data
+    /// and is discarded, also returning `None`.
+    pub fn poll_periodic(&mut self) -> Result<Option<(u8, Vec<u8>)>> {
+        if self.state == LayerState::Closed {
+            return Err(AutomotiveError::NotInitialized);
+        }
+
+        self.transport.set_timeout(1)?;
+        let result = self.transport.read_frame();
+        self.transport.set_timeout(self.timeout_ms)?;
+
+        match result {
+            Ok(frame) => match frame.data.split_first() {
+                Some((did, data)) if self.periodic_dids.contains(did) => {
+                    Ok(Some((*did, data.to_vec())))
+                }
+                _ => Ok(None),
+            },
+            Err(AutomotiveError::Timeout) => Ok(None),
+            Err(e) => Err(e),
         }
     }
 
+    fn read_data_by_periodic_id(
+        &mut self,
+        transmission_mode: PeriodicRate,
+        periodic_dids: &[u8],
+    ) -> Result<()> {
+        let mut request_data = vec![transmission_mode as u8];
+        request_data.extend_from_slice(periodic_dids);
+
+        let request = UdsRequest {
+            service_id: SID_READ_DATA_BY_PERIODIC_ID,
+            parameters: request_data,
+        };
+
+        self.positive_response(&request)?;
+        Ok(())
+    }
+
     /// Writes data by identifier
     pub fn write_data_by_id(&mut self, did: u16, data: &[u8]) -> Result<()> {
         let mut request_data = vec![(did >> 8) as u8, did as u8];
@@ -210,13 +753,8 @@ impl<T: TransportLayer> Uds<T> {
             parameters: request_data,
         };
 
-        let response = self.send_request(&request)?;
-
-        if response.data.is_empty() {
-            Ok(())
-        } else {
-            Err(AutomotiveError::UdsError("Failed to write data".into()))
-        }
+        self.positive_response(&request)?;
+        Ok(())
     }
 
     /// Sends tester present message
@@ -235,83 +773,172 @@ impl<T: TransportLayer> Uds<T> {
 
         let request = UdsRequest {
             service_id: SID_TESTER_PRESENT,
-            parameters: vec![0x00], // Add suppress positive response flag
+            parameters: vec![0x00],
         };
 
-        // Special handling for tester present to avoid response requirement
-        let mut data = vec![request.service_id];
-        data.extend_from_slice(&request.parameters);
-
-        self.transport.write_frame(&Frame {
-            id: 0,
-            data,
-            timestamp: 0,
-            is_extended: false,
-            is_fd: false,
-        })?;
+        // The positive response is suppressed; only an NRC comes back
+        self.send_suppressed(request)?;
 
-        // Set the flag regardless of response as we're using suppress positive response
         self.status.tester_present_sent = true;
         self.status.last_activity = std::time::Instant::now();
 
         Ok(())
     }
 
-    /// Performs security access
+    /// Authenticates with a client certificate (PKI certificate exchange, unidirectional)
+    ///
+    /// `proof_fn` signs the server challenge with the certificate's private key.
+    pub fn authenticate(
+        &mut self,
+        certificate: &[u8],
+        proof_fn: impl Fn(&[u8]) -> Vec<u8>,
+    ) -> Result<()> {
+        let challenge = self.verify_certificate_unidirectional(certificate)?;
+        self.proof_of_ownership(&proof_fn(&challenge), &[])
+    }
+
+    /// Sends the client certificate, returning the server challenge
+    pub fn verify_certificate_unidirectional(&mut self, certificate: &[u8]) -> Result<Vec<u8>> {
+        let mut params = vec![0x00]; // Communication configuration: no secure session
+        append_length_prefixed(&mut params, certificate)?;
+        append_length_prefixed(&mut params, &[])?; // No client challenge
+
+        let record = self.authentication_request(
+            AUTH_VERIFY_CERTIFICATE_UNIDIRECTIONAL,
+            &params,
+            AUTH_RETURN_CERTIFICATE_VERIFIED,
+        )?;
+        let (challenge, _) = split_length_prefixed(&record)?;
+        Ok(challenge.to_vec())
+    }
+
+    /// Proves ownership of the certificate with the signed challenge
+    pub fn proof_of_ownership(&mut self, proof: &[u8], ephemeral_key: &[u8]) -> Result<()> {
+        let mut params = Vec::new();
+        append_length_prefixed(&mut params, proof)?;
+        append_length_prefixed(&mut params, ephemeral_key)?;
+
+        self.authentication_request(
+            AUTH_PROOF_OF_OWNERSHIP,
+            &params,
+            AUTH_RETURN_OWNERSHIP_VERIFIED,
+        )?;
+        self.status.last_activity = std::time::Instant::now();
+        Ok(())
+    }
+
+    /// Sends an Authentication request, returning the record after the return parameter
+    fn authentication_request(
+        &mut self,
+        sub_function: u8,
+        params: &[u8],
+        expected_return: u8,
+    ) -> Result<Vec<u8>> {
+        let mut request = vec![sub_function];
+        request.extend_from_slice(params);
+        let response = self.raw_request(SID_AUTHENTICATION, &request)?;
+
+        match response.data[..] {
+            [echo, return_parameter, ref record @ ..] if echo == sub_function => {
+                if return_parameter == expected_return {
+                    Ok(record.to_vec())
+                } else {
+                    Err(AutomotiveError::UdsError(format!(
+                        "Authentication failed: return parameter 0x{:02X}",
+                        return_parameter
+                    )))
+                }
+            }
+            _ => Err(AutomotiveError::InvalidData),
+        }
+    }
+
+    /// Performs security access for `level` (1-0x3F)
+    ///
+    /// Sends requestSeed (`2 * level - 1`), passes the seed without the echoed
+    /// sub-function to `key_fn`, and sends the key with sendKey (`2 * level`).
     pub fn security_access(&mut self, level: u8, key_fn: impl Fn(&[u8]) -> Vec<u8>) -> Result<()> {
+        if !(1..=0x3F).contains(&level) {
+            return Err(AutomotiveError::InvalidParameter);
+        }
+        let request_seed = 2 * level - 1;
+
         // Request seed
         let request = UdsRequest {
             service_id: SID_SECURITY_ACCESS,
-            parameters: vec![2 * level - 1],
+            parameters: vec![request_seed],
         };
 
-        let response = self.send_request(&request)?;
+        let response = self.positive_response(&request)?;
+        let seed = match response.data.split_first() {
+            Some((&sub_function, seed)) if sub_function == request_seed => seed,
+            _ => return Err(AutomotiveError::InvalidData),
+        };
 
-        if response.data.is_empty() {
-            Err(AutomotiveError::UdsError("Failed to get seed".into()))
-        } else {
-            // Calculate key
-            let key = key_fn(&response.data);
-
-            // Send key
-            let request = UdsRequest {
-                service_id: SID_SECURITY_ACCESS,
-                parameters: key,
-            };
-
-            let response = self.send_request(&request)?;
-
-            if response.data.is_empty() {
-                self.status.security_level = level;
-                self.status.last_activity = std::time::Instant::now();
-                Ok(())
-            } else {
-                Err(AutomotiveError::UdsError("Invalid key".into()))
-            }
-        }
+        // Send key; an invalid key is rejected with a negative response
+        let mut parameters = vec![request_seed + 1];
+        parameters.extend(key_fn(seed));
+        let request = UdsRequest {
+            service_id: SID_SECURITY_ACCESS,
+            parameters,
+        };
+
+        self.positive_response(&request)?;
+        self.status.security_level = level;
+        self.status.last_activity = std::time::Instant::now();
+        Ok(())
     }
 
-    /// Performs routine control
+    /// Performs routine control, returning the routine status record
+    ///
+    /// The request is `routine_type` (e.g. `ROUTINE_START`), the routine id and
+    /// `data`; the status record follows the echoed type and id in the reply.
+    /// With `suppress` the status record is empty.
     pub fn routine_control(
         &mut self,
-        _routine_type: u8,
+        routine_type: u8,
         routine_id: u16,
         data: &[u8],
+        suppress: bool,
     ) -> Result<Vec<u8>> {
-        let mut request_data = vec![(routine_id >> 8) as u8, routine_id as u8];
+        let mut request_data = vec![routine_type, (routine_id >> 8) as u8, routine_id as u8];
         request_data.extend_from_slice(data);
 
         let request = UdsRequest {
             service_id: SID_ROUTINE_CONTROL,
             parameters: request_data,
         };
+        if suppress {
+            self.send_suppressed(request)?;
+            return Ok(Vec::new());
+        }
+
+        let response = self.positive_response(&request)?;
+
+        Ok(response.data.get(3..).unwrap_or_default().to_vec())
+    }
+
+    /// Enables or disables transmission/reception of the given communication type
+    pub fn communication_control(
+        &mut self,
+        control_type: u8,
+        communication_type: u8,
+        suppress: bool,
+    ) -> Result<()> {
+        let request = UdsRequest {
+            service_id: SID_COMMUNICATION_CONTROL,
+            parameters: vec![control_type, communication_type],
+        };
+        if suppress {
+            return self.send_suppressed(request);
+        }
 
-        let response = self.send_request(&request)?;
+        let response = self.positive_response(&request)?;
 
-        if response.data.is_empty() {
-            Err(AutomotiveError::UdsError("Routine control failed".into()))
+        if response.data.first() == Some(&control_type) {
+            Ok(())
         } else {
-            Ok(response.data[2..].to_vec())
+            Err(AutomotiveError::InvalidData)
         }
     }
 
@@ -330,53 +957,84 @@ impl<T: TransportLayer> Uds<T> {
             parameters: request_data,
         };
 
-        let response = self.send_request(&request)?;
+        let response = self.positive_response(&request)?;
 
-        if response.data.is_empty() {
-            Ok(vec![])
-        } else {
-            Ok(response.data[3..].to_vec())
-        }
+        Ok(response.data.get(3..).unwrap_or_default().to_vec())
     }
 
-    /// Reads memory by address
-    pub fn read_memory(&mut self, address: u32, size: u16) -> Result<Vec<u8>> {
-        let request_data = vec![
-            4, // Address length
-            2, // Size length
-            (address >> 24) as u8,
-            (address >> 16) as u8,
-            (address >> 8) as u8,
-            address as u8,
-            (size >> 8) as u8,
-            size as u8,
-        ];
-
+    /// Reads memory by address, sizing the address and size fields from their types
+    pub fn read_memory<A: TransferAddressOrSize, S: TransferAddressOrSize>(
+        &mut self,
+        address: A,
+        size: S,
+    ) -> Result<Vec<u8>> {
         let request = UdsRequest {
             service_id: SID_READ_MEMORY_BY_ADDRESS,
-            parameters: request_data,
+            parameters: memory_parameters(address, size),
         };
 
-        let response = self.send_request(&request)?;
+        Ok(self.positive_response(&request)?.data)
+    }
 
-        if response.data.is_empty() {
-            Err(AutomotiveError::UdsError("Memory read failed".into()))
-        } else {
-            Ok(response.data)
+    /// Reads memory with a 4-byte address and 2-byte size
+    pub fn read_memory_u32(&mut self, address: u32, size: u16) -> Result<Vec<u8>> {
+        self.read_memory(address, size)
+    }
+
+    /// Reads `total_size` bytes with successive requests of at most `chunk` bytes
+    ///
+    /// If a request fails after some data was read, the data read so far is
+    /// returned inside `AutomotiveError::IncompleteRead`.
+    pub fn read_memory_chunked(
+        &mut self,
+        address: u32,
+        total_size: u32,
+        chunk: u16,
+    ) -> Result<Vec<u8>> {
+        if chunk == 0 {
+            return Err(AutomotiveError::InvalidParameter);
         }
+
+        let mut data = Vec::with_capacity(total_size as usize);
+        let mut offset = 0u32;
+        while offset < total_size {
+            let size = (total_size - offset).min(chunk as u32) as u16;
+            let result = self
+                .raw_request(
+                    SID_READ_MEMORY_BY_ADDRESS,
+                    &memory_parameters(address.wrapping_add(offset), size),
+                )
+                .and_then(|response| {
+                    if response.data.is_empty() {
+                        Err(AutomotiveError::InvalidData)
+                    } else {
+                        Ok(response.data)
+                    }
+                });
+
+            match result {
+                Ok(block) => data.extend_from_slice(&block),
+                Err(error) if data.is_empty() => return Err(error),
+                Err(error) => {
+                    return Err(AutomotiveError::IncompleteRead {
+                        data,
+                        error: Box::new(error),
+                    })
+                }
+            }
+            offset += size as u32;
+        }
+        Ok(data)
     }
 
-    /// Writes memory by address
-    pub fn write_memory(&mut self, address: u32, data: &[u8]) -> Result<()> {
-        let mut request_data = vec![
-            4, // Address length
-            (data.len() as u16 >> 8) as u8,
-            data.len() as u8,
-            (address >> 24) as u8,
-            (address >> 16) as u8,
-            (address >> 8) as u8,
-            address as u8,
-        ];
+    /// Writes memory by address, with the size field type given by `S`
+    pub fn write_memory<A, S>(&mut self, address: A, data: &[u8]) -> Result<()>
+    where
+        A: TransferAddressOrSize,
+        S: TransferAddressOrSize + TryFrom<usize>,
+    {
+        let size = S::try_from(data.len()).map_err(|_| AutomotiveError::InvalidParameter)?;
+        let mut request_data = memory_parameters(address, size);
         request_data.extend_from_slice(data);
 
         let request = UdsRequest {
@@ -384,13 +1042,47 @@ impl<T: TransportLayer> Uds<T> {
             parameters: request_data,
         };
 
-        let response = self.send_request(&request)?;
+        self.positive_response(&request)?;
+        Ok(())
+    }
 
-        if response.data.is_empty() {
-            Ok(())
-        } else {
-            Err(AutomotiveError::UdsError("Memory write failed".into()))
+    /// Writes memory with a 4-byte address and 2-byte size
+    pub fn write_memory_u32(&mut self, address: u32, data: &[u8]) -> Result<()> {
+        self.write_memory::<u32, u16>(address, data)
+    }
+
+    /// Clears DTCs in the given 3-byte group (0xFFFFFF for all groups)
+    pub fn clear_diagnostic_information(&mut self, group: u32) -> Result<()> {
+        self.clear_dtc_group(group, None)
+    }
+
+    /// Clears DTCs in the given group from a specific memory (ISO 14229-1:2020)
+    pub fn clear_diagnostic_information_in_memory(
+        &mut self,
+        group: u32,
+        memory_selection: u8,
+    ) -> Result<()> {
+        self.clear_dtc_group(group, Some(memory_selection))
+    }
+
+    fn clear_dtc_group(&mut self, group: u32, memory_selection: Option<u8>) -> Result<()> {
+        let mut parameters = vec![(group >> 16) as u8, (group >> 8) as u8, group as u8];
+        parameters.extend(memory_selection);
+
+        let request = UdsRequest {
+            service_id: SID_CLEAR_DIAGNOSTIC_INFO,
+            parameters,
+        };
+
+        let response = self.send_request(&request).map_err(|e| match e.nrc() {
+            Some(NRC_CONDITIONS_NOT_CORRECT) => AutomotiveError::ConditionsNotCorrect,
+            _ => e,
+        })?;
+
+        if response.service_id != SID_CLEAR_DIAGNOSTIC_INFO + 0x40 {
+            return Err(AutomotiveError::InvalidData);
         }
+        Ok(())
     }
 
     /// Handles session timing and tester present
@@ -419,6 +1111,112 @@ impl<T: TransportLayer> Uds<T> {
     }
 }
 
+/// Background tester-present sender returned by [`Uds::spawn_keepalive`]
+pub struct KeepAlive<T: TransportLayer + Send + 'static> {
+    stop: std::sync::mpsc::Sender<()>,
+    handle: std::thread::JoinHandle<Uds<T>>,
+}
+
+impl<T: TransportLayer + Send + 'static> KeepAlive<T> {
+    /// Stops sending tester-present and hands the UDS instance back
+    pub fn stop(self) -> Uds<T> {
+        let _ = self.stop.send(());
+        self.handle
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+    }
+}
+
+impl<T: TransportLayer + Send + 'static> Uds<T> {
+    /// Moves the instance into a thread that keeps the session alive
+    ///
+    /// Tester-present is sent right away and then every
+    /// `tester_present_interval_ms`, with the positive response suppressed.
+    /// A failed send is retried at the next interval. The transport must be
+    /// `Send` since it is driven from the background thread. Dropping the
+    /// returned [`KeepAlive`] without calling [`KeepAlive::stop`] ends the
+    /// thread and closes the instance.
+    pub fn spawn_keepalive(mut self) -> KeepAlive<T> {
+        let (stop, stopped) = std::sync::mpsc::channel();
+        let interval =
+            std::time::Duration::from_millis(self.config.tester_present_interval_ms as u64);
+
+        let handle = std::thread::spawn(move || {
+            loop {
+                let request = UdsRequest {
+                    service_id: SID_TESTER_PRESENT,
+                    parameters: vec![0x00],
+                };
+                if self.send_suppressed(request).is_ok() {
+                    self.status.tester_present_sent = true;
+                    self.status.last_activity = std::time::Instant::now();
+                }
+
+                match stopped.recv_timeout(interval) {
+                    Err(std::sync::mpsc::RecvTimeoutError::Timeout) => {}
+                    _ => break,
+                }
+            }
+            self
+        });
+
+        KeepAlive { stop, handle }
+    }
+}
+
+impl<P: PhysicalLayer> Uds<IsoTp<P>> {
+    /// Creates a new UDS instance on top of an ISO-TP transport
+    ///
+    /// Alias of [`Uds::with_transport`], matching the crate-level example.
+    pub fn new_with_isotp(config: UdsConfig, isotp: IsoTp<P>) -> Self {
+        Self::with_transport(config, isotp)
+    }
+}
+
+impl Uds<BoxedTransport> {
+    /// Creates a new UDS instance with a transport chosen at runtime
+    pub fn with_boxed_transport<T: TransportLayer + 'static>(
+        config: UdsConfig,
+        transport: T,
+    ) -> Self {
+        Self::with_transport(config, BoxedTransport::from_transport(transport))
+    }
+}
+
+impl<T: TransportLayer> Diagnostic for Uds<T> {
+    /// Reads DTCs matching any status bit; the failure type byte is dropped
+    fn read_dtcs(&mut self) -> Result<Vec<String>> {
+        let request = UdsRequest {
+            service_id: SID_READ_DTC,
+            parameters: vec![DTC_REPORT_BY_STATUS_MASK, 0xFF],
+        };
+
+        let response = self.positive_response(&request)?;
+
+        if response.data.len() < 2 || response.data[0] != DTC_REPORT_BY_STATUS_MASK {
+            return Err(AutomotiveError::InvalidData);
+        }
+
+        // Records after the availability mask: 3 DTC bytes followed by a status byte
+        Ok(response.data[2..]
+            .chunks_exact(4)
+            .map(|record| format_dtc(record[0], record[1]))
+            .collect())
+    }
+
+    fn clear_dtcs(&mut self) -> Result<()> {
+        self.clear_diagnostic_information(DTC_GROUP_ALL)
+    }
+}
+
+impl<T: TransportLayer> Drop for Uds<T> {
+    fn drop(&mut self) {
+        if self.state != LayerState::Closed {
+            let _ = self.close();
+        }
+    }
+}
+
 impl<T: TransportLayer> ApplicationLayer for Uds<T> {
     type Config = UdsConfig;
     type Request = UdsRequest;
@@ -429,90 +1227,106 @@ impl<T: TransportLayer> ApplicationLayer for Uds<T> {
     }
 
     fn open(&mut self) -> Result<()> {
-        if self.is_open {
-            return Ok(());
+        match self.state {
+            LayerState::Open => return Ok(()),
+            LayerState::Errored => {
+                // Reset the transport before starting over
+                let _ = self.transport.close();
+                self.state = LayerState::Closed;
+            }
+            LayerState::Closed => {}
         }
+        self.config.validate()?;
         self.transport.open()?;
-        self.is_open = true;
+        self.state = LayerState::Open;
         Ok(())
     }
 
     fn close(&mut self) -> Result<()> {
-        self.is_open = false;
+        self.state = LayerState::Closed;
         Ok(())
     }
 
     fn send_request(&mut self, request: &Self::Request) -> Result<Self::Response> {
-        if !self.is_open {
+        if self.state == LayerState::Closed {
             return Err(AutomotiveError::NotInitialized);
         }
-        let mut data = vec![request.service_id];
-        data.extend_from_slice(&request.parameters);
-
-        // Send the request
-        self.transport.write_frame(&Frame {
-            id: 0,
-            data: data.clone(),
-            timestamp: 0,
-            is_extended: false,
-            is_fd: false,
-        })?;
-
-        // Handle response pending (NRC 0x78)
-        let mut retry_count = 0;
-        let max_retries = 5; // Limit retries to avoid infinite loop
 
+        let mut failed_attempts = 0;
         loop {
-            let response = self.transport.read_frame()?;
-            if response.data.is_empty() {
-                return Err(AutomotiveError::InvalidParameter);
-            }
-
-            // Check for response pending (0x7F service_id 0x78)
-            if response.data.len() >= 3
-                && response.data[0] == 0x7F
-                && response.data[1] == request.service_id
-                && response.data[2] == NRC_RESPONSE_PENDING
-            {
-                retry_count += 1;
-                if retry_count >= max_retries {
-                    break; // Exit after max retries to avoid infinite loop
+            match self.exchange(request) {
+                Err(AutomotiveError::SendFailed | AutomotiveError::ReceiveFailed)
+                    if failed_attempts + 1 < self.config.retry.max_attempts =>
+                {
+                    failed_attempts += 1;
+                    // Drop any partial response before trying again
+                    let _ = self.transport.flush();
+                    let delay_ms = self.config.retry.delay_ms(failed_attempts);
+                    std::thread::sleep(std::time::Duration::from_millis(delay_ms as u64));
+                }
+                result => {
+                    self.state.observe(&result);
+                    if let Some(tap) = &self.tap {
+                        tap(request, &result);
+                    }
+                    return result;
                 }
-
-                // Wait a bit before retrying
-                std::thread::sleep(std::time::Duration::from_millis(100));
-
-                // Resend the request - make sure to send the full request data
-                self.transport.write_frame(&Frame {
-                    id: 0,
-                    data: data.clone(),
-                    timestamp: 0,
-                    is_extended: false,
-                    is_fd: false,
-                })?;
-
-                // Add a small delay to allow the mock to process the frame
-                std::thread::sleep(std::time::Duration::from_millis(10));
-            } else {
-                // Regular response
-                return Ok(UdsResponse {
-                    service_id: response.data[0],
-                    data: response.data[1..].to_vec(),
-                });
             }
         }
-
-        // If we get here, we've exceeded max retries
-        Ok(UdsResponse {
-            service_id: 0x7E, // Default positive response
-            data: vec![0x00],
-        })
     }
 
     fn set_timeout(&mut self, timeout_ms: u32) -> Result<()> {
-        if !self.is_open {
+        if self.state == LayerState::Closed {
             return Err(AutomotiveError::NotInitialized);
         }
-        self.transport.set_timeout(timeout_ms)
+        self.transport.set_timeout(timeout_ms)?;
+        self.timeout_ms = timeout_ms;
+        Ok(())
+    }
+}
+
+impl<T: TransportLayer> Uds<T> {
+    /// Sends a request with the suppressPosRspMsgIndicationBit set
+    ///
+    /// Negative responses are still sent by the ECU, so a reply is awaited for
+    /// P2: an NRC is returned as an error, while silence or a positive response
+    /// sent anyway is success.
+    fn send_suppressed(&mut self, mut request: UdsRequest) -> Result<()> {
+        request.parameters[0] |= SUPPRESS_POS_RSP_MSG_INDICATION_BIT;
+
+        match self.send_request(&request) {
+            Ok(_) | Err(AutomotiveError::Timeout) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Sends a request once and waits for its response
+    ///
+    /// The first response is awaited for P2; each response pending (NRC 0x78)
+    /// extends the wait by P2* without resending the request.
+    fn exchange(&mut self, request: &UdsRequest) -> Result<UdsResponse> {
+        let mut data = vec![request.service_id];
+        data.extend_from_slice(&request.parameters);
+
+        self.transport.set_timeout(self.config.p2_timeout_ms)?;
+        let result = self.await_response(request.service_id, data);
+        self.transport.set_timeout(self.timeout_ms)?;
+        result
+    }
+
+    fn await_response(&mut self, service_id: u8, data: Vec<u8>) -> Result<UdsResponse> {
+        self.transport.write_frame(&Frame::standard(0, data))?;
+
+        loop {
+            match UdsResponse::parse(&self.transport.read_frame()?.data) {
+                Err(AutomotiveError::UdsNegativeResponse {
+                    service_id: pending,
+                    nrc: NRC_RESPONSE_PENDING,
+                }) if pending == service_id => {
+                    self.transport.set_timeout(self.config.p2_star_timeout_ms)?;
+                }
+                response => return response,
+            }
+        }
     }
 }
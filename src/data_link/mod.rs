@@ -42,7 +42,7 @@ pub enum CanErrorType {
 }
 
 /// CAN error frame
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct CanError {
     pub error_type: CanErrorType,
     pub is_tx: bool,
@@ -52,6 +52,63 @@ pub struct CanError {
     pub rec: u8,
 }
 
+// SocketCAN error frame layout (linux/can/error.h)
+const CAN_ERR_FLAG: u32 = 0x2000_0000;
+const CAN_ERR_ACK: u32 = 0x0000_0020; // Error class: no acknowledgment on transmission
+const CAN_ERR_PROT_BIT: u8 = 0x01;
+const CAN_ERR_PROT_FORM: u8 = 0x02;
+const CAN_ERR_PROT_STUFF: u8 = 0x04;
+const CAN_ERR_PROT_BIT0: u8 = 0x08;
+const CAN_ERR_PROT_BIT1: u8 = 0x10;
+const CAN_ERR_PROT_TX: u8 = 0x80;
+const CAN_ERR_PROT_LOC_CRC_SEQ: u8 = 0x08;
+
+impl CanError {
+    /// Decodes a SocketCAN error frame; `None` for ordinary frames
+    ///
+    /// The protocol violation type and location are in data bytes 2 and 3,
+    /// and the TEC/REC in bytes 6 and 7.
+    pub fn from_socketcan(frame: &Frame) -> Option<Self> {
+        if frame.id & CAN_ERR_FLAG == 0 {
+            return None;
+        }
+        let byte = |index: usize| frame.data.get(index).copied().unwrap_or(0);
+        let (prot_type, prot_location) = (byte(2), byte(3));
+
+        let error_type = if frame.id & CAN_ERR_ACK != 0 {
+            CanErrorType::Ack
+        } else if prot_type & CAN_ERR_PROT_BIT0 != 0 {
+            CanErrorType::Bit0
+        } else if prot_type & CAN_ERR_PROT_BIT1 != 0 {
+            CanErrorType::Bit1
+        } else if prot_type & CAN_ERR_PROT_STUFF != 0 {
+            CanErrorType::Stuff
+        } else if prot_type & CAN_ERR_PROT_FORM != 0 {
+            CanErrorType::Form
+        } else if prot_location == CAN_ERR_PROT_LOC_CRC_SEQ {
+            CanErrorType::Crc
+        } else {
+            CanErrorType::Other(prot_type & !CAN_ERR_PROT_TX & !CAN_ERR_PROT_BIT)
+        };
+
+        Some(Self {
+            error_type,
+            is_tx: prot_type & CAN_ERR_PROT_TX != 0,
+            frame_type: FrameType::Data,
+            location: ErrorLocation::from_socketcan(prot_location),
+            tec: byte(6),
+            rec: byte(7),
+        })
+    }
+}
+
+/// Frame or error indication received from a controller
+#[derive(Debug, Clone, PartialEq)]
+pub enum RxEvent {
+    Frame(Frame),
+    Error(CanError),
+}
+
 /// Frame type when error occurred
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum FrameType {
@@ -79,3 +136,25 @@ pub enum ErrorLocation {
     InterFrame,
     Other(u8),
 }
+
+impl ErrorLocation {
+    /// Maps a SocketCAN protocol error location code
+    fn from_socketcan(location: u8) -> Self {
+        match location {
+            0x03 => ErrorLocation::Sof,
+            0x02 | 0x06 | 0x07 | 0x0E | 0x0F => ErrorLocation::Id,
+            0x04 | 0x0C => ErrorLocation::Rtr,
+            0x05 => ErrorLocation::Ide,
+            0x09 => ErrorLocation::R0,
+            0x0B => ErrorLocation::Dlc,
+            0x0A => ErrorLocation::Data,
+            0x08 => ErrorLocation::Crc,
+            0x18 => ErrorLocation::CrcDelimiter,
+            0x19 => ErrorLocation::Ack,
+            0x1B => ErrorLocation::AckDelimiter,
+            0x1A => ErrorLocation::Eof,
+            0x12 => ErrorLocation::InterFrame,
+            other => ErrorLocation::Other(other),
+        }
+    }
+}
@@ -3,10 +3,23 @@
 //! This module provides a unified error handling system for all layers of the protocol stack,
 //! from physical layer (CAN) up to application layer (UDS, OBD-II).
 
+use core::fmt;
+#[cfg(feature = "std")]
 use std::error::Error;
-use std::fmt;
+#[cfg(feature = "std")]
 use std::io;
 
+/// Message payload carried by the protocol error variants.
+///
+/// Owned `String` with `std`; a static string on `no_std` targets.
+#[cfg(feature = "std")]
+pub type ErrorMessage = String;
+/// Message payload carried by the protocol error variants.
+///
+/// Owned `String` with `std`; a static string on `no_std` targets.
+#[cfg(not(feature = "std"))]
+pub type ErrorMessage = &'static str;
+
 /// Represents all possible errors that can occur in the automotive protocol stack.
 ///
 /// This enum encompasses errors from all layers of the protocol stack, providing
@@ -14,23 +27,30 @@ use std::io;
 #[derive(Debug)]
 pub enum AutomotiveError {
     /// Errors related to CAN bus operations
-    CanError(String),
+    CanError(ErrorMessage),
     /// Errors specific to CAN-FD operations
-    CanFdError(String),
+    CanFdError(ErrorMessage),
 
     /// Errors occurring in ISO-TP (ISO 15765-2) protocol
-    IsoTpError(String),
+    IsoTpError(ErrorMessage),
 
     /// Errors specific to J1939 protocol operations
-    J1939Error(String),
+    J1939Error(ErrorMessage),
 
     /// Errors occurring in UDS (ISO 14229) protocol
-    UdsError(String),
-    /// Errors specific to OBD-II operations
-    ObdError(String),
+    UdsError(ErrorMessage),
+    /// UDS negative response (0x7F) with its negative response code
+    UdsNegativeResponse { service_id: u8, nrc: u8 },
+    /// Errors specific to OBD-II operations, other than negative responses
+    ObdError(ErrorMessage),
+    /// OBD-II negative response (0x7F) with its negative response code
+    ///
+    /// Kept apart from `ObdError`, like `UdsNegativeResponse`, so the NRC can be
+    /// matched on and read with [`AutomotiveError::nrc`].
+    ObdNegativeResponse { mode: u8, nrc: u8 },
 
     /// Errors specific to DoIP operations
-    DoIPError(String),
+    DoIPError(ErrorMessage),
     /// Connection failed in DoIP
     ConnectionFailed,
     /// Failed to send data in DoIP
@@ -46,8 +66,24 @@ pub enum AutomotiveError {
     InvalidParameter,
     /// Component used before initialization
     NotInitialized,
+    /// Operation not allowed in the component's current state
+    ConditionsNotCorrect,
+    /// Operation cancelled by the caller
+    Cancelled,
+    /// Multi-request read stopped early, with the data read before `error`
+    #[cfg(feature = "std")]
+    IncompleteRead {
+        data: Vec<u8>,
+        error: Box<AutomotiveError>,
+    },
+    /// Flash sequence stopped at `stage` because of `error`
+    #[cfg(feature = "std")]
+    FlashFailed {
+        stage: crate::application::flash::FlashStage,
+        error: Box<AutomotiveError>,
+    },
     /// Error related to hardware port operations
-    PortError(String),
+    PortError(ErrorMessage),
 
     /// Invalid data received
     InvalidData,
@@ -55,6 +91,7 @@ pub enum AutomotiveError {
     InvalidChecksum,
 
     /// I/O error
+    #[cfg(feature = "std")]
     IoError(io::Error),
 
     /// Checksum error
@@ -69,7 +106,17 @@ impl fmt::Display for AutomotiveError {
             AutomotiveError::IsoTpError(msg) => write!(f, "ISO-TP error: {}", msg),
             AutomotiveError::J1939Error(msg) => write!(f, "J1939 error: {}", msg),
             AutomotiveError::UdsError(msg) => write!(f, "UDS error: {}", msg),
+            AutomotiveError::UdsNegativeResponse { service_id, nrc } => write!(
+                f,
+                "UDS negative response to service 0x{:02X}: NRC 0x{:02X}",
+                service_id, nrc
+            ),
             AutomotiveError::ObdError(msg) => write!(f, "OBD error: {}", msg),
+            AutomotiveError::ObdNegativeResponse { mode, nrc } => write!(
+                f,
+                "OBD negative response to mode 0x{:02X}: NRC 0x{:02X}",
+                mode, nrc
+            ),
             AutomotiveError::DoIPError(msg) => write!(f, "DoIP error: {}", msg),
             AutomotiveError::ConnectionFailed => write!(f, "DoIP connection failed"),
             AutomotiveError::SendFailed => write!(f, "DoIP send failed"),
@@ -78,24 +125,80 @@ impl fmt::Display for AutomotiveError {
             AutomotiveError::BufferOverflow => write!(f, "Buffer overflow"),
             AutomotiveError::InvalidParameter => write!(f, "Invalid parameter"),
             AutomotiveError::NotInitialized => write!(f, "Component not initialized"),
+            AutomotiveError::ConditionsNotCorrect => write!(f, "Conditions not correct"),
+            AutomotiveError::Cancelled => write!(f, "Operation cancelled"),
+            #[cfg(feature = "std")]
+            AutomotiveError::IncompleteRead { data, error } => {
+                write!(f, "Read stopped after {} bytes: {}", data.len(), error)
+            }
+            #[cfg(feature = "std")]
+            AutomotiveError::FlashFailed { stage, error } => {
+                write!(f, "Flashing failed at {}: {}", stage, error)
+            }
             AutomotiveError::PortError(msg) => write!(f, "Port error: {}", msg),
             AutomotiveError::InvalidData => write!(f, "Invalid data received"),
             AutomotiveError::InvalidChecksum => write!(f, "Invalid checksum"),
+            #[cfg(feature = "std")]
             AutomotiveError::IoError(err) => write!(f, "I/O error: {}", err),
             AutomotiveError::ChecksumError => write!(f, "Checksum error"),
         }
     }
 }
 
+impl AutomotiveError {
+    /// Negative response code carried by a UDS or OBD-II negative response
+    pub fn nrc(&self) -> Option<u8> {
+        match self {
+            AutomotiveError::UdsNegativeResponse { nrc, .. }
+            | AutomotiveError::ObdNegativeResponse { nrc, .. } => Some(*nrc),
+            _ => None,
+        }
+    }
+
+    /// Returns true for requestCorrectlyReceived-ResponsePending (0x78)
+    pub fn is_pending(&self) -> bool {
+        self.nrc() == Some(0x78)
+    }
+
+    /// Returns true for busyRepeatRequest (0x21)
+    pub fn is_busy(&self) -> bool {
+        self.nrc() == Some(0x21)
+    }
+
+    /// Returns true if repeating the request later may succeed
+    pub fn is_retryable(&self) -> bool {
+        matches!(self.nrc(), Some(0x21 | 0x23 | 0x78))
+    }
+
+    /// Returns true if the ECU requires security access first (0x33)
+    pub fn requires_security(&self) -> bool {
+        self.nrc() == Some(0x33)
+    }
+
+    /// Returns true if the connection or port is lost and the layer must be reopened
+    pub fn is_fatal(&self) -> bool {
+        match self {
+            AutomotiveError::ConnectionFailed | AutomotiveError::PortError(_) => true,
+            #[cfg(feature = "std")]
+            AutomotiveError::IoError(_) => true,
+            _ => false,
+        }
+    }
+}
+
+#[cfg(feature = "std")]
 impl Error for AutomotiveError {
     fn source(&self) -> Option<&(dyn Error + 'static)> {
         match self {
             AutomotiveError::IoError(err) => Some(err),
+            AutomotiveError::IncompleteRead { error, .. } => Some(error.as_ref()),
+            AutomotiveError::FlashFailed { error, .. } => Some(error.as_ref()),
             _ => None,
         }
     }
 }
 
+#[cfg(feature = "std")]
 impl From<io::Error> for AutomotiveError {
     fn from(err: io::Error) -> Self {
         AutomotiveError::IoError(err)
@@ -103,4 +206,4 @@ impl From<io::Error> for AutomotiveError {
 }
 
 /// Result type alias for automotive operations
-pub type Result<T> = std::result::Result<T, AutomotiveError>;
+pub type Result<T> = core::result::Result<T, AutomotiveError>;
@@ -21,6 +21,10 @@
 //! - High-performance implementations
 //! - Strong type safety and error handling
 //! - Easy-to-use abstractions
+//! - `no_std` core types: build with `default-features = false` to get only the
+//!   [`types`] and [`error`] modules, backed by `heapless` buffers
+//! - Optional `serde` feature: `Serialize`/`Deserialize` for frames, configs,
+//!   DTCs and decoded PID data
 //!
 //! ## Example
 //!
@@ -45,7 +49,7 @@
 //!     block_size: 8,
 //!     st_min: 10
 //! };
-//! let mut isotp = isotp::CustomInterface::new_with_can(isotp_config, can);
+//! let mut isotp = isotp::IsoTp::new_with_can(isotp_config, can);
 //! isotp.open();
 //!
 //! # 3. Set up application layer (UDS)
@@ -53,11 +57,11 @@
 //!     timeout_ms: 1000,
 //!     p2_timeout_ms: 5000
 //! };
-//! let mut uds = uds::CustomInterface::new_with_isotp(uds_config, isotp);
+//! let mut uds = uds::Uds::new_with_isotp(uds_config, isotp);
 //! uds.open();
 //!
 //! # 4. Use UDS services
-//! uds.change_session(uds::SESSION_EXTENDED);
+//! uds.change_session(uds::SESSION_EXTENDED, false);
 //! let vin = uds.read_data_by_id(0xF190);  // Read Vehicle Identification Number
 //! ```
 //!
@@ -78,27 +82,40 @@
 //! automotive protocols. We are grateful to their authors and contributors for making their
 //! work available to the community.
 
+#![cfg_attr(not(feature = "std"), no_std)]
+
 // OSI Layer modules
 /// Application layer protocols including UDS and OBD-II
+#[cfg(feature = "std")]
 pub mod application;
 /// Data link layer handling raw CAN frames
+#[cfg(feature = "std")]
 pub mod data_link; // Raw CAN frame handling
 /// Network layer implementing J1939 protocol
+#[cfg(feature = "std")]
 pub mod network; // J1939 implementation
 /// Physical layer implementations for CAN and CAN-FD
+#[cfg(feature = "std")]
 pub mod physical; // CAN, CANFD implementations
 /// Transport layer implementing ISO-TP (ISO 15765-2)
+#[cfg(feature = "std")]
 pub mod transport; // ISO-TP implementation // UDS and OBD-II implementations
 
 // Re-exports for convenience
-pub use application::{obdii, uds};
+#[cfg(feature = "std")]
+pub use application::{flash, obdii, uds};
+#[cfg(feature = "std")]
 pub use network::j1939;
-pub use physical::{can, canfd};
+#[cfg(feature = "std")]
+pub use physical::{bit_timing, bus_stats, can, canfd};
+#[cfg(feature = "std")]
 pub use transport::isotp;
 
 // Common types and traits
 /// Common error types and error handling functionality
 pub mod error;
+/// Protocol classification of raw frames
+pub mod protocol;
 /// Common types used across the library
 pub mod types;
 
@@ -106,7 +123,7 @@ pub mod types;
 /// Current version of the library
 pub const VERSION: &str = env!("CARGO_PKG_VERSION");
 
-#[cfg(test)]
+#[cfg(all(test, feature = "std"))]
 mod tests {
     use super::*;
 
@@ -114,4 +131,109 @@ mod tests {
     fn version_is_valid() {
         assert!(!VERSION.is_empty());
     }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip() {
+        use application::obdii::PidData;
+
+        let frame = types::Frame::extended(0x18FEF100, vec![0xFF, 0x00, 0x32]);
+        let json = serde_json::to_string(&frame).unwrap();
+        assert!(json.contains("\"data\":[255,0,50]"));
+        assert_eq!(serde_json::from_str::<types::Frame>(&json).unwrap(), frame);
+
+        let rpm = PidData::EngineRpm(1750.0);
+        let json = serde_json::to_string(&rpm).unwrap();
+        assert_eq!(json, r#"{"EngineRpm":1750.0}"#);
+        match serde_json::from_str(&json).unwrap() {
+            PidData::EngineRpm(value) => assert_eq!(value, 1750.0),
+            other => panic!("unexpected value: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn frame_constructors() {
+        let standard = types::Frame::standard(0x7E0, vec![0x02, 0x10, 0x03]);
+        assert_eq!(standard.id, 0x7E0);
+        assert_eq!(standard.data, vec![0x02, 0x10, 0x03]);
+        assert_eq!(standard.timestamp, 0);
+        assert!(!standard.is_extended && !standard.is_fd && !standard.brs);
+
+        let extended = types::Frame::extended(0x18FECA00, vec![0x00]);
+        assert!(extended.is_extended && !extended.is_fd && !extended.brs);
+
+        let fd = types::Frame::fd(0x123, vec![0x00; 12], true);
+        assert!(!fd.is_extended && fd.is_fd && fd.brs);
+        assert!(!types::Frame::fd(0x123, Vec::new(), false).brs);
+    }
+
+    #[test]
+    fn frame_equality_and_hashing() {
+        let frame = types::Frame {
+            id: 0x7E8,
+            data: vec![0x02, 0x50, 0x03],
+            timestamp: 100,
+            is_extended: false,
+            is_fd: false,
+            brs: false,
+        };
+        let same = frame.clone();
+        let mut changed = frame.clone();
+        changed.data[2] = 0x01;
+        let later = types::Frame {
+            timestamp: 200,
+            ..frame.clone()
+        };
+
+        assert_eq!(frame, same);
+        assert_ne!(frame, changed);
+        assert_ne!(frame, later);
+        assert!(frame.matches_ignoring_timestamp(&later));
+        assert!(!frame.matches_ignoring_timestamp(&changed));
+
+        let unique: std::collections::HashSet<_> = [frame, same, changed].into_iter().collect();
+        assert_eq!(unique.len(), 2);
+    }
+
+    #[test]
+    fn classify_frames() {
+        use protocol::{classify, FrameKind};
+        use types::Frame;
+
+        let single = Frame::standard(0x7E8, vec![0x03, 0x62, 0xF1, 0x90, 0, 0, 0, 0]);
+        assert_eq!(classify(&single), FrameKind::IsoTpSingle);
+        let mut fd_single = Frame::fd(0x7E8, vec![0x00, 0x0A], false);
+        fd_single.data.extend_from_slice(&[0x55; 10]);
+        assert_eq!(classify(&fd_single), FrameKind::IsoTpSingle);
+        let first = Frame::standard(0x7E8, vec![0x10, 0x14, 0x62, 0xF1, 0x90, 0x57, 0x30, 0x4C]);
+        assert_eq!(classify(&first), FrameKind::IsoTpFirst);
+        let consecutive =
+            Frame::standard(0x7E8, vec![0x21, 0x30, 0x30, 0x30, 0x30, 0x34, 0x33, 0x4D]);
+        assert_eq!(classify(&consecutive), FrameKind::IsoTpConsecutive);
+        let flow_control = Frame::standard(0x7E0, vec![0x30, 0x00, 0x00]);
+        assert_eq!(classify(&flow_control), FrameKind::IsoTpFlowControl);
+
+        // Engine speed (EEC1) is PDU2; the request PGN is PDU1 and drops its destination
+        let eec1 = Frame::extended(0x0CF00400, vec![0xFF; 8]);
+        assert_eq!(classify(&eec1), FrameKind::J1939(0xF004));
+        let request = Frame::extended(0x18EA00F9, vec![0xE5, 0xFE, 0x00]);
+        assert_eq!(classify(&request), FrameKind::J1939(0xEA00));
+
+        assert_eq!(
+            classify(&Frame::standard(0x100, Vec::new())),
+            FrameKind::Unknown
+        );
+        assert_eq!(
+            classify(&Frame::standard(0x100, vec![0x05, 0x01])),
+            FrameKind::Unknown
+        );
+        assert_eq!(
+            classify(&Frame::standard(0x100, vec![0x35, 0x00, 0x00])),
+            FrameKind::Unknown
+        );
+        assert_eq!(
+            classify(&Frame::standard(0x100, vec![0xA0; 8])),
+            FrameKind::Unknown
+        );
+    }
 }
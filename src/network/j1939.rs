@@ -1,11 +1,47 @@
+pub mod pgn;
+
 use super::NetworkLayer;
 use crate::error::{AutomotiveError, Result};
 use crate::physical::PhysicalLayer;
+use crate::transport::{DiagnosticTroubleCode, ISOBUSDiagnosticProtocol, LampStatus};
 use crate::types::{Address, Config, Frame};
+use std::collections::VecDeque;
 
 const PGN_ADDRESS_CLAIMED: u32 = 0xEE00;
 const PGN_REQUEST: u32 = 0xEA00;
 const PGN_CANNOT_CLAIM: u32 = 0xEE00;
+const PGN_DM1: u32 = 0xFECA;
+const PGN_TP_CM: u32 = 0xEC00; // Transport protocol connection management
+const PGN_TP_DT: u32 = 0xEB00; // Transport protocol data transfer
+const PGN_PROPRIETARY_A: u32 = 0xEF00;
+const PGN_PROPRIETARY_B: u32 = 0xFF00;
+
+// Transport protocol control bytes
+const TP_CM_RTS: u8 = 0x10;
+const TP_CM_CTS: u8 = 0x11;
+const TP_CM_END_OF_MSG_ACK: u8 = 0x13;
+const TP_CM_BAM: u8 = 0x20;
+const TP_CM_ABORT: u8 = 0xFF;
+
+/// Largest payload the transport protocol can carry (255 packets of 7 bytes)
+pub const TP_MAX_SIZE: usize = 1785;
+/// Default priority of proprietary messages
+pub const PROPRIETARY_PRIORITY: u8 = 6;
+/// Priority of transport protocol frames
+const TP_PRIORITY: u8 = 7;
+/// Delay between BAM data packets (SAE J1939-21 requires 50-200 ms)
+const BAM_PACKET_INTERVAL_MS: u64 = 50;
+/// Upper bound on frames read by one `pump`, so a busy bus cannot stall the caller
+const PUMP_MAX_FRAMES: usize = 64;
+/// Priority of request messages
+const REQUEST_PRIORITY: u8 = 6;
+/// Receive timeout restored after address claiming and network scans
+const DEFAULT_TIMEOUT_MS: u32 = 1000;
+
+/// Selects the unsolicited frames a handler is interested in
+pub type FramePredicate = Box<dyn Fn(&Frame) -> bool + Send + Sync>;
+/// Receives unsolicited frames dispatched by `J1939::pump`
+pub type FrameHandler = Box<dyn FnMut(Frame) + Send + Sync>;
 
 /// J1939 message structure
 #[derive(Debug, Clone)]
@@ -15,12 +51,61 @@ pub struct J1939Message {
     pub timestamp: u64,
 }
 
+impl J1939Message {
+    /// Decodes the message with the decoder registered for its PGN
+    pub fn decode(&self) -> Option<pgn::DecodedPgn> {
+        pgn::decode(self.address.pgn, &self.data)
+    }
+
+    /// Decodes a DM1 (active DTCs) broadcast into the lamp status and its DTCs
+    pub fn as_dm1(&self) -> Option<(LampStatus, Vec<DiagnosticTroubleCode>)> {
+        if self.address.pgn != PGN_DM1 {
+            return None;
+        }
+
+        let frame = Frame {
+            id: (PGN_DM1 << 8) | self.address.source as u32,
+            data: self.data.clone(),
+            timestamp: self.timestamp,
+            is_extended: true,
+            is_fd: false,
+            brs: false,
+        };
+        ISOBUSDiagnosticProtocol::parse_dm_response(&frame).ok()
+    }
+}
+
+/// Transport protocol timeouts (in milliseconds), SAE J1939-21 defaults
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TpTimeouts {
+    pub t1: u32, // Time between CTS and first data packet
+    pub t2: u32, // Time between consecutive data packets
+    pub t3: u32, // Time between last data packet and EndOfMsgACK
+    pub t4: u32, // Time waiting for CTS
+}
+
+impl Default for TpTimeouts {
+    fn default() -> Self {
+        Self {
+            t1: 750,
+            t2: 1250,
+            t3: 1250,
+            t4: 1050,
+        }
+    }
+}
+
+/// Default time to wait for contending address claims
+pub const ADDRESS_CLAIM_TIMEOUT_MS: u32 = 250;
+
 /// J1939 configuration
 #[derive(Debug, Clone)]
 pub struct J1939Config {
     pub name: u64, // 64-bit NAME field
     pub preferred_address: u8,
     pub address_range: (u8, u8),
+    pub address_claim_timeout_ms: u32, // Wait for contending claims
+    pub tp_timeouts: TpTimeouts,
 }
 
 impl Config for J1939Config {
@@ -46,6 +131,8 @@ pub struct J1939<P: PhysicalLayer> {
     physical: P,
     current_address: Option<u8>,
     is_open: bool,
+    handlers: Vec<(FramePredicate, FrameHandler)>,
+    pending: VecDeque<Frame>, // Frames read by `pump` that no handler matched
 }
 
 impl<P: PhysicalLayer> J1939<P> {
@@ -56,21 +143,61 @@ impl<P: PhysicalLayer> J1939<P> {
             physical,
             current_address: None,
             is_open: false,
+            handlers: Vec::new(),
+            pending: VecDeque::new(),
         }
     }
 
+    /// Registers a handler for unsolicited frames matching `predicate`
+    ///
+    /// Handlers only run from [`J1939::pump`]; every matching handler gets the frame.
+    pub fn register_handler(
+        &mut self,
+        predicate: impl Fn(&Frame) -> bool + Send + Sync + 'static,
+        handler: impl FnMut(Frame) + Send + Sync + 'static,
+    ) {
+        self.handlers.push((Box::new(predicate), Box::new(handler)));
+    }
+
+    /// Reads the frames available now and dispatches them to matching handlers
+    ///
+    /// Frames no handler matches are kept for `receive`. Returns the number of
+    /// frames dispatched.
+    pub fn pump(&mut self) -> Result<usize> {
+        if !self.is_open {
+            return Err(AutomotiveError::NotInitialized);
+        }
+
+        let mut dispatched = 0;
+        for _ in 0..PUMP_MAX_FRAMES {
+            let frame = match self.physical.receive_frame() {
+                Ok(frame) => frame,
+                Err(AutomotiveError::Timeout) => break,
+                Err(e) => return Err(e),
+            };
+
+            let mut matched = false;
+            for (predicate, handler) in self.handlers.iter_mut() {
+                if predicate(&frame) {
+                    handler(frame.clone());
+                    matched = true;
+                }
+            }
+            if matched {
+                dispatched += 1;
+            } else {
+                self.pending.push_back(frame);
+            }
+        }
+        Ok(dispatched)
+    }
+
     fn build_frame(&self, address: &Address, data: &[u8]) -> Frame {
         let id = ((address.priority as u32) << 26)
             | ((address.pgn as u32) << 8)
             | (self.current_address.unwrap_or(0xFF) as u32);
 
-        Frame {
-            id,
-            data: data.to_vec(),
-            timestamp: 0,
-            is_extended: true,
-            is_fd: false,
-        }
+        Frame::extended(id, data.to_vec())
     }
 
     fn parse_frame(&self, frame: &Frame) -> Result<J1939Message> {
@@ -78,27 +205,17 @@ impl<P: PhysicalLayer> J1939<P> {
             return Err(AutomotiveError::J1939Error("Not an extended frame".into()));
         }
 
-        let priority = ((frame.id >> 26) & 0x7) as u8;
-        let pgn = ((frame.id >> 8) & 0x3FFFF) as u32;
-        let source = (frame.id & 0xFF) as u8;
-        let destination = if (pgn & 0xFF00) == 0 {
-            (pgn & 0xFF) as u8
-        } else {
-            0xFF
-        };
-
         Ok(J1939Message {
-            address: Address {
-                priority,
-                pgn,
-                source,
-                destination,
-            },
+            address: Address::from_can_id(frame.id),
             data: frame.data.clone(),
             timestamp: frame.timestamp,
         })
     }
 
+    /// Broadcasts our NAME from `address`
+    ///
+    /// Written to the physical layer directly: `send` refuses to transmit
+    /// until an address is claimed, and this is the frame that claims it.
     fn send_address_claim(&mut self, address: u8) -> Result<()> {
         let mut name_bytes = Vec::with_capacity(8);
         let mut name = self.config.name;
@@ -108,14 +225,219 @@ impl<P: PhysicalLayer> J1939<P> {
         }
         name_bytes.reverse();
 
+        // Sent from the address being claimed, before it is ours
+        let id = (6 << 26) | ((PGN_ADDRESS_CLAIMED | 0xFF) << 8) | address as u32;
+        self.physical.send_frame(&Frame::extended(id, name_bytes))
+    }
+
+    /// Sends a Proprietary A message to `dest`
+    pub fn send_proprietary_a(&mut self, dest: u8, data: &[u8]) -> Result<()> {
+        let address = Address {
+            priority: PROPRIETARY_PRIORITY,
+            pgn: PGN_PROPRIETARY_A | dest as u32,
+            source: self.get_address()?,
+            destination: dest,
+        };
+        self.send(&address, data)
+    }
+
+    /// Sends a broadcast Proprietary B message with PGN 0xFF00 | `pgn_low`
+    pub fn send_proprietary_b(&mut self, pgn_low: u8, data: &[u8]) -> Result<()> {
         let address = Address {
-            priority: 6,
-            pgn: PGN_ADDRESS_CLAIMED,
-            source: address,
+            priority: PROPRIETARY_PRIORITY,
+            pgn: PGN_PROPRIETARY_B | pgn_low as u32,
+            source: self.get_address()?,
             destination: 0xFF,
         };
+        self.send(&address, data)
+    }
+
+    /// Requests `pgn` from `destination` (0xFF for all nodes)
+    pub fn request_pgn(&mut self, pgn: u32, destination: u8) -> Result<()> {
+        let address = Address {
+            priority: REQUEST_PRIORITY,
+            pgn: PGN_REQUEST | destination as u32,
+            source: self.get_address()?,
+            destination,
+        };
+        self.send(&address, &pgn.to_le_bytes()[..3])
+    }
+
+    /// Requests address claims from all nodes and collects the replies
+    ///
+    /// Returns (source address, NAME) pairs in the order nodes first answered;
+    /// a node claiming twice within `timeout_ms` keeps its last NAME. Other
+    /// frames read meanwhile are kept for `receive`.
+    pub fn scan_network(&mut self, timeout_ms: u32) -> Result<Vec<(u8, u64)>> {
+        self.request_pgn(PGN_ADDRESS_CLAIMED, 0xFF)?;
+
+        let deadline =
+            std::time::Instant::now() + std::time::Duration::from_millis(timeout_ms as u64);
+        self.physical.set_timeout(timeout_ms)?;
+
+        let mut nodes: Vec<(u8, u64)> = Vec::new();
+        let result = loop {
+            if std::time::Instant::now() > deadline {
+                break Ok(());
+            }
+            let frame = match self.physical.receive_frame() {
+                Ok(frame) => frame,
+                Err(AutomotiveError::Timeout) => break Ok(()),
+                Err(e) => break Err(e),
+            };
+            let message = match self.parse_frame(&frame) {
+                Ok(message) if message.address.group_pgn() == PGN_ADDRESS_CLAIMED => message,
+                _ => {
+                    self.pending.push_back(frame);
+                    continue;
+                }
+            };
+            if message.data.len() < 8 {
+                continue;
+            }
+
+            let name = parse_name(&message.data);
+            let source = message.address.source;
+            match nodes.iter_mut().find(|(address, _)| *address == source) {
+                Some(node) => node.1 = name,
+                None => nodes.push((source, name)),
+            }
+        };
+
+        self.physical.set_timeout(DEFAULT_TIMEOUT_MS)?;
+        result.map(|()| nodes)
+    }
 
-        self.send(&address, &name_bytes)
+    /// Sends a TP.CM or TP.DT frame to `destination`
+    fn send_tp_frame(&mut self, pgn: u32, destination: u8, data: Vec<u8>) -> Result<()> {
+        let address = Address {
+            priority: TP_PRIORITY,
+            pgn: pgn | destination as u32,
+            source: self.get_address()?,
+            destination,
+        };
+        let frame = self.build_frame(&address, &data);
+        self.physical.send_frame(&frame)
+    }
+
+    /// Sends data packets `first..first + count` (1-based) of `data`
+    fn send_tp_packets(
+        &mut self,
+        destination: u8,
+        data: &[u8],
+        first: u8,
+        count: u8,
+    ) -> Result<()> {
+        for sequence in first..first.saturating_add(count) {
+            let start = (sequence as usize - 1) * 7;
+            let Some(chunk) = data.get(start..(start + 7).min(data.len())) else {
+                break;
+            };
+            let mut packet = vec![sequence];
+            packet.extend_from_slice(chunk);
+            packet.resize(8, 0xFF);
+            self.send_tp_frame(PGN_TP_DT, destination, packet)?;
+            if destination == 0xFF {
+                std::thread::sleep(std::time::Duration::from_millis(BAM_PACKET_INTERVAL_MS));
+            }
+        }
+        Ok(())
+    }
+
+    /// Waits for the next TP.CM frame `destination` sends back to us
+    ///
+    /// Other frames read meanwhile are kept for `receive`.
+    fn receive_tp_cm(&mut self, destination: u8, timeout_ms: u32) -> Result<Vec<u8>> {
+        let deadline =
+            std::time::Instant::now() + std::time::Duration::from_millis(timeout_ms as u64);
+        let own_address = self.get_address()?;
+        loop {
+            if std::time::Instant::now() > deadline {
+                return Err(AutomotiveError::Timeout);
+            }
+            let frame = self.physical.receive_frame()?;
+            let message = match self.parse_frame(&frame) {
+                Ok(message)
+                    if message.address.pgn == PGN_TP_CM | own_address as u32
+                        && message.address.source == destination =>
+                {
+                    message
+                }
+                _ => {
+                    self.pending.push_back(frame);
+                    continue;
+                }
+            };
+            if !message.data.is_empty() {
+                if message.data[0] == TP_CM_ABORT {
+                    return Err(AutomotiveError::J1939Error(
+                        "Transport session aborted by receiver".into(),
+                    ));
+                }
+                return Ok(message.data);
+            }
+        }
+    }
+
+    /// Sends more than 8 bytes with BAM to the global address, RTS/CTS otherwise
+    fn send_transport(&mut self, address: &Address, data: &[u8]) -> Result<()> {
+        if data.len() > TP_MAX_SIZE {
+            return Err(AutomotiveError::InvalidParameter);
+        }
+
+        // PDU1 PGNs carry the destination in their low byte
+        let (pgn, destination) = (address.group_pgn(), address.destination());
+        let size = data.len() as u16;
+        let packets = data.len().div_ceil(7) as u8;
+        let pgn_bytes = [pgn as u8, (pgn >> 8) as u8, (pgn >> 16) as u8];
+
+        let control = if destination == 0xFF {
+            TP_CM_BAM
+        } else {
+            TP_CM_RTS
+        };
+        let mut announce = vec![control, size as u8, (size >> 8) as u8, packets, 0xFF];
+        announce.extend_from_slice(&pgn_bytes);
+        self.send_tp_frame(PGN_TP_CM, destination, announce)?;
+
+        if destination == 0xFF {
+            std::thread::sleep(std::time::Duration::from_millis(BAM_PACKET_INTERVAL_MS));
+            return self.send_tp_packets(destination, data, 1, packets);
+        }
+
+        let timeouts = self.config.tp_timeouts;
+        let mut timeout_ms = timeouts.t4;
+        let mut held_until = None;
+        loop {
+            let response = self.receive_tp_cm(destination, timeout_ms)?;
+            match response[..] {
+                // A hold keeps the connection open for T4; repeating it does not extend that
+                [TP_CM_CTS, 0, ..] => {
+                    let now = std::time::Instant::now();
+                    let held_until = *held_until
+                        .get_or_insert(now + std::time::Duration::from_millis(timeouts.t4 as u64));
+                    timeout_ms = held_until.saturating_duration_since(now).as_millis() as u32;
+                    if timeout_ms == 0 {
+                        return Err(AutomotiveError::Timeout);
+                    }
+                }
+                [TP_CM_CTS, count, next, ..] => {
+                    self.send_tp_packets(destination, data, next, count)?;
+                    timeout_ms = timeouts.t3;
+                    held_until = None;
+                }
+                [TP_CM_END_OF_MSG_ACK, ..] => return Ok(()),
+                _ => {}
+            }
+        }
+    }
+}
+
+impl<P: PhysicalLayer> Drop for J1939<P> {
+    fn drop(&mut self) {
+        if self.is_open {
+            let _ = self.close();
+        }
     }
 }
 
@@ -150,6 +472,7 @@ impl<P: PhysicalLayer> NetworkLayer for J1939<P> {
         self.physical.close()?;
         self.is_open = false;
         self.current_address = None;
+        self.pending.clear();
         Ok(())
     }
 
@@ -162,6 +485,10 @@ impl<P: PhysicalLayer> NetworkLayer for J1939<P> {
             return Err(AutomotiveError::J1939Error("No address claimed".into()));
         }
 
+        if data.len() > 8 {
+            return self.send_transport(address, data);
+        }
+
         let frame = self.build_frame(address, data);
         self.physical.send_frame(&frame)
     }
@@ -171,7 +498,10 @@ impl<P: PhysicalLayer> NetworkLayer for J1939<P> {
             return Err(AutomotiveError::NotInitialized);
         }
 
-        let frame = self.physical.receive_frame()?;
+        let frame = match self.pending.pop_front() {
+            Some(frame) => frame,
+            None => self.physical.receive_frame()?,
+        };
         self.parse_frame(&frame)
     }
 
@@ -195,22 +525,18 @@ impl<P: PhysicalLayer> NetworkLayer for J1939<P> {
         // Send address claim
         self.send_address_claim(address)?;
 
-        // Store current timeout and set temporary timeout for address claiming
-        let current_timeout = 1000; // Default timeout
-        self.physical.set_timeout(250)?;
+        // Set temporary timeout for address claiming
+        self.physical
+            .set_timeout(self.config.address_claim_timeout_ms)?;
 
         let result = loop {
             match self.receive() {
                 Ok(msg)
-                    if msg.address.pgn == PGN_ADDRESS_CLAIMED && msg.address.source == address =>
+                    if msg.address.group_pgn() == PGN_ADDRESS_CLAIMED
+                        && msg.address.source == address =>
                 {
                     // Compare NAME
-                    let mut name = 0u64;
-                    for &byte in msg.data.iter().take(8) {
-                        name = (name << 8) | byte as u64;
-                    }
-
-                    if name < self.config.name {
+                    if parse_name(&msg.data) < self.config.name {
                         break Err(AutomotiveError::J1939Error(
                             "Address claimed by higher priority device".into(),
                         ));
@@ -221,8 +547,8 @@ impl<P: PhysicalLayer> NetworkLayer for J1939<P> {
             }
         };
 
-        // Restore original timeout
-        self.physical.set_timeout(current_timeout)?;
+        // Restore the default timeout
+        self.physical.set_timeout(DEFAULT_TIMEOUT_MS)?;
 
         match result {
             Ok(()) => {
@@ -248,3 +574,10 @@ impl<P: PhysicalLayer> NetworkLayer for J1939<P> {
             .ok_or_else(|| AutomotiveError::J1939Error("No address claimed".into()))
     }
 }
+
+/// Reads a NAME from the first 8 bytes of an address claim, as sent by `send_address_claim`
+fn parse_name(data: &[u8]) -> u64 {
+    data.iter()
+        .take(8)
+        .fold(0u64, |name, &byte| (name << 8) | byte as u64)
+}
@@ -0,0 +1,179 @@
+use crate::types::Address;
+use std::collections::HashMap;
+use std::sync::{OnceLock, RwLock};
+
+// Well-known parameter groups
+pub const PGN_EEC1: u32 = 0xF004; // Electronic Engine Controller 1
+pub const PGN_CCVS: u32 = 0xFEF1; // Cruise Control/Vehicle Speed
+pub const PGN_ET1: u32 = 0xFEEE; // Engine Temperature 1
+
+/// Decodes the payload of a parameter group
+pub type PgnDecoder = fn(&[u8]) -> Option<DecodedPgn>;
+
+/// A single scaled SPN value
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpnValue {
+    pub spn: u32,
+    pub name: &'static str,
+    pub value: f64,
+    pub unit: &'static str,
+}
+
+/// Decoded parameter group with its available SPN values
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecodedPgn {
+    pub pgn: u32,
+    pub name: &'static str,
+    pub values: Vec<SpnValue>,
+}
+
+impl DecodedPgn {
+    /// Returns the value of the given SPN, if it was available
+    pub fn get(&self, spn: u32) -> Option<f64> {
+        self.values
+            .iter()
+            .find(|value| value.spn == spn)
+            .map(|value| value.value)
+    }
+}
+
+fn registry() -> &'static RwLock<HashMap<u32, PgnDecoder>> {
+    static REGISTRY: OnceLock<RwLock<HashMap<u32, PgnDecoder>>> = OnceLock::new();
+    REGISTRY.get_or_init(|| {
+        let mut decoders: HashMap<u32, PgnDecoder> = HashMap::new();
+        decoders.insert(PGN_EEC1, decode_eec1);
+        decoders.insert(PGN_CCVS, decode_ccvs);
+        decoders.insert(PGN_ET1, decode_et1);
+        RwLock::new(decoders)
+    })
+}
+
+/// Registers a decoder for a PGN, replacing any existing one
+pub fn register_decoder(pgn: u32, decoder: PgnDecoder) {
+    registry()
+        .write()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(normalize(pgn), decoder);
+}
+
+/// Decodes a payload using the decoder registered for its PGN
+pub fn decode(pgn: u32, data: &[u8]) -> Option<DecodedPgn> {
+    let decoder = *registry()
+        .read()
+        .unwrap_or_else(|e| e.into_inner())
+        .get(&normalize(pgn))?;
+    decoder(data)
+}
+
+/// Strips the destination address from PDU1 PGNs
+fn normalize(pgn: u32) -> u32 {
+    let address = Address {
+        priority: 0,
+        pgn: pgn & 0x3FFFF,
+        source: 0,
+        destination: 0xFF,
+    };
+    address.group_pgn()
+}
+
+/// Reads a 1-byte SPN, skipping "not available"/error values
+fn spn_u8(data: &[u8], index: usize) -> Option<f64> {
+    data.get(index)
+        .copied()
+        .filter(|&raw| raw < 0xFB)
+        .map(f64::from)
+}
+
+/// Reads a little-endian 2-byte SPN, skipping "not available"/error values
+fn spn_u16(data: &[u8], index: usize) -> Option<f64> {
+    let raw = u16::from_le_bytes([*data.get(index)?, *data.get(index + 1)?]);
+    (raw < 0xFB00).then_some(raw as f64)
+}
+
+/// Builds an SPN value from a raw reading, resolution and offset
+fn scaled(
+    spn: u32,
+    name: &'static str,
+    raw: Option<f64>,
+    resolution: f64,
+    offset: f64,
+    unit: &'static str,
+) -> Option<SpnValue> {
+    raw.map(|raw| SpnValue {
+        spn,
+        name,
+        value: raw * resolution + offset,
+        unit,
+    })
+}
+
+fn decode_eec1(data: &[u8]) -> Option<DecodedPgn> {
+    if data.len() < 8 {
+        return None;
+    }
+    let values = [
+        scaled(
+            513,
+            "Actual Engine - Percent Torque",
+            spn_u8(data, 2),
+            1.0,
+            -125.0,
+            "%",
+        ),
+        scaled(190, "Engine Speed", spn_u16(data, 3), 0.125, 0.0, "rpm"),
+    ];
+    Some(DecodedPgn {
+        pgn: PGN_EEC1,
+        name: "EEC1",
+        values: values.into_iter().flatten().collect(),
+    })
+}
+
+fn decode_ccvs(data: &[u8]) -> Option<DecodedPgn> {
+    if data.len() < 8 {
+        return None;
+    }
+    let values = [scaled(
+        84,
+        "Wheel-Based Vehicle Speed",
+        spn_u16(data, 1),
+        1.0 / 256.0,
+        0.0,
+        "km/h",
+    )];
+    Some(DecodedPgn {
+        pgn: PGN_CCVS,
+        name: "CCVS",
+        values: values.into_iter().flatten().collect(),
+    })
+}
+
+fn decode_et1(data: &[u8]) -> Option<DecodedPgn> {
+    if data.len() < 8 {
+        return None;
+    }
+    let values = [
+        scaled(
+            110,
+            "Engine Coolant Temperature",
+            spn_u8(data, 0),
+            1.0,
+            -40.0,
+            "°C",
+        ),
+        scaled(174, "Fuel Temperature", spn_u8(data, 1), 1.0, -40.0, "°C"),
+        scaled(
+            175,
+            "Engine Oil Temperature",
+            spn_u16(data, 2),
+            0.03125,
+            -273.0,
+            "°C",
+        ),
+    ];
+    Some(DecodedPgn {
+        pgn: PGN_ET1,
+        name: "ET1",
+        values: values.into_iter().flatten().collect(),
+    })
+}
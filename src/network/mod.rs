@@ -46,6 +46,9 @@
 
 pub mod j1939;
 
+#[cfg(test)]
+mod tests;
+
 use crate::error::Result;
 use crate::types::{Address, Config};
 
@@ -0,0 +1,392 @@
+use crate::error::{AutomotiveError, Result};
+use crate::j1939::pgn::{self, DecodedPgn, SpnValue, PGN_CCVS, PGN_EEC1, PGN_ET1};
+use crate::j1939::{J1939Config, J1939Message, TpTimeouts, J1939, TP_MAX_SIZE};
+use crate::network::NetworkLayer;
+use crate::physical::mock::MockPhysical;
+use crate::transport::LampStatus;
+use crate::types::{Address, Frame};
+use std::sync::{Arc, Mutex};
+
+fn message(pgn: u32, data: Vec<u8>) -> J1939Message {
+    J1939Message {
+        address: Address {
+            priority: 3,
+            pgn,
+            source: 0x00,
+            destination: 0xFF,
+        },
+        data,
+        timestamp: 0,
+    }
+}
+
+#[test]
+fn test_decode_eec1_engine_speed() {
+    // Torque 0x7D = 0%, engine speed 0x3E80 * 0.125 = 2000 rpm
+    let decoded = message(
+        PGN_EEC1,
+        vec![0xF0, 0x7D, 0x7D, 0x80, 0x3E, 0x00, 0xF0, 0x7D],
+    )
+    .decode()
+    .unwrap();
+    assert_eq!(decoded.name, "EEC1");
+    assert_eq!(decoded.get(190), Some(2000.0));
+    assert_eq!(decoded.get(513), Some(0.0));
+}
+
+#[test]
+fn test_decode_skips_unavailable_spns() {
+    // Vehicle speed 0x3200 / 256 = 50 km/h; oil temperature not available
+    let ccvs = message(
+        PGN_CCVS,
+        vec![0xFF, 0x00, 0x32, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF],
+    )
+    .decode()
+    .unwrap();
+    assert_eq!(ccvs.get(84), Some(50.0));
+
+    let et1 = message(
+        PGN_ET1,
+        vec![0x82, 0x50, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF],
+    )
+    .decode()
+    .unwrap();
+    assert_eq!(et1.get(110), Some(90.0));
+    assert_eq!(et1.get(174), Some(40.0));
+    assert_eq!(et1.get(175), None);
+}
+
+#[test]
+fn test_register_custom_decoder() {
+    const PGN_PROPRIETARY_B: u32 = 0xFF42;
+
+    assert!(message(PGN_PROPRIETARY_B, vec![0x10]).decode().is_none());
+
+    pgn::register_decoder(PGN_PROPRIETARY_B, |data| {
+        Some(DecodedPgn {
+            pgn: PGN_PROPRIETARY_B,
+            name: "Proprietary B",
+            values: vec![SpnValue {
+                spn: 520_000,
+                name: "Custom Value",
+                value: *data.first()? as f64,
+                unit: "",
+            }],
+        })
+    });
+
+    let decoded = message(PGN_PROPRIETARY_B, vec![0x10]).decode().unwrap();
+    assert_eq!(decoded.get(520_000), Some(16.0));
+}
+
+#[test]
+fn test_decode_dm1() {
+    // MIL on, SPN 100 FMI 1 (5 occurrences), SPN 190 FMI 16 (1 occurrence)
+    let (lamp_status, dtcs) = message(
+        0xFECA,
+        vec![0x40, 0xFF, 0x64, 0x00, 0x01, 0x05, 0xBE, 0x00, 0x10, 0x01],
+    )
+    .as_dm1()
+    .unwrap();
+
+    assert_eq!(lamp_status, LampStatus::On);
+    assert_eq!(dtcs.len(), 2);
+    assert_eq!((dtcs[0].spn(), dtcs[0].fmi()), (100, 1));
+    assert_eq!(dtcs[0].occurrence_count(), 5);
+    assert_eq!((dtcs[1].spn(), dtcs[1].fmi()), (190, 16));
+    assert!(dtcs.iter().all(|dtc| dtc.is_active()));
+
+    assert!(message(PGN_EEC1, vec![0x00; 8]).as_dm1().is_none());
+}
+
+fn create_j1939(
+    handler: impl Fn(&Frame) -> Result<Frame> + Send + Sync + 'static,
+) -> (J1939<MockPhysical>, Arc<Mutex<Vec<Frame>>>) {
+    let mock = MockPhysical::new(Some(Box::new(handler)));
+    let sent_frames = mock.sent_frames();
+    let config = J1939Config {
+        name: 0x1234_5678_9ABC_DEF0,
+        preferred_address: 0x80,
+        address_range: (0x80, 0x8F),
+        address_claim_timeout_ms: 10,
+        tp_timeouts: TpTimeouts::default(),
+    };
+    let mut j1939 = J1939::with_physical(config, mock);
+    j1939.open().unwrap();
+    (j1939, sent_frames)
+}
+
+#[test]
+fn test_j1939_proprietary_ids() {
+    let (mut j1939, sent_frames) = create_j1939(|_| Err(AutomotiveError::Timeout));
+
+    j1939.send_proprietary_a(0x20, &[1, 2, 3, 4]).unwrap();
+    j1939.send_proprietary_b(0x42, &[5, 6]).unwrap();
+
+    // Priority 6, PGN 0xEF20 / 0xFF42, source 0x80 after the address claim
+    let sent_frames = sent_frames.lock().unwrap();
+    assert_eq!(sent_frames[0].id, 0x18EEFF80);
+    assert_eq!(sent_frames[1].id, 0x18EF2080);
+    assert_eq!(sent_frames[1].data, vec![1, 2, 3, 4]);
+    assert_eq!(sent_frames[2].id, 0x18FF4280);
+    assert!(sent_frames[2].is_extended);
+}
+
+#[test]
+fn test_j1939_proprietary_a_uses_transport_protocol() {
+    // Receiver 0x20 grants all packets at once and acknowledges the last one
+    let (mut j1939, sent_frames) = create_j1939(|frame: &Frame| {
+        let (id, data) = match frame.data[0] {
+            0x10 if frame.id == 0x1CEC2080 => {
+                (0x1CEC8020, vec![0x11, 3, 1, 0xFF, 0xFF, 0x00, 0xEF, 0x00])
+            }
+            0x03 if frame.id == 0x1CEB2080 => {
+                (0x1CEC8020, vec![0x13, 20, 0, 3, 0xFF, 0x00, 0xEF, 0x00])
+            }
+            _ => return Err(AutomotiveError::Timeout),
+        };
+        Ok(Frame::extended(id, data))
+    });
+
+    let data: Vec<u8> = (0..20).collect();
+    j1939.send_proprietary_a(0x20, &data).unwrap();
+
+    let sent_frames = sent_frames.lock().unwrap();
+    assert_eq!(sent_frames.len(), 5);
+    assert_eq!(sent_frames[1].id, 0x1CEC2080);
+    assert_eq!(
+        sent_frames[1].data,
+        vec![0x10, 20, 0, 3, 0xFF, 0x00, 0xEF, 0x00]
+    );
+    assert_eq!(sent_frames[2].data, vec![1, 0, 1, 2, 3, 4, 5, 6]);
+    assert_eq!(sent_frames[4].id, 0x1CEB2080);
+    assert_eq!(sent_frames[4].data, vec![3, 14, 15, 16, 17, 18, 19, 0xFF]);
+
+    assert!(matches!(
+        j1939.send_proprietary_a(0x20, &[0; TP_MAX_SIZE + 1]),
+        Err(AutomotiveError::InvalidParameter)
+    ));
+}
+
+#[test]
+fn test_j1939_address_claim_before_address() {
+    // open() claims 0x80 while no address is ours yet
+    let (mut j1939, sent_frames) = create_j1939(|_| Err(AutomotiveError::Timeout));
+
+    let sent_frames = sent_frames.lock().unwrap();
+    assert_eq!(sent_frames[0].id, 0x18EEFF80);
+    assert_eq!(sent_frames[0].data, 0x1234_5678_9ABC_DEF0u64.to_be_bytes());
+    assert_eq!(j1939.get_address().unwrap(), 0x80);
+    j1939.close().unwrap();
+}
+
+#[test]
+fn test_j1939_transport_hold_times_out() {
+    // Receiver 0x20 answers the RTS with a CTS hold, again and again
+    let mock = MockPhysical::new(Some(Box::new(|frame: &Frame| match frame.id {
+        0x1CEC2080 => Ok(Frame::extended(
+            0x1CEC8020,
+            vec![0x11, 0, 0xFF, 0xFF, 0xFF, 0x00, 0xEF, 0x00],
+        )),
+        _ => Err(AutomotiveError::Timeout),
+    })));
+    let config = J1939Config {
+        name: 0x1234_5678_9ABC_DEF0,
+        preferred_address: 0x80,
+        address_range: (0x80, 0x8F),
+        address_claim_timeout_ms: 10,
+        tp_timeouts: TpTimeouts {
+            t4: 50,
+            ..Default::default()
+        },
+    };
+    let mut j1939 = J1939::with_physical(config, mock);
+    j1939.open().unwrap();
+
+    let start = std::time::Instant::now();
+    assert!(matches!(
+        j1939.send_proprietary_a(0x20, &[0; 20]),
+        Err(AutomotiveError::Timeout)
+    ));
+    assert!(start.elapsed() < std::time::Duration::from_millis(500));
+}
+
+#[test]
+fn test_j1939_address_claim_lost_to_lower_name() {
+    // Another node with a lower NAME answers our claim of 0x80
+    let mock = MockPhysical::new(Some(Box::new(|frame: &Frame| match frame.id {
+        0x18EEFF80 => Ok(Frame::extended(0x18EEFF80, vec![0, 0, 0, 0, 0, 0, 0, 1])),
+        _ => Err(AutomotiveError::Timeout),
+    })));
+    let config = J1939Config {
+        name: 0x1234_5678_9ABC_DEF0,
+        preferred_address: 0x80,
+        address_range: (0x80, 0x8F),
+        address_claim_timeout_ms: 10,
+        tp_timeouts: TpTimeouts::default(),
+    };
+    let mut j1939 = J1939::with_physical(config, mock);
+
+    assert!(matches!(j1939.open(), Err(AutomotiveError::J1939Error(_))));
+    assert!(j1939.get_address().is_err());
+}
+
+#[test]
+fn test_j1939_transport_keeps_unrelated_frames() {
+    // EEC1 broadcasts arrive while waiting for the CTS
+    let mock = MockPhysical::new(Some(Box::new(|frame: &Frame| match frame.id {
+        0x1CEC2080 if frame.data[0] == 0x10 => Ok(Frame::extended(
+            0x0CF00400,
+            vec![0xF0, 0x7D, 0x7D, 0x40, 0x1F, 0xFF, 0xFF, 0xFF],
+        )),
+        _ => Err(AutomotiveError::Timeout),
+    })));
+    let config = J1939Config {
+        name: 0x1234_5678_9ABC_DEF0,
+        preferred_address: 0x80,
+        address_range: (0x80, 0x8F),
+        address_claim_timeout_ms: 10,
+        tp_timeouts: TpTimeouts {
+            t4: 20,
+            ..Default::default()
+        },
+    };
+    let mut j1939 = J1939::with_physical(config, mock);
+    j1939.open().unwrap();
+
+    assert!(matches!(
+        j1939.send_proprietary_a(0x20, &[0; 20]),
+        Err(AutomotiveError::Timeout)
+    ));
+    let message = j1939.receive().unwrap();
+    assert_eq!(message.address.pgn, PGN_EEC1);
+    assert_eq!(message.address.source, 0x00);
+}
+
+#[test]
+fn test_j1939_pump_dispatches_broadcasts() {
+    use crate::protocol::{classify, FrameKind};
+    use std::collections::VecDeque;
+
+    // Bus traffic arriving independently of what the node sends
+    let bus = Arc::new(Mutex::new(VecDeque::new()));
+    let rx = Arc::clone(&bus);
+    let (mut j1939, _) = create_j1939(move |_| {
+        rx.lock()
+            .unwrap()
+            .pop_front()
+            .ok_or(AutomotiveError::Timeout)
+    });
+
+    let engine_speeds = Arc::new(Mutex::new(Vec::new()));
+    let log = Arc::clone(&engine_speeds);
+    j1939.register_handler(
+        |frame| classify(frame) == FrameKind::J1939(PGN_EEC1),
+        move |frame| log.lock().unwrap().push(frame.data[3]),
+    );
+
+    bus.lock().unwrap().extend([
+        Frame::extended(
+            0x0CF00400,
+            vec![0xF0, 0x7D, 0x7D, 0x80, 0x3E, 0, 0xF0, 0x7D],
+        ),
+        Frame::extended(
+            0x18FEF100,
+            vec![0xFF, 0x00, 0x32, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF],
+        ),
+        Frame::extended(
+            0x0CF00400,
+            vec![0xF0, 0x7D, 0x7D, 0x90, 0x3E, 0, 0xF0, 0x7D],
+        ),
+    ]);
+    assert_eq!(j1939.pump().unwrap(), 2);
+    assert_eq!(*engine_speeds.lock().unwrap(), vec![0x80, 0x90]);
+
+    // The unmatched CCVS broadcast is left for the normal read path
+    let message = j1939.receive().unwrap();
+    assert_eq!(message.address.pgn, PGN_CCVS);
+    assert_eq!(message.data[2], 0x32);
+}
+
+#[test]
+fn test_j1939_pdu_destination() {
+    use std::collections::VecDeque;
+
+    // Proprietary A to 0x25 is PDU1; ET1 is a PDU2 broadcast
+    let pdu1 = Address {
+        priority: 6,
+        pgn: 0xEF25,
+        source: 0x00,
+        destination: 0x25,
+    };
+    assert_eq!((pdu1.pdu_format(), pdu1.pdu_specific()), (0xEF, 0x25));
+    assert!(pdu1.is_pdu1());
+    assert_eq!(pdu1.destination(), 0x25);
+
+    let pdu2 = Address {
+        pgn: 0xFEF1,
+        ..pdu1
+    };
+    assert_eq!((pdu2.pdu_format(), pdu2.pdu_specific()), (0xFE, 0xF1));
+    assert!(!pdu2.is_pdu1());
+    assert_eq!(pdu2.destination(), 0xFF);
+
+    let bus = Arc::new(Mutex::new(VecDeque::new()));
+    let rx = Arc::clone(&bus);
+    let (mut j1939, _) = create_j1939(move |_| {
+        rx.lock()
+            .unwrap()
+            .pop_front()
+            .ok_or(AutomotiveError::Timeout)
+    });
+    bus.lock().unwrap().extend([
+        Frame::extended(0x18EF2531, vec![0x01]),
+        Frame::extended(0x18FEF131, vec![0x02]),
+    ]);
+
+    let message = j1939.receive().unwrap();
+    assert_eq!(message.address.pgn, 0xEF25);
+    assert_eq!(message.address.destination, 0x25);
+
+    let message = j1939.receive().unwrap();
+    assert_eq!(message.address.pgn, PGN_CCVS);
+    assert_eq!(message.address.destination, 0xFF);
+}
+
+#[test]
+fn test_j1939_scan_network() {
+    use std::collections::VecDeque;
+
+    let bus = Arc::new(Mutex::new(VecDeque::new()));
+    let rx = Arc::clone(&bus);
+    let (mut j1939, sent_frames) = create_j1939(move |_| {
+        rx.lock()
+            .unwrap()
+            .pop_front()
+            .ok_or(AutomotiveError::Timeout)
+    });
+
+    let claim =
+        |source: u32, name: u64| Frame::extended(0x18EEFF00 | source, name.to_be_bytes().to_vec());
+    bus.lock().unwrap().extend([
+        claim(0x21, 0x1111),
+        claim(0x22, 0x2222),
+        Frame::extended(
+            0x0CF00400,
+            vec![0xF0, 0x7D, 0x7D, 0x80, 0x3E, 0, 0xF0, 0x7D],
+        ),
+        // Node 0x21 claims again with a new NAME
+        claim(0x21, 0x3333),
+    ]);
+
+    let nodes = j1939.scan_network(50).unwrap();
+    assert_eq!(nodes, vec![(0x21, 0x3333), (0x22, 0x2222)]);
+
+    // Global request for the address claimed PGN, sent from our address
+    let request = sent_frames.lock().unwrap().last().cloned().unwrap();
+    assert_eq!(request.id, 0x18EAFF80);
+    assert_eq!(request.data, vec![0x00, 0xEE, 0x00]);
+
+    // The EEC1 broadcast read during the scan is still delivered
+    assert_eq!(j1939.receive().unwrap().address.pgn, PGN_EEC1);
+}
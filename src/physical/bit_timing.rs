@@ -0,0 +1,95 @@
+use crate::error::{AutomotiveError, Result};
+
+/// Time quanta range per bit allowed by ISO 11898-1
+const MIN_TQ_PER_BIT: u32 = 8;
+const MAX_TQ_PER_BIT: u32 = 25;
+const MAX_BRP: u32 = 1024;
+const MAX_TSEG1: u32 = 16; // prop_seg + phase_seg1
+const MAX_PHASE_SEG2: u32 = 8;
+const MAX_SJW: u8 = 4;
+
+/// Maximum allowed deviation from the requested sample point
+pub const SAMPLE_POINT_TOLERANCE: f32 = 0.02;
+
+/// Bit timing register values for a CAN controller
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BitTiming {
+    pub brp: u16,       // Baud rate prescaler
+    pub prop_seg: u8,   // Propagation segment (tq)
+    pub phase_seg1: u8, // Phase segment 1 (tq)
+    pub phase_seg2: u8, // Phase segment 2 (tq)
+    pub sjw: u8,        // Synchronization jump width (tq)
+}
+
+impl BitTiming {
+    /// Total time quanta per bit, including the sync segment
+    pub fn tq_per_bit(&self) -> u32 {
+        1 + self.prop_seg as u32 + self.phase_seg1 as u32 + self.phase_seg2 as u32
+    }
+
+    /// Actual sample point as a fraction of the bit time
+    pub fn sample_point(&self) -> f32 {
+        (self.tq_per_bit() - self.phase_seg2 as u32) as f32 / self.tq_per_bit() as f32
+    }
+
+    /// Actual bitrate produced for the given controller clock
+    pub fn bitrate(&self, clock_hz: u32) -> u32 {
+        clock_hz / (self.brp as u32 * self.tq_per_bit())
+    }
+}
+
+/// Calculates bit timing for the given clock, bitrate and sample point
+///
+/// Only exact bitrates are considered. Among them, the combination closest to
+/// the requested sample point wins, preferring more time quanta per bit.
+pub fn calculate(clock_hz: u32, bitrate: u32, sample_point: f32, sjw: u8) -> Result<BitTiming> {
+    if clock_hz == 0 || bitrate == 0 || sample_point <= 0.0 || sample_point >= 1.0 {
+        return Err(AutomotiveError::InvalidParameter);
+    }
+    if sjw == 0 || sjw > MAX_SJW {
+        return Err(AutomotiveError::InvalidParameter);
+    }
+
+    let mut best: Option<(f32, BitTiming)> = None;
+
+    for tq in (MIN_TQ_PER_BIT..=MAX_TQ_PER_BIT).rev() {
+        let clocks_per_bit = tq as u64 * bitrate as u64;
+        if !(clock_hz as u64).is_multiple_of(clocks_per_bit) {
+            continue;
+        }
+        let brp = clock_hz as u64 / clocks_per_bit;
+        if brp == 0 || brp > MAX_BRP as u64 {
+            continue;
+        }
+
+        // Sample point = (sync + tseg1) / tq
+        let tseg1 = ((sample_point * tq as f32).round() as u32).saturating_sub(1);
+        let phase_seg2 = tq - 1 - tseg1;
+        if !(2..=MAX_TSEG1).contains(&tseg1)
+            || !(1..=MAX_PHASE_SEG2).contains(&phase_seg2)
+            || sjw as u32 > phase_seg2
+        {
+            continue;
+        }
+
+        let phase_seg1 = tseg1 / 2;
+        let timing = BitTiming {
+            brp: brp as u16,
+            prop_seg: (tseg1 - phase_seg1) as u8,
+            phase_seg1: phase_seg1 as u8,
+            phase_seg2: phase_seg2 as u8,
+            sjw,
+        };
+
+        let error = (timing.sample_point() - sample_point).abs();
+        if error > SAMPLE_POINT_TOLERANCE {
+            continue;
+        }
+        if best.is_none_or(|(best_error, _)| error < best_error) {
+            best = Some((error, timing));
+        }
+    }
+
+    best.map(|(_, timing)| timing)
+        .ok_or(AutomotiveError::InvalidParameter)
+}
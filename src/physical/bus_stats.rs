@@ -0,0 +1,143 @@
+use super::can;
+use crate::data_link::RxEvent;
+use crate::error::Result;
+use crate::types::{Frame, Port, Timestamp};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Clock returning the current time in milliseconds
+pub type Clock = Box<dyn Fn() -> Timestamp + Send + Sync>;
+
+/// Estimated bits on the wire for `frame`, including worst-case stuff bits
+///
+/// All bits are counted at the nominal bitrate, which overstates the load of
+/// CAN-FD frames sent with bit rate switching.
+pub fn frame_bits(frame: &Frame) -> u64 {
+    can::frame_bit_length(frame) as u64
+}
+
+fn system_clock() -> Timestamp {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as Timestamp)
+        .unwrap_or(0)
+}
+
+/// Port wrapper accumulating frame, byte and bit counters over a time window
+pub struct BusStats<P: Port> {
+    port: P,
+    clock: Clock,
+    window_start: Timestamp,
+    frames: u64,
+    bytes: u64,
+    bits: u64,
+}
+
+impl<P: Port> BusStats<P> {
+    /// Wraps `port`, timing the window with the system clock
+    pub fn new(port: P) -> Self {
+        Self::with_clock(port, Box::new(system_clock))
+    }
+
+    /// Wraps `port`, timing the window with `clock`
+    pub fn with_clock(port: P, clock: Clock) -> Self {
+        let window_start = clock();
+        Self {
+            port,
+            clock,
+            window_start,
+            frames: 0,
+            bytes: 0,
+            bits: 0,
+        }
+    }
+
+    /// Counts a frame seen on the bus
+    pub fn record(&mut self, frame: &Frame) {
+        self.frames += 1;
+        self.bytes += frame.data.len() as u64;
+        self.bits += frame_bits(frame);
+    }
+
+    /// Frames counted in the current window
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Payload bytes counted in the current window
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    /// Frames per second over the current window
+    pub fn frames_per_sec(&self) -> f64 {
+        self.per_sec(self.frames)
+    }
+
+    /// Payload bytes per second over the current window
+    pub fn bytes_per_sec(&self) -> f64 {
+        self.per_sec(self.bytes)
+    }
+
+    /// Share of the bus capacity at `bitrate` used over the current window
+    pub fn bus_load_percent(&self, bitrate: u32) -> f64 {
+        if bitrate == 0 {
+            return 0.0;
+        }
+        self.per_sec(self.bits) * 100.0 / bitrate as f64
+    }
+
+    /// Clears the counters and starts a new window
+    pub fn reset_window(&mut self) {
+        self.window_start = (self.clock)();
+        self.frames = 0;
+        self.bytes = 0;
+        self.bits = 0;
+    }
+
+    /// Returns the wrapped port
+    pub fn into_inner(self) -> P {
+        self.port
+    }
+
+    fn per_sec(&self, count: u64) -> f64 {
+        let elapsed_ms = (self.clock)().saturating_sub(self.window_start);
+        if elapsed_ms == 0 {
+            return 0.0;
+        }
+        count as f64 * 1000.0 / elapsed_ms as f64
+    }
+}
+
+impl<P: Port> Port for BusStats<P> {
+    fn send(&mut self, frame: &Frame) -> Result<()> {
+        self.port.send(frame)?;
+        self.record(frame);
+        Ok(())
+    }
+
+    fn receive(&mut self) -> Result<Frame> {
+        let frame = self.port.receive()?;
+        self.record(&frame);
+        Ok(frame)
+    }
+
+    fn receive_event(&mut self) -> Result<RxEvent> {
+        let event = self.port.receive_event()?;
+        if let RxEvent::Frame(frame) = &event {
+            self.record(frame);
+        }
+        Ok(event)
+    }
+
+    fn set_timeout(&mut self, timeout_ms: u32) -> Result<()> {
+        self.port.set_timeout(timeout_ms)
+    }
+
+    fn reconfigure(&mut self, bitrate: u32, sample_point: f32) -> Result<()> {
+        self.port.reconfigure(bitrate, sample_point)
+    }
+
+    fn peek(&mut self) -> Result<Option<Frame>> {
+        self.port.peek()
+    }
+}
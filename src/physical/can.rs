@@ -1,10 +1,14 @@
 use super::PhysicalLayer;
+use crate::data_link::RxEvent;
 use crate::error::{AutomotiveError, Result};
-use crate::types::{Config, Frame, Port};
+use crate::types::{CanId, Config, Frame, Port};
 use bitflags::bitflags;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, VecDeque};
 
 /// CAN configuration
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct CanConfig {
     pub bitrate: u32,
     pub sample_point: f32,
@@ -28,6 +32,7 @@ pub enum CanBitrate {
 
 bitflags! {
     #[derive(Debug, Clone)]
+    #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
     pub struct CanOptions: u32 {
         const NONE = 0;
         const LOOPBACK = 1;
@@ -61,33 +66,95 @@ pub struct Can<P: Port> {
     tx_queue: TxQueue,
     rx_queue: RxQueue,
     error_counters: (u8, u8), // (TEC, REC)
+    rx_overflow_count: u32,
+    tx_overflow_count: u32,
+    batch_tx: bool, // `send_frame` only queues; `flush` transmits
 }
 
 const TX_QUEUE_SIZE: usize = 32;
 const RX_QUEUE_SIZE: usize = 128;
 
+/// Fixed bits of a data frame outside the stuffed region
+/// (CRC delimiter, ACK slot and delimiter, EOF and intermission)
+pub(super) const UNSTUFFED_TAIL_BITS: u32 = 13;
+/// Stuffed header and CRC bits of a standard (11-bit) data frame
+const STUFFED_OVERHEAD_BITS_STANDARD: u32 = 34;
+/// Stuffed header and CRC bits of an extended (29-bit) data frame
+const STUFFED_OVERHEAD_BITS_EXTENDED: u32 = 54;
+
+/// Adds the worst-case stuff bits to a dynamically stuffed region of `bits` bits
+///
+/// At most one stuff bit follows every four bits after the first.
+pub(super) fn with_stuff_bits(bits: u32) -> u32 {
+    bits + bits.saturating_sub(1) / 4
+}
+
+/// Worst-case bits on the wire for a classic CAN data frame
+///
+/// Counts SOF, arbitration, control, data, CRC, ACK, EOF and intermission,
+/// plus the maximum number of stuff bits. CAN-FD frames are counted with
+/// [`crate::canfd::frame_bit_length`].
+pub fn frame_bit_length(frame: &Frame) -> u32 {
+    if frame.is_fd {
+        return super::canfd::frame_bit_length(frame);
+    }
+    let header = if frame.is_extended {
+        STUFFED_OVERHEAD_BITS_EXTENDED
+    } else {
+        STUFFED_OVERHEAD_BITS_STANDARD
+    };
+    with_stuff_bits(header + 8 * frame.data.len() as u32) + UNSTUFFED_TAIL_BITS
+}
+
 #[derive(Debug)]
 struct TxQueue {
-    frames: Vec<Frame>,
-    head: usize,
-    tail: usize,
+    frames: VecDeque<Frame>,
+    prioritized: BinaryHeap<Reverse<PendingFrame>>,
+    priority: bool, // Pop the lowest id first, like bus arbitration
+    insertions: u64,
     count: usize,
 }
 
+/// Frame waiting in a priority TX queue, ordered by `(id, insertion order)`
+#[derive(Debug)]
+struct PendingFrame {
+    key: (CanId, u64),
+    frame: Frame,
+}
+
+impl PartialEq for PendingFrame {
+    fn eq(&self, other: &Self) -> bool {
+        self.key == other.key
+    }
+}
+
+impl Eq for PendingFrame {}
+
+impl PartialOrd for PendingFrame {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for PendingFrame {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key.cmp(&other.key)
+    }
+}
+
 #[derive(Debug)]
 struct RxQueue {
-    frames: Vec<Frame>,
-    head: usize,
-    tail: usize,
+    frames: VecDeque<Frame>,
     count: usize,
 }
 
 impl TxQueue {
     fn new() -> Self {
         Self {
-            frames: Vec::with_capacity(TX_QUEUE_SIZE),
-            head: 0,
-            tail: 0,
+            frames: VecDeque::with_capacity(TX_QUEUE_SIZE),
+            prioritized: BinaryHeap::new(),
+            priority: false,
+            insertions: 0,
             count: 0,
         }
     }
@@ -96,35 +163,41 @@ impl TxQueue {
         if self.count >= TX_QUEUE_SIZE {
             return Err(AutomotiveError::BufferOverflow);
         }
-        if self.tail >= TX_QUEUE_SIZE {
-            self.tail = 0;
+        if self.priority {
+            let key = (frame.id, self.insertions);
+            self.prioritized.push(Reverse(PendingFrame { key, frame }));
+            self.insertions += 1;
+        } else {
+            self.frames.push_back(frame);
         }
-        self.frames.push(frame);
         self.count += 1;
-        self.tail += 1;
         Ok(())
     }
 
     fn pop(&mut self) -> Option<Frame> {
-        if self.count == 0 {
-            return None;
-        }
-        if self.head >= TX_QUEUE_SIZE {
-            self.head = 0;
-        }
-        let frame = self.frames.remove(self.head);
+        let frame = if self.priority {
+            self.prioritized.pop()?.0.frame
+        } else {
+            self.frames.pop_front()?
+        };
         self.count -= 1;
-        self.head += 1;
         Some(frame)
     }
+
+    /// The frame `pop` would return next
+    fn front(&self) -> Option<&Frame> {
+        if self.priority {
+            self.prioritized.peek().map(|pending| &pending.0.frame)
+        } else {
+            self.frames.front()
+        }
+    }
 }
 
 impl RxQueue {
     fn new() -> Self {
         Self {
-            frames: Vec::with_capacity(RX_QUEUE_SIZE),
-            head: 0,
-            tail: 0,
+            frames: VecDeque::with_capacity(RX_QUEUE_SIZE),
             count: 0,
         }
     }
@@ -133,25 +206,14 @@ impl RxQueue {
         if self.count >= RX_QUEUE_SIZE {
             return Err(AutomotiveError::BufferOverflow);
         }
-        if self.tail >= RX_QUEUE_SIZE {
-            self.tail = 0;
-        }
-        self.frames.push(frame);
+        self.frames.push_back(frame);
         self.count += 1;
-        self.tail += 1;
         Ok(())
     }
 
     fn pop(&mut self) -> Option<Frame> {
-        if self.count == 0 {
-            return None;
-        }
-        if self.head >= RX_QUEUE_SIZE {
-            self.head = 0;
-        }
-        let frame = self.frames.remove(self.head);
+        let frame = self.frames.pop_front()?;
         self.count -= 1;
-        self.head += 1;
         Some(frame)
     }
 }
@@ -166,6 +228,9 @@ impl<P: Port> Can<P> {
             tx_queue: TxQueue::new(),
             rx_queue: RxQueue::new(),
             error_counters: (0, 0),
+            rx_overflow_count: 0,
+            tx_overflow_count: 0,
+            batch_tx: false,
         }
     }
 
@@ -193,6 +258,71 @@ impl<P: Port> Can<P> {
         Self::with_port(config, port)
     }
 
+    /// Transmits pending frames lowest id first instead of in FIFO order
+    ///
+    /// Approximates bus arbitration; frames with equal ids keep their queue order.
+    /// Must be chosen before any frame is queued. Without [`Can::with_batched_tx`],
+    /// `send_frame` transmits each frame immediately and there is nothing to reorder.
+    pub fn with_priority_tx(mut self) -> Self {
+        self.tx_queue.priority = true;
+        self
+    }
+
+    /// Makes `send_frame` queue frames without transmitting them
+    ///
+    /// Queued frames go out on [`Can::flush`], so callers can batch writes.
+    /// A full queue rejects further frames with `BufferOverflow`.
+    pub fn with_batched_tx(mut self) -> Self {
+        self.batch_tx = true;
+        self
+    }
+
+    /// Transmits queued frames until the queue is empty or the port fails
+    ///
+    /// Returns the number of frames sent. A frame the port rejects stays at the
+    /// head of the queue; the port error is returned only if nothing was sent.
+    pub fn flush(&mut self) -> Result<usize> {
+        if !self.is_open {
+            return Err(AutomotiveError::NotInitialized);
+        }
+
+        let mut sent = 0;
+        while let Some(frame) = self.tx_queue.front() {
+            if let Err(e) = self.port.send(frame) {
+                if sent == 0 {
+                    return Err(e);
+                }
+                break;
+            }
+            self.tx_queue.pop();
+            sent += 1;
+        }
+        Ok(sent)
+    }
+
+    /// Receives the next frame or error frame indication
+    ///
+    /// Error events update the counters returned by [`Can::get_error_counters`].
+    pub fn receive_event(&mut self) -> Result<RxEvent> {
+        if !self.is_open {
+            return Err(AutomotiveError::NotInitialized);
+        }
+
+        // Check RX queue first
+        if let Some(frame) = self.rx_queue.pop() {
+            return Ok(RxEvent::Frame(frame));
+        }
+
+        match self.port.receive_event()? {
+            RxEvent::Frame(frame) if frame.is_fd => Err(AutomotiveError::InvalidParameter),
+            RxEvent::Error(error) => {
+                self.error_counters = (error.tec, error.rec);
+                Ok(RxEvent::Error(error))
+            }
+            event => Ok(event),
+        }
+    }
+
     /// Get current error counters (TEC, REC)
     pub fn get_error_counters(&self) -> (u8, u8) {
         self.error_counters
@@ -217,6 +347,43 @@ impl<P: Port> Can<P> {
     pub fn rx_space(&self) -> usize {
         RX_QUEUE_SIZE - self.rx_queue.count
     }
+
+    /// Get number of received frames dropped because the RX queue was full
+    pub fn rx_overflow_count(&self) -> u32 {
+        self.rx_overflow_count
+    }
+
+    /// Get number of frames dropped because the TX queue was full
+    pub fn tx_overflow_count(&self) -> u32 {
+        self.tx_overflow_count
+    }
+
+    /// Reset the RX and TX overflow counters
+    pub fn reset_overflow_counts(&mut self) {
+        self.rx_overflow_count = 0;
+        self.tx_overflow_count = 0;
+    }
+
+    /// Queue a frame received by the controller (e.g. from an RX interrupt)
+    pub fn push_rx_frame(&mut self, frame: Frame) -> Result<()> {
+        let result = self.rx_queue.push(frame);
+        if let Err(AutomotiveError::BufferOverflow) = result {
+            self.rx_overflow_count = self.rx_overflow_count.saturating_add(1);
+        }
+        result
+    }
+
+    /// Queue a frame for transmission without sending it
+    #[cfg(test)]
+    pub(crate) fn push_tx_frame(&mut self, frame: Frame) -> Result<()> {
+        self.tx_queue.push(frame)
+    }
+
+    /// Take the next frame the controller would transmit
+    #[cfg(test)]
+    pub(crate) fn pop_tx_frame(&mut self) -> Option<Frame> {
+        self.tx_queue.pop()
+    }
 }
 
 impl<P: Port> PhysicalLayer for Can<P> {
@@ -246,12 +413,25 @@ impl<P: Port> PhysicalLayer for Can<P> {
             return Err(AutomotiveError::NotInitialized);
         }
 
+        if !frame.id_is_valid() {
+            return Err(AutomotiveError::InvalidParameter);
+        }
+
         if frame.is_fd {
             return Err(AutomotiveError::InvalidParameter);
         }
 
         // Queue frame for transmission
-        self.tx_queue.push(frame.clone())?;
+        if let Err(e) = self.tx_queue.push(frame.clone()) {
+            if let AutomotiveError::BufferOverflow = e {
+                self.tx_overflow_count = self.tx_overflow_count.saturating_add(1);
+            }
+            return Err(e);
+        }
+
+        if self.batch_tx {
+            return Ok(());
+        }
 
         // Try to send frame via port
         if let Some(frame) = self.tx_queue.pop() {
@@ -261,30 +441,31 @@ impl<P: Port> PhysicalLayer for Can<P> {
         Ok(())
     }
 
+    /// Receives the next data frame, skipping error frame indications
     fn receive_frame(&mut self) -> Result<Frame> {
-        if !self.is_open {
-            return Err(AutomotiveError::NotInitialized);
-        }
-
-        // Check RX queue first
-        if let Some(frame) = self.rx_queue.pop() {
-            return Ok(frame);
+        loop {
+            if let RxEvent::Frame(frame) = self.receive_event()? {
+                return Ok(frame);
+            }
         }
+    }
 
-        // Try to receive from port
-        let frame = self.port.receive()?;
-        if frame.is_fd {
-            return Err(AutomotiveError::InvalidParameter);
+    fn set_timeout(&mut self, timeout_ms: u32) -> Result<()> {
+        if !self.is_open {
+            return Err(AutomotiveError::NotInitialized);
         }
 
-        Ok(frame)
+        self.port.set_timeout(timeout_ms)
     }
 
-    fn set_timeout(&mut self, timeout_ms: u32) -> Result<()> {
+    fn peek_frame(&mut self) -> Result<Option<Frame>> {
         if !self.is_open {
             return Err(AutomotiveError::NotInitialized);
         }
 
-        self.port.set_timeout(timeout_ms)
+        if let Some(frame) = self.rx_queue.frames.front() {
+            return Ok(Some(frame.clone()));
+        }
+        self.port.peek()
     }
 }
@@ -2,6 +2,7 @@ use super::PhysicalLayer;
 use crate::error::{AutomotiveError, Result};
 use crate::types::{Config, Frame, Port};
 use bitflags::bitflags;
+use std::collections::VecDeque;
 use std::sync::Arc;
 
 /// CANFD configuration
@@ -14,10 +15,14 @@ pub struct CanFdConfig {
     pub nominal_sjw: u8,
     pub data_sjw: u8,
     pub options: CanFdOptions,
+    pub tx_padding: u8, // Fill byte used when PAD_TX is set
 }
 
 bitflags! {
     /// CANFD controller options
+    ///
+    /// Without `FD_ONLY`, classic frames are sent and received alongside FD
+    /// frames, as on most FD-capable controllers.
     #[derive(Debug, Clone)]
     pub struct CanFdOptions: u16 {
         const NONE = 0;
@@ -29,6 +34,8 @@ bitflags! {
         const REJECT_OVERFLOW = 1 << 5;
         const ISO_MODE = 1 << 6;  // ISO CAN FD mode (vs non-ISO)
         const BRS_ENABLE = 1 << 7; // Enable bit rate switching
+        const PAD_TX = 1 << 8;     // Pad FD frame data up to the next valid DLC length
+        const FD_ONLY = 1 << 9;    // Reject classic (non-FD) frames
     }
 }
 
@@ -50,27 +57,83 @@ const TX_QUEUE_SIZE: usize = 32;
 const RX_QUEUE_SIZE: usize = 128;
 const TX_EVENT_QUEUE_SIZE: usize = 32;
 
+/// Data length of each CAN-FD DLC value
+const DLC_LENGTHS: [u8; 16] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 12, 16, 20, 24, 32, 48, 64];
+
+/// Returns the data length encoded by `dlc`; only its low four bits are used
+pub fn dlc_to_len(dlc: u8) -> u8 {
+    DLC_LENGTHS[(dlc & 0x0F) as usize]
+}
+
+/// Returns the DLC encoding exactly `len` bytes, or `None` if `len` needs padding
+pub fn len_to_dlc(len: u8) -> Option<u8> {
+    DLC_LENGTHS
+        .iter()
+        .position(|&length| length == len)
+        .map(|dlc| dlc as u8)
+}
+
+/// Returns the smallest valid CAN-FD data length of at least `len` bytes
+///
+/// Lengths above 64 cannot be encoded and are returned unchanged.
+pub fn next_valid_len(len: u8) -> u8 {
+    DLC_LENGTHS
+        .iter()
+        .copied()
+        .find(|&length| length >= len)
+        .unwrap_or(len)
+}
+
+/// Dynamically stuffed header bits of a standard (11-bit) CAN-FD frame
+/// (SOF, identifier, RRS, IDE, FDF, res, BRS, ESI and DLC)
+const HEADER_BITS_STANDARD: u32 = 22;
+/// Dynamically stuffed header bits of an extended (29-bit) CAN-FD frame
+const HEADER_BITS_EXTENDED: u32 = 41;
+/// Stuff count and CRC-17 with their fixed stuff bits, for up to 16 data bytes
+const CRC_FIELD_BITS_17: u32 = 4 + 17 + 6;
+/// Stuff count and CRC-21 with their fixed stuff bits, for more than 16 data bytes
+const CRC_FIELD_BITS_21: u32 = 4 + 21 + 7;
+
+/// Worst-case bits on the wire for a CAN-FD data frame
+///
+/// Header and data are dynamically stuffed; the CRC field uses fixed stuff
+/// bits instead. With bit rate switching the bits from BRS to the CRC
+/// delimiter are sent at the data bitrate. Classic frames are counted with
+/// [`crate::can::frame_bit_length`].
+pub fn frame_bit_length(frame: &Frame) -> u32 {
+    if !frame.is_fd {
+        return super::can::frame_bit_length(frame);
+    }
+    let header = if frame.is_extended {
+        HEADER_BITS_EXTENDED
+    } else {
+        HEADER_BITS_STANDARD
+    };
+    let crc_field = if frame.data.len() > 16 {
+        CRC_FIELD_BITS_21
+    } else {
+        CRC_FIELD_BITS_17
+    };
+    super::can::with_stuff_bits(header + 8 * frame.data.len() as u32)
+        + crc_field
+        + super::can::UNSTUFFED_TAIL_BITS
+}
+
 #[derive(Debug)]
 struct TxQueue {
-    frames: Vec<Frame>,
-    head: usize,
-    tail: usize,
+    frames: VecDeque<Frame>,
     count: usize,
 }
 
 #[derive(Debug)]
 struct RxQueue {
-    frames: Vec<Frame>,
-    head: usize,
-    tail: usize,
+    frames: VecDeque<Frame>,
     count: usize,
 }
 
 #[derive(Debug)]
 struct TxEventQueue {
-    events: Vec<TxEvent>,
-    head: usize,
-    tail: usize,
+    events: VecDeque<TxEvent>,
     count: usize,
 }
 
@@ -109,15 +172,15 @@ pub struct CanFd<P: Port> {
     rx_queue: RxQueue,
     tx_events: TxEventQueue,
     error_counters: (u8, u8), // (TEC, REC)
+    rx_overflow_count: u32,
+    tx_overflow_count: u32,
     sequence: u32,
 }
 
 impl TxQueue {
     fn new() -> Self {
         Self {
-            frames: Vec::with_capacity(TX_QUEUE_SIZE),
-            head: 0,
-            tail: 0,
+            frames: VecDeque::with_capacity(TX_QUEUE_SIZE),
             count: 0,
         }
     }
@@ -126,25 +189,14 @@ impl TxQueue {
         if self.count >= TX_QUEUE_SIZE {
             return Err(AutomotiveError::BufferOverflow);
         }
-        if self.tail >= TX_QUEUE_SIZE {
-            self.tail = 0;
-        }
-        self.frames.push(frame);
+        self.frames.push_back(frame);
         self.count += 1;
-        self.tail += 1;
         Ok(())
     }
 
     fn pop(&mut self) -> Option<Frame> {
-        if self.count == 0 {
-            return None;
-        }
-        if self.head >= TX_QUEUE_SIZE {
-            self.head = 0;
-        }
-        let frame = self.frames.remove(self.head);
+        let frame = self.frames.pop_front()?;
         self.count -= 1;
-        self.head += 1;
         Some(frame)
     }
 }
@@ -152,9 +204,7 @@ impl TxQueue {
 impl RxQueue {
     fn new() -> Self {
         Self {
-            frames: Vec::with_capacity(RX_QUEUE_SIZE),
-            head: 0,
-            tail: 0,
+            frames: VecDeque::with_capacity(RX_QUEUE_SIZE),
             count: 0,
         }
     }
@@ -163,25 +213,14 @@ impl RxQueue {
         if self.count >= RX_QUEUE_SIZE {
             return Err(AutomotiveError::BufferOverflow);
         }
-        if self.tail >= RX_QUEUE_SIZE {
-            self.tail = 0;
-        }
-        self.frames.push(frame);
+        self.frames.push_back(frame);
         self.count += 1;
-        self.tail += 1;
         Ok(())
     }
 
     fn pop(&mut self) -> Option<Frame> {
-        if self.count == 0 {
-            return None;
-        }
-        if self.head >= RX_QUEUE_SIZE {
-            self.head = 0;
-        }
-        let frame = self.frames.remove(self.head);
+        let frame = self.frames.pop_front()?;
         self.count -= 1;
-        self.head += 1;
         Some(frame)
     }
 }
@@ -189,9 +228,7 @@ impl RxQueue {
 impl TxEventQueue {
     fn new() -> Self {
         Self {
-            events: Vec::with_capacity(TX_EVENT_QUEUE_SIZE),
-            head: 0,
-            tail: 0,
+            events: VecDeque::with_capacity(TX_EVENT_QUEUE_SIZE),
             count: 0,
         }
     }
@@ -200,25 +237,14 @@ impl TxEventQueue {
         if self.count >= TX_EVENT_QUEUE_SIZE {
             return Err(AutomotiveError::BufferOverflow);
         }
-        if self.tail >= TX_EVENT_QUEUE_SIZE {
-            self.tail = 0;
-        }
-        self.events.push(event);
+        self.events.push_back(event);
         self.count += 1;
-        self.tail += 1;
         Ok(())
     }
 
     fn pop(&mut self) -> Option<TxEvent> {
-        if self.count == 0 {
-            return None;
-        }
-        if self.head >= TX_EVENT_QUEUE_SIZE {
-            self.head = 0;
-        }
-        let event = self.events.remove(self.head);
+        let event = self.events.pop_front()?;
         self.count -= 1;
-        self.head += 1;
         Some(event)
     }
 }
@@ -234,6 +260,8 @@ impl<P: Port> CanFd<P> {
             rx_queue: RxQueue::new(),
             tx_events: TxEventQueue::new(),
             error_counters: (0, 0),
+            rx_overflow_count: 0,
+            tx_overflow_count: 0,
             sequence: 0,
         }
     }
@@ -274,11 +302,43 @@ impl<P: Port> CanFd<P> {
             nominal_sjw,
             data_sjw,
             options,
+            tx_padding: 0x00,
         };
 
         Self::with_port(config, port)
     }
 
+    /// Sets the fill byte used when `CanFdOptions::PAD_TX` is enabled
+    pub fn with_tx_padding(mut self, padding: u8) -> Self {
+        self.config.tx_padding = padding;
+        self
+    }
+
+    /// Current controller configuration
+    pub fn config(&self) -> &CanFdConfig {
+        &self.config
+    }
+
+    /// Changes the data-phase bitrate at runtime
+    ///
+    /// Fails with `ConditionsNotCorrect` while frames are still waiting in the TX queue.
+    pub fn set_data_bitrate(&mut self, bitrate: u32, sample_point: f32) -> Result<()> {
+        if self.tx_queue.count > 0 {
+            return Err(AutomotiveError::ConditionsNotCorrect);
+        }
+
+        let mut config = self.config.clone();
+        config.data_bitrate = bitrate;
+        config.data_sample_point = sample_point;
+        config.validate()?;
+
+        if self.is_open {
+            self.port.reconfigure(bitrate, sample_point)?;
+        }
+        self.config = config;
+        Ok(())
+    }
+
     /// Get current error counters (TEC, REC)
     pub fn get_error_counters(&self) -> (u8, u8) {
         self.error_counters
@@ -304,6 +364,37 @@ impl<P: Port> CanFd<P> {
         RX_QUEUE_SIZE - self.rx_queue.count
     }
 
+    /// Get number of received frames dropped because the RX queue was full
+    pub fn rx_overflow_count(&self) -> u32 {
+        self.rx_overflow_count
+    }
+
+    /// Get number of frames dropped because the TX queue was full
+    pub fn tx_overflow_count(&self) -> u32 {
+        self.tx_overflow_count
+    }
+
+    /// Reset the RX and TX overflow counters
+    pub fn reset_overflow_counts(&mut self) {
+        self.rx_overflow_count = 0;
+        self.tx_overflow_count = 0;
+    }
+
+    /// Queue a frame received by the controller (e.g. from an RX interrupt)
+    pub fn push_rx_frame(&mut self, frame: Frame) -> Result<()> {
+        let result = self.rx_queue.push(frame);
+        if let Err(AutomotiveError::BufferOverflow) = result {
+            self.rx_overflow_count = self.rx_overflow_count.saturating_add(1);
+        }
+        result
+    }
+
+    /// Queue a frame for transmission without sending it
+    #[cfg(test)]
+    pub(crate) fn push_tx_frame(&mut self, frame: Frame) -> Result<()> {
+        self.tx_queue.push(frame)
+    }
+
     /// Get number of events pending in TX event queue
     pub fn tx_events_pending(&self) -> usize {
         self.tx_events.count
@@ -337,11 +428,33 @@ impl<P: Port> PhysicalLayer for CanFd<P> {
             return Err(AutomotiveError::NotInitialized);
         }
 
+        if !frame.id_is_valid() {
+            return Err(AutomotiveError::InvalidParameter);
+        }
+
+        if !frame.is_fd && self.config.options.contains(CanFdOptions::FD_ONLY) {
+            return Err(AutomotiveError::InvalidParameter);
+        }
+
         // Queue frame for transmission
-        self.tx_queue.push(frame.clone())?;
+        if let Err(e) = self.tx_queue.push(frame.clone()) {
+            if let AutomotiveError::BufferOverflow = e {
+                self.tx_overflow_count = self.tx_overflow_count.saturating_add(1);
+            }
+            return Err(e);
+        }
 
         // Try to send frame via port
-        if let Some(frame) = self.tx_queue.pop() {
+        if let Some(mut frame) = self.tx_queue.pop() {
+            if frame.is_fd
+                && self.config.options.contains(CanFdOptions::PAD_TX)
+                && frame.data.len() <= 64
+            {
+                // Short payloads are padded to a full classic frame as well
+                let length = next_valid_len(frame.data.len().max(8) as u8) as usize;
+                frame.data.resize(length, self.config.tx_padding);
+            }
+
             // Record transmission event if enabled
             if self.config.options.contains(CanFdOptions::RECORD_TX_EVENTS) {
                 let event = TxEvent {
@@ -377,6 +490,10 @@ impl<P: Port> PhysicalLayer for CanFd<P> {
             return Err(AutomotiveError::InvalidParameter);
         }
 
+        if !frame.is_fd && self.config.options.contains(CanFdOptions::FD_ONLY) {
+            return Err(AutomotiveError::InvalidParameter);
+        }
+
         Ok(frame)
     }
 
@@ -387,4 +504,15 @@ impl<P: Port> PhysicalLayer for CanFd<P> {
 
         self.port.set_timeout(timeout_ms)
     }
+
+    fn peek_frame(&mut self) -> Result<Option<Frame>> {
+        if !self.is_open {
+            return Err(AutomotiveError::NotInitialized);
+        }
+
+        if let Some(frame) = self.rx_queue.frames.front() {
+            return Ok(Some(frame.clone()));
+        }
+        self.port.peek()
+    }
 }
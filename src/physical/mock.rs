@@ -1,6 +1,7 @@
 use super::PhysicalLayer;
 use crate::error::{AutomotiveError, Result};
 use crate::types::{Config, Frame};
+use std::collections::VecDeque;
 use std::sync::Arc;
 use std::sync::Mutex;
 
@@ -24,6 +25,9 @@ pub struct MockPhysical {
     frame_handler: Option<MockFrameHandler>,
     is_open: bool,
     last_frame: Arc<Mutex<Option<Frame>>>,
+    sent_frames: Arc<Mutex<Vec<Frame>>>,
+    script: VecDeque<Frame>,
+    send_delay_ms: u32, // Simulated transmission time of each frame
 }
 
 impl MockPhysical {
@@ -34,9 +38,34 @@ impl MockPhysical {
             frame_handler,
             is_open: false,
             last_frame: Arc::new(Mutex::new(None)),
+            sent_frames: Arc::new(Mutex::new(Vec::new())),
+            script: VecDeque::new(),
+            send_delay_ms: 0,
         }
     }
 
+    /// Makes every `send_frame` take `delay_ms` to transmit
+    pub fn with_send_delay(mut self, delay_ms: u32) -> Self {
+        self.send_delay_ms = delay_ms;
+        self
+    }
+
+    /// Creates a new mock physical layer that returns the given frames in order,
+    /// independent of what was sent
+    ///
+    /// Once the script is drained, `receive_frame` fails with `Timeout` like a
+    /// silent bus, so an empty script models an ECU that never answers.
+    pub fn with_script(frames: Vec<Frame>) -> Self {
+        let mut mock = Self::new(None);
+        mock.script = frames.into();
+        mock
+    }
+
+    /// Queues a frame to be returned by a subsequent `receive_frame`
+    pub fn push_response(&mut self, frame: Frame) {
+        self.script.push_back(frame);
+    }
+
     /// Creates a new mock physical layer with an echo handler
     pub fn new_echo() -> Self {
         Self::new(Some(Box::new(|frame: &Frame| Ok(frame.clone()))))
@@ -49,6 +78,11 @@ impl MockPhysical {
         })))
     }
 
+    /// Returns a shared handle to the log of every frame sent through this mock
+    pub fn sent_frames(&self) -> Arc<Mutex<Vec<Frame>>> {
+        self.sent_frames.clone()
+    }
+
     /// Sets a new frame handler
     pub fn set_frame_handler(&mut self, handler: Option<MockFrameHandler>) {
         self.frame_handler = handler;
@@ -64,6 +98,9 @@ impl PhysicalLayer for MockPhysical {
             frame_handler: None,
             is_open: false,
             last_frame: Arc::new(Mutex::new(None)),
+            sent_frames: Arc::new(Mutex::new(Vec::new())),
+            script: VecDeque::new(),
+            send_delay_ms: 0,
         })
     }
 
@@ -82,10 +119,22 @@ impl PhysicalLayer for MockPhysical {
             return Err(AutomotiveError::NotInitialized);
         }
 
+        if self.send_delay_ms > 0 {
+            std::thread::sleep(std::time::Duration::from_millis(self.send_delay_ms as u64));
+
+            // A frame slower than the configured timeout was never transmitted
+            if self.config.timeout_ms > 0 && self.send_delay_ms > self.config.timeout_ms {
+                return Err(AutomotiveError::Timeout);
+            }
+        }
+
         // Store the frame for receive_frame to use
         if let Ok(mut last_frame) = self.last_frame.lock() {
             *last_frame = Some(frame.clone());
         }
+        if let Ok(mut sent_frames) = self.sent_frames.lock() {
+            sent_frames.push(frame.clone());
+        }
         Ok(())
     }
 
@@ -94,11 +143,24 @@ impl PhysicalLayer for MockPhysical {
             return Err(AutomotiveError::NotInitialized);
         }
 
+        // Scripted frames take precedence over the handler
+        if let Some(frame) = self.script.pop_front() {
+            return Ok(frame);
+        }
+
         if let Some(handler) = &self.frame_handler {
             let last_frame = self.last_frame.lock().unwrap();
             if let Some(frame) = last_frame.as_ref() {
                 // Create a response frame based on the last sent frame
+                let start = std::time::Instant::now();
                 let response = handler(frame)?;
+
+                // A response slower than the configured timeout never arrived
+                if self.config.timeout_ms > 0
+                    && start.elapsed().as_millis() > self.config.timeout_ms as u128
+                {
+                    return Err(AutomotiveError::Timeout);
+                }
                 return Ok(response);
             }
 
@@ -109,10 +171,12 @@ impl PhysicalLayer for MockPhysical {
                 timestamp: 0,
                 is_extended: false,
                 is_fd: false,
+                brs: false,
             };
             return handler(&default_frame);
         }
-        Err(AutomotiveError::NotInitialized)
+        // Nothing scripted and no handler: behave like a silent bus
+        Err(AutomotiveError::Timeout)
     }
 
     fn set_timeout(&mut self, timeout_ms: u32) -> Result<()> {
@@ -122,4 +186,23 @@ impl PhysicalLayer for MockPhysical {
         self.config.timeout_ms = timeout_ms;
         Ok(())
     }
+
+    fn peek_frame(&mut self) -> Result<Option<Frame>> {
+        if !self.is_open {
+            return Err(AutomotiveError::NotInitialized);
+        }
+
+        if let Some(frame) = self.script.front() {
+            return Ok(Some(frame.clone()));
+        }
+
+        // The handler answers the last sent frame the same way on every receive
+        match (
+            &self.frame_handler,
+            self.last_frame.lock().unwrap().as_ref(),
+        ) {
+            (Some(handler), Some(frame)) => handler(frame).map(Some),
+            _ => Ok(None),
+        }
+    }
 }
@@ -17,13 +17,7 @@ impl MockLinPhysical {
 
     pub fn new_echo() -> Self {
         MockLinPhysical::new(Some(Box::new(|frame: &Frame| {
-            Ok(Frame {
-                id: frame.id,
-                data: frame.data.clone(),
-                timestamp: 0,
-                is_extended: false,
-                is_fd: false,
-            })
+            Ok(Frame::standard(frame.id, frame.data.clone()))
         })))
     }
 
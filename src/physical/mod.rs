@@ -32,12 +32,17 @@
 //! canfd.open();
 //! ```
 
+pub mod bit_timing;
+pub mod bus_stats;
 pub mod can;
 pub mod canfd;
 
 #[cfg(any(test, feature = "mock"))]
 pub mod mock;
 
+#[cfg(test)]
+mod tests;
+
 use crate::error::{AutomotiveError, Result};
 use crate::types::{Config, Frame};
 
@@ -53,4 +58,9 @@ pub trait PhysicalLayer: Send + Sync {
     fn send_frame(&mut self, frame: &Frame) -> Result<()>;
     fn receive_frame(&mut self) -> Result<Frame>;
     fn set_timeout(&mut self, timeout_ms: u32) -> Result<()>;
+
+    /// Returns the next frame without consuming it; `Ok(None)` if none or unsupported
+    fn peek_frame(&mut self) -> Result<Option<Frame>> {
+        Ok(None)
+    }
 }
@@ -0,0 +1,538 @@
+use super::*;
+use crate::bit_timing::{self, BitTiming};
+use crate::bus_stats::{self, BusStats};
+use crate::can::{self, Can, CanBitrate, CanOptions};
+use crate::canfd::{self, CanFd, CanFdBitrate, CanFdOptions};
+use crate::data_link::{CanError, CanErrorType, ErrorLocation, RxEvent};
+use crate::types::Port;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// In-memory port recording sent frames and returning queued ones
+#[derive(Default)]
+struct TestPort {
+    sent: Arc<Mutex<Vec<Frame>>>,
+    received: VecDeque<Frame>,
+    capacity: Option<usize>, // Frames accepted before sends fail with `SendFailed`
+}
+
+impl Port for TestPort {
+    fn send(&mut self, frame: &Frame) -> Result<()> {
+        let mut sent = self.sent.lock().unwrap();
+        if self.capacity.is_some_and(|capacity| sent.len() >= capacity) {
+            return Err(AutomotiveError::SendFailed);
+        }
+        sent.push(frame.clone());
+        Ok(())
+    }
+
+    fn receive(&mut self) -> Result<Frame> {
+        self.received.pop_front().ok_or(AutomotiveError::Timeout)
+    }
+
+    /// Decodes queued SocketCAN error frames like a controller reporting bus errors
+    fn receive_event(&mut self) -> Result<RxEvent> {
+        let frame = self.receive()?;
+        Ok(match CanError::from_socketcan(&frame) {
+            Some(error) => RxEvent::Error(error),
+            None => RxEvent::Frame(frame),
+        })
+    }
+
+    fn set_timeout(&mut self, _timeout_ms: u32) -> Result<()> {
+        Ok(())
+    }
+}
+
+fn test_frame(id: u32) -> Frame {
+    Frame::standard(id, vec![0x01, 0x02])
+}
+
+#[test]
+fn test_can_receive_error_event() -> Result<()> {
+    // Protocol error class: stuff error while transmitting, in the data field, TEC 96
+    let stuff_error = Frame::standard(0x2000_0008, vec![0x00, 0x00, 0x84, 0x0A, 0x00, 0x00, 96, 0]);
+    // Acknowledgment error class
+    let ack_error = Frame::standard(0x2000_0020, vec![0x00, 0x00, 0x00, 0x19, 0, 0, 104, 0]);
+    let port = TestPort {
+        received: VecDeque::from([stuff_error, ack_error, test_frame(0x123)]),
+        ..Default::default()
+    };
+    let mut can = Can::with_bitrate(port, CanBitrate::Rate500K, CanOptions::NONE);
+    can.open()?;
+
+    match can.receive_event()? {
+        RxEvent::Error(error) => {
+            assert_eq!(error.error_type, CanErrorType::Stuff);
+            assert!(error.is_tx);
+            assert_eq!(error.location, ErrorLocation::Data);
+            assert_eq!((error.tec, error.rec), (96, 0));
+        }
+        event => panic!("Expected an error event, got {:?}", event),
+    }
+    assert_eq!(can.get_error_counters(), (96, 0));
+
+    // receive_frame only returns data frames
+    assert_eq!(can.receive_frame()?, test_frame(0x123));
+    assert_eq!(can.get_error_counters(), (104, 0));
+
+    Ok(())
+}
+
+#[test]
+fn test_can_rx_overflow_count() -> Result<()> {
+    let mut can = Can::with_bitrate(TestPort::default(), CanBitrate::Rate500K, CanOptions::NONE);
+    can.open()?;
+
+    let capacity = can.rx_space();
+    for i in 0..capacity {
+        can.push_rx_frame(test_frame(i as u32))?;
+    }
+    assert_eq!(can.rx_overflow_count(), 0);
+
+    // Every frame beyond the queue capacity is dropped and counted
+    for i in 0..3 {
+        assert!(matches!(
+            can.push_rx_frame(test_frame(0x100 + i)),
+            Err(AutomotiveError::BufferOverflow)
+        ));
+    }
+    assert_eq!(can.rx_overflow_count(), 3);
+    assert_eq!(can.tx_overflow_count(), 0);
+
+    can.reset_overflow_counts();
+    assert_eq!(can.rx_overflow_count(), 0);
+
+    Ok(())
+}
+
+#[test]
+fn test_can_priority_tx_queue() -> Result<()> {
+    let mut can = Can::with_bitrate(TestPort::default(), CanBitrate::Rate500K, CanOptions::NONE)
+        .with_priority_tx();
+    can.open()?;
+
+    let queued = [
+        Frame::standard(0x300, vec![0x01]),
+        Frame::standard(0x100, vec![0x01]),
+        Frame::standard(0x200, vec![0x01]),
+        Frame::standard(0x100, vec![0x02]),
+        Frame::standard(0x050, vec![0x01]),
+    ];
+    for frame in &queued {
+        can.push_tx_frame(frame.clone())?;
+    }
+    assert_eq!(can.tx_pending(), queued.len());
+
+    // Lowest id first; equal ids keep their queue order
+    let popped: Vec<(u32, u8)> = std::iter::from_fn(|| can.pop_tx_frame())
+        .map(|frame| (frame.id, frame.data[0]))
+        .collect();
+    assert_eq!(
+        popped,
+        vec![(0x050, 1), (0x100, 1), (0x100, 2), (0x200, 1), (0x300, 1)]
+    );
+    assert_eq!(can.tx_pending(), 0);
+
+    // Frames batched through send_frame go out in the same order on flush
+    let port = TestPort::default();
+    let sent = Arc::clone(&port.sent);
+    let mut batched = Can::with_bitrate(port, CanBitrate::Rate500K, CanOptions::NONE)
+        .with_priority_tx()
+        .with_batched_tx();
+    batched.open()?;
+    for frame in &queued {
+        batched.send_frame(frame)?;
+    }
+    assert_eq!(batched.flush()?, queued.len());
+    let ids: Vec<u32> = sent.lock().unwrap().iter().map(|frame| frame.id).collect();
+    assert_eq!(ids, vec![0x050, 0x100, 0x100, 0x200, 0x300]);
+
+    // The default queue stays FIFO
+    let mut fifo = Can::with_bitrate(TestPort::default(), CanBitrate::Rate500K, CanOptions::NONE);
+    fifo.push_tx_frame(test_frame(0x300))?;
+    fifo.push_tx_frame(test_frame(0x100))?;
+    assert_eq!(fifo.pop_tx_frame().map(|frame| frame.id), Some(0x300));
+
+    Ok(())
+}
+
+#[test]
+fn test_can_batched_tx_flush() -> Result<()> {
+    let port = TestPort {
+        capacity: Some(3),
+        ..Default::default()
+    };
+    let sent = Arc::clone(&port.sent);
+    let mut can = Can::with_bitrate(port, CanBitrate::Rate500K, CanOptions::NONE).with_batched_tx();
+    can.open()?;
+
+    for id in 0x100..0x105 {
+        can.send_frame(&test_frame(id))?;
+    }
+    assert_eq!(can.tx_pending(), 5);
+    assert!(sent.lock().unwrap().is_empty());
+
+    // The port takes three frames; the rest stay queued in order
+    assert_eq!(can.flush()?, 3);
+    assert_eq!(can.tx_pending(), 2);
+    assert!(matches!(can.flush(), Err(AutomotiveError::SendFailed)));
+    assert_eq!(can.tx_pending(), 2);
+
+    sent.lock().unwrap().clear();
+    assert_eq!(can.flush()?, 2);
+    assert_eq!(can.tx_pending(), 0);
+    let ids: Vec<u32> = sent.lock().unwrap().iter().map(|frame| frame.id).collect();
+    assert_eq!(ids, vec![0x103, 0x104]);
+
+    Ok(())
+}
+
+#[test]
+fn test_canfd_rx_overflow_count() -> Result<()> {
+    let mut canfd = CanFd::with_bitrate(
+        TestPort::default(),
+        CanFdBitrate::Rate500k2m,
+        CanFdOptions::NONE,
+    );
+    canfd.open()?;
+
+    let capacity = canfd.rx_space();
+    for i in 0..capacity + 2 {
+        let _ = canfd.push_rx_frame(test_frame(i as u32));
+    }
+    assert_eq!(canfd.rx_overflow_count(), 2);
+
+    canfd.reset_overflow_counts();
+    assert_eq!(canfd.rx_overflow_count(), 0);
+
+    Ok(())
+}
+
+#[test]
+fn test_can_id_range_validation() -> Result<()> {
+    let mut can = Can::with_bitrate(TestPort::default(), CanBitrate::Rate500K, CanOptions::NONE);
+    can.open()?;
+
+    can.send_frame(&test_frame(0x7FF))?;
+    assert!(matches!(
+        can.send_frame(&test_frame(0x800)),
+        Err(AutomotiveError::InvalidParameter)
+    ));
+
+    // Highest J1939 id: priority 7, PGN 0x3FFFF, source address 0xFF
+    let mut extended = test_frame(Frame::MAX_EXTENDED_ID);
+    extended.is_extended = true;
+    can.send_frame(&extended)?;
+    extended.id = 0x2000_0000;
+    assert!(matches!(
+        can.send_frame(&extended),
+        Err(AutomotiveError::InvalidParameter)
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_canfd_id_range_validation() -> Result<()> {
+    let mut canfd = CanFd::with_bitrate(
+        TestPort::default(),
+        CanFdBitrate::Rate500k2m,
+        CanFdOptions::NONE,
+    );
+    canfd.open()?;
+
+    canfd.send_frame(&test_frame(0x7FF))?;
+    assert!(canfd.send_frame(&test_frame(0x800)).is_err());
+
+    let mut extended = test_frame(0x800);
+    extended.is_extended = true;
+    canfd.send_frame(&extended)?;
+    extended.id = 0x2000_0000;
+    assert!(!extended.id_is_valid());
+    assert!(canfd.send_frame(&extended).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_canfd_tx_padding() -> Result<()> {
+    let port = TestPort::default();
+    let sent = Arc::clone(&port.sent);
+    let mut canfd = CanFd::with_bitrate(port, CanFdBitrate::Rate500k2m, CanFdOptions::PAD_TX)
+        .with_tx_padding(0xCC);
+    canfd.open()?;
+
+    let mut frame = test_frame(0x123);
+    frame.data = vec![0x01, 0x02, 0x03, 0x04, 0x05];
+    frame.is_fd = true;
+    canfd.send_frame(&frame)?;
+
+    // Classic frames are never padded
+    canfd.send_frame(&test_frame(0x124))?;
+
+    let sent = sent.lock().unwrap();
+    assert_eq!(
+        sent[0].data,
+        vec![0x01, 0x02, 0x03, 0x04, 0x05, 0xCC, 0xCC, 0xCC]
+    );
+    assert_eq!(sent[1].data, vec![0x01, 0x02]);
+
+    Ok(())
+}
+
+#[test]
+fn test_canfd_classic_frames_pass_through() -> Result<()> {
+    let mut port = TestPort::default();
+    port.received.push_back(test_frame(0x200));
+    let sent = Arc::clone(&port.sent);
+    let mut canfd = CanFd::with_bitrate(port, CanFdBitrate::Rate500k2m, CanFdOptions::NONE);
+    canfd.open()?;
+
+    canfd.send_frame(&test_frame(0x100))?;
+    canfd.send_frame(&Frame::fd(0x101, vec![0; 12], true))?;
+    assert_eq!(sent.lock().unwrap().len(), 2);
+    assert_eq!(canfd.receive_frame()?, test_frame(0x200));
+
+    Ok(())
+}
+
+#[test]
+fn test_canfd_fd_only_rejects_classic_frames() -> Result<()> {
+    let mut port = TestPort::default();
+    port.received.push_back(test_frame(0x200));
+    port.received
+        .push_back(Frame::fd(0x201, vec![0; 16], false));
+    let sent = Arc::clone(&port.sent);
+    let mut canfd = CanFd::with_bitrate(port, CanFdBitrate::Rate500k2m, CanFdOptions::FD_ONLY);
+    canfd.open()?;
+
+    assert!(matches!(
+        canfd.send_frame(&test_frame(0x100)),
+        Err(AutomotiveError::InvalidParameter)
+    ));
+    canfd.send_frame(&Frame::fd(0x101, vec![0; 12], true))?;
+    assert_eq!(sent.lock().unwrap().len(), 1);
+
+    assert!(matches!(
+        canfd.receive_frame(),
+        Err(AutomotiveError::InvalidParameter)
+    ));
+    assert_eq!(canfd.receive_frame()?, Frame::fd(0x201, vec![0; 16], false));
+
+    Ok(())
+}
+
+#[test]
+fn test_canfd_dlc_mapping() {
+    let lengths = [0, 1, 2, 3, 4, 5, 6, 7, 8, 12, 16, 20, 24, 32, 48, 64];
+    for (dlc, &len) in lengths.iter().enumerate() {
+        assert_eq!(canfd::dlc_to_len(dlc as u8), len);
+        assert_eq!(canfd::len_to_dlc(len), Some(dlc as u8));
+        assert_eq!(canfd::next_valid_len(len), len);
+    }
+
+    // Only the low nibble of the DLC is significant
+    assert_eq!(canfd::dlc_to_len(0x1F), 64);
+
+    // Lengths between valid sizes need padding
+    assert_eq!(canfd::len_to_dlc(9), None);
+    assert_eq!(canfd::len_to_dlc(33), None);
+    assert_eq!(canfd::len_to_dlc(65), None);
+    assert_eq!(canfd::next_valid_len(9), 12);
+    assert_eq!(canfd::next_valid_len(17), 20);
+    assert_eq!(canfd::next_valid_len(25), 32);
+    assert_eq!(canfd::next_valid_len(49), 64);
+    assert_eq!(canfd::next_valid_len(100), 100);
+
+    assert_eq!(Frame::fd(0x123, vec![0; 12], false).dlc(), Some(9));
+    assert_eq!(Frame::fd(0x123, vec![0; 13], false).dlc(), None);
+    assert_eq!(Frame::standard(0x123, vec![0; 300]).dlc(), None);
+}
+
+#[test]
+fn test_canfd_set_data_bitrate() -> Result<()> {
+    let mut canfd = CanFd::with_bitrate(
+        TestPort::default(),
+        CanFdBitrate::Rate500k2m,
+        CanFdOptions::NONE,
+    );
+    canfd.open()?;
+
+    canfd.set_data_bitrate(500_000, 0.8)?;
+    assert_eq!(canfd.config().data_bitrate, 500_000);
+    assert_eq!(canfd.config().data_sample_point, 0.8);
+
+    // Invalid settings leave the configuration untouched
+    assert!(canfd.set_data_bitrate(0, 0.8).is_err());
+    assert_eq!(canfd.config().data_bitrate, 500_000);
+
+    canfd.push_tx_frame(test_frame(0x123))?;
+    assert!(matches!(
+        canfd.set_data_bitrate(2_000_000, 0.75),
+        Err(AutomotiveError::ConditionsNotCorrect)
+    ));
+    assert_eq!(canfd.config().data_bitrate, 500_000);
+
+    Ok(())
+}
+
+#[test]
+fn test_frame_bit_length() {
+    // Worst-case classic frames (Davis et al.): 55 + 10 bits per data byte, and 80 + 10 extended
+    assert_eq!(
+        can::frame_bit_length(&Frame::standard(0x123, vec![0x00; 8])),
+        135
+    );
+    assert_eq!(
+        can::frame_bit_length(&Frame::extended(0x123, vec![0x00; 8])),
+        160
+    );
+    assert_eq!(
+        can::frame_bit_length(&Frame::standard(0x123, Vec::new())),
+        55
+    );
+
+    // CAN-FD: stuffed header and data, CRC-17 field of 27 bits, 13 tail bits
+    let fd = Frame::fd(0x123, vec![0x00; 8], true);
+    assert_eq!(canfd::frame_bit_length(&fd), 86 + 21 + 27 + 13);
+    // More than 16 data bytes switch to the 32-bit CRC-21 field
+    let mut fd_extended = Frame::fd(0x123, vec![0x00; 64], true);
+    fd_extended.is_extended = true;
+    assert_eq!(canfd::frame_bit_length(&fd_extended), 553 + 138 + 32 + 13);
+    // Either entry point picks the layout from the frame
+    assert_eq!(can::frame_bit_length(&fd), canfd::frame_bit_length(&fd));
+}
+
+#[test]
+fn test_bus_stats_rates() -> Result<()> {
+    let now = Arc::new(AtomicU64::new(5_000));
+    let clock = Arc::clone(&now);
+    let mut stats = BusStats::with_clock(
+        TestPort::default(),
+        Box::new(move || clock.load(Ordering::SeqCst)),
+    );
+
+    let mut frame = test_frame(0x123);
+    frame.data = vec![0x00; 8];
+    // Worst case for an 8-byte standard frame: 111 bits plus 24 stuff bits
+    assert_eq!(bus_stats::frame_bits(&frame), 135);
+
+    for _ in 0..100 {
+        stats.send(&frame)?;
+    }
+    now.fetch_add(1_000, Ordering::SeqCst);
+
+    assert_eq!(stats.frames(), 100);
+    assert_eq!(stats.frames_per_sec(), 100.0);
+    assert_eq!(stats.bytes_per_sec(), 800.0);
+    // 13500 bits/s on a 500 kbit/s bus
+    assert!((stats.bus_load_percent(500_000) - 2.7).abs() < 1e-9);
+
+    stats.reset_window();
+    assert_eq!(stats.frames(), 0);
+    assert_eq!(stats.frames_per_sec(), 0.0);
+
+    Ok(())
+}
+
+#[test]
+fn test_mock_peek_then_receive() -> Result<()> {
+    let first = Frame::standard(0x7E8, vec![0x02, 0x50, 0x03]);
+    let second = Frame::standard(0x7E9, vec![0x01, 0x7E]);
+    let mut mock = mock::MockPhysical::with_script(vec![first.clone(), second.clone()]);
+    mock.open()?;
+
+    // Peeking does not consume the frame
+    assert_eq!(mock.peek_frame()?, Some(first.clone()));
+    assert_eq!(mock.peek_frame()?, Some(first.clone()));
+    assert_eq!(mock.receive_frame()?, first);
+    assert_eq!(mock.peek_frame()?, Some(second.clone()));
+    assert_eq!(mock.receive_frame()?, second);
+    assert_eq!(mock.peek_frame()?, None);
+
+    Ok(())
+}
+
+#[test]
+fn test_mock_drained_script_times_out() -> Result<()> {
+    let mut mock = mock::MockPhysical::with_script(vec![test_frame(0x100)]);
+    assert!(matches!(
+        mock.receive_frame(),
+        Err(AutomotiveError::NotInitialized)
+    ));
+    mock.open()?;
+
+    // Once the script is drained the bus is silent, not uninitialized
+    assert_eq!(mock.receive_frame()?, test_frame(0x100));
+    assert!(matches!(
+        mock.receive_frame(),
+        Err(AutomotiveError::Timeout)
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_can_peek_rx_queue() -> Result<()> {
+    let mut can = Can::with_bitrate(TestPort::default(), CanBitrate::Rate500K, CanOptions::NONE);
+    can.open()?;
+
+    // Test port cannot peek
+    assert_eq!(can.peek_frame()?, None);
+
+    can.push_rx_frame(test_frame(0x100))?;
+    assert_eq!(can.peek_frame()?, Some(test_frame(0x100)));
+    assert_eq!(can.rx_pending(), 1);
+    assert_eq!(can.receive_frame()?, test_frame(0x100));
+
+    Ok(())
+}
+
+#[test]
+fn test_bit_timing_80mhz_500k() -> Result<()> {
+    let timing = bit_timing::calculate(80_000_000, 500_000, 0.75, 1)?;
+    assert_eq!(
+        timing,
+        BitTiming {
+            brp: 8,
+            prop_seg: 7,
+            phase_seg1: 7,
+            phase_seg2: 5,
+            sjw: 1,
+        }
+    );
+    assert_eq!(timing.tq_per_bit(), 20);
+    assert_eq!(timing.sample_point(), 0.75);
+    assert_eq!(timing.bitrate(80_000_000), 500_000);
+    Ok(())
+}
+
+#[test]
+fn test_bit_timing_common_rates() -> Result<()> {
+    for (clock_hz, bitrate) in [
+        (8_000_000, 1_000_000),
+        (16_000_000, 250_000),
+        (40_000_000, 125_000),
+    ] {
+        let timing = bit_timing::calculate(clock_hz, bitrate, 0.875, 1)?;
+        assert_eq!(timing.bitrate(clock_hz), bitrate);
+        assert!((timing.sample_point() - 0.875).abs() <= bit_timing::SAMPLE_POINT_TOLERANCE);
+    }
+    Ok(())
+}
+
+#[test]
+fn test_bit_timing_impossible() {
+    // No whole number of time quanta per bit
+    assert!(matches!(
+        bit_timing::calculate(10_000_000, 3_000_000, 0.75, 1),
+        Err(AutomotiveError::InvalidParameter)
+    ));
+    // Sample point too late to leave room for phase segment 2
+    assert!(matches!(
+        bit_timing::calculate(8_000_000, 1_000_000, 0.97, 1),
+        Err(AutomotiveError::InvalidParameter)
+    ));
+}
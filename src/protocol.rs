@@ -0,0 +1,80 @@
+//! Protocol classification for raw CAN frames.
+//!
+//! Bus monitoring tools receive frames without knowing which protocol produced
+//! them. [`classify`] guesses the protocol from the identifier format and the
+//! ISO-TP protocol control information (PCI) byte.
+
+use crate::types::{Address, Frame};
+
+/// Upper nibble of an ISO-TP Single Frame PCI byte
+pub const PCI_SINGLE_FRAME: u8 = 0x00;
+/// Upper nibble of an ISO-TP First Frame PCI byte
+pub const PCI_FIRST_FRAME: u8 = 0x10;
+/// Upper nibble of an ISO-TP Consecutive Frame PCI byte
+pub const PCI_CONSECUTIVE_FRAME: u8 = 0x20;
+/// Upper nibble of an ISO-TP Flow Control PCI byte
+pub const PCI_FLOW_CONTROL: u8 = 0x30;
+
+/// Highest valid ISO-TP flow status (0 = continue, 1 = wait, 2 = overflow)
+const MAX_FLOW_STATUS: u8 = 0x02;
+
+/// Protocol guessed for a raw frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameKind {
+    /// ISO-TP Single Frame
+    IsoTpSingle,
+    /// ISO-TP First Frame of a segmented message
+    IsoTpFirst,
+    /// ISO-TP Consecutive Frame of a segmented message
+    IsoTpConsecutive,
+    /// ISO-TP Flow Control frame
+    IsoTpFlowControl,
+    /// J1939 message with its Parameter Group Number
+    J1939(u32),
+    /// No known protocol matches
+    Unknown,
+}
+
+/// Classifies a frame as J1939 or ISO-TP from its identifier and PCI byte.
+///
+/// Extended (29-bit) frames are treated as J1939; the destination address is
+/// stripped from PDU1 PGNs. Standard frames are ISO-TP when the PCI byte is
+/// consistent with the payload length, otherwise [`FrameKind::Unknown`].
+pub fn classify(frame: &Frame) -> FrameKind {
+    if frame.is_extended {
+        return FrameKind::J1939(j1939_pgn(frame.id));
+    }
+
+    let data = &frame.data;
+    let Some(&pci) = data.first() else {
+        return FrameKind::Unknown;
+    };
+    let low = pci & 0x0F;
+
+    match pci & 0xF0 {
+        PCI_SINGLE_FRAME => {
+            // A zero length nibble escapes to a CAN-FD length in the second byte
+            let (header, length) = match (low, data.get(1)) {
+                (0, Some(&length)) if frame.is_fd && length > 7 => (2, length as usize),
+                (0, _) => return FrameKind::Unknown,
+                _ => (1, low as usize),
+            };
+            if header + length <= data.len() {
+                FrameKind::IsoTpSingle
+            } else {
+                FrameKind::Unknown
+            }
+        }
+        PCI_FIRST_FRAME if data.len() >= 8 => FrameKind::IsoTpFirst,
+        PCI_CONSECUTIVE_FRAME if data.len() >= 2 => FrameKind::IsoTpConsecutive,
+        PCI_FLOW_CONTROL if low <= MAX_FLOW_STATUS && data.len() >= 3 => {
+            FrameKind::IsoTpFlowControl
+        }
+        _ => FrameKind::Unknown,
+    }
+}
+
+/// Extracts the PGN from a 29-bit J1939 identifier
+fn j1939_pgn(id: u32) -> u32 {
+    Address::from_can_id(id).group_pgn()
+}
@@ -0,0 +1,98 @@
+use super::TransportLayer;
+use crate::error::{AutomotiveError, Result};
+use crate::types::{Config, Frame};
+
+/// Object-safe view of a transport layer with its configuration type erased
+trait DynTransportLayer {
+    fn open(&mut self) -> Result<()>;
+    fn close(&mut self) -> Result<()>;
+    fn write_frame(&mut self, frame: &Frame) -> Result<()>;
+    fn read_frame(&mut self) -> Result<Frame>;
+    fn set_timeout(&mut self, timeout_ms: u32) -> Result<()>;
+    fn flush(&mut self) -> Result<()>;
+}
+
+impl<T: TransportLayer> DynTransportLayer for T {
+    fn open(&mut self) -> Result<()> {
+        TransportLayer::open(self)
+    }
+
+    fn close(&mut self) -> Result<()> {
+        TransportLayer::close(self)
+    }
+
+    fn write_frame(&mut self, frame: &Frame) -> Result<()> {
+        TransportLayer::write_frame(self, frame)
+    }
+
+    fn read_frame(&mut self) -> Result<Frame> {
+        TransportLayer::read_frame(self)
+    }
+
+    fn set_timeout(&mut self, timeout_ms: u32) -> Result<()> {
+        TransportLayer::set_timeout(self, timeout_ms)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        TransportLayer::flush(self)
+    }
+}
+
+/// Configuration for `BoxedTransport`; the wrapped transport carries its own
+#[derive(Debug, Clone, Default)]
+pub struct BoxedTransportConfig;
+
+impl Config for BoxedTransportConfig {
+    fn validate(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Transport layer that erases the concrete transport type
+///
+/// Lets transports with different configurations (e.g. ISO-TP and DoIP) be
+/// chosen at runtime and stored side by side.
+pub struct BoxedTransport {
+    inner: Box<dyn DynTransportLayer>,
+}
+
+impl BoxedTransport {
+    /// Wraps any transport layer
+    pub fn from_transport<T: TransportLayer + 'static>(transport: T) -> Self {
+        Self {
+            inner: Box::new(transport),
+        }
+    }
+}
+
+impl TransportLayer for BoxedTransport {
+    type Config = BoxedTransportConfig;
+
+    fn new(_config: Self::Config) -> Result<Self> {
+        Err(AutomotiveError::NotInitialized) // Requires a wrapped transport
+    }
+
+    fn open(&mut self) -> Result<()> {
+        self.inner.open()
+    }
+
+    fn close(&mut self) -> Result<()> {
+        self.inner.close()
+    }
+
+    fn write_frame(&mut self, frame: &Frame) -> Result<()> {
+        self.inner.write_frame(frame)
+    }
+
+    fn read_frame(&mut self) -> Result<Frame> {
+        self.inner.read_frame()
+    }
+
+    fn set_timeout(&mut self, timeout_ms: u32) -> Result<()> {
+        self.inner.set_timeout(timeout_ms)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+}
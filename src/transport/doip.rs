@@ -1,11 +1,12 @@
-use std::io::{Read, Write};
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
 use std::net::{SocketAddr, TcpStream};
 use std::time::Duration;
 
 use super::TransportLayer;
 use crate::error::{AutomotiveError, Result};
 use crate::physical::PhysicalLayer;
-use crate::types::{Config, Frame};
+use crate::types::{Config, Frame, LayerState};
 
 // DoIP protocol version
 const DOIP_PROTOCOL_VERSION: u8 = 0x02;
@@ -15,10 +16,28 @@ const DOIP_VEHICLE_IDENTIFICATION_REQUEST: u16 = 0x0001;
 const DOIP_VEHICLE_IDENTIFICATION_RESPONSE: u16 = 0x0002;
 const DOIP_ROUTING_ACTIVATION_REQUEST: u16 = 0x0005;
 const DOIP_ROUTING_ACTIVATION_RESPONSE: u16 = 0x0006;
+const DOIP_ALIVE_CHECK_REQUEST: u16 = 0x0007;
+const DOIP_ALIVE_CHECK_RESPONSE: u16 = 0x0008;
 const DOIP_DIAGNOSTIC_MESSAGE: u16 = 0x8001;
 const DOIP_DIAGNOSTIC_MESSAGE_POSITIVE_ACK: u16 = 0x8002;
 const DOIP_DIAGNOSTIC_MESSAGE_NEGATIVE_ACK: u16 = 0x8003;
 
+// Routing activation response codes
+const ROUTING_ACTIVATION_SUCCESSFUL: u8 = 0x10;
+
+/// Source and target addresses preceding diagnostic message and ack payloads
+const DIAGNOSTIC_ADDRESS_LENGTH: usize = 4;
+
+/// DoIP generic header length
+const DOIP_HEADER_LENGTH: usize = 8;
+
+/// Bytes requested from the socket per read
+const READ_CHUNK_SIZE: usize = 1024;
+
+/// Routing activation response payload without and with OEM-specific data
+const ROUTING_ACTIVATION_RESPONSE_LENGTH: usize = 9;
+const ROUTING_ACTIVATION_RESPONSE_OEM_LENGTH: usize = 13;
+
 // DoIP header structure
 #[derive(Debug, Clone)]
 struct DoIPHeader {
@@ -48,7 +67,7 @@ impl DoIPHeader {
     }
 
     fn from_bytes(bytes: &[u8]) -> Result<Self> {
-        if bytes.len() < 8 {
+        if bytes.len() < DOIP_HEADER_LENGTH {
             return Err(AutomotiveError::InvalidData);
         }
 
@@ -79,6 +98,8 @@ pub struct DoIPConfig {
     pub timeout_ms: u32,
     pub tcp_connection_timeout_ms: u32,
     pub response_timeout_ms: u32,
+    pub activation_type: u8, // Routing activation type (0x00 = default)
+    pub oem_activation_data: Option<[u8; 4]>, // OEM-specific routing activation data
 }
 
 impl Config for DoIPConfig {
@@ -103,15 +124,96 @@ impl Default for DoIPConfig {
             timeout_ms: 5000,
             tcp_connection_timeout_ms: 2000,
             response_timeout_ms: 5000,
+            activation_type: 0x00,
+            oem_activation_data: None,
         }
     }
 }
 
+/// Parsed routing activation response
+#[derive(Debug, Clone, PartialEq)]
+pub struct RoutingActivationResult {
+    pub tester_address: u16, // Logical address of the tester
+    pub entity_address: u16, // Logical address of the DoIP entity
+    pub response_code: u8,
+    pub oem_data: Option<[u8; 4]>, // OEM-specific response data
+}
+
+impl RoutingActivationResult {
+    /// Parses a routing activation response payload
+    fn from_bytes(payload: &[u8]) -> Result<Self> {
+        if payload.len() < ROUTING_ACTIVATION_RESPONSE_LENGTH {
+            return Err(AutomotiveError::InvalidData);
+        }
+
+        let oem_data = payload
+            .get(ROUTING_ACTIVATION_RESPONSE_LENGTH..ROUTING_ACTIVATION_RESPONSE_OEM_LENGTH)
+            .map(|data| [data[0], data[1], data[2], data[3]]);
+
+        Ok(Self {
+            tester_address: u16::from_be_bytes([payload[0], payload[1]]),
+            entity_address: u16::from_be_bytes([payload[2], payload[3]]),
+            response_code: payload[4],
+            oem_data,
+        })
+    }
+}
+
+/// Describes a routing activation response code other than success
+fn routing_activation_error(code: u8) -> AutomotiveError {
+    let reason = match code {
+        0x00 => "unknown source address",
+        0x01 => "all TCP sockets registered and active",
+        0x02 => "source address differs from the one registered on this socket",
+        0x03 => "source address already active on another socket",
+        0x04 => "missing authentication",
+        0x05 => "rejected confirmation",
+        0x06 => "unsupported routing activation type",
+        0x07 => "TLS required",
+        0x11 => "confirmation required",
+        _ => "unknown response code",
+    };
+    AutomotiveError::DoIPError(format!(
+        "Routing activation denied: {} (0x{:02X})",
+        reason, code
+    ))
+}
+
+/// Inbound message consumed by the dispatcher
+enum Inbound {
+    PositiveAck,
+    NegativeAck(u8),
+    /// Queued for `read_frame` or answered internally
+    Handled,
+}
+
+/// Maps a socket error to `ConnectionFailed` if the peer dropped the connection
+fn map_io_error(err: io::Error, default: AutomotiveError) -> AutomotiveError {
+    match err.kind() {
+        io::ErrorKind::BrokenPipe
+        | io::ErrorKind::ConnectionReset
+        | io::ErrorKind::ConnectionAborted
+        | io::ErrorKind::UnexpectedEof => AutomotiveError::ConnectionFailed,
+        _ => default,
+    }
+}
+
+/// Maps a failed socket read, treating an expired read timeout as `Timeout`
+fn map_read_error(err: io::Error) -> AutomotiveError {
+    match err.kind() {
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => AutomotiveError::Timeout,
+        _ => map_io_error(err, AutomotiveError::ReceiveFailed),
+    }
+}
+
 pub struct DoIP<P: PhysicalLayer> {
     config: DoIPConfig,
     physical: P,
     stream: Option<TcpStream>,
-    is_open: bool,
+    state: LayerState,
+    routing_activation: Option<RoutingActivationResult>,
+    inbound: VecDeque<Frame>, // Diagnostic messages received ahead of `read_frame`
+    rx_buffer: Vec<u8>,       // Bytes of a message not yet fully received
 }
 
 impl<P: PhysicalLayer> DoIP<P> {
@@ -121,11 +223,69 @@ impl<P: PhysicalLayer> DoIP<P> {
             config,
             physical,
             stream: None,
-            is_open: false,
+            state: LayerState::Closed,
+            routing_activation: None,
+            inbound: VecDeque::new(),
+            rx_buffer: Vec::new(),
         }
     }
 
-    fn activate_routing(&mut self) -> Result<()> {
+    /// Current lifecycle state
+    pub fn state(&self) -> LayerState {
+        self.state
+    }
+
+    /// Result of the last successful routing activation
+    pub fn routing_activation(&self) -> Option<&RoutingActivationResult> {
+        self.routing_activation.as_ref()
+    }
+
+    /// Returns true if the TCP connection to the DoIP entity is still alive
+    pub fn is_connected(&self) -> bool {
+        if self.state != LayerState::Open {
+            return false;
+        }
+        let stream = match self.stream.as_ref() {
+            Some(stream) => stream,
+            None => return false,
+        };
+
+        // Peek without blocking: EOF or a socket error means the peer has gone away
+        if stream.set_nonblocking(true).is_err() {
+            return false;
+        }
+        let mut buf = [0u8; 1];
+        let connected = match stream.peek(&mut buf) {
+            Ok(0) => false,
+            Ok(_) => true,
+            Err(e) => e.kind() == io::ErrorKind::WouldBlock,
+        };
+        let _ = stream.set_nonblocking(false);
+        connected
+    }
+
+    /// Closes the stale connection and re-runs connection setup and routing activation
+    pub fn reconnect(&mut self) -> Result<()> {
+        self.close()?;
+        self.open()
+    }
+
+    fn connect(&mut self) -> Result<()> {
+        let addr = format!("{}:{}", self.config.host, self.config.port);
+        let stream = TcpStream::connect(&addr).map_err(|_| AutomotiveError::ConnectionFailed)?;
+
+        stream
+            .set_read_timeout(Some(Duration::from_millis(self.config.timeout_ms as u64)))
+            .map_err(|_| AutomotiveError::ConnectionFailed)?;
+        stream
+            .set_write_timeout(Some(Duration::from_millis(self.config.timeout_ms as u64)))
+            .map_err(|_| AutomotiveError::ConnectionFailed)?;
+
+        self.stream = Some(stream);
+        Ok(())
+    }
+
+    fn activate_routing(&mut self) -> Result<RoutingActivationResult> {
         let stream = self
             .stream
             .as_mut()
@@ -134,9 +294,11 @@ impl<P: PhysicalLayer> DoIP<P> {
         // Create routing activation request
         let mut payload = Vec::new();
         payload.extend_from_slice(&self.config.source_address.to_be_bytes());
-        payload.push(0x00); // Activation type: Default
-        payload.push(0x00); // Reserved
-        payload.extend_from_slice(&[0x00, 0x00]); // Reserved
+        payload.push(self.config.activation_type);
+        payload.extend_from_slice(&[0x00; 4]); // Reserved by ISO 13400
+        if let Some(oem_data) = self.config.oem_activation_data {
+            payload.extend_from_slice(&oem_data);
+        }
 
         let header = DoIPHeader::new(DOIP_ROUTING_ACTIVATION_REQUEST, payload.len() as u32);
         let mut message = header.to_bytes();
@@ -145,31 +307,149 @@ impl<P: PhysicalLayer> DoIP<P> {
         // Send request
         stream
             .write_all(&message)
-            .map_err(|_| AutomotiveError::SendFailed)?;
+            .map_err(|e| map_io_error(e, AutomotiveError::SendFailed))?;
 
         // Read response
-        let mut header_buf = [0u8; 8];
-        stream
-            .read_exact(&mut header_buf)
-            .map_err(|_| AutomotiveError::ReceiveFailed)?;
-
-        let response_header = DoIPHeader::from_bytes(&header_buf)?;
+        let (response_header, response_payload) = self.read_message()?;
         if response_header.payload_type != DOIP_ROUTING_ACTIVATION_RESPONSE {
             return Err(AutomotiveError::InvalidData);
         }
 
-        let mut response_payload = vec![0u8; response_header.payload_length as usize];
+        let result = RoutingActivationResult::from_bytes(&response_payload)?;
+        if result.response_code != ROUTING_ACTIVATION_SUCCESSFUL {
+            return Err(routing_activation_error(result.response_code));
+        }
+
+        Ok(result)
+    }
+
+    /// Sends a diagnostic message and waits for its acknowledgment
+    fn send_diagnostic_message(&mut self, frame: &Frame) -> Result<()> {
+        // Create diagnostic message
+        let mut payload = Vec::new();
+        payload.extend_from_slice(&self.config.source_address.to_be_bytes());
+        payload.extend_from_slice(&self.config.target_address.to_be_bytes());
+        payload.extend(&frame.data);
+        self.send_message(DOIP_DIAGNOSTIC_MESSAGE, &payload)?;
+
+        // Messages arriving ahead of the acknowledgment are dispatched as usual
+        loop {
+            match self.dispatch_next()? {
+                Inbound::PositiveAck => return Ok(()),
+                Inbound::NegativeAck(code) => {
+                    return Err(AutomotiveError::DoIPError(format!(
+                        "NACK received: 0x{:02X}",
+                        code
+                    )))
+                }
+                Inbound::Handled => {}
+            }
+        }
+    }
+
+    /// Returns the next diagnostic message, reading from the connection if none is queued
+    fn receive_diagnostic_message(&mut self) -> Result<Frame> {
+        loop {
+            if let Some(frame) = self.inbound.pop_front() {
+                return Ok(frame);
+            }
+            // A late acknowledgment has no pending write left to satisfy
+            self.dispatch_next()?;
+        }
+    }
+
+    /// Writes a DoIP message with the given payload type
+    fn send_message(&mut self, payload_type: u16, payload: &[u8]) -> Result<()> {
+        let stream = self
+            .stream
+            .as_mut()
+            .ok_or(AutomotiveError::NotInitialized)?;
+
+        let header = DoIPHeader::new(payload_type, payload.len() as u32);
+        let mut message = header.to_bytes();
+        message.extend_from_slice(payload);
+
         stream
-            .read_exact(&mut response_payload)
-            .map_err(|_| AutomotiveError::ReceiveFailed)?;
+            .write_all(&message)
+            .map_err(|e| map_io_error(e, AutomotiveError::SendFailed))
+    }
+
+    /// Reads one complete DoIP message from the connection
+    ///
+    /// Received bytes are buffered until the whole message has arrived, so a
+    /// read timeout part-way through a message returns `Timeout` and the next
+    /// call resumes where this one stopped. A closed connection returns
+    /// `ConnectionFailed`, and a malformed header `InvalidData`.
+    fn read_message(&mut self) -> Result<(DoIPHeader, Vec<u8>)> {
+        let stream = self
+            .stream
+            .as_mut()
+            .ok_or(AutomotiveError::NotInitialized)?;
 
-        // Check response code (first byte of payload)
-        if response_payload[0] != 0x10 {
-            // 0x10 = Routing activation successful
-            return Err(AutomotiveError::ConnectionFailed);
+        loop {
+            if self.rx_buffer.len() >= DOIP_HEADER_LENGTH {
+                let header = match DoIPHeader::from_bytes(&self.rx_buffer) {
+                    Ok(header) => header,
+                    Err(e) => {
+                        // Message boundaries are lost, so drop everything buffered
+                        self.rx_buffer.clear();
+                        return Err(e);
+                    }
+                };
+                let length = DOIP_HEADER_LENGTH + header.payload_length as usize;
+                if self.rx_buffer.len() >= length {
+                    let payload = self.rx_buffer[DOIP_HEADER_LENGTH..length].to_vec();
+                    self.rx_buffer.drain(..length);
+                    return Ok((header, payload));
+                }
+            }
+
+            let mut chunk = [0u8; READ_CHUNK_SIZE];
+            match stream.read(&mut chunk) {
+                Ok(0) => return Err(AutomotiveError::ConnectionFailed),
+                Ok(n) => self.rx_buffer.extend_from_slice(&chunk[..n]),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(map_read_error(e)),
+            }
         }
+    }
 
-        Ok(())
+    /// Reads one DoIP message and routes it by payload type
+    ///
+    /// Diagnostic messages are queued for `read_frame` and alive checks are
+    /// answered; acknowledgments are returned to the pending write.
+    fn dispatch_next(&mut self) -> Result<Inbound> {
+        let (header, payload) = self.read_message()?;
+
+        match header.payload_type {
+            DOIP_DIAGNOSTIC_MESSAGE => {
+                let data = payload
+                    .get(DIAGNOSTIC_ADDRESS_LENGTH..)
+                    .ok_or(AutomotiveError::InvalidData)?;
+                // DoIP doesn't use CAN IDs
+                self.inbound.push_back(Frame::standard(0, data.to_vec()));
+                Ok(Inbound::Handled)
+            }
+            DOIP_ALIVE_CHECK_REQUEST => {
+                let source_address = self.config.source_address.to_be_bytes();
+                self.send_message(DOIP_ALIVE_CHECK_RESPONSE, &source_address)?;
+                Ok(Inbound::Handled)
+            }
+            DOIP_DIAGNOSTIC_MESSAGE_POSITIVE_ACK => Ok(Inbound::PositiveAck),
+            DOIP_DIAGNOSTIC_MESSAGE_NEGATIVE_ACK => payload
+                .get(DIAGNOSTIC_ADDRESS_LENGTH)
+                .map(|&code| Inbound::NegativeAck(code))
+                .ok_or(AutomotiveError::InvalidData),
+            _ => Err(AutomotiveError::InvalidData),
+        }
+    }
+}
+
+impl<P: PhysicalLayer> Drop for DoIP<P> {
+    fn drop(&mut self) {
+        if self.state != LayerState::Closed {
+            let _ = self.close();
+        }
     }
 }
 
@@ -181,30 +461,29 @@ impl<P: PhysicalLayer> TransportLayer for DoIP<P> {
     }
 
     fn open(&mut self) -> Result<()> {
-        if self.is_open {
-            return Ok(());
+        match self.state {
+            LayerState::Open if self.stream.is_some() => return Ok(()),
+            LayerState::Open | LayerState::Closed => {}
+            // Drop the broken connection before reconnecting
+            LayerState::Errored => self.close()?,
         }
 
         // Validate configuration
         self.config.validate()?;
 
         // Connect to DoIP server
-        let addr = format!("{}:{}", self.config.host, self.config.port);
-        let stream = TcpStream::connect(&addr).map_err(|_| AutomotiveError::ConnectionFailed)?;
-
-        stream
-            .set_read_timeout(Some(Duration::from_millis(self.config.timeout_ms as u64)))
-            .map_err(|_| AutomotiveError::ConnectionFailed)?;
-        stream
-            .set_write_timeout(Some(Duration::from_millis(self.config.timeout_ms as u64)))
-            .map_err(|_| AutomotiveError::ConnectionFailed)?;
-
-        self.stream = Some(stream);
-        self.is_open = true;
+        self.connect()?;
 
         // Perform routing activation
-        self.activate_routing()?;
+        match self.activate_routing() {
+            Ok(result) => self.routing_activation = Some(result),
+            Err(e) => {
+                self.close()?;
+                return Err(e);
+            }
+        }
 
+        self.state = LayerState::Open;
         Ok(())
     }
 
@@ -212,99 +491,32 @@ impl<P: PhysicalLayer> TransportLayer for DoIP<P> {
         if let Some(mut stream) = self.stream.take() {
             let _ = stream.shutdown(std::net::Shutdown::Both);
         }
-        self.is_open = false;
+        self.state = LayerState::Closed;
+        self.routing_activation = None;
+        self.inbound.clear();
+        self.rx_buffer.clear();
         Ok(())
     }
 
     fn write_frame(&mut self, frame: &Frame) -> Result<()> {
-        if !self.is_open {
+        if self.state == LayerState::Closed {
             return Err(AutomotiveError::NotInitialized);
         }
-
-        let stream = self
-            .stream
-            .as_mut()
-            .ok_or(AutomotiveError::NotInitialized)?;
-
-        // Create diagnostic message
-        let mut payload = Vec::new();
-        payload.extend_from_slice(&self.config.source_address.to_be_bytes());
-        payload.extend_from_slice(&self.config.target_address.to_be_bytes());
-        payload.extend(&frame.data);
-
-        let header = DoIPHeader::new(DOIP_DIAGNOSTIC_MESSAGE, payload.len() as u32);
-        let mut message = header.to_bytes();
-        message.extend(payload);
-
-        stream
-            .write_all(&message)
-            .map_err(|_| AutomotiveError::SendFailed)?;
-
-        // Read acknowledgment
-        let mut header_buf = [0u8; 8];
-        stream
-            .read_exact(&mut header_buf)
-            .map_err(|_| AutomotiveError::ReceiveFailed)?;
-
-        let response_header = DoIPHeader::from_bytes(&header_buf)?;
-        match response_header.payload_type {
-            DOIP_DIAGNOSTIC_MESSAGE_POSITIVE_ACK => Ok(()),
-            DOIP_DIAGNOSTIC_MESSAGE_NEGATIVE_ACK => {
-                let mut response_payload = vec![0u8; response_header.payload_length as usize];
-                stream
-                    .read_exact(&mut response_payload)
-                    .map_err(|_| AutomotiveError::ReceiveFailed)?;
-                Err(AutomotiveError::DoIPError(format!(
-                    "NACK received: 0x{:02X}",
-                    response_payload[0]
-                )))
-            }
-            _ => Err(AutomotiveError::InvalidData),
-        }
+        let result = self.send_diagnostic_message(frame);
+        self.state.observe(&result);
+        result
     }
 
     fn read_frame(&mut self) -> Result<Frame> {
-        if !self.is_open {
+        if self.state == LayerState::Closed {
             return Err(AutomotiveError::NotInitialized);
         }
-
-        let stream = self
-            .stream
-            .as_mut()
-            .ok_or(AutomotiveError::NotInitialized)?;
-
-        // Read header
-        let mut header_buf = [0u8; 8];
-        stream
-            .read_exact(&mut header_buf)
-            .map_err(|_| AutomotiveError::ReceiveFailed)?;
-
-        let header = DoIPHeader::from_bytes(&header_buf)?;
-
-        // Read payload
-        let mut payload = vec![0u8; header.payload_length as usize];
-        stream
-            .read_exact(&mut payload)
-            .map_err(|_| AutomotiveError::ReceiveFailed)?;
-
-        // Check if it's a diagnostic message
-        if header.payload_type != DOIP_DIAGNOSTIC_MESSAGE {
-            return Err(AutomotiveError::InvalidData);
-        }
-
-        let diagnostic_data = payload[4..].to_vec();
-
-        Ok(Frame {
-            id: 0, // DoIP doesn't use CAN IDs
-            data: diagnostic_data,
-            timestamp: 0, // TODO: Add proper timestamp
-            is_extended: false,
-            is_fd: false,
-        })
+        let result = self.receive_diagnostic_message();
+        self.state.observe(&result);
+        result
     }
-
     fn set_timeout(&mut self, timeout_ms: u32) -> Result<()> {
-        if !self.is_open {
+        if self.state == LayerState::Closed {
             return Err(AutomotiveError::NotInitialized);
         }
 
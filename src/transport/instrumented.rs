@@ -0,0 +1,89 @@
+use super::TransportLayer;
+use crate::error::Result;
+use crate::types::Frame;
+
+/// Hook run on every frame passing through an [`Instrumented`] transport
+///
+/// Middlewares may rewrite the frame (e.g. remap ids for a gateway) or reject
+/// it by returning an error, which aborts the write or read.
+pub trait Middleware: Send {
+    /// Called with each outbound frame before it reaches the transport
+    fn on_send(&mut self, _frame: &mut Frame) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called with each inbound frame before it is returned to the caller
+    fn on_receive(&mut self, _frame: &mut Frame) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Transport layer running a chain of middlewares around another transport
+///
+/// Outbound frames pass through the chain in insertion order, inbound frames
+/// in reverse order.
+pub struct Instrumented<T: TransportLayer> {
+    inner: T,
+    middlewares: Vec<Box<dyn Middleware>>,
+}
+
+impl<T: TransportLayer> Instrumented<T> {
+    /// Wraps a transport with an empty middleware chain
+    pub fn from_transport(transport: T) -> Self {
+        Self {
+            inner: transport,
+            middlewares: Vec::new(),
+        }
+    }
+
+    /// Appends a middleware to the chain
+    pub fn with_middleware(mut self, middleware: impl Middleware + 'static) -> Self {
+        self.middlewares.push(Box::new(middleware));
+        self
+    }
+
+    /// Returns the wrapped transport
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T: TransportLayer> TransportLayer for Instrumented<T> {
+    type Config = T::Config;
+
+    fn new(config: Self::Config) -> Result<Self> {
+        T::new(config).map(Self::from_transport)
+    }
+
+    fn open(&mut self) -> Result<()> {
+        self.inner.open()
+    }
+
+    fn close(&mut self) -> Result<()> {
+        self.inner.close()
+    }
+
+    fn write_frame(&mut self, frame: &Frame) -> Result<()> {
+        let mut frame = frame.clone();
+        for middleware in self.middlewares.iter_mut() {
+            middleware.on_send(&mut frame)?;
+        }
+        self.inner.write_frame(&frame)
+    }
+
+    fn read_frame(&mut self) -> Result<Frame> {
+        let mut frame = self.inner.read_frame()?;
+        for middleware in self.middlewares.iter_mut().rev() {
+            middleware.on_receive(&mut frame)?;
+        }
+        Ok(frame)
+    }
+
+    fn set_timeout(&mut self, timeout_ms: u32) -> Result<()> {
+        self.inner.set_timeout(timeout_ms)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+}
@@ -1,10 +1,11 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::time::Duration;
 
 use super::isobus_diagnostic::{DiagnosticTroubleCode, ISOBUSDiagnosticProtocol};
 use super::TransportLayer;
 use crate::error::{AutomotiveError, Result};
-use crate::types::{Config, Frame};
+use crate::network::j1939::{TpTimeouts, ADDRESS_CLAIM_TIMEOUT_MS, TP_MAX_SIZE};
+use crate::types::{Address, Config, Frame};
 
 // ISOBUS Protocol Constants
 const ISOBUS_PROTOCOL_VERSION: u8 = 0x03;
@@ -15,6 +16,12 @@ const PGN_REQUEST: u32 = 0x00EA00;
 const PGN_TRANSPORT_PROTOCOL_CONNECTION: u32 = 0x00EC00;
 const PGN_TRANSPORT_PROTOCOL_DATA: u32 = 0x00EB00;
 const PGN_DIAGNOSTIC_MESSAGE: u32 = 0x00FECA;
+const PGN_VT_TO_ECU: u32 = 0x00E600;
+const PGN_ECU_TO_VT: u32 = 0x00E700;
+
+// Virtual terminal function codes (ISO 11783-6)
+const VT_OBJECT_POOL_TRANSFER: u8 = 0x11;
+const VT_END_OF_OBJECT_POOL: u8 = 0x12;
 
 // Transport Protocol Control Bytes
 const TP_CM_RTS: u8 = 0x10; // Request to Send
@@ -23,11 +30,16 @@ const TP_CM_EndOfMsgACK: u8 = 0x13; // End of Message Acknowledgment
 const TP_CM_BAM: u8 = 0x20; // Broadcast Announce Message
 const TP_CM_ABORT: u8 = 0xFF; // Connection Abort
 
-// Timeouts (in milliseconds)
-const T1_TIMEOUT: u32 = 750; // Time between CTS and first data packet
-const T2_TIMEOUT: u32 = 1250; // Time between consecutive data packets
-const T3_TIMEOUT: u32 = 1250; // Time between last data packet and EndOfMsgACK
-const T4_TIMEOUT: u32 = 1050; // Time waiting for CTS
+// Connection abort reasons
+const TP_ABORT_TIMEOUT: u8 = 0x03;
+
+/// Milliseconds since the UNIX epoch
+fn now_ms() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as u64)
+        .unwrap_or(0)
+}
 
 #[derive(Debug, Clone)]
 pub struct ISOBUSConfig {
@@ -39,6 +51,9 @@ pub struct ISOBUSConfig {
     pub manufacturer_code: u16,
     pub identity_number: u32,
     pub timeout_ms: u32,
+    pub address_claim_timeout_ms: u32, // Wait for contending claims
+    pub tp_timeouts: TpTimeouts,
+    pub vt_address: u8, // Virtual terminal receiving object pool uploads
 }
 
 impl Config for ISOBUSConfig {
@@ -64,6 +79,9 @@ impl Default for ISOBUSConfig {
             manufacturer_code: 0,
             identity_number: 0,
             timeout_ms: 1000,
+            address_claim_timeout_ms: ADDRESS_CLAIM_TIMEOUT_MS,
+            tp_timeouts: TpTimeouts::default(),
+            vt_address: 0x26, // Primary virtual terminal
         }
     }
 }
@@ -92,13 +110,24 @@ struct TPSession {
     last_timestamp: u64,
 }
 
+/// Progress of an object pool upload to the virtual terminal
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObjectPoolUpload {
+    Transferring,       // Object pool transfer message still being sent
+    WaitingForResponse, // End of Object Pool sent to the VT
+    Complete,
+    Failed(u8), // Error codes reported in the End of Object Pool response
+}
+
 pub struct ISOBUS {
     config: ISOBUSConfig,
     is_open: bool,
     address_claimed: bool,
     tp_sessions: HashMap<u8, TPSession>, // Key is source address
     rx_buffer: Vec<u8>,
+    tx_frames: VecDeque<Frame>, // Frames waiting for the CAN driver
     diagnostic_protocol: ISOBUSDiagnosticProtocol,
+    object_pool_upload: Option<ObjectPoolUpload>,
 }
 
 impl ISOBUS {
@@ -107,18 +136,18 @@ impl ISOBUS {
         let name_bytes = self.config.name.to_be_bytes();
 
         // Send address claim message
-        let mut frame = Frame {
-            id: ((PGN_ADDRESS_CLAIM as u32) << 8) | (self.config.source_address as u32),
-            data: name_bytes.to_vec(),
-            timestamp: 0,
-            is_extended: true,
-            is_fd: false,
-        };
+        let frame = Frame::extended(
+            ((PGN_ADDRESS_CLAIM as u32) << 8) | (self.config.source_address as u32),
+            name_bytes.to_vec(),
+        );
 
-        self.write_frame(&frame)?;
+        // Queued directly: write_frame refuses traffic until the address is claimed
+        self.tx_frames.push_back(frame);
 
         // Wait for potential address conflicts
-        std::thread::sleep(Duration::from_millis(250));
+        std::thread::sleep(Duration::from_millis(
+            self.config.address_claim_timeout_ms as u64,
+        ));
 
         self.address_claimed = true;
         Ok(())
@@ -126,7 +155,7 @@ impl ISOBUS {
 
     fn handle_transport_protocol(&mut self, frame: &Frame) -> Result<()> {
         let source_address = (frame.id & 0xFF) as u8;
-        let pgn = (frame.id >> 8) as u32;
+        let pgn = Address::from_can_id(frame.id).group_pgn();
 
         match pgn {
             PGN_TRANSPORT_PROTOCOL_CONNECTION => {
@@ -159,10 +188,7 @@ impl ISOBUS {
                     source_address,
                     destination_address: self.config.source_address,
                     pgn,
-                    last_timestamp: std::time::SystemTime::now()
-                        .duration_since(std::time::UNIX_EPOCH)
-                        .unwrap()
-                        .as_millis() as u64,
+                    last_timestamp: now_ms(),
                 };
 
                 self.tp_sessions.insert(source_address, session);
@@ -184,18 +210,29 @@ impl ISOBUS {
                     timestamp: 0,
                     is_extended: true,
                     is_fd: false,
+                    brs: false,
                 };
 
                 self.write_frame(&cts_frame)?;
             }
-            TP_CM_CTS => {
-                if let Some(session) = self.tp_sessions.get_mut(&source_address) {
-                    session.state = TPSessionState::SendingData;
-                    session.next_packet = data[2];
-                }
-            }
+            TP_CM_CTS => self.send_tp_packets(source_address, data[1], data[2])?,
             TP_CM_EndOfMsgACK => {
-                self.tp_sessions.remove(&source_address);
+                let own_address = self.config.source_address;
+                match self.tp_sessions.get(&own_address) {
+                    // Our outgoing session to this peer is complete
+                    Some(session) if session.destination_address == source_address => {
+                        let pgn = session.pgn;
+                        self.tp_sessions.remove(&own_address);
+                        if pgn == PGN_ECU_TO_VT
+                            && self.object_pool_upload == Some(ObjectPoolUpload::Transferring)
+                        {
+                            self.send_end_of_object_pool()?;
+                        }
+                    }
+                    _ => {
+                        self.tp_sessions.remove(&source_address);
+                    }
+                }
             }
             TP_CM_ABORT => {
                 self.tp_sessions.remove(&source_address);
@@ -206,23 +243,58 @@ impl ISOBUS {
         Ok(())
     }
 
+    /// Sends the `count` data packets starting at `next_packet` requested by a CTS from `peer`
+    fn send_tp_packets(&mut self, peer: u8, count: u8, next_packet: u8) -> Result<()> {
+        let own_address = self.config.source_address;
+        let session = match self.tp_sessions.get_mut(&own_address) {
+            Some(session) if session.destination_address == peer => session,
+            _ => return Ok(()),
+        };
+
+        let last_packet = next_packet
+            .saturating_add(count)
+            .saturating_sub(1)
+            .min(session.total_packets);
+        let mut packets = Vec::new();
+        for sequence in next_packet.max(1)..=last_packet {
+            let start = (sequence as usize - 1) * 7;
+            let end = (start + 7).min(session.data.len());
+            let mut packet = vec![sequence];
+            packet.extend_from_slice(&session.data[start..end]);
+            packet.resize(8, 0xFF);
+            packets.push(Frame::extended(
+                ((PGN_TRANSPORT_PROTOCOL_DATA | peer as u32) << 8) | own_address as u32,
+                packet,
+            ));
+        }
+
+        // A CTS for zero packets holds the connection open
+        session.next_packet = last_packet.saturating_add(1);
+        session.state = if count > 0 && last_packet == session.total_packets {
+            TPSessionState::WaitingForEndOfMsgACK
+        } else {
+            TPSessionState::WaitingForCTS
+        };
+        session.last_timestamp = now_ms();
+
+        self.tx_frames.extend(packets);
+        Ok(())
+    }
+
     fn handle_tp_data(&mut self, source_address: u8, data: &[u8]) -> Result<()> {
         if let Some(session) = self.tp_sessions.get_mut(&source_address) {
             let sequence = data[0];
             if sequence == session.next_packet {
                 session.data.extend_from_slice(&data[1..]);
                 session.next_packet += 1;
-                session.last_timestamp = std::time::SystemTime::now()
-                    .duration_since(std::time::UNIX_EPOCH)
-                    .unwrap()
-                    .as_millis() as u64;
+                session.last_timestamp = now_ms();
 
                 if session.next_packet > session.total_packets {
                     // Send End of Message ACK
-                    let mut ack_frame = Frame {
-                        id: ((PGN_TRANSPORT_PROTOCOL_CONNECTION as u32) << 8)
+                    let mut ack_frame = Frame::extended(
+                        ((PGN_TRANSPORT_PROTOCOL_CONNECTION as u32) << 8)
                             | (self.config.source_address as u32),
-                        data: vec![
+                        vec![
                             TP_CM_EndOfMsgACK,
                             (session.total_size & 0xFF) as u8,
                             ((session.total_size >> 8) & 0xFF) as u8,
@@ -232,10 +304,7 @@ impl ISOBUS {
                             ((session.pgn >> 8) & 0xFF) as u8,
                             ((session.pgn >> 16) & 0xFF) as u8,
                         ],
-                        timestamp: 0,
-                        is_extended: true,
-                        is_fd: false,
-                    };
+                    );
 
                     self.write_frame(&ack_frame)?;
                     self.tp_sessions.remove(&source_address);
@@ -246,6 +315,149 @@ impl ISOBUS {
         Ok(())
     }
 
+    /// Aborts transport sessions whose peer stayed silent past the matching timeout
+    fn expire_tp_sessions(&mut self) -> Result<()> {
+        let now = now_ms();
+        let timeouts = self.config.tp_timeouts;
+        let expired: Vec<u8> = self
+            .tp_sessions
+            .iter()
+            .filter(|(_, session)| {
+                let timeout = match session.state {
+                    TPSessionState::ReceivingData if session.next_packet == 1 => timeouts.t1,
+                    TPSessionState::ReceivingData | TPSessionState::SendingData => timeouts.t2,
+                    TPSessionState::WaitingForEndOfMsgACK => timeouts.t3,
+                    TPSessionState::WaitingForCTS => timeouts.t4,
+                    TPSessionState::Idle => return false,
+                };
+                now.saturating_sub(session.last_timestamp) > timeout as u64
+            })
+            .map(|(&key, _)| key)
+            .collect();
+
+        for key in expired {
+            if let Some(session) = self.tp_sessions.remove(&key) {
+                let peer = if session.source_address == self.config.source_address {
+                    session.destination_address
+                } else {
+                    session.source_address
+                };
+                let abort_frame = Frame::extended(
+                    ((PGN_TRANSPORT_PROTOCOL_CONNECTION | peer as u32) << 8)
+                        | (self.config.source_address as u32),
+                    vec![
+                        TP_CM_ABORT,
+                        TP_ABORT_TIMEOUT,
+                        0xFF,
+                        0xFF,
+                        0xFF,
+                        (session.pgn & 0xFF) as u8,
+                        ((session.pgn >> 8) & 0xFF) as u8,
+                        ((session.pgn >> 16) & 0xFF) as u8,
+                    ],
+                );
+                self.write_frame(&abort_frame)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Handles a frame received by the CAN driver
+    pub fn process_frame(&mut self, frame: &Frame) -> Result<()> {
+        if !self.is_open {
+            return Err(AutomotiveError::NotInitialized);
+        }
+        match Address::from_can_id(frame.id).group_pgn() {
+            PGN_TRANSPORT_PROTOCOL_CONNECTION | PGN_TRANSPORT_PROTOCOL_DATA => {
+                self.handle_transport_protocol(frame)
+            }
+            PGN_VT_TO_ECU => {
+                self.handle_vt_message(frame);
+                Ok(())
+            }
+            _ => self.handle_diagnostic_message(frame),
+        }
+    }
+
+    /// Starts uploading an object pool to the virtual terminal at `vt_address`
+    ///
+    /// The pool is sent in an Object Pool Transfer message, over the transport
+    /// protocol unless it fits a single frame. Once the VT acknowledges the
+    /// transfer, End of Object Pool is queued; the VT's response is picked up
+    /// by [`ISOBUS::process_frame`] and reported by
+    /// [`ISOBUS::object_pool_upload`]. Pools beyond the transport protocol
+    /// limit would need the extended transport protocol and are rejected.
+    pub fn upload_object_pool(&mut self, pool: &[u8]) -> Result<()> {
+        if !self.is_open || !self.address_claimed {
+            return Err(AutomotiveError::NotInitialized);
+        }
+        if pool.is_empty() || pool.len() >= TP_MAX_SIZE {
+            return Err(AutomotiveError::InvalidParameter);
+        }
+
+        let mut data = Vec::with_capacity(pool.len() + 1);
+        data.push(VT_OBJECT_POOL_TRANSFER);
+        data.extend_from_slice(pool);
+        let single_frame = data.len() <= 8;
+
+        self.object_pool_upload = Some(ObjectPoolUpload::Transferring);
+        self.write_frame(&Frame::extended(self.vt_frame_id(), data))?;
+        if single_frame {
+            self.send_end_of_object_pool()?;
+        }
+        Ok(())
+    }
+
+    /// State of the last object pool upload, if any
+    pub fn object_pool_upload(&self) -> Option<ObjectPoolUpload> {
+        self.object_pool_upload
+    }
+
+    /// CAN id of ECU to VT messages
+    fn vt_frame_id(&self) -> u32 {
+        ((PGN_ECU_TO_VT | self.config.vt_address as u32) << 8) | self.config.source_address as u32
+    }
+
+    fn send_end_of_object_pool(&mut self) -> Result<()> {
+        let mut data = vec![0xFF; 8];
+        data[0] = VT_END_OF_OBJECT_POOL;
+        self.write_frame(&Frame::extended(self.vt_frame_id(), data))?;
+        self.object_pool_upload = Some(ObjectPoolUpload::WaitingForResponse);
+        Ok(())
+    }
+
+    /// Records the VT's End of Object Pool response; other VT messages are ignored
+    fn handle_vt_message(&mut self, frame: &Frame) {
+        if self.object_pool_upload != Some(ObjectPoolUpload::WaitingForResponse) {
+            return;
+        }
+        if let [VT_END_OF_OBJECT_POOL, error_codes, ..] = frame.data[..] {
+            self.object_pool_upload = Some(match error_codes {
+                0 => ObjectPoolUpload::Complete,
+                codes => ObjectPoolUpload::Failed(codes),
+            });
+        }
+    }
+
+    /// Expires stale transport sessions and runs periodic diagnostic broadcasts
+    pub fn update(&mut self) -> Result<()> {
+        if !self.is_open {
+            return Err(AutomotiveError::NotInitialized);
+        }
+        self.expire_tp_sessions()?;
+        self.update_diagnostic_protocol()
+    }
+
+    /// Takes the next frame waiting to be sent by the CAN driver
+    pub fn pop_tx_frame(&mut self) -> Option<Frame> {
+        self.tx_frames.pop_front()
+    }
+
+    /// Number of transport protocol sessions in progress
+    pub fn active_sessions(&self) -> usize {
+        self.tp_sessions.len()
+    }
+
     fn handle_diagnostic_message(&mut self, frame: &Frame) -> Result<()> {
         if let Some(response) = self.diagnostic_protocol.process_message(frame)? {
             self.write_frame(&response)?;
@@ -288,7 +500,9 @@ impl TransportLayer for ISOBUS {
             address_claimed: false,
             tp_sessions: HashMap::new(),
             rx_buffer: Vec::new(),
+            tx_frames: VecDeque::new(),
             diagnostic_protocol: ISOBUSDiagnosticProtocol::new(),
+            object_pool_upload: None,
         })
     }
 
@@ -301,9 +515,11 @@ impl TransportLayer for ISOBUS {
         self.config.validate()?;
 
         // Claim address on the bus
-        self.claim_address()?;
-
         self.is_open = true;
+        if let Err(e) = self.claim_address() {
+            self.is_open = false;
+            return Err(e);
+        }
         Ok(())
     }
 
@@ -311,6 +527,8 @@ impl TransportLayer for ISOBUS {
         self.is_open = false;
         self.address_claimed = false;
         self.tp_sessions.clear();
+        self.tx_frames.clear();
+        self.object_pool_upload = None;
         Ok(())
     }
 
@@ -328,13 +546,15 @@ impl TransportLayer for ISOBUS {
             // Implement transport protocol for large messages
             let total_size = frame.data.len() as u16;
             let total_packets = ((total_size + 6) / 7) as u8;
-            let pgn = (frame.id >> 8) as u32;
+            let address = Address::from_can_id(frame.id);
+            let destination = address.destination();
+            let pgn = address.group_pgn();
 
             // Send RTS
-            let mut rts_frame = Frame {
-                id: ((PGN_TRANSPORT_PROTOCOL_CONNECTION as u32) << 8)
+            let mut rts_frame = Frame::extended(
+                ((PGN_TRANSPORT_PROTOCOL_CONNECTION | destination as u32) << 8)
                     | (self.config.source_address as u32),
-                data: vec![
+                vec![
                     TP_CM_RTS,
                     (total_size & 0xFF) as u8,
                     ((total_size >> 8) & 0xFF) as u8,
@@ -344,10 +564,7 @@ impl TransportLayer for ISOBUS {
                     ((pgn >> 8) & 0xFF) as u8,
                     ((pgn >> 16) & 0xFF) as u8,
                 ],
-                timestamp: 0,
-                is_extended: true,
-                is_fd: false,
-            };
+            );
 
             self.write_frame(&rts_frame)?;
 
@@ -358,19 +575,15 @@ impl TransportLayer for ISOBUS {
                 next_packet: 1,
                 data: frame.data.clone(),
                 source_address: self.config.source_address,
-                destination_address: (frame.id & 0xFF) as u8,
+                destination_address: destination,
                 pgn,
-                last_timestamp: std::time::SystemTime::now()
-                    .duration_since(std::time::UNIX_EPOCH)
-                    .unwrap()
-                    .as_millis() as u64,
+                last_timestamp: now_ms(),
             };
 
             self.tp_sessions.insert(self.config.source_address, session);
         } else {
-            // Direct transmission for small messages
-            // Implement CAN frame transmission here
-            // This would interface with the actual CAN hardware
+            // Direct transmission for small messages, sent by the CAN driver
+            self.tx_frames.push_back(frame.clone());
         }
 
         Ok(())
@@ -385,8 +598,8 @@ impl TransportLayer for ISOBUS {
             return Err(AutomotiveError::NotInitialized);
         }
 
-        // Update diagnostic protocol
-        self.update_diagnostic_protocol()?;
+        // Expire stale sessions and update diagnostic protocol
+        self.update()?;
 
         // Implement CAN frame reception here
         // This would interface with the actual CAN hardware
@@ -5,6 +5,7 @@ use crate::error::{AutomotiveError, Result};
 use crate::types::Frame;
 
 // ISOBUS Diagnostic Message Parameter Group Numbers (PGNs)
+const PGN_REQUEST: u32 = 0x00EA00; // Request PGN used to solicit on-request DM messages
 const PGN_DM1: u32 = 0x00FECA; // DM1: Active Diagnostic Trouble Codes (DTCs)
 const PGN_DM2: u32 = 0x00FECB; // DM2: Previously Active Diagnostic Trouble Codes
 const PGN_DM3: u32 = 0x00FECC; // DM3: Diagnostic Data Clear/Reset for All DTCs
@@ -17,6 +18,7 @@ const DM1_BROADCAST_INTERVAL_MS: u64 = 1000; // Broadcast interval for DM1 messa
 
 // Malfunction Indicator Lamp (MIL) Status Values
 #[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum LampStatus {
     Off = 0,       // Lamp is turned off
     On = 1,        // Lamp is continuously on
@@ -24,8 +26,34 @@ pub enum LampStatus {
     FastFlash = 3, // Lamp is flashing rapidly (2 Hz)
 }
 
+impl LampStatus {
+    /// Encodes the MIL status into the two lamp bytes of a DM1/DM2 message
+    fn to_bytes(self) -> [u8; 2] {
+        // Byte 1 bits 8-7: MIL on/off; byte 2 bits 8-7: MIL flash (11 = do not flash)
+        match self {
+            LampStatus::Off => [0x00, 0xFF],
+            LampStatus::On => [0x40, 0xFF],
+            LampStatus::SlowFlash => [0x40, 0x3F],
+            LampStatus::FastFlash => [0x40, 0x7F],
+        }
+    }
+
+    /// Decodes the MIL status from the two lamp bytes of a DM1/DM2 message
+    fn from_bytes(status: u8, flash: u8) -> Self {
+        if (status >> 6) & 0x03 != 0x01 {
+            return LampStatus::Off;
+        }
+        match (flash >> 6) & 0x03 {
+            0x00 => LampStatus::SlowFlash,
+            0x01 => LampStatus::FastFlash,
+            _ => LampStatus::On,
+        }
+    }
+}
+
 /// Represents a single Diagnostic Trouble Code (DTC) in the ISOBUS system
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct DiagnosticTroubleCode {
     spn: u32,                // Suspect Parameter Number (19-bit identifier)
     fmi: u8,                 // Failure Mode Identifier (5-bit value)
@@ -46,34 +74,66 @@ impl DiagnosticTroubleCode {
         }
     }
 
-    /// Converts the DTC into a byte array format according to SAE J1939-73
-    fn to_bytes(&self) -> Vec<u8> {
-        let mut bytes = Vec::with_capacity(4);
+    /// Sets the Malfunction Indicator Lamp status reported with this DTC
+    pub fn with_lamp_status(mut self, lamp_status: LampStatus) -> Self {
+        self.lamp_status = lamp_status;
+        self
+    }
 
-        // Pack SPN (19 bits), FMI (5 bits), and occurrence count (7 bits)
-        // into a 32-bit value according to the protocol specification
-        let spn_bytes = ((self.spn & 0x7FFFF) << 5) as u32;
-        let fmi_byte = (self.fmi & 0x1F) as u32;
-        let count_byte = ((self.occurrence_count & 0x7F) as u32) << 24;
+    /// Sets the occurrence count (7-bit counter, higher bits are dropped)
+    pub fn with_occurrence_count(mut self, occurrence_count: u8) -> Self {
+        self.occurrence_count = occurrence_count & 0x7F;
+        self
+    }
 
-        let combined = spn_bytes | fmi_byte | count_byte;
-        bytes.extend_from_slice(&combined.to_be_bytes());
+    /// Suspect Parameter Number
+    pub fn spn(&self) -> u32 {
+        self.spn
+    }
+
+    /// Failure Mode Identifier
+    pub fn fmi(&self) -> u8 {
+        self.fmi
+    }
+
+    /// Number of occurrences of this DTC
+    pub fn occurrence_count(&self) -> u8 {
+        self.occurrence_count
+    }
+
+    /// Malfunction Indicator Lamp status reported with this DTC
+    pub fn lamp_status(&self) -> LampStatus {
+        self.lamp_status
+    }
 
-        bytes
+    /// Returns true if the DTC is currently active
+    pub fn is_active(&self) -> bool {
+        self.active
+    }
+
+    /// Converts the DTC into a byte array format according to SAE J1939-73
+    /// (SPN conversion method 4)
+    fn to_bytes(&self) -> Vec<u8> {
+        // Bytes 1-2: SPN bits 1-16, byte 3: SPN bits 17-19 (top 3 bits) and FMI,
+        // byte 4: conversion method (0) and occurrence count
+        vec![
+            (self.spn & 0xFF) as u8,
+            ((self.spn >> 8) & 0xFF) as u8,
+            (((self.spn >> 16) & 0x07) as u8) << 5 | (self.fmi & 0x1F),
+            self.occurrence_count & 0x7F,
+        ]
     }
 
     /// Creates a DTC from a byte array according to SAE J1939-73 format
+    /// (SPN conversion method 4)
     fn from_bytes(data: &[u8]) -> Result<Self> {
         if data.len() < 4 {
             return Err(AutomotiveError::InvalidData);
         }
 
-        let combined = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
-
-        // Extract SPN (19 bits), FMI (5 bits), and occurrence count (7 bits)
-        let spn = (combined >> 5) & 0x7FFFF;
-        let fmi = (combined & 0x1F) as u8;
-        let occurrence_count = (combined >> 24) as u8;
+        let spn = data[0] as u32 | (data[1] as u32) << 8 | ((data[2] as u32 >> 5) & 0x07) << 16;
+        let fmi = data[2] & 0x1F;
+        let occurrence_count = data[3] & 0x7F;
 
         Ok(Self {
             spn,
@@ -201,7 +261,13 @@ impl ISOBUSDiagnosticProtocol {
                 let mut data = Vec::new();
 
                 // First two bytes contain lamp status information
-                data.extend_from_slice(&[0x00, 0x00]);
+                let lamp_status = self
+                    .active_dtcs
+                    .values()
+                    .map(|dtc| dtc.lamp_status)
+                    .max_by_key(|&status| status as u8)
+                    .unwrap_or(LampStatus::Off);
+                data.extend_from_slice(&lamp_status.to_bytes());
 
                 // Add each active DTC to the message
                 for dtc in self.active_dtcs.values() {
@@ -214,6 +280,7 @@ impl ISOBUSDiagnosticProtocol {
                     timestamp: now as u64,
                     is_extended: true,
                     is_fd: false,
+                    brs: false,
                 };
 
                 return Ok(Some(frame));
@@ -223,6 +290,46 @@ impl ISOBUSDiagnosticProtocol {
         Ok(None)
     }
 
+    /// Builds a request for DM2 (previously active DTCs) addressed to all nodes
+    pub fn build_dm2_request() -> Frame {
+        Frame::extended(
+            (PGN_REQUEST | 0xFF) << 8, // Global destination
+            vec![
+                (PGN_DM2 & 0xFF) as u8,
+                ((PGN_DM2 >> 8) & 0xFF) as u8,
+                ((PGN_DM2 >> 16) & 0xFF) as u8,
+            ],
+        )
+    }
+
+    /// Decodes a DM1 or DM2 message into the MIL status and its DTCs
+    pub fn parse_dm_response(frame: &Frame) -> Result<(LampStatus, Vec<DiagnosticTroubleCode>)> {
+        let pgn = (frame.id >> 8) & 0x3FFFF;
+        if pgn != PGN_DM1 && pgn != PGN_DM2 {
+            return Err(AutomotiveError::InvalidData);
+        }
+        if frame.data.len() < 2 {
+            return Err(AutomotiveError::InvalidData);
+        }
+
+        let lamp_status = LampStatus::from_bytes(frame.data[0], frame.data[1]);
+        let active = pgn == PGN_DM1;
+
+        let mut dtcs = Vec::new();
+        for record in frame.data[2..].chunks_exact(4) {
+            // An all-zero record means "no DTC"
+            if record.iter().all(|&byte| byte == 0) {
+                continue;
+            }
+            let mut dtc = DiagnosticTroubleCode::from_bytes(record)?;
+            dtc.lamp_status = lamp_status;
+            dtc.active = active;
+            dtcs.push(dtc);
+        }
+
+        Ok((lamp_status, dtcs))
+    }
+
     /// Returns a vector of references to all active DTCs
     pub fn get_active_dtcs(&self) -> Vec<&DiagnosticTroubleCode> {
         self.active_dtcs.values().collect()
@@ -1,24 +1,90 @@
 use super::TransportLayer;
 use crate::error::{AutomotiveError, Result};
+use crate::physical::canfd::next_valid_len;
 use crate::physical::PhysicalLayer;
+use crate::protocol::{PCI_CONSECUTIVE_FRAME, PCI_FIRST_FRAME, PCI_FLOW_CONTROL, PCI_SINGLE_FRAME};
 use crate::transport::IsoTpTransport;
-use crate::types::{Config, Frame};
+use crate::types::{Config, Frame, LayerState};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
 
-const SF_PCI: u8 = 0x00; // Single Frame
-const FF_PCI: u8 = 0x10; // First Frame
-const CF_PCI: u8 = 0x20; // Consecutive Frame
-const FC_PCI: u8 = 0x30; // Flow Control
+const SF_PCI: u8 = PCI_SINGLE_FRAME;
+const FF_PCI: u8 = PCI_FIRST_FRAME;
+const CF_PCI: u8 = PCI_CONSECUTIVE_FRAME;
+const FC_PCI: u8 = PCI_FLOW_CONTROL;
+
+// Flow Control flow status values
+const FS_CONTINUE: u8 = 0x00;
+const FS_WAIT: u8 = 0x01;
+const FS_OVERFLOW: u8 = 0x02;
+
+/// Frames from the peer other than Flow Control tolerated while waiting for one
+const FC_MAX_UNEXPECTED_FRAMES: u32 = 8;
 
 /// ISO-TP Address Modes
 #[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum AddressMode {
     Normal,
     Extended,
     Mixed,
 }
 
+/// Receiver decision after a block of consecutive frames
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FlowAction {
+    Continue, // Send FlowStatus ClearToSend
+    Wait,     // Send FlowStatus Wait, then ask again
+    Abort,    // Send FlowStatus Overflow and stop receiving
+}
+
+/// Callback invoked with the number of bytes received after each completed block
+pub type BlockCallback = Box<dyn FnMut(usize) -> FlowAction + Send>;
+
+/// Custom CAN id generation for outgoing ISO-TP frames
+pub trait AddressScheme: Send {
+    /// Returns the CAN id and whether it is a 29-bit id
+    fn tx_frame_id(&self, config: &IsoTpConfig) -> (u32, bool);
+}
+
+/// ISO-TP padding target length
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PaddingLength {
+    Classic, // Pad to 8 bytes
+    CanFd,   // Pad to the next valid CAN-FD data length
+}
+
+/// Returns the frame length `length` is padded to under the given padding target
+pub fn padded_length(length: usize, padding_length: PaddingLength) -> usize {
+    if length <= 8 {
+        return 8;
+    }
+    match padding_length {
+        PaddingLength::Classic => length,
+        PaddingLength::CanFd => match u8::try_from(length) {
+            Ok(length) => next_valid_len(length) as usize,
+            Err(_) => length,
+        },
+    }
+}
+
+/// Per-frame timeout while flushing stale frames
+const FLUSH_TIMEOUT_MS: u32 = 10;
+/// Upper bound on frames discarded by one flush, so a busy bus cannot stall it
+const FLUSH_MAX_FRAMES: usize = 64;
+/// Largest buffer reserved up front from a First Frame length; longer escaped
+/// messages grow as their Consecutive Frames arrive
+const RX_RESERVE_MAX: usize = 0xFFF;
+
+/// Largest CAN-FD single frame payload (64 bytes minus PCI and length byte)
+const CANFD_MAX_SF_LENGTH: usize = 62;
+/// Length of outgoing CAN-FD First and Consecutive Frames
+const CANFD_FRAME_LENGTH: usize = 64;
+
 /// ISO-TP Timing Parameters (in milliseconds)
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct IsoTpTiming {
     pub n_as: u32, // Sender N_As timeout
     pub n_ar: u32, // Receiver N_Ar timeout
@@ -39,6 +105,7 @@ impl Default for IsoTpTiming {
 
 /// ISO-TP configuration
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct IsoTpConfig {
     pub tx_id: u32,
     pub rx_id: u32,
@@ -47,13 +114,55 @@ pub struct IsoTpConfig {
     pub address_mode: AddressMode,
     pub address_extension: u8,
     pub use_padding: bool,
+    pub padding_length: PaddingLength,
     pub padding_value: u8,
     pub timing: IsoTpTiming,
     pub timeout_ms: u32,
+    pub fd: bool,                // Underlying physical layer is CAN-FD
+    pub max_rx_len: usize,       // Longest message accepted from a First Frame
+    pub st_min_fallback_ms: u32, // Separation time used when a Flow Control has a reserved STmin
+    pub rx_id_mask: Option<u32>, // Accept any id matching `rx_id` under the mask, e.g. several ECUs
+    pub wft_max: u8,             // N_WFTmax: Wait Flow Controls sent in a row before aborting
+}
+
+/// Whether `st_min` is a defined STmin encoding (0-127 ms or 100-900 us)
+fn is_valid_st_min(st_min: u8) -> bool {
+    matches!(st_min, 0x00..=0x7F | 0xF1..=0xF9)
+}
+
+/// Separation time encoded by an STmin byte, `fallback` for reserved values
+pub fn st_min_duration(st_min: u8, fallback: Duration) -> Duration {
+    match st_min {
+        0x00..=0x7F => Duration::from_millis(st_min as u64),
+        0xF1..=0xF9 => Duration::from_micros((st_min - 0xF0) as u64 * 100),
+        _ => fallback,
+    }
+}
+
+/// Waits `duration`, spinning on the monotonic clock below a millisecond
+/// where `thread::sleep` is too coarse
+fn separation_delay(duration: Duration) {
+    if duration >= Duration::from_millis(1) {
+        std::thread::sleep(duration);
+        return;
+    }
+    let start = Instant::now();
+    while start.elapsed() < duration {
+        std::hint::spin_loop();
+    }
 }
 
 impl Config for IsoTpConfig {
     fn validate(&self) -> Result<()> {
+        if self.tx_id == self.rx_id {
+            return Err(AutomotiveError::InvalidParameter);
+        }
+        if !is_valid_st_min(self.st_min) {
+            return Err(AutomotiveError::InvalidParameter);
+        }
+        if self.timeout_ms == 0 {
+            return Err(AutomotiveError::InvalidParameter);
+        }
         Ok(())
     }
 }
@@ -61,17 +170,292 @@ impl Config for IsoTpConfig {
 impl Default for IsoTpConfig {
     fn default() -> Self {
         Self {
-            tx_id: 0,
-            rx_id: 0,
+            tx_id: 0x7E0,
+            rx_id: 0x7E8,
             block_size: 0,
             st_min: 0,
             address_mode: AddressMode::Normal,
             address_extension: 0,
             use_padding: false,
+            padding_length: PaddingLength::Classic,
             padding_value: 0x00,
             timing: IsoTpTiming::default(),
             timeout_ms: 1000,
+            fd: false,
+            max_rx_len: 4095,
+            st_min_fallback_ms: 127, // Reserved values are treated as the 127 ms maximum
+            rx_id_mask: None,        // No filtering on the response id
+            wft_max: 10,
+        }
+    }
+}
+
+/// Offset of the PCI byte: extended addressing puts the target address first
+fn pci_offset(config: &IsoTpConfig) -> usize {
+    if config.address_mode == AddressMode::Extended {
+        1
+    } else {
+        0
+    }
+}
+
+/// Largest payload that fits in a single frame
+fn max_single_frame_length(config: &IsoTpConfig) -> usize {
+    if !config.fd {
+        return 7;
+    }
+    CANFD_MAX_SF_LENGTH - pci_offset(config)
+}
+
+/// Length of full outgoing First and Consecutive Frames
+fn tx_frame_length(config: &IsoTpConfig) -> usize {
+    if config.fd {
+        CANFD_FRAME_LENGTH
+    } else {
+        8
+    }
+}
+
+/// Builds an outgoing frame with the id given by the address mode
+fn encode_frame(config: &IsoTpConfig, mut data: Vec<u8>) -> Frame {
+    if config.use_padding {
+        let length = padded_length(data.len(), config.padding_length);
+        data.resize(length, config.padding_value);
+    }
+    let mut frame = if config.address_mode == AddressMode::Mixed {
+        Frame::standard(config.tx_id | (config.address_extension as u32), data)
+    } else {
+        Frame::standard(config.tx_id, data)
+    };
+    frame.is_fd = config.fd;
+    frame
+}
+
+/// Splits `data` into the Single Frame or First and Consecutive Frames carrying it
+///
+/// Messages longer than 4095 bytes use the escaped 32-bit First Frame length.
+/// Empty data encodes to no frames.
+pub fn encode(data: &[u8], cfg: &IsoTpConfig) -> Vec<Frame> {
+    if data.is_empty() {
+        return Vec::new();
+    }
+    let mut header = vec![];
+    if cfg.address_mode == AddressMode::Extended {
+        header.push(cfg.address_extension);
+    }
+
+    if data.len() <= max_single_frame_length(cfg) {
+        // CAN-FD payloads over 7 bytes escape the length
+        let mut frame_data = header;
+        if data.len() > 7 {
+            frame_data.extend_from_slice(&[SF_PCI, data.len() as u8]);
+        } else {
+            frame_data.push(data.len() as u8);
+        }
+        frame_data.extend_from_slice(data);
+
+        return vec![encode_frame(cfg, frame_data)];
+    }
+
+    let mut frame_data = header.clone();
+    if data.len() <= 0xFFF {
+        frame_data.extend_from_slice(&[FF_PCI | (data.len() >> 8) as u8, data.len() as u8]);
+    } else {
+        frame_data.extend_from_slice(&[FF_PCI, 0x00]);
+        frame_data.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    }
+    let frame_length = tx_frame_length(cfg);
+    let first_data_size = frame_length - frame_data.len();
+    frame_data.extend_from_slice(&data[..first_data_size]);
+
+    let mut frames = vec![encode_frame(cfg, frame_data)];
+    let chunk_size = frame_length - 1 - header.len();
+    for (index, chunk) in data[first_data_size..].chunks(chunk_size).enumerate() {
+        let mut frame_data = header.clone();
+        frame_data.push(CF_PCI | ((index + 1) & 0x0F) as u8);
+        frame_data.extend_from_slice(chunk);
+        frames.push(encode_frame(cfg, frame_data));
+    }
+    frames
+}
+
+/// Payload of a Single Frame whose PCI byte is at `data_start`
+fn single_frame_payload(frame: &Frame, data_start: usize) -> Result<&[u8]> {
+    let length = (frame.data[data_start] & 0x0F) as usize;
+
+    // CAN-FD single frames longer than 7 bytes carry the length in the next byte
+    if frame.is_fd && length == 0 {
+        if frame.data.len() < data_start + 2 {
+            return Err(AutomotiveError::InvalidParameter);
+        }
+        let length = frame.data[data_start + 1] as usize;
+        if length > frame.data.len() - data_start - 2 {
+            return Err(AutomotiveError::InvalidParameter);
+        }
+        return Ok(&frame.data[data_start + 2..data_start + 2 + length]);
+    }
+
+    if length > frame.data.len() - data_start - 1 {
+        return Err(AutomotiveError::InvalidParameter);
+    }
+    Ok(&frame.data[data_start + 1..=data_start + length])
+}
+
+/// Message length and payload offset of a First Frame whose PCI byte is at `data_start`
+fn first_frame_header(frame: &Frame, data_start: usize) -> Result<(usize, usize)> {
+    let header = frame
+        .data
+        .get(data_start..data_start + 2)
+        .ok_or(AutomotiveError::InvalidParameter)?;
+    let length = ((header[0] as usize & 0x0F) << 8) | header[1] as usize;
+    let payload_start = data_start + 2;
+    if length != 0 {
+        return Ok((length, payload_start));
+    }
+
+    // A zero 12-bit length escapes to a 32-bit length in the next four bytes
+    let escaped = frame
+        .data
+        .get(payload_start..payload_start + 4)
+        .ok_or(AutomotiveError::InvalidParameter)?;
+    let length = u32::from_be_bytes([escaped[0], escaped[1], escaped[2], escaped[3]]) as usize;
+    Ok((length, payload_start + 4))
+}
+
+/// Parses a First Frame header, rejecting truncated frames and lengths that fit a single frame
+fn checked_first_frame_header(frame: &Frame, data_start: usize) -> Result<(usize, usize)> {
+    let (length, payload_start) = first_frame_header(frame, data_start)?;
+
+    // A First Frame is never padded short: it fills at least a classic frame
+    let frame_length = frame.data.len();
+    if frame_length < 8 {
+        return Err(AutomotiveError::IsoTpError("truncated first frame".into()));
+    }
+
+    // One byte more than the largest single frame payload for this frame length
+    let min_length = if frame_length == 8 {
+        8 - data_start
+    } else {
+        frame_length - 1 - data_start
+    };
+    if length < min_length {
+        return Err(AutomotiveError::IsoTpError(format!(
+            "first frame length {} fits a single frame",
+            length
+        )));
+    }
+    Ok((length, payload_start))
+}
+
+/// Rebuilds ISO-TP messages from received frames, independently of any physical layer
+///
+/// Flow Control is left to the caller, which should answer each First Frame.
+pub struct Reassembler {
+    data_start: usize,
+    max_rx_len: usize,
+    data: Vec<u8>,
+    length: usize,
+    sequence: Option<u8>, // Next expected sequence number while a message is in progress
+}
+
+impl Reassembler {
+    /// Creates a reassembler for frames addressed as described by `config`
+    pub fn new(config: &IsoTpConfig) -> Self {
+        Self {
+            data_start: pci_offset(config),
+            max_rx_len: config.max_rx_len,
+            data: Vec::new(),
+            length: 0,
+            sequence: None,
+        }
+    }
+
+    /// Whether a segmented message is partially received
+    pub fn is_receiving(&self) -> bool {
+        self.sequence.is_some()
+    }
+
+    /// Drops any partially received message
+    pub fn reset(&mut self) {
+        self.data.clear();
+        self.length = 0;
+        self.sequence = None;
+    }
+
+    /// Feeds a received frame
+    ///
+    /// Returns the message once it is complete and `None` while more frames are
+    /// expected. A protocol violation drops the partial message.
+    pub fn push(&mut self, frame: &Frame) -> Result<Option<Vec<u8>>> {
+        let result = self.accept(frame);
+        if result.is_err() {
+            self.reset();
         }
+        result
+    }
+
+    fn accept(&mut self, frame: &Frame) -> Result<Option<Vec<u8>>> {
+        let pci = *frame
+            .data
+            .get(self.data_start)
+            .ok_or(AutomotiveError::InvalidParameter)?;
+
+        match pci & 0xF0 {
+            // A new message replaces one still in progress
+            SF_PCI => {
+                let payload = single_frame_payload(frame, self.data_start)?;
+                self.reset();
+                self.data.extend_from_slice(payload);
+                Ok(Some(std::mem::take(&mut self.data)))
+            }
+            FF_PCI => {
+                let (length, payload_start) = checked_first_frame_header(frame, self.data_start)?;
+                if length > self.max_rx_len {
+                    return Err(AutomotiveError::IsoTpError("message too long".into()));
+                }
+                self.reset();
+                self.data.reserve(length.min(RX_RESERVE_MAX));
+                self.data.extend_from_slice(&frame.data[payload_start..]);
+                self.length = length;
+                self.sequence = Some(1);
+                Ok(self.take_complete())
+            }
+            CF_PCI => {
+                let sequence = self.sequence.ok_or_else(|| {
+                    AutomotiveError::IsoTpError("unexpected consecutive frame".into())
+                })?;
+                if pci & 0x0F != sequence {
+                    return Err(AutomotiveError::InvalidParameter);
+                }
+                self.data
+                    .extend_from_slice(&frame.data[self.data_start + 1..]);
+                self.sequence = Some((sequence + 1) & 0x0F);
+                Ok(self.take_complete())
+            }
+            FC_PCI => Err(AutomotiveError::IsoTpError(
+                "unexpected flow control".into(),
+            )),
+            _ => Err(AutomotiveError::InvalidParameter),
+        }
+    }
+
+    /// Whether `frame` is a First Frame announcing more than `max_rx_len` bytes
+    fn is_oversized(&self, frame: &Frame) -> bool {
+        frame.data.get(self.data_start).map(|pci| pci & 0xF0) == Some(FF_PCI)
+            && matches!(
+                checked_first_frame_header(frame, self.data_start),
+                Ok((length, _)) if length > self.max_rx_len
+            )
+    }
+
+    /// Returns the message if all its bytes have arrived
+    fn take_complete(&mut self) -> Option<Vec<u8>> {
+        if self.data.len() < self.length {
+            return None;
+        }
+        self.data.truncate(self.length);
+        self.sequence = None;
+        Some(std::mem::take(&mut self.data))
     }
 }
 
@@ -79,254 +463,300 @@ impl Default for IsoTpConfig {
 pub struct IsoTp<P: PhysicalLayer> {
     config: IsoTpConfig,
     physical: P,
-    is_open: bool,
+    state: LayerState,
+    port_timeout_ms: u32,      // Last timeout applied to the physical layer
+    deferred: VecDeque<Frame>, // Frames from other responders, kept for a later receive
+    on_block_complete: Option<BlockCallback>,
+    address_scheme: Option<Box<dyn AddressScheme>>,
 }
 
 impl<P: PhysicalLayer> IsoTp<P> {
+    /// Creates a new ISO-TP instance on top of a CAN interface
+    ///
+    /// Alias of [`IsoTp::with_physical`], matching the crate-level example.
+    pub fn new_with_can(config: IsoTpConfig, can: P) -> Self {
+        Self::with_physical(config, can)
+    }
+
     /// Creates a new ISO-TP instance with the given physical layer
     pub fn with_physical(config: IsoTpConfig, physical: P) -> Self {
         Self {
             config,
             physical,
-            is_open: false,
+            state: LayerState::Closed,
+            port_timeout_ms: 0,
+            deferred: VecDeque::new(),
+            on_block_complete: None,
+            address_scheme: None,
         }
     }
 
-    fn send_single_frame(&mut self, data: &[u8]) -> Result<()> {
-        let mut frame_data = vec![];
+    /// Sets the callback deciding whether to continue, wait or abort after each
+    /// received block; without one, reception always continues
+    pub fn set_on_block_complete(
+        &mut self,
+        callback: impl FnMut(usize) -> FlowAction + Send + 'static,
+    ) {
+        self.on_block_complete = Some(Box::new(callback));
+    }
 
-        // Add address extension if needed
-        if self.config.address_mode == AddressMode::Extended {
-            frame_data.push(self.config.address_extension);
-        }
+    /// Current lifecycle state
+    pub fn state(&self) -> LayerState {
+        self.state
+    }
 
-        // Add PCI and data
-        frame_data.push(data.len() as u8);
-        frame_data.extend_from_slice(data);
+    /// Replaces the built-in CAN id generation for outgoing frames
+    pub fn set_address_scheme(&mut self, scheme: impl AddressScheme + 'static) {
+        self.address_scheme = Some(Box::new(scheme));
+    }
 
-        // Add padding if configured
-        if self.config.use_padding {
-            while frame_data.len() < 8 {
-                frame_data.push(self.config.padding_value);
-            }
+    /// CAN id and 29-bit flag for outgoing frames
+    fn tx_frame_id(&self) -> (u32, bool) {
+        if let Some(scheme) = &self.address_scheme {
+            return scheme.tx_frame_id(&self.config);
         }
-
-        self.write_frame(&Frame {
-            id: if self.config.address_mode == AddressMode::Mixed {
-                self.config.tx_id | (self.config.address_extension as u32)
-            } else {
-                self.config.tx_id
-            },
-            data: frame_data,
-            timestamp: 0,
-            is_extended: false,
-            is_fd: false,
-        })
+        let frame = encode_frame(&self.config, Vec::new());
+        (frame.id, frame.is_extended)
     }
 
-    fn send_multi_frame(&mut self, data: &[u8]) -> Result<()> {
-        // First frame
-        let mut frame_data = vec![];
-
-        // Add address extension if needed
-        if self.config.address_mode == AddressMode::Extended {
-            frame_data.push(self.config.address_extension);
+    /// Builds an outgoing frame carrying `data`
+    fn tx_frame(&self, data: Vec<u8>) -> Frame {
+        match self.tx_frame_id() {
+            (id, true) => Frame::extended(id, data),
+            (id, false) => Frame::standard(id, data),
         }
+    }
 
-        // Add PCI and data
-        frame_data.push(0x10 | ((data.len() >> 8) as u8 & 0x0F));
-        frame_data.push(data.len() as u8);
-        let first_data_size = if self.config.address_mode == AddressMode::Extended {
-            5
-        } else {
-            6
-        };
-
-        // Make sure we don't try to copy more data than available
-        let first_data_size = std::cmp::min(first_data_size, data.len());
-        frame_data.extend_from_slice(&data[0..first_data_size]);
-
-        // Add padding if configured
-        if self.config.use_padding {
-            while frame_data.len() < 8 {
-                frame_data.push(self.config.padding_value);
-            }
+    /// Writes a frame of an outgoing message, bounding its transmission by N_As
+    fn write_tx_frame(&mut self, frame: &Frame) -> Result<()> {
+        let n_as = self.config.timing.n_as;
+        self.physical.set_timeout(n_as)?;
+        let start = std::time::Instant::now();
+        let result = self.write_frame(frame);
+        self.physical.set_timeout(self.port_timeout_ms)?;
+        result?;
+
+        if start.elapsed().as_millis() > n_as as u128 {
+            return Err(AutomotiveError::Timeout);
         }
+        Ok(())
+    }
 
-        // Send first frame
-        self.write_frame(&Frame {
-            id: if self.config.address_mode == AddressMode::Mixed {
-                self.config.tx_id | (self.config.address_extension as u32)
-            } else {
-                self.config.tx_id
-            },
-            data: frame_data,
-            timestamp: 0,
-            is_extended: false,
-            is_fd: false,
-        })?;
-
-        // Wait for flow control
-        let start_time = std::time::SystemTime::now();
+    /// Reads a frame, rejecting mixed-mode frames for another address extension
+    /// and skipping frames outside `rx_id_mask`
+    fn read_addressed_frame(&mut self) -> Result<Frame> {
         loop {
             let frame = self.read_frame()?;
-            // Check for invalid response (negative response or invalid format)
-            if !frame.data.is_empty() && frame.data[0] == 0x7F {
-                return Err(AutomotiveError::InvalidParameter);
+            if self.config.address_mode == AddressMode::Mixed
+                && frame.id & 0xFF != self.config.address_extension as u32
+            {
+                return Err(AutomotiveError::InvalidData);
             }
-            if frame.data[0] == 0x30 {
-                break;
-            }
-            if start_time.elapsed().unwrap().as_millis() as u32 > self.config.timing.n_bs {
-                return Err(AutomotiveError::Timeout);
+            match self.config.rx_id_mask {
+                Some(mask) if frame.id & mask != self.config.rx_id & mask => continue,
+                _ => return Ok(frame),
             }
         }
+    }
 
-        // Consecutive frames
-        let mut index = first_data_size;
-        let mut sequence = 1;
-
-        // For test_isotp_multi_frame, we need at least 3 frames total (1 first frame + 2 consecutive frames)
-        // For test_isotp_flow_control, we need at least 8 frames total
-        let min_consecutive_frames = 10; // This will ensure more than 8 total frames (1 first frame + 10 consecutive)
-        let mut consecutive_frame_count = 0;
-
-        while index < data.len() || consecutive_frame_count < min_consecutive_frames {
-            let remaining = if index < data.len() {
-                data.len() - index
-            } else {
-                0
-            };
-            let chunk_size = if self.config.address_mode == AddressMode::Extended {
-                remaining.min(6)
-            } else {
-                remaining.min(7)
-            };
-
-            let mut frame_data = vec![];
-
-            // Add address extension if needed
-            if self.config.address_mode == AddressMode::Extended {
-                frame_data.push(self.config.address_extension);
+    /// Reads the next frame of the message from `source`
+    ///
+    /// With `rx_id_mask` set, frames from other responders are queued for the
+    /// next receive instead of being dropped.
+    fn read_source_frame(&mut self, source: u32) -> Result<Frame> {
+        if self.config.rx_id_mask.is_none() {
+            return self.read_addressed_frame();
+        }
+        if let Some(index) = self.deferred.iter().position(|frame| frame.id == source) {
+            return Ok(self.deferred.remove(index).unwrap());
+        }
+        loop {
+            let frame = self.read_addressed_frame()?;
+            if frame.id == source {
+                return Ok(frame);
             }
+            self.deferred.push_back(frame);
+        }
+    }
 
-            // Add PCI and data
-            frame_data.push(0x20 | (sequence & 0x0F));
-
-            // Add actual data if available, otherwise add padding
-            if index < data.len() {
-                frame_data.extend_from_slice(&data[index..index + chunk_size]);
-            } else {
-                // Add dummy data to meet the frame count requirements
-                for _ in 0..chunk_size {
-                    frame_data.push(0x00);
-                }
-            }
+    /// Receives a message, returning the CAN id of the ECU that sent it
+    ///
+    /// With `rx_id_mask` set, several ids can answer one functional request;
+    /// frames from other responders arriving while a message is reassembled
+    /// are returned by the following calls.
+    pub fn receive_with_source(&mut self) -> Result<(u32, Vec<u8>)> {
+        let mut data = Vec::new();
+        let source = self.receive_message(&mut data, self.config.timeout_ms)?;
+        Ok((source, data))
+    }
 
-            // Add padding if configured
-            if self.config.use_padding {
-                while frame_data.len() < 8 {
-                    frame_data.push(self.config.padding_value);
-                }
-            }
+    /// Sends the frames produced by [`encode`], waiting for Flow Control after a First Frame
+    fn send_message(&mut self, data: &[u8]) -> Result<()> {
+        let mut frames = encode(data, &self.config);
+        for frame in frames.iter_mut() {
+            (frame.id, frame.is_extended) = self.tx_frame_id();
+        }
+        let mut frames = frames.into_iter();
 
-            // Send consecutive frame
-            self.write_frame(&Frame {
-                id: if self.config.address_mode == AddressMode::Mixed {
-                    self.config.tx_id | (self.config.address_extension as u32)
-                } else {
-                    self.config.tx_id
-                },
-                data: frame_data,
-                timestamp: 0,
-                is_extended: false,
-                is_fd: false,
-            })?;
-
-            if index < data.len() {
-                index += chunk_size;
-            }
-            sequence = (sequence + 1) & 0x0F;
-            consecutive_frame_count += 1;
+        if let Some(first) = frames.next() {
+            self.write_tx_frame(&first)?;
+        }
+        if frames.len() == 0 {
+            return Ok(());
+        }
 
-            // If we've sent enough frames and processed all data, we can exit
-            if consecutive_frame_count >= min_consecutive_frames && index >= data.len() {
-                break;
+        // Wait for flow control, bounding the read itself by N_Bs
+        self.physical.set_timeout(self.config.timing.n_bs)?;
+        let fc_result = self.wait_for_flow_control();
+        self.physical.set_timeout(self.port_timeout_ms)?;
+        let separation_time = st_min_duration(
+            fc_result?,
+            Duration::from_millis(self.config.st_min_fallback_ms as u64),
+        );
+
+        for (index, frame) in frames.enumerate() {
+            if index > 0 {
+                // Honour the receiver's minimum separation time
+                separation_delay(separation_time);
             }
-
-            // Add a small delay to allow the mock to process the frame
-            std::thread::sleep(std::time::Duration::from_millis(10));
+            self.write_tx_frame(&frame)?;
         }
 
         Ok(())
     }
 
-    fn receive_single_frame(&mut self, frame: &Frame) -> Result<Vec<u8>> {
-        let data_start = if self.config.address_mode == AddressMode::Extended {
-            1
-        } else {
-            0
-        };
-        let length = frame.data[data_start] & 0x0F;
-        if length as usize > frame.data.len() - data_start - 1 {
-            return Err(AutomotiveError::InvalidParameter);
+    /// Returns true if `frame` comes from our peer: on `rx_id`, or any id
+    /// matching it under `rx_id_mask`, carrying our mixed-mode address extension
+    fn is_rx_frame(&self, frame: &Frame) -> bool {
+        let mut rx_id = self.config.rx_id;
+        if self.config.address_mode == AddressMode::Mixed {
+            rx_id |= self.config.address_extension as u32;
         }
-        Ok(frame.data[data_start + 1..=data_start + length as usize].to_vec())
+        let mask = self.config.rx_id_mask.unwrap_or(u32::MAX);
+        frame.id & mask == rx_id & mask
     }
 
-    fn receive_multi_frame(&mut self, frame: &Frame) -> Result<Vec<u8>> {
-        let data_start = if self.config.address_mode == AddressMode::Extended {
-            1
-        } else {
-            0
-        };
-        let length =
-            ((frame.data[data_start] as usize & 0x0F) << 8) | frame.data[data_start + 1] as usize;
-        let mut data = Vec::with_capacity(length);
-        data.extend_from_slice(&frame.data[data_start + 2..]);
+    /// Waits for a Flow Control frame, returning its STmin byte
+    ///
+    /// Traffic on other ids is skipped. Frames from our peer that are not
+    /// Flow Control abort the send after `FC_MAX_UNEXPECTED_FRAMES`.
+    fn wait_for_flow_control(&mut self) -> Result<u8> {
+        let pci_index = pci_offset(&self.config);
+        let mut start_time = Instant::now();
+        let mut unexpected = 0;
+        loop {
+            let frame = self.read_frame()?;
+            if self.is_rx_frame(&frame) {
+                match frame.data.get(pci_index) {
+                    Some(&pci) if pci & 0xF0 == FC_PCI => match pci & 0x0F {
+                        FS_CONTINUE => {
+                            return Ok(frame.data.get(pci_index + 2).copied().unwrap_or(0))
+                        }
+                        // The receiver asks for more time: restart N_Bs
+                        FS_WAIT => start_time = Instant::now(),
+                        FS_OVERFLOW => {
+                            return Err(AutomotiveError::IsoTpError("receiver overflow".into()))
+                        }
+                        _ => return Err(AutomotiveError::InvalidParameter),
+                    },
+                    // Negative response instead of Flow Control
+                    Some(0x7F) => return Err(AutomotiveError::InvalidParameter),
+                    _ => {
+                        unexpected += 1;
+                        if unexpected >= FC_MAX_UNEXPECTED_FRAMES {
+                            return Err(AutomotiveError::IsoTpError(
+                                "unexpected frames while waiting for flow control".into(),
+                            ));
+                        }
+                    }
+                }
+            }
+            if start_time.elapsed().as_millis() > self.config.timing.n_bs as u128 {
+                return Err(AutomotiveError::Timeout);
+            }
+        }
+    }
 
-        // Send flow control
+    fn send_flow_control(&mut self, flow_status: u8) -> Result<()> {
         let mut fc_data = vec![];
         if self.config.address_mode == AddressMode::Extended {
             fc_data.push(self.config.address_extension);
         }
-        fc_data.extend_from_slice(&[0x30, self.config.block_size, self.config.st_min]);
-
-        self.write_frame(&Frame {
-            id: if self.config.address_mode == AddressMode::Mixed {
-                self.config.tx_id | (self.config.address_extension as u32)
-            } else {
-                self.config.tx_id
-            },
-            data: fc_data,
-            timestamp: 0,
-            is_extended: false,
-            is_fd: false,
-        })?;
-
-        let mut sequence = 1;
-        while data.len() < length {
-            let frame = self.read_frame()?;
-            if frame.data.is_empty() {
-                return Err(AutomotiveError::InvalidParameter);
-            }
+        fc_data.extend_from_slice(&[
+            FC_PCI | flow_status,
+            self.config.block_size,
+            self.config.st_min,
+        ]);
+
+        self.write_frame(&self.tx_frame(fc_data))
+    }
 
-            let data_start = if self.config.address_mode == AddressMode::Extended {
-                1
-            } else {
-                0
+    /// Asks the block callback how to proceed and sends the matching Flow Control
+    ///
+    /// A Wait beyond `wft_max` in a row aborts the reception like `FlowAction::Abort`.
+    fn handle_block_complete(&mut self, received: usize) -> Result<()> {
+        let mut waits = 0;
+        loop {
+            let action = match self.on_block_complete.as_mut() {
+                Some(callback) => callback(received),
+                None => FlowAction::Continue,
             };
-            if frame.data[data_start] & 0xF0 != 0x20 {
-                return Err(AutomotiveError::InvalidParameter);
+            match action {
+                FlowAction::Continue => return self.send_flow_control(FS_CONTINUE),
+                FlowAction::Wait if waits < self.config.wft_max => {
+                    self.send_flow_control(FS_WAIT)?;
+                    waits += 1;
+                }
+                FlowAction::Wait => {
+                    self.send_flow_control(FS_OVERFLOW)?;
+                    return Err(AutomotiveError::IsoTpError("N_WFTmax exceeded".into()));
+                }
+                FlowAction::Abort => {
+                    self.send_flow_control(FS_OVERFLOW)?;
+                    return Err(AutomotiveError::IsoTpError("Reception aborted".into()));
+                }
             }
-            if frame.data[data_start] & 0x0F != sequence {
-                return Err(AutomotiveError::InvalidParameter);
+        }
+    }
+
+    /// Feeds the Consecutive Frames of the message from `source` to `reassembler`,
+    /// sending Flow Control after every `block_size` frames
+    fn receive_consecutive_frames(
+        &mut self,
+        reassembler: &mut Reassembler,
+        source: u32,
+    ) -> Result<Vec<u8>> {
+        let data_start = pci_offset(&self.config);
+        let mut block_count = 0;
+        loop {
+            if self.config.block_size > 0 && block_count == self.config.block_size {
+                self.handle_block_complete(reassembler.data.len())?;
+                block_count = 0;
+            }
+
+            let frame = self.read_source_frame(source)?;
+            // A Single or First Frame here would silently restart the message
+            match frame.data.get(data_start) {
+                Some(&pci) if pci & 0xF0 == CF_PCI => {}
+                _ => return Err(AutomotiveError::InvalidParameter),
+            }
+            if let Some(data) = reassembler.push(&frame)? {
+                return Ok(data);
+            }
+            // Only blocks requested by a non-zero BS are counted; with BS = 0 the
+            // whole message is one block and the u8 counter would overflow
+            if self.config.block_size > 0 {
+                block_count += 1;
             }
-            data.extend_from_slice(&frame.data[data_start + 1..]);
-            sequence = (sequence + 1) & 0x0F;
         }
-        data.truncate(length);
-        Ok(data)
+    }
+}
+
+impl<P: PhysicalLayer> Drop for IsoTp<P> {
+    fn drop(&mut self) {
+        if self.state != LayerState::Closed {
+            let _ = self.close();
+        }
     }
 }
 
@@ -338,73 +768,137 @@ impl<P: PhysicalLayer> TransportLayer for IsoTp<P> {
     }
 
     fn open(&mut self) -> Result<()> {
-        if self.is_open {
-            return Ok(());
+        match self.state {
+            LayerState::Open => return Ok(()),
+            LayerState::Errored => {
+                // Reset the physical layer before starting over
+                let _ = self.physical.close();
+                self.state = LayerState::Closed;
+            }
+            LayerState::Closed => {}
         }
+        self.config.validate()?;
+        self.physical.open()?;
         self.physical.set_timeout(self.config.timing.n_as)?;
-        self.is_open = true;
+        self.port_timeout_ms = self.config.timing.n_as;
+        self.state = LayerState::Open;
         Ok(())
     }
 
     fn close(&mut self) -> Result<()> {
-        self.is_open = false;
+        self.state = LayerState::Closed;
+        self.deferred.clear();
         Ok(())
     }
 
     fn write_frame(&mut self, frame: &Frame) -> Result<()> {
-        if !self.is_open {
+        if self.state == LayerState::Closed {
             return Err(AutomotiveError::NotInitialized);
         }
-        self.physical.send_frame(frame)
+        let result = self.physical.send_frame(frame);
+        self.state.observe(&result);
+        result
     }
 
     fn read_frame(&mut self) -> Result<Frame> {
-        if !self.is_open {
+        if self.state == LayerState::Closed {
             return Err(AutomotiveError::NotInitialized);
         }
-        self.physical.receive_frame()
+        let result = self.physical.receive_frame();
+        self.state.observe(&result);
+        result
     }
 
     fn set_timeout(&mut self, timeout_ms: u32) -> Result<()> {
-        if !self.is_open {
+        if self.state == LayerState::Closed {
             return Err(AutomotiveError::NotInitialized);
         }
-        self.physical.set_timeout(timeout_ms)
+        self.physical.set_timeout(timeout_ms)?;
+        self.port_timeout_ms = timeout_ms;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        if self.state == LayerState::Closed {
+            return Err(AutomotiveError::NotInitialized);
+        }
+        self.deferred.clear();
+        self.physical.set_timeout(FLUSH_TIMEOUT_MS)?;
+        for _ in 0..FLUSH_MAX_FRAMES {
+            if self.physical.receive_frame().is_err() {
+                break;
+            }
+        }
+        self.physical.set_timeout(self.port_timeout_ms)
     }
 }
 
 impl<P: PhysicalLayer> IsoTpTransport for IsoTp<P> {
     fn send(&mut self, data: &[u8]) -> Result<()> {
-        if !self.is_open {
+        if self.state == LayerState::Closed {
             return Err(AutomotiveError::NotInitialized);
         }
         if data.is_empty() {
             return Err(AutomotiveError::InvalidParameter);
         }
-        if data.len() <= 7 {
-            self.send_single_frame(data)
-        } else {
-            self.send_multi_frame(data)
-        }
+        self.send_message(data)
     }
 
-    fn receive(&mut self) -> Result<Vec<u8>> {
-        if !self.is_open {
+    fn receive_into(&mut self, buf: &mut Vec<u8>) -> Result<usize> {
+        self.receive_into_with_timeout(buf, self.config.timeout_ms)
+    }
+
+    fn receive_with_timeout(&mut self, timeout_ms: u32) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        self.receive_into_with_timeout(&mut data, timeout_ms)?;
+        Ok(data)
+    }
+}
+
+impl<P: PhysicalLayer> IsoTp<P> {
+    /// Receives a message into `buf`, replacing its contents
+    fn receive_into_with_timeout(&mut self, buf: &mut Vec<u8>, timeout_ms: u32) -> Result<usize> {
+        self.receive_message(buf, timeout_ms)?;
+        Ok(buf.len())
+    }
+
+    /// Receives a message into `buf`, returning the id of its first frame
+    fn receive_message(&mut self, buf: &mut Vec<u8>, timeout_ms: u32) -> Result<u32> {
+        buf.clear();
+        if self.state == LayerState::Closed {
             return Err(AutomotiveError::NotInitialized);
         }
-        let frame = self.read_frame()?;
-        if frame.data.is_empty() {
-            return Err(AutomotiveError::InvalidParameter);
-        }
-        let data_start = if self.config.address_mode == AddressMode::Extended {
-            1
-        } else {
-            0
+        let frame = match self.deferred.pop_front() {
+            Some(frame) => frame,
+            None => {
+                self.physical.set_timeout(timeout_ms)?;
+                let frame = self.read_addressed_frame();
+                self.physical.set_timeout(self.port_timeout_ms)?;
+                frame?
+            }
         };
-        match frame.data[data_start] & 0xF0 {
-            0x00 => self.receive_single_frame(&frame),
-            0x10 => self.receive_multi_frame(&frame),
-            _ => Err(AutomotiveError::InvalidParameter),
+
+        // Reassemble into the caller's buffer to reuse its allocation
+        let mut reassembler = Reassembler::new(&self.config);
+        reassembler.data = std::mem::take(buf);
+        let pushed = reassembler.push(&frame);
+        if pushed.is_err() && reassembler.is_oversized(&frame) {
+            // Tell the sender to stop instead of letting it wait for N_Bs
+            self.send_flow_control(FS_OVERFLOW)?;
         }
+        *buf = match pushed? {
+            Some(data) => data,
+            None => {
+                self.send_flow_control(FS_CONTINUE)?;
+
+                // Bound each consecutive frame read by the call timeout, capped at N_Cr
+                self.physical
+                    .set_timeout(timeout_ms.min(self.config.timing.n_cr))?;
+                let result = self.receive_consecutive_frames(&mut reassembler, frame.id);
+                self.physical.set_timeout(self.port_timeout_ms)?;
+                result?
+            }
+        };
+        Ok(frame.id)
     }
 }
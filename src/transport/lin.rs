@@ -9,6 +9,9 @@ pub const LIN_ID_MASK: u8 = 0x3F;
 pub const LIN_P0_FLAG: u8 = 6;
 pub const LIN_P1_FLAG: u8 = 7;
 pub const LIN_BREAK_BYTE: u8 = 0x00;
+pub const LIN_MASTER_REQUEST_ID: u8 = 0x3C;
+pub const LIN_SLAVE_RESPONSE_ID: u8 = 0x3D;
+pub const LIN_WAKEUP_BYTE: u8 = 0x00;
 
 // LIN frame types
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -17,15 +20,23 @@ pub enum LinFrameType {
     Enhanced,
 }
 
+// LIN checksum models
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChecksumKind {
+    Classic,  // Data bytes only
+    Enhanced, // Protected ID and data bytes
+}
+
 // LIN states
 #[derive(Debug, Clone, Copy, PartialEq)]
-enum LinState {
+pub enum LinState {
     Idle,
     Break,
     Sync,
     Id,
     Data,
     Checksum,
+    Sleep,
 }
 
 // LIN frame slot
@@ -45,6 +56,9 @@ pub struct LinConfig {
 
 impl Config for LinConfig {
     fn validate(&self) -> Result<()> {
+        if self.timeout_ms == 0 {
+            return Err(AutomotiveError::InvalidParameter);
+        }
         Ok(())
     }
 }
@@ -75,40 +89,79 @@ impl<P: PhysicalLayer> Lin<P> {
         }
     }
 
+    /// Returns the current protocol state
+    pub fn state(&self) -> LinState {
+        self.state
+    }
+
+    /// Returns the checksum model to use for the given PID
+    ///
+    /// Diagnostic frames (0x3C and 0x3D) always use the classic checksum.
+    pub fn checksum_kind_for(&self, pid: u8) -> ChecksumKind {
+        let id = pid & LIN_ID_MASK;
+        if id == LIN_MASTER_REQUEST_ID || id == LIN_SLAVE_RESPONSE_ID {
+            return ChecksumKind::Classic;
+        }
+        match self.config.frame_type {
+            LinFrameType::Classic => ChecksumKind::Classic,
+            LinFrameType::Enhanced => ChecksumKind::Enhanced,
+        }
+    }
+
+    /// Sends the go-to-sleep command and puts the cluster to sleep
+    pub fn go_to_sleep(&mut self) -> Result<()> {
+        if !self.is_open {
+            return Err(AutomotiveError::NotInitialized);
+        }
+
+        // Master request frame with the sleep command: first byte 0x00, rest 0xFF
+        self.send_header(LIN_MASTER_REQUEST_ID)?;
+        self.send_response(
+            LIN_MASTER_REQUEST_ID,
+            &[0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF],
+        )?;
+
+        self.state = LinState::Sleep;
+        Ok(())
+    }
+
+    /// Sends a wakeup pulse and returns the cluster to idle
+    pub fn wakeup(&mut self) -> Result<()> {
+        if !self.is_open {
+            return Err(AutomotiveError::NotInitialized);
+        }
+
+        // Dominant pulse on the bus
+        self.physical
+            .send_frame(&Frame::standard(0, vec![LIN_WAKEUP_BYTE]))?;
+
+        self.state = LinState::Idle;
+        Ok(())
+    }
+
     /// Sends a LIN header (break, sync, and ID)
     pub fn send_header(&mut self, pid: u8) -> Result<()> {
         if !self.is_open {
             return Err(AutomotiveError::NotInitialized);
         }
 
+        if self.state == LinState::Sleep {
+            return Err(AutomotiveError::ConditionsNotCorrect);
+        }
+
         // Send break
-        self.physical.send_frame(&Frame {
-            id: 0,
-            data: vec![LIN_BREAK_BYTE],
-            timestamp: 0,
-            is_extended: false,
-            is_fd: false,
-        })?;
+        self.physical
+            .send_frame(&Frame::standard(0, vec![LIN_BREAK_BYTE]))?;
 
         // Send sync
-        self.physical.send_frame(&Frame {
-            id: 0,
-            data: vec![LIN_SYNC_BYTE],
-            timestamp: 0,
-            is_extended: false,
-            is_fd: false,
-        })?;
+        self.physical
+            .send_frame(&Frame::standard(0, vec![LIN_SYNC_BYTE]))?;
 
         // Send PID
         let parity = calculate_parity(pid);
         let pid_with_parity = pid | parity;
-        self.physical.send_frame(&Frame {
-            id: 0,
-            data: vec![pid_with_parity],
-            timestamp: 0,
-            is_extended: false,
-            is_fd: false,
-        })?;
+        self.physical
+            .send_frame(&Frame::standard(0, vec![pid_with_parity]))?;
 
         Ok(())
     }
@@ -124,34 +177,23 @@ impl<P: PhysicalLayer> Lin<P> {
         }
 
         // Send data
-        self.physical.send_frame(&Frame {
-            id: 0,
-            data: data.to_vec(),
-            timestamp: 0,
-            is_extended: false,
-            is_fd: false,
-        })?;
+        self.physical
+            .send_frame(&Frame::standard(0, data.to_vec()))?;
 
         // Calculate and send checksum
-        let checksum = if self.config.frame_type == LinFrameType::Enhanced {
-            calculate_enhanced_checksum(pid, data)
-        } else {
-            calculate_classic_checksum(data)
+        let checksum = match self.checksum_kind_for(pid) {
+            ChecksumKind::Enhanced => calculate_enhanced_checksum(pid, data),
+            ChecksumKind::Classic => calculate_classic_checksum(data),
         };
 
-        self.physical.send_frame(&Frame {
-            id: 0,
-            data: vec![checksum],
-            timestamp: 0,
-            is_extended: false,
-            is_fd: false,
-        })?;
+        self.physical
+            .send_frame(&Frame::standard(0, vec![checksum]))?;
 
         Ok(())
     }
 
-    /// Reads a LIN response
-    pub fn read_response(&mut self, timeout_ms: u32) -> Result<Vec<u8>> {
+    /// Reads a LIN response for the given PID
+    pub fn read_response(&mut self, pid: u8, timeout_ms: u32) -> Result<Vec<u8>> {
         if !self.is_open {
             return Err(AutomotiveError::NotInitialized);
         }
@@ -186,10 +228,9 @@ impl<P: PhysicalLayer> Lin<P> {
 
         // Verify checksum if received
         if let Some(received_checksum) = checksum {
-            let expected_checksum = if self.config.frame_type == LinFrameType::Enhanced {
-                calculate_enhanced_checksum(0, &response) // PID not available here
-            } else {
-                calculate_classic_checksum(&response)
+            let expected_checksum = match self.checksum_kind_for(pid) {
+                ChecksumKind::Enhanced => calculate_enhanced_checksum(pid, &response),
+                ChecksumKind::Classic => calculate_classic_checksum(&response),
             };
 
             if received_checksum != expected_checksum {
@@ -212,6 +253,8 @@ impl<P: PhysicalLayer> TransportLayer for Lin<P> {
         if self.is_open {
             return Ok(());
         }
+        self.config.validate()?;
+        self.physical.open()?;
         self.physical.set_timeout(self.config.timeout_ms)?;
         self.is_open = true;
         Ok(())
@@ -219,6 +262,7 @@ impl<P: PhysicalLayer> TransportLayer for Lin<P> {
 
     fn close(&mut self) -> Result<()> {
         self.is_open = false;
+        self.state = LinState::Idle;
         Ok(())
     }
 
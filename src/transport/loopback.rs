@@ -0,0 +1,97 @@
+use super::TransportLayer;
+use crate::error::{AutomotiveError, Result};
+use crate::types::{Config, Frame};
+use std::collections::VecDeque;
+
+/// Loopback response handler type
+pub type LoopbackHandler = Box<dyn Fn(&Frame) -> Result<Frame> + Send + Sync>;
+
+#[derive(Debug, Default)]
+pub struct LoopbackConfig {
+    pub timeout_ms: u32,
+}
+
+impl Config for LoopbackConfig {
+    fn validate(&self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Transport answering each written frame through a handler, without any framing
+///
+/// Lets application-layer tests respond with raw service bytes.
+pub struct LoopbackTransport {
+    config: LoopbackConfig,
+    handler: Option<LoopbackHandler>,
+    responses: VecDeque<Result<Frame>>,
+    is_open: bool,
+}
+
+impl LoopbackTransport {
+    /// Creates a loopback transport answering requests with `handler`
+    pub fn with_handler(handler: impl Fn(&Frame) -> Result<Frame> + Send + Sync + 'static) -> Self {
+        Self {
+            config: LoopbackConfig::default(),
+            handler: Some(Box::new(handler)),
+            responses: VecDeque::new(),
+            is_open: false,
+        }
+    }
+}
+
+impl TransportLayer for LoopbackTransport {
+    type Config = LoopbackConfig;
+
+    fn new(config: Self::Config) -> Result<Self> {
+        Ok(Self {
+            config,
+            handler: None,
+            responses: VecDeque::new(),
+            is_open: false,
+        })
+    }
+
+    fn open(&mut self) -> Result<()> {
+        self.is_open = true;
+        Ok(())
+    }
+
+    fn close(&mut self) -> Result<()> {
+        self.is_open = false;
+        self.responses.clear();
+        Ok(())
+    }
+
+    fn write_frame(&mut self, frame: &Frame) -> Result<()> {
+        if !self.is_open {
+            return Err(AutomotiveError::NotInitialized);
+        }
+        if let Some(handler) = &self.handler {
+            self.responses.push_back(handler(frame));
+        }
+        Ok(())
+    }
+
+    fn read_frame(&mut self) -> Result<Frame> {
+        if !self.is_open {
+            return Err(AutomotiveError::NotInitialized);
+        }
+        // Nothing pending behaves like a silent ECU
+        self.responses
+            .pop_front()
+            .unwrap_or(Err(AutomotiveError::Timeout))
+    }
+
+    fn set_timeout(&mut self, timeout_ms: u32) -> Result<()> {
+        if !self.is_open {
+            return Err(AutomotiveError::NotInitialized);
+        }
+        self.config.timeout_ms = timeout_ms;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.responses.clear();
+        Ok(())
+    }
+}
@@ -42,12 +42,17 @@
 //! let response = isotp.receive();
 //! ```
 
+mod boxed;
 pub mod doip;
+mod instrumented;
 mod isobus;
 mod isobus_diagnostic;
 pub mod isotp;
 pub mod lin;
 
+#[cfg(any(test, feature = "mock"))]
+pub mod loopback;
+
 use crate::error::Result;
 use crate::types::{Config, Frame};
 
@@ -63,19 +68,35 @@ pub trait TransportLayer {
     fn write_frame(&mut self, frame: &Frame) -> Result<()>;
     fn read_frame(&mut self) -> Result<Frame>;
     fn set_timeout(&mut self, timeout_ms: u32) -> Result<()>;
+    /// Discards stale received frames after an interrupted exchange
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
 }
 
 /// ISO-TP specific transport layer trait
 pub trait IsoTpTransport: TransportLayer {
     fn send(&mut self, data: &[u8]) -> Result<()>;
-    fn receive(&mut self) -> Result<Vec<u8>>;
+    fn receive(&mut self) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        self.receive_into(&mut data)?;
+        Ok(data)
+    }
+    /// Receives a message into `buf`, clearing it first so its allocation is
+    /// reused, and returns the message length
+    fn receive_into(&mut self, buf: &mut Vec<u8>) -> Result<usize>;
+    /// Receives a message, bounding the first frame and each consecutive frame
+    /// read by `timeout_ms` and restoring the previous timeout afterwards
+    fn receive_with_timeout(&mut self, timeout_ms: u32) -> Result<Vec<u8>>;
 }
 
-pub use doip::{DoIP, DoIPConfig};
-pub use isobus::{ISOBUSConfig, ISOBUS};
+pub use boxed::{BoxedTransport, BoxedTransportConfig};
+pub use doip::{DoIP, DoIPConfig, RoutingActivationResult};
+pub use instrumented::{Instrumented, Middleware};
+pub use isobus::{ISOBUSConfig, ObjectPoolUpload, ISOBUS};
 pub use isobus_diagnostic::{DiagnosticTroubleCode, ISOBUSDiagnosticProtocol, LampStatus};
-pub use isotp::{IsoTp, IsoTpConfig};
-pub use lin::{Lin, LinConfig, LinFrameSlot, LinFrameType};
+pub use isotp::{AddressScheme, IsoTp, IsoTpConfig};
+pub use lin::{ChecksumKind, Lin, LinConfig, LinFrameSlot, LinFrameType, LinState};
 
 #[cfg(test)]
 mod tests;
@@ -1,14 +1,17 @@
 use super::*;
 use crate::error::AutomotiveError;
-use crate::isotp::{AddressMode, IsoTp, IsoTpConfig, IsoTpTiming};
-use crate::physical::{mock::MockPhysical, PhysicalLayer};
+use crate::isotp::{
+    self, padded_length, AddressMode, AddressScheme, FlowAction, IsoTp, IsoTpConfig, IsoTpTiming,
+    PaddingLength, Reassembler,
+};
+use crate::physical::mock::MockPhysical;
 use crate::types::Frame;
 use std::sync::atomic::{AtomicU32, Ordering};
 use std::sync::Arc;
 
 #[test]
 fn test_isotp_single_frame() -> Result<()> {
-    let mut mock = MockPhysical::new(Some(Box::new(|frame: &Frame| {
+    let mock = MockPhysical::new(Some(Box::new(|frame: &Frame| {
         // Echo back a response with service ID + 0x40
         Ok(Frame {
             id: frame.id,
@@ -16,9 +19,9 @@ fn test_isotp_single_frame() -> Result<()> {
             timestamp: 0,
             is_extended: false,
             is_fd: false,
+            brs: false,
         })
     })));
-    mock.open()?;
 
     let config = IsoTpConfig {
         tx_id: 0x123,
@@ -40,299 +43,1870 @@ fn test_isotp_single_frame() -> Result<()> {
 }
 
 #[test]
-fn test_isotp_multi_frame() {
-    // Skip the frame count check and just verify that the send method works
-    let mock = MockPhysical::new(Some(Box::new(|_frame: &Frame| {
-        // Always return a flow control frame to keep the test simple
-        Ok(Frame {
-            id: 0x456,
-            data: vec![0x30, 0x00, 0x00], // Flow control with BS=0, STmin=0
-            timestamp: 0,
-            is_extended: false,
-            is_fd: false,
-        })
+fn test_isotp_canfd_single_frame() -> Result<()> {
+    let mock = MockPhysical::new(Some(Box::new(|frame: &Frame| {
+        // CAN-FD single frame: PCI low nibble 0, length in the following byte
+        let mut data = vec![0x00, 12];
+        data.extend_from_slice(&[0x62; 12]);
+        data.extend_from_slice(&[0xCC; 2]); // Padding up to the 16-byte DLC
+        Ok(Frame::fd(frame.id, data, false))
     })));
 
-    let mut mock = mock;
-    mock.open().unwrap();
-
     let config = IsoTpConfig {
         tx_id: 0x123,
         rx_id: 0x456,
-        block_size: 0, // No block size limit
-        st_min: 0,     // No separation time
-        timing: IsoTpTiming {
-            n_as: 1000,
-            n_ar: 1000,
-            n_bs: 1000,
-            n_cr: 1000,
-        },
         ..Default::default()
     };
 
     let mut isotp = IsoTp::with_physical(config, mock);
-    isotp.open().unwrap();
+    isotp.open()?;
 
-    // Send multi-frame message (20 bytes will require multiple frames)
-    let data = vec![0x10; 20];
-    // Just verify that the send method works without errors
-    isotp.send(&data).unwrap();
+    isotp.send(&[0x22])?;
 
-    isotp.close().unwrap();
+    let response = isotp.receive()?;
+    assert_eq!(response, vec![0x62; 12]);
+
+    Ok(())
 }
 
 #[test]
-fn test_isotp_extended_addressing() -> Result<()> {
-    let mut mock = MockPhysical::new(Some(Box::new(|frame: &Frame| {
-        // Verify extended addressing
-        assert_eq!(frame.data[0], 0x55); // Address extension
-        Ok(Frame {
-            id: frame.id,
-            data: vec![0x55, 0x01, 0x50], // Address extension + single frame with length 1
-            timestamp: 0,
-            is_extended: false,
-            is_fd: false,
-        })
-    })));
-    mock.open()?;
+fn test_isotp_canfd_single_frame_round_trip() -> Result<()> {
+    // Loop the sent frame straight back
+    let mock = MockPhysical::new(Some(Box::new(|frame: &Frame| Ok(frame.clone()))));
+    let sent_frames = mock.sent_frames();
 
     let config = IsoTpConfig {
         tx_id: 0x123,
         rx_id: 0x456,
-        address_mode: AddressMode::Extended,
-        address_extension: 0x55,
+        fd: true,
         ..Default::default()
     };
 
     let mut isotp = IsoTp::with_physical(config, mock);
     isotp.open()?;
 
-    // Send single frame with extended addressing
-    isotp.send(&[0x10])?;
+    let payload: Vec<u8> = (0..50).collect();
+    isotp.send(&payload)?;
 
-    // Receive response
-    let response = isotp.receive()?;
-    assert_eq!(response, vec![0x50]);
+    {
+        let sent_frames = sent_frames.lock().unwrap();
+        assert_eq!(sent_frames.len(), 1);
+        assert!(sent_frames[0].is_fd);
+        assert_eq!(sent_frames[0].data[..2], [0x00, 50]);
+    }
+
+    assert_eq!(isotp.receive()?, payload);
+
+    // Short payloads keep the classic single frame form
+    isotp.send(&[0x3E, 0x00])?;
+    assert_eq!(sent_frames.lock().unwrap()[1].data, vec![0x02, 0x3E, 0x00]);
+    assert_eq!(isotp.receive()?, vec![0x3E, 0x00]);
 
     Ok(())
 }
 
 #[test]
-fn test_isotp_mixed_addressing() -> Result<()> {
-    let mut mock = MockPhysical::new(Some(Box::new(|frame: &Frame| {
-        // Verify mixed addressing
-        assert_eq!(frame.id & 0xFF, 0x55); // Address extension in ID
-        Ok(Frame {
-            id: (frame.id & 0xFFFFFF00) | 0x55, // Keep address extension
-            data: vec![0x01, 0x50],             // Single frame with length 1
-            timestamp: 0,
-            is_extended: false,
-            is_fd: false,
-        })
-    })));
-    mock.open()?;
+fn test_isotp_receive_scripted_multi_frame() -> Result<()> {
+    let payload: Vec<u8> = (0..20).collect();
+    let frame = |data: Vec<u8>| Frame::standard(0x456, data);
+
+    let mut ff = vec![0x10, 20];
+    ff.extend_from_slice(&payload[0..6]);
+    let mut cf1 = vec![0x21];
+    cf1.extend_from_slice(&payload[6..13]);
+    let mut cf2 = vec![0x22];
+    cf2.extend_from_slice(&payload[13..20]);
+
+    let mut mock = MockPhysical::with_script(vec![frame(ff), frame(cf1)]);
+    mock.push_response(frame(cf2));
+    let sent_frames = mock.sent_frames();
 
     let config = IsoTpConfig {
         tx_id: 0x123,
         rx_id: 0x456,
-        address_mode: AddressMode::Mixed,
-        address_extension: 0x55,
         ..Default::default()
     };
+    let mut isotp = IsoTp::with_physical(config, mock);
+    isotp.open()?;
+
+    assert_eq!(isotp.receive()?, payload);
+
+    // A single Flow Control frame was sent after the First Frame
+    let sent_frames = sent_frames.lock().unwrap();
+    assert_eq!(sent_frames.len(), 1);
+    assert_eq!(sent_frames[0].data[0], 0x30);
 
+    Ok(())
+}
+
+#[test]
+fn test_isotp_rx_id_mask() -> Result<()> {
+    let mut ff = vec![0x10, 10];
+    ff.extend_from_slice(&[0xAA; 6]);
+    let mock = MockPhysical::with_script(vec![
+        // Outside 0x7E8-0x7EF
+        Frame::standard(0x7F0, vec![0x02, 0x41, 0x00]),
+        Frame::standard(0x7E9, ff),
+        // A second ECU answering while the first one is mid-message
+        Frame::standard(0x7EA, vec![0x02, 0x41, 0x0D]),
+        Frame::standard(0x7E9, vec![0x21, 0xAA, 0xAA, 0xAA, 0xAA]),
+        Frame::standard(0x7EA, vec![0x03, 0x41, 0x0D, 0x32]),
+    ]);
+    let sent_frames = mock.sent_frames();
+
+    let config = IsoTpConfig {
+        tx_id: 0x7DF,
+        rx_id: 0x7E8,
+        rx_id_mask: Some(0x7F8),
+        ..Default::default()
+    };
     let mut isotp = IsoTp::with_physical(config, mock);
     isotp.open()?;
 
-    // Send single frame with mixed addressing
-    isotp.send(&[0x10])?;
+    assert_eq!(isotp.receive_with_source()?, (0x7E9, vec![0xAA; 10]));
+    assert_eq!(sent_frames.lock().unwrap()[0].data[0], 0x30);
+    // The frame interleaved with the first message is not lost
+    assert_eq!(isotp.receive_with_source()?, (0x7EA, vec![0x41, 0x0D]));
+    assert_eq!(
+        isotp.receive_with_source()?,
+        (0x7EA, vec![0x41, 0x0D, 0x32])
+    );
 
     Ok(())
 }
 
 #[test]
-fn test_isotp_padding() -> Result<()> {
-    let mut mock = MockPhysical::new(Some(Box::new(|frame: &Frame| {
-        // Verify padding
-        assert_eq!(frame.data.len(), 8);
-        assert_eq!(&frame.data[2..], &[0xAA; 6]);
-        Ok(Frame {
-            id: frame.id,
-            data: vec![0x01, 0x50], // Single frame with length 1, response 0x50
-            timestamp: 0,
-            is_extended: false,
-            is_fd: false,
-        })
-    })));
-    mock.open()?;
+fn test_isotp_receive_into_reuses_buffer() -> Result<()> {
+    let frame = |data: Vec<u8>| Frame::standard(0x456, data);
+    let mut ff = vec![0x10, 10];
+    ff.extend_from_slice(&[0xAA; 6]);
+    let mock = MockPhysical::with_script(vec![
+        frame(ff),
+        frame(vec![0x21, 0xAA, 0xAA, 0xAA, 0xAA]),
+        frame(vec![0x03, 0x01, 0x02, 0x03]),
+    ]);
 
     let config = IsoTpConfig {
         tx_id: 0x123,
         rx_id: 0x456,
-        use_padding: true,
-        padding_value: 0xAA,
         ..Default::default()
     };
-
     let mut isotp = IsoTp::with_physical(config, mock);
     isotp.open()?;
 
-    // Send single frame with padding
-    isotp.send(&[0x10])?;
+    let mut buf = Vec::new();
+    assert_eq!(isotp.receive_into(&mut buf)?, 10);
+    assert_eq!(buf, vec![0xAA; 10]);
+    let capacity = buf.capacity();
 
-    // Receive response
-    let response = isotp.receive()?;
-    assert_eq!(response, vec![0x50]);
+    // The shorter second message replaces the first one in place
+    assert_eq!(isotp.receive_into(&mut buf)?, 3);
+    assert_eq!(buf, vec![0x01, 0x02, 0x03]);
+    assert_eq!(buf.capacity(), capacity);
 
     Ok(())
 }
 
 #[test]
-fn test_isotp_flow_control() {
-    // Skip the frame count check and just verify that the send method works
-    let mock = MockPhysical::new(Some(Box::new(|_frame: &Frame| {
-        // Always return a flow control frame to keep the test simple
-        Ok(Frame {
-            id: 0x456,
-            data: vec![0x30, 0x02, 0x10], // Flow control with BS=2, STmin=16ms
-            timestamp: 0,
-            is_extended: false,
-            is_fd: false,
-        })
-    })));
-
-    let mut mock = mock;
-    mock.open().unwrap();
+fn test_isotp_first_frame_length_check() -> Result<()> {
+    // Five bytes would have fit a single frame
+    let short_ff = Frame::standard(0x456, vec![0x10, 5, 1, 2, 3, 4, 5, 0xCC]);
+    // Valid length, but the frame stops after two data bytes
+    let truncated_ff = Frame::standard(0x456, vec![0x10, 20, 1, 2]);
+    let mock = MockPhysical::with_script(vec![short_ff.clone(), truncated_ff.clone()]);
+    let sent_frames = mock.sent_frames();
 
     let config = IsoTpConfig {
         tx_id: 0x123,
         rx_id: 0x456,
-        block_size: 0, // Will be overridden by flow control
-        st_min: 0,     // Will be overridden by flow control
-        timing: IsoTpTiming {
-            n_as: 1000,
-            n_ar: 1000,
-            n_bs: 1000,
-            n_cr: 1000,
-        },
         ..Default::default()
     };
+    let mut isotp = IsoTp::with_physical(config.clone(), mock);
+    isotp.open()?;
 
-    let mut isotp = IsoTp::with_physical(config, mock);
-    isotp.open().unwrap();
+    assert!(matches!(
+        isotp.receive(),
+        Err(AutomotiveError::IsoTpError(_))
+    ));
+    assert!(matches!(
+        isotp.receive(),
+        Err(AutomotiveError::IsoTpError(_))
+    ));
+    // Neither frame was answered with Flow Control
+    assert!(sent_frames.lock().unwrap().is_empty());
 
-    // Send multi-frame message (50 bytes will require multiple frames and flow control)
-    let data = vec![0x10; 50];
-    // Just verify that the send method works without errors
-    isotp.send(&data).unwrap();
+    let mut reassembler = Reassembler::new(&config);
+    assert!(matches!(
+        reassembler.push(&short_ff),
+        Err(AutomotiveError::IsoTpError(_))
+    ));
+    assert!(!reassembler.is_receiving());
 
-    isotp.close().unwrap();
+    Ok(())
 }
 
 #[test]
-fn test_isotp_timeouts() -> Result<()> {
-    let mut mock = MockPhysical::new(Some(Box::new(|frame: &Frame| {
-        match frame.data[0] & 0xF0 {
-            0x10 => {
-                // First frame
-                std::thread::sleep(std::time::Duration::from_millis(100));
-                Err(AutomotiveError::Timeout)
-            }
-            _ => {
-                std::thread::sleep(std::time::Duration::from_millis(100));
-                Err(AutomotiveError::Timeout)
-            }
-        }
+fn test_isotp_encode_and_reassemble() -> Result<()> {
+    let payload: Vec<u8> = (0..30).collect();
+    let config = IsoTpConfig {
+        use_padding: true,
+        padding_value: 0xCC,
+        ..Default::default()
+    };
+
+    // First Frame with 6 bytes, then 7 + 7 + 7 + 3 bytes in Consecutive Frames
+    let frames = isotp::encode(&payload, &config);
+    assert_eq!(frames.len(), 5);
+    assert_eq!(frames[0].data, vec![0x10, 30, 0, 1, 2, 3, 4, 5]);
+    assert_eq!(
+        frames[4].data,
+        vec![0x24, 27, 28, 29, 0xCC, 0xCC, 0xCC, 0xCC]
+    );
+    assert!(frames.iter().all(|frame| frame.id == config.tx_id));
+
+    let mut reassembler = Reassembler::new(&config);
+    for frame in &frames[..4] {
+        assert_eq!(reassembler.push(frame)?, None);
+    }
+    assert!(reassembler.is_receiving());
+    assert_eq!(reassembler.push(&frames[4])?, Some(payload.clone()));
+    assert!(!reassembler.is_receiving());
+
+    // A skipped Consecutive Frame is a protocol violation
+    reassembler.push(&frames[0])?;
+    assert!(reassembler.push(&frames[2]).is_err());
+    assert!(!reassembler.is_receiving());
+    assert!(reassembler.push(&frames[1]).is_err());
+
+    // Short messages fit in a single frame
+    let frames = isotp::encode(&payload[..5], &config);
+    assert_eq!(frames.len(), 1);
+    assert_eq!(reassembler.push(&frames[0])?, Some(payload[..5].to_vec()));
+
+    Ok(())
+}
+
+/// Normal fixed addressing: the target address is carried in a 29-bit id
+struct NormalFixed;
+
+impl AddressScheme for NormalFixed {
+    fn tx_frame_id(&self, config: &IsoTpConfig) -> (u32, bool) {
+        (
+            config.tx_id | ((config.address_extension as u32) << 8),
+            true,
+        )
+    }
+}
+
+#[test]
+fn test_isotp_custom_address_scheme() -> Result<()> {
+    let mock = MockPhysical::new(Some(Box::new(|_frame: &Frame| {
+        // Flow Control: continue, no block limit, no separation time
+        Ok(Frame::extended(0x18DAF133, vec![0x30, 0x00, 0x00]))
     })));
-    mock.open()?;
+    let sent_frames = mock.sent_frames();
 
     let config = IsoTpConfig {
-        tx_id: 0x123,
-        rx_id: 0x456,
-        timing: IsoTpTiming {
-            n_as: 50, // 50ms timeout
-            n_ar: 50,
-            n_bs: 50,
-            n_cr: 50,
-        },
+        tx_id: 0x18DA00F1,
+        rx_id: 0x18DAF133,
+        address_extension: 0x33,
         ..Default::default()
     };
-
     let mut isotp = IsoTp::with_physical(config, mock);
+    isotp.set_address_scheme(NormalFixed);
     isotp.open()?;
 
-    // Send should timeout waiting for flow control
-    let data = vec![0x10; 20]; // Multi-frame message
-    assert!(matches!(isotp.send(&data), Err(AutomotiveError::Timeout)));
+    isotp.send(&[0x3E, 0x00])?;
+    isotp.send(&[0x22; 12])?;
 
-    // Receive should timeout waiting for response
-    assert!(matches!(isotp.receive(), Err(AutomotiveError::Timeout)));
+    // Single, First and Consecutive frames all use the custom id
+    let sent_frames = sent_frames.lock().unwrap();
+    assert_eq!(sent_frames.len(), 3);
+    for frame in sent_frames.iter() {
+        assert_eq!(frame.id, 0x18DA33F1);
+        assert!(frame.is_extended);
+    }
 
     Ok(())
 }
 
 #[test]
-fn test_isotp_error_handling() -> Result<()> {
-    let mut mock = MockPhysical::new(Some(Box::new(|_frame: &Frame| {
-        Err(AutomotiveError::NotInitialized)
-    })));
+fn test_isotp_open_rejects_invalid_config() {
+    let invalid = [
+        IsoTpConfig {
+            tx_id: 0x7E0,
+            rx_id: 0x7E0,
+            ..Default::default()
+        },
+        IsoTpConfig {
+            st_min: 0x80, // Reserved STmin encoding
+            ..Default::default()
+        },
+        IsoTpConfig {
+            st_min: 0xFA,
+            ..Default::default()
+        },
+        IsoTpConfig {
+            timeout_ms: 0,
+            ..Default::default()
+        },
+    ];
 
+    for config in invalid {
+        let mut isotp = IsoTp::with_physical(config, MockPhysical::new(None));
+        assert!(matches!(
+            isotp.open(),
+            Err(AutomotiveError::InvalidParameter)
+        ));
+    }
+
+    // Microsecond STmin encodings are accepted
+    let config = IsoTpConfig {
+        st_min: 0xF5,
+        ..Default::default()
+    };
+    let mut isotp = IsoTp::with_physical(config, MockPhysical::new(None));
+    assert!(isotp.open().is_ok());
+}
+
+#[test]
+fn test_lin_open_rejects_zero_timeout() {
+    use crate::transport::lin::{Lin, LinConfig};
+
+    let config = LinConfig {
+        timeout_ms: 0,
+        ..Default::default()
+    };
+    let mut lin = Lin::with_physical(config, MockPhysical::new(None));
+    assert!(matches!(lin.open(), Err(AutomotiveError::InvalidParameter)));
+}
+
+#[test]
+fn test_isotp_receive_stray_frames() -> Result<()> {
     let config = IsoTpConfig {
         tx_id: 0x123,
         rx_id: 0x456,
-        timing: IsoTpTiming {
-            n_as: 50, // 50ms timeout
-            n_ar: 50,
-            n_bs: 50,
-            n_cr: 50,
-        },
         ..Default::default()
     };
 
-    let mut isotp = IsoTp::with_physical(config, mock);
+    // A Consecutive Frame without a preceding First Frame
+    let mock = MockPhysical::with_script(vec![Frame::standard(0x456, vec![0x21, 0x01, 0x02])]);
+    let mut isotp = IsoTp::with_physical(config.clone(), mock);
+    isotp.open()?;
+    match isotp.receive() {
+        Err(AutomotiveError::IsoTpError(msg)) => assert_eq!(msg, "unexpected consecutive frame"),
+        other => panic!("expected IsoTpError, got {:?}", other),
+    }
 
-    // Should fail since we haven't opened the connection
-    assert!(matches!(
-        isotp.send(&[0x10]),
-        Err(AutomotiveError::NotInitialized)
-    ));
-    assert!(matches!(
-        isotp.receive(),
-        Err(AutomotiveError::NotInitialized)
-    ));
+    // A Flow Control frame while no transmission is in progress
+    let mock = MockPhysical::with_script(vec![Frame::standard(0x456, vec![0x30, 0x00, 0x00])]);
+    let mut isotp = IsoTp::with_physical(config, mock);
+    isotp.open()?;
+    match isotp.receive() {
+        Err(AutomotiveError::IsoTpError(msg)) => assert_eq!(msg, "unexpected flow control"),
+        other => panic!("expected IsoTpError, got {:?}", other),
+    }
 
     Ok(())
 }
 
 #[test]
-fn test_isotp_invalid_response() {
-    let mock = MockPhysical::new(Some(Box::new(|_frame: &Frame| {
-        Ok(Frame {
-            id: 0,
-            data: vec![0x7F, 0x00, 0x31], // Invalid response
-            timestamp: 0,
-            is_extended: false,
-            is_fd: false,
-        })
-    })));
+fn test_isotp_receive_escaped_first_frame() -> Result<()> {
+    let payload: Vec<u8> = (0..5000).map(|i| i as u8).collect();
+    let frame = |data: Vec<u8>| Frame::standard(0x456, data);
 
-    let mut mock = mock;
-    mock.open().unwrap();
+    // Escaped First Frame: zero 12-bit length, then 5000 as a 32-bit length
+    let mut ff = vec![0x10, 0x00, 0x00, 0x00, 0x13, 0x88];
+    ff.extend_from_slice(&payload[0..2]);
+    let mut frames = vec![frame(ff)];
+    for (index, chunk) in payload[2..].chunks(7).enumerate() {
+        let mut cf = vec![0x20 | ((index + 1) as u8 & 0x0F)];
+        cf.extend_from_slice(chunk);
+        frames.push(frame(cf));
+    }
 
     let config = IsoTpConfig {
         tx_id: 0x123,
         rx_id: 0x456,
+        max_rx_len: 5000,
         ..Default::default()
     };
+    let mut isotp = IsoTp::with_physical(config, MockPhysical::with_script(frames));
+    isotp.open()?;
+
+    assert_eq!(isotp.receive()?, payload);
+
+    Ok(())
+}
+
+#[test]
+fn test_isotp_receive_rejects_oversized_first_frame() -> Result<()> {
+    let frame = |data: Vec<u8>| Frame::standard(0x456, data);
+    // 12-bit length 0xFFF above the cap, then an escaped length of 4 GiB
+    let ff = frame(vec![0x1F, 0xFF, 0, 1, 2, 3, 4, 5]);
+    let escaped_ff = frame(vec![0x10, 0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0, 1]);
 
+    let mock = MockPhysical::with_script(vec![ff, escaped_ff]);
+    let sent_frames = mock.sent_frames();
+    let config = IsoTpConfig {
+        tx_id: 0x123,
+        rx_id: 0x456,
+        max_rx_len: 1024,
+        ..Default::default()
+    };
     let mut isotp = IsoTp::with_physical(config, mock);
-    isotp.open().unwrap();
+    isotp.open()?;
 
-    // Send should fail due to invalid response
-    let data = vec![0x10; 20]; // Multi-frame message
-    assert!(matches!(
-        isotp.send(&data),
-        Err(AutomotiveError::InvalidParameter)
-    ));
+    for _ in 0..2 {
+        match isotp.receive() {
+            Err(AutomotiveError::IsoTpError(message)) => assert_eq!(message, "message too long"),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
 
-    isotp.close().unwrap();
+    // Each sender is told to stop with an overflow Flow Control
+    let sent_frames = sent_frames.lock().unwrap();
+    assert_eq!(sent_frames.len(), 2);
+    assert!(sent_frames.iter().all(|fc| fc.data[0] == 0x32));
+
+    Ok(())
+}
+
+#[test]
+fn test_isotp_receive_flow_control_wait() -> Result<()> {
+    let payload: Vec<u8> = (0..20).collect();
+    let frame = |data: Vec<u8>| Frame::standard(0x456, data);
+
+    let mut ff = vec![0x10, 20];
+    ff.extend_from_slice(&payload[0..6]);
+    let mut cf1 = vec![0x21];
+    cf1.extend_from_slice(&payload[6..13]);
+    let mut cf2 = vec![0x22];
+    cf2.extend_from_slice(&payload[13..20]);
+
+    let mock = MockPhysical::with_script(vec![frame(ff), frame(cf1), frame(cf2)]);
+    let sent_frames = mock.sent_frames();
+
+    let config = IsoTpConfig {
+        tx_id: 0x123,
+        rx_id: 0x456,
+        block_size: 1,
+        ..Default::default()
+    };
+    let mut isotp = IsoTp::with_physical(config, mock);
+    isotp.open()?;
+
+    // Ask the sender to wait once after the first block
+    let mut waited = false;
+    isotp.set_on_block_complete(move |received| {
+        assert_eq!(received, 13);
+        if waited {
+            FlowAction::Continue
+        } else {
+            waited = true;
+            FlowAction::Wait
+        }
+    });
+
+    assert_eq!(isotp.receive()?, payload);
+
+    let flow_status: Vec<u8> = sent_frames
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|frame| frame.data[0])
+        .collect();
+    assert_eq!(flow_status, vec![0x30, 0x31, 0x30]);
+
+    Ok(())
+}
+
+#[test]
+fn test_isotp_receive_wait_limit() -> Result<()> {
+    let frame = |data: Vec<u8>| Frame::standard(0x456, data);
+    let ff = frame(vec![0x10, 20, 0, 1, 2, 3, 4, 5]);
+    let cf1 = frame(vec![0x21, 6, 7, 8, 9, 10, 11, 12]);
+
+    let mock = MockPhysical::with_script(vec![ff, cf1]);
+    let sent_frames = mock.sent_frames();
+
+    let config = IsoTpConfig {
+        tx_id: 0x123,
+        rx_id: 0x456,
+        block_size: 1,
+        wft_max: 2,
+        ..Default::default()
+    };
+    let mut isotp = IsoTp::with_physical(config, mock);
+    isotp.open()?;
+
+    // The application never lets the sender continue
+    isotp.set_on_block_complete(|_| FlowAction::Wait);
+
+    match isotp.receive() {
+        Err(AutomotiveError::IsoTpError(message)) => assert_eq!(message, "N_WFTmax exceeded"),
+        other => panic!("unexpected result: {:?}", other),
+    }
+
+    // Two Waits, then an overflow instead of a third
+    let flow_status: Vec<u8> = sent_frames
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|frame| frame.data[0])
+        .collect();
+    assert_eq!(flow_status, vec![0x30, 0x31, 0x31, 0x32]);
+
+    Ok(())
+}
+
+#[test]
+fn test_isotp_sequence_number_wraparound() -> Result<()> {
+    let sender_mock = MockPhysical::new(Some(Box::new(|_frame: &Frame| {
+        Ok(Frame {
+            id: 0x456,
+            data: vec![0x30, 0x00, 0x00], // Flow control with BS=0, STmin=0
+            timestamp: 0,
+            is_extended: false,
+            is_fd: false,
+            brs: false,
+        })
+    })));
+    let sent_frames = sender_mock.sent_frames();
+
+    let config = IsoTpConfig {
+        tx_id: 0x123,
+        rx_id: 0x456,
+        ..Default::default()
+    };
+
+    // 120 bytes: First Frame carries 6, then 17 Consecutive Frames
+    let payload: Vec<u8> = (0..120).collect();
+    let mut sender = IsoTp::with_physical(config.clone(), sender_mock);
+    sender.open()?;
+    sender.send(&payload)?;
+
+    let frames = sent_frames.lock().unwrap().clone();
+    assert_eq!(frames.len(), 18);
+    let sequence: Vec<u8> = frames[1..].iter().map(|frame| frame.data[0]).collect();
+    let mut expected: Vec<u8> = (0x21..=0x2F).collect();
+    expected.extend_from_slice(&[0x20, 0x21]);
+    assert_eq!(sequence, expected);
+
+    // Feed the same frames to a receiver
+    let receiver_mock = MockPhysical::with_script(frames);
+    let mut receiver = IsoTp::with_physical(config, receiver_mock);
+    receiver.open()?;
+    assert_eq!(receiver.receive()?, payload);
+
+    Ok(())
+}
+
+#[test]
+fn test_isotp_flush_discards_stray_frames() -> Result<()> {
+    let mut mock = MockPhysical::new(Some(Box::new(|frame: &Frame| {
+        // ECU answers TesterPresent only
+        if frame.data[..2] != [0x02, 0x3E] {
+            return Err(AutomotiveError::Timeout);
+        }
+        Ok(Frame::standard(0x456, vec![0x02, 0x7E, 0x00]))
+    })));
+
+    // Leftover Consecutive Frames from an interrupted transfer
+    for data in [vec![0x21, 0x01, 0x02], vec![0x22, 0x03, 0x04]] {
+        mock.push_response(Frame::standard(0x456, data));
+    }
+
+    let config = IsoTpConfig {
+        tx_id: 0x123,
+        rx_id: 0x456,
+        ..Default::default()
+    };
+    let mut isotp = IsoTp::with_physical(config, mock);
+    isotp.open()?;
+    isotp.flush()?;
+
+    isotp.send(&[0x3E, 0x00])?;
+    assert_eq!(isotp.receive()?, vec![0x7E, 0x00]);
+
+    Ok(())
+}
+
+#[test]
+fn test_isotp_multi_frame() {
+    // Skip the frame count check and just verify that the send method works
+    let mock = MockPhysical::new(Some(Box::new(|_frame: &Frame| {
+        // Always return a flow control frame to keep the test simple
+        Ok(Frame {
+            id: 0x456,
+            data: vec![0x30, 0x00, 0x00], // Flow control with BS=0, STmin=0
+            timestamp: 0,
+            is_extended: false,
+            is_fd: false,
+            brs: false,
+        })
+    })));
+
+    let config = IsoTpConfig {
+        tx_id: 0x123,
+        rx_id: 0x456,
+        block_size: 0, // No block size limit
+        st_min: 0,     // No separation time
+        timing: IsoTpTiming {
+            n_as: 1000,
+            n_ar: 1000,
+            n_bs: 1000,
+            n_cr: 1000,
+        },
+        ..Default::default()
+    };
+
+    let mut isotp = IsoTp::with_physical(config, mock);
+    isotp.open().unwrap();
+
+    // Send multi-frame message (20 bytes will require multiple frames)
+    let data = vec![0x10; 20];
+    // Just verify that the send method works without errors
+    isotp.send(&data).unwrap();
+
+    isotp.close().unwrap();
+}
+
+#[test]
+fn test_isotp_multi_frame_exact_count() -> Result<()> {
+    let mock = MockPhysical::new(Some(Box::new(|_frame: &Frame| {
+        Ok(Frame {
+            id: 0x456,
+            data: vec![0x30, 0x00, 0x00], // Flow control with BS=0, STmin=0
+            timestamp: 0,
+            is_extended: false,
+            is_fd: false,
+            brs: false,
+        })
+    })));
+    let sent_frames = mock.sent_frames();
+
+    let config = IsoTpConfig {
+        tx_id: 0x123,
+        rx_id: 0x456,
+        ..Default::default()
+    };
+
+    let mut isotp = IsoTp::with_physical(config, mock);
+    isotp.open()?;
+
+    // 8 bytes: First Frame carries 6, one Consecutive Frame carries the last 2
+    isotp.send(&[0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08])?;
+
+    let sent_frames = sent_frames.lock().unwrap();
+    assert_eq!(sent_frames.len(), 2);
+    assert_eq!(
+        sent_frames[0].data,
+        vec![0x10, 0x08, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06]
+    );
+    assert_eq!(sent_frames[1].data, vec![0x21, 0x07, 0x08]);
+
+    Ok(())
+}
+
+#[test]
+fn test_isotp_extended_addressing() -> Result<()> {
+    let mock = MockPhysical::new(Some(Box::new(|frame: &Frame| {
+        // Verify extended addressing
+        assert_eq!(frame.data[0], 0x55); // Address extension
+        Ok(Frame {
+            id: frame.id,
+            data: vec![0x55, 0x01, 0x50], // Address extension + single frame with length 1
+            timestamp: 0,
+            is_extended: false,
+            is_fd: false,
+            brs: false,
+        })
+    })));
+
+    let config = IsoTpConfig {
+        tx_id: 0x123,
+        rx_id: 0x456,
+        address_mode: AddressMode::Extended,
+        address_extension: 0x55,
+        ..Default::default()
+    };
+
+    let mut isotp = IsoTp::with_physical(config, mock);
+    isotp.open()?;
+
+    // Send single frame with extended addressing
+    isotp.send(&[0x10])?;
+
+    // Receive response
+    let response = isotp.receive()?;
+    assert_eq!(response, vec![0x50]);
+
+    Ok(())
+}
+
+#[test]
+fn test_isotp_mixed_addressing() -> Result<()> {
+    let mock = MockPhysical::new(Some(Box::new(|frame: &Frame| {
+        // Verify mixed addressing
+        assert_eq!(frame.id & 0xFF, 0x55); // Address extension in ID
+        Ok(Frame {
+            id: (frame.id & 0xFFFFFF00) | 0x55, // Keep address extension
+            data: vec![0x01, 0x50],             // Single frame with length 1
+            timestamp: 0,
+            is_extended: false,
+            is_fd: false,
+            brs: false,
+        })
+    })));
+
+    let config = IsoTpConfig {
+        tx_id: 0x123,
+        rx_id: 0x456,
+        address_mode: AddressMode::Mixed,
+        address_extension: 0x55,
+        ..Default::default()
+    };
+
+    let mut isotp = IsoTp::with_physical(config, mock);
+    isotp.open()?;
+
+    // Send single frame with mixed addressing
+    isotp.send(&[0x10])?;
+
+    Ok(())
+}
+
+#[test]
+fn test_isotp_mixed_addressing_wrong_extension() -> Result<()> {
+    let mock = MockPhysical::new(Some(Box::new(|frame: &Frame| {
+        Ok(Frame {
+            id: (frame.id & 0xFFFFFF00) | 0x66, // Response for another extension
+            data: vec![0x01, 0x50],
+            timestamp: 0,
+            is_extended: false,
+            is_fd: false,
+            brs: false,
+        })
+    })));
+
+    let config = IsoTpConfig {
+        tx_id: 0x123,
+        rx_id: 0x456,
+        address_mode: AddressMode::Mixed,
+        address_extension: 0x55,
+        ..Default::default()
+    };
+
+    let mut isotp = IsoTp::with_physical(config, mock);
+    isotp.open()?;
+    isotp.send(&[0x10])?;
+
+    assert!(matches!(isotp.receive(), Err(AutomotiveError::InvalidData)));
+
+    Ok(())
+}
+
+#[test]
+fn test_isotp_padding() -> Result<()> {
+    let mock = MockPhysical::new(Some(Box::new(|frame: &Frame| {
+        // Verify padding
+        assert_eq!(frame.data.len(), 8);
+        assert_eq!(&frame.data[2..], &[0xAA; 6]);
+        Ok(Frame {
+            id: frame.id,
+            data: vec![0x01, 0x50], // Single frame with length 1, response 0x50
+            timestamp: 0,
+            is_extended: false,
+            is_fd: false,
+            brs: false,
+        })
+    })));
+
+    let config = IsoTpConfig {
+        tx_id: 0x123,
+        rx_id: 0x456,
+        use_padding: true,
+        padding_value: 0xAA,
+        ..Default::default()
+    };
+
+    let mut isotp = IsoTp::with_physical(config, mock);
+    isotp.open()?;
+
+    // Send single frame with padding
+    isotp.send(&[0x10])?;
+
+    // Receive response
+    let response = isotp.receive()?;
+    assert_eq!(response, vec![0x50]);
+
+    Ok(())
+}
+
+#[test]
+fn test_isotp_padding_length() {
+    // Classic CAN always pads to 8 bytes
+    assert_eq!(padded_length(3, PaddingLength::Classic), 8);
+    assert_eq!(padded_length(3, PaddingLength::CanFd), 8);
+
+    // CAN-FD pads to the next valid data length
+    assert_eq!(padded_length(10, PaddingLength::CanFd), 12);
+    assert_eq!(padded_length(40, PaddingLength::CanFd), 48);
+    assert_eq!(padded_length(64, PaddingLength::CanFd), 64);
+}
+
+#[test]
+fn test_isotp_fd_frames() -> Result<()> {
+    // Every First Frame gets a Flow Control (CTS) from the receiver
+    let mock = MockPhysical::new(Some(Box::new(|_: &Frame| {
+        Ok(Frame::standard(0x456, vec![0x30, 0x00, 0x00]))
+    })));
+    let sent_frames = mock.sent_frames();
+
+    let config = IsoTpConfig {
+        tx_id: 0x123,
+        rx_id: 0x456,
+        fd: true,
+        use_padding: true,
+        padding_length: PaddingLength::CanFd,
+        padding_value: 0xCC,
+        ..Default::default()
+    };
+    let mut isotp = IsoTp::with_physical(config, mock);
+    isotp.open()?;
+
+    // 62 bytes in the First Frame, 63 per Consecutive Frame, 9 left over
+    isotp.send(&[0x11; 134])?;
+    // 62 bytes in the First Frame, 39 left over
+    isotp.send(&[0x22; 101])?;
+
+    let sent_frames = sent_frames.lock().unwrap();
+    let lengths: Vec<usize> = sent_frames.iter().map(|frame| frame.data.len()).collect();
+    assert_eq!(lengths, vec![64, 64, 12, 64, 48]);
+    assert!(sent_frames.iter().all(|frame| frame.is_fd));
+    // Second Consecutive Frame: PCI, the 9 remaining bytes, then padding
+    assert_eq!(sent_frames[2].data[0], 0x22);
+    assert_eq!(&sent_frames[2].data[1..10], &[0x11; 9]);
+    assert_eq!(&sent_frames[2].data[10..], &[0xCC; 2]);
+    assert_eq!(sent_frames[4].data[0], 0x21);
+    assert_eq!(&sent_frames[4].data[40..], &[0xCC; 8]);
+
+    Ok(())
+}
+
+#[test]
+fn test_isotp_flow_control() {
+    // Skip the frame count check and just verify that the send method works
+    let mock = MockPhysical::new(Some(Box::new(|_frame: &Frame| {
+        // Always return a flow control frame to keep the test simple
+        Ok(Frame {
+            id: 0x456,
+            data: vec![0x30, 0x02, 0x10], // Flow control with BS=2, STmin=16ms
+            timestamp: 0,
+            is_extended: false,
+            is_fd: false,
+            brs: false,
+        })
+    })));
+
+    let config = IsoTpConfig {
+        tx_id: 0x123,
+        rx_id: 0x456,
+        block_size: 0, // Will be overridden by flow control
+        st_min: 0,     // Will be overridden by flow control
+        timing: IsoTpTiming {
+            n_as: 1000,
+            n_ar: 1000,
+            n_bs: 1000,
+            n_cr: 1000,
+        },
+        ..Default::default()
+    };
+
+    let mut isotp = IsoTp::with_physical(config, mock);
+    isotp.open().unwrap();
+
+    // Send multi-frame message (50 bytes will require multiple frames and flow control)
+    let data = vec![0x10; 50];
+    // Just verify that the send method works without errors
+    isotp.send(&data).unwrap();
+
+    isotp.close().unwrap();
+}
+
+/// Mock physical layer recording when each frame was sent
+struct TimedPhysical {
+    mock: MockPhysical,
+    sent_at: Arc<std::sync::Mutex<Vec<std::time::Instant>>>,
+}
+
+impl crate::physical::PhysicalLayer for TimedPhysical {
+    type Config = crate::physical::mock::MockConfig;
+
+    fn new(_config: Self::Config) -> Result<Self> {
+        Err(AutomotiveError::NotInitialized)
+    }
+    fn open(&mut self) -> Result<()> {
+        self.mock.open()
+    }
+    fn close(&mut self) -> Result<()> {
+        self.mock.close()
+    }
+    fn send_frame(&mut self, frame: &Frame) -> Result<()> {
+        self.sent_at.lock().unwrap().push(std::time::Instant::now());
+        self.mock.send_frame(frame)
+    }
+    fn receive_frame(&mut self) -> Result<Frame> {
+        self.mock.receive_frame()
+    }
+    fn set_timeout(&mut self, timeout_ms: u32) -> Result<()> {
+        self.mock.set_timeout(timeout_ms)
+    }
+}
+
+/// Gaps between consecutive frames of a 30-byte message sent against a Flow Control with `st_min`
+fn consecutive_frame_gaps(st_min: u8) -> Vec<std::time::Duration> {
+    let mock = MockPhysical::new(Some(Box::new(move |_frame: &Frame| {
+        Ok(Frame::standard(0x456, vec![0x30, 0x00, st_min]))
+    })));
+    let sent_at = Arc::new(std::sync::Mutex::new(Vec::new()));
+    let physical = TimedPhysical {
+        mock,
+        sent_at: Arc::clone(&sent_at),
+    };
+
+    let config = IsoTpConfig {
+        tx_id: 0x123,
+        rx_id: 0x456,
+        ..Default::default()
+    };
+    let mut isotp = IsoTp::with_physical(config, physical);
+    isotp.open().unwrap();
+    isotp.send(&[0x55; 30]).unwrap();
+
+    // First Frame, then four Consecutive Frames
+    let sent_at = sent_at.lock().unwrap();
+    assert_eq!(sent_at.len(), 5);
+    sent_at[1..].windows(2).map(|w| w[1] - w[0]).collect()
+}
+
+#[test]
+fn test_isotp_st_min_separation_time() {
+    use crate::isotp::st_min_duration;
+    use std::time::Duration;
+
+    let fallback = Duration::from_millis(127);
+    assert_eq!(st_min_duration(0x0A, fallback), Duration::from_millis(10));
+    assert_eq!(st_min_duration(0xF5, fallback), Duration::from_micros(500));
+    assert_eq!(st_min_duration(0x80, fallback), fallback);
+
+    // Delays never undershoot; allow generous overshoot for scheduler jitter
+    let gaps = consecutive_frame_gaps(0x0A);
+    assert!(gaps.iter().all(|gap| *gap >= Duration::from_millis(10)));
+    assert!(gaps.iter().min().unwrap() < &Duration::from_millis(50));
+
+    let gaps = consecutive_frame_gaps(0xF5);
+    assert!(gaps.iter().all(|gap| *gap >= Duration::from_micros(500)));
+    assert!(gaps.iter().min().unwrap() < &Duration::from_millis(1));
+}
+
+#[test]
+fn test_isotp_timeouts() -> Result<()> {
+    let mock = MockPhysical::new(Some(Box::new(|frame: &Frame| {
+        match frame.data[0] & 0xF0 {
+            0x10 => {
+                // First frame
+                std::thread::sleep(std::time::Duration::from_millis(100));
+                Err(AutomotiveError::Timeout)
+            }
+            _ => {
+                std::thread::sleep(std::time::Duration::from_millis(100));
+                Err(AutomotiveError::Timeout)
+            }
+        }
+    })));
+
+    let config = IsoTpConfig {
+        tx_id: 0x123,
+        rx_id: 0x456,
+        timing: IsoTpTiming {
+            n_as: 50, // 50ms timeout
+            n_ar: 50,
+            n_bs: 50,
+            n_cr: 50,
+        },
+        ..Default::default()
+    };
+
+    let mut isotp = IsoTp::with_physical(config, mock);
+    isotp.open()?;
+
+    // Send should timeout waiting for flow control
+    let data = vec![0x10; 20]; // Multi-frame message
+    assert!(matches!(isotp.send(&data), Err(AutomotiveError::Timeout)));
+
+    // Receive should timeout waiting for response
+    assert!(matches!(isotp.receive(), Err(AutomotiveError::Timeout)));
+
+    Ok(())
+}
+
+#[test]
+fn test_isotp_n_as_timeout() -> Result<()> {
+    // Every frame takes 50ms to leave the controller
+    let mock = MockPhysical::new(None).with_send_delay(50);
+
+    let config = IsoTpConfig {
+        tx_id: 0x123,
+        rx_id: 0x456,
+        timing: IsoTpTiming {
+            n_as: 20,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let mut isotp = IsoTp::with_physical(config, mock);
+    isotp.open()?;
+
+    // The receive timeout does not apply to transmission
+    isotp.set_timeout(1000)?;
+    assert!(matches!(
+        isotp.send(&[0x3E, 0x00]),
+        Err(AutomotiveError::Timeout)
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_isotp_receive_with_timeout() -> Result<()> {
+    let mock = MockPhysical::new(Some(Box::new(|frame: &Frame| {
+        // Slow ECU: answers after 100ms
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        Ok(Frame::standard(frame.id, vec![0x01, 0x50]))
+    })));
+
+    let config = IsoTpConfig {
+        tx_id: 0x123,
+        rx_id: 0x456,
+        ..Default::default()
+    };
+
+    let mut isotp = IsoTp::with_physical(config, mock);
+    isotp.open()?;
+    isotp.send(&[0x10])?;
+
+    assert!(matches!(
+        isotp.receive_with_timeout(20),
+        Err(AutomotiveError::Timeout)
+    ));
+
+    // The previous timeout is restored, so a plain receive waits long enough
+    assert_eq!(isotp.receive()?, vec![0x50]);
+    assert_eq!(isotp.receive_with_timeout(500)?, vec![0x50]);
+
+    Ok(())
+}
+
+#[test]
+fn test_isotp_error_handling() -> Result<()> {
+    let mock = MockPhysical::new(Some(Box::new(|_frame: &Frame| {
+        Err(AutomotiveError::NotInitialized)
+    })));
+
+    let config = IsoTpConfig {
+        tx_id: 0x123,
+        rx_id: 0x456,
+        timing: IsoTpTiming {
+            n_as: 50, // 50ms timeout
+            n_ar: 50,
+            n_bs: 50,
+            n_cr: 50,
+        },
+        ..Default::default()
+    };
+
+    let mut isotp = IsoTp::with_physical(config, mock);
+
+    // Should fail since we haven't opened the connection
+    assert!(matches!(
+        isotp.send(&[0x10]),
+        Err(AutomotiveError::NotInitialized)
+    ));
+    assert!(matches!(
+        isotp.receive(),
+        Err(AutomotiveError::NotInitialized)
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_isotp_flow_control_skips_other_traffic() -> Result<()> {
+    let mock = MockPhysical::with_script(vec![
+        // Unrelated traffic on another id
+        Frame::standard(0x7E8, vec![0x03, 0x41, 0x0D, 0x32]),
+        // A single frame from the peer where Flow Control was expected
+        Frame::standard(0x456, vec![0x02, 0x50, 0x01]),
+        Frame::standard(0x456, vec![0x30, 0x00, 0x00]),
+    ]);
+    let sent_frames = mock.sent_frames();
+
+    let config = IsoTpConfig {
+        tx_id: 0x123,
+        rx_id: 0x456,
+        ..Default::default()
+    };
+    let mut isotp = IsoTp::with_physical(config, mock);
+    isotp.open()?;
+
+    isotp.send(&[0x10; 20])?;
+    assert_eq!(sent_frames.lock().unwrap().len(), 3);
+
+    // A peer that keeps sending anything but Flow Control aborts the send
+    let mock = MockPhysical::new(Some(Box::new(|_frame: &Frame| {
+        Ok(Frame::standard(0x456, vec![0x02, 0x50, 0x01]))
+    })));
+    let config = IsoTpConfig {
+        tx_id: 0x123,
+        rx_id: 0x456,
+        ..Default::default()
+    };
+    let mut isotp = IsoTp::with_physical(config, mock);
+    isotp.open()?;
+    assert!(matches!(
+        isotp.send(&[0x10; 20]),
+        Err(AutomotiveError::IsoTpError(_))
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_isotp_invalid_response() {
+    let mock = MockPhysical::new(Some(Box::new(|_frame: &Frame| {
+        Ok(Frame {
+            id: 0x456,
+            data: vec![0x7F, 0x00, 0x31], // Invalid response
+            timestamp: 0,
+            is_extended: false,
+            is_fd: false,
+            brs: false,
+        })
+    })));
+
+    let config = IsoTpConfig {
+        tx_id: 0x123,
+        rx_id: 0x456,
+        ..Default::default()
+    };
+
+    let mut isotp = IsoTp::with_physical(config, mock);
+    isotp.open().unwrap();
+
+    // Send should fail due to invalid response
+    let data = vec![0x10; 20]; // Multi-frame message
+    assert!(matches!(
+        isotp.send(&data),
+        Err(AutomotiveError::InvalidParameter)
+    ));
+
+    isotp.close().unwrap();
+}
+
+/// Reads a DoIP message, returning its payload type and payload
+fn read_doip_message(stream: &mut std::net::TcpStream) -> (u16, Vec<u8>) {
+    use std::io::Read;
+
+    let mut header = [0u8; 8];
+    stream.read_exact(&mut header).unwrap();
+    let length = u32::from_be_bytes([header[4], header[5], header[6], header[7]]);
+    let mut payload = vec![0u8; length as usize];
+    stream.read_exact(&mut payload).unwrap();
+    (u16::from_be_bytes([header[2], header[3]]), payload)
+}
+
+/// Accepts a connection and answers its routing activation request with `response`
+fn accept_doip_routing_with(
+    listener: &std::net::TcpListener,
+    response: &[u8],
+) -> (std::net::TcpStream, Vec<u8>) {
+    use std::io::Write;
+
+    let (mut stream, _) = listener.accept().unwrap();
+    let (payload_type, request) = read_doip_message(&mut stream);
+    assert_eq!(payload_type, 0x0005);
+
+    let mut message = vec![0x02, 0xFD, 0x00, 0x06];
+    message.extend_from_slice(&(response.len() as u32).to_be_bytes());
+    message.extend_from_slice(response);
+    stream.write_all(&message).unwrap();
+    (stream, request)
+}
+
+fn accept_doip_routing(listener: &std::net::TcpListener) -> std::net::TcpStream {
+    // Tester 0x0E00, entity 0x0E80, routing activation successful
+    let response = [0x0E, 0x00, 0x0E, 0x80, 0x10, 0x00, 0x00, 0x00, 0x00];
+    accept_doip_routing_with(listener, &response).0
+}
+
+#[test]
+fn test_doip_errored_state_reopens() -> Result<()> {
+    use crate::transport::doip::{DoIP, DoIPConfig};
+    use crate::types::LayerState;
+    use std::io::Read;
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    let server = std::thread::spawn(move || {
+        // First connection is dropped as soon as routing is active
+        drop(accept_doip_routing(&listener));
+
+        // Second connection stays up until the client closes
+        let mut stream = accept_doip_routing(&listener);
+        let mut buf = [0u8; 1];
+        let _ = stream.read(&mut buf);
+    });
+
+    let config = DoIPConfig {
+        host: String::from("127.0.0.1"),
+        port,
+        timeout_ms: 1000,
+        ..Default::default()
+    };
+    let mut doip = DoIP::with_physical(config, MockPhysical::new(None));
+    assert_eq!(doip.state(), LayerState::Closed);
+    doip.open()?;
+    assert_eq!(doip.state(), LayerState::Open);
+
+    // The peer is gone, so the exchange fails with a broken connection
+    let result = doip.write_frame(&Frame::standard(0, vec![0x3E]));
+    assert!(matches!(result, Err(AutomotiveError::ConnectionFailed)));
+    assert_eq!(doip.state(), LayerState::Errored);
+
+    // Opening from Errored drops the dead socket and connects again
+    doip.open()?;
+    assert_eq!(doip.state(), LayerState::Open);
+    assert!(doip.is_connected());
+
+    doip.close()?;
+    assert_eq!(doip.state(), LayerState::Closed);
+    server.join().unwrap();
+
+    Ok(())
+}
+
+#[test]
+fn test_doip_connection_dropped() -> Result<()> {
+    use crate::transport::doip::{DoIP, DoIPConfig};
+    use std::io::Read;
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    let server = std::thread::spawn(move || {
+        // First connection: read the diagnostic message, then drop the socket
+        let mut stream = accept_doip_routing(&listener);
+        let mut request = [0u8; 13]; // 8-byte header + addresses + 1 data byte
+        stream.read_exact(&mut request).unwrap();
+        drop(stream);
+
+        // Second connection: accept the reconnect and keep it alive until the client closes
+        let mut stream = accept_doip_routing(&listener);
+        let mut buf = [0u8; 1];
+        let _ = stream.read(&mut buf);
+    });
+
+    let config = DoIPConfig {
+        host: String::from("127.0.0.1"),
+        port,
+        timeout_ms: 1000,
+        ..Default::default()
+    };
+    let mut doip = DoIP::with_physical(config, MockPhysical::new(None));
+    doip.open()?;
+    assert!(doip.is_connected());
+
+    // Opening an already open connection is a no-op
+    doip.open()?;
+
+    let result = doip.write_frame(&Frame::standard(0, vec![0x3E]));
+    assert!(matches!(result, Err(AutomotiveError::ConnectionFailed)));
+    assert!(!doip.is_connected());
+
+    doip.reconnect()?;
+    assert!(doip.is_connected());
+
+    doip.close()?;
+    server.join().unwrap();
+
+    Ok(())
+}
+
+#[test]
+fn test_doip_read_frame_partial_message() -> Result<()> {
+    use crate::transport::doip::{DoIP, DoIPConfig};
+    use crate::types::LayerState;
+    use std::io::Write;
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let (resume_tx, resume_rx) = std::sync::mpsc::channel::<()>();
+
+    let server = std::thread::spawn(move || {
+        let mut stream = accept_doip_routing(&listener);
+
+        // Diagnostic message from 0x0E80 to 0x0E00 carrying 0x7E 0x00
+        let message = [
+            0x02, 0xFD, 0x80, 0x01, 0x00, 0x00, 0x00, 0x06, 0x0E, 0x80, 0x0E, 0x00, 0x7E, 0x00,
+        ];
+
+        // Header and part of the payload, then stall until the client times out
+        stream.write_all(&message[..10]).unwrap();
+        resume_rx.recv().unwrap();
+        stream.write_all(&message[10..]).unwrap();
+
+        // A header whose payload never arrives before the connection closes
+        stream.write_all(&message[..8]).unwrap();
+    });
+
+    let config = DoIPConfig {
+        host: String::from("127.0.0.1"),
+        port,
+        timeout_ms: 1000,
+        ..Default::default()
+    };
+    let mut doip = DoIP::with_physical(config, MockPhysical::new(None));
+    doip.open()?;
+    doip.set_timeout(100)?;
+
+    // A stalled payload is a timeout, not a broken connection
+    assert!(matches!(doip.read_frame(), Err(AutomotiveError::Timeout)));
+    assert_eq!(doip.state(), LayerState::Open);
+
+    // The next read resumes the buffered message instead of misparsing the remainder
+    resume_tx.send(()).unwrap();
+    assert_eq!(doip.read_frame()?.data, vec![0x7E, 0x00]);
+
+    // The peer closed mid-message
+    server.join().unwrap();
+    assert!(matches!(
+        doip.read_frame(),
+        Err(AutomotiveError::ConnectionFailed)
+    ));
+    assert_eq!(doip.state(), LayerState::Errored);
+
+    doip.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_doip_read_frame_malformed_header() -> Result<()> {
+    use crate::transport::doip::{DoIP, DoIPConfig};
+    use crate::types::LayerState;
+    use std::io::{Read, Write};
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    let server = std::thread::spawn(move || {
+        let mut stream = accept_doip_routing(&listener);
+
+        // Protocol version and its inverse do not match
+        stream
+            .write_all(&[0x02, 0x02, 0x80, 0x01, 0x00, 0x00, 0x00, 0x00])
+            .unwrap();
+        let mut buf = [0u8; 1];
+        let _ = stream.read(&mut buf);
+    });
+
+    let config = DoIPConfig {
+        host: String::from("127.0.0.1"),
+        port,
+        timeout_ms: 1000,
+        ..Default::default()
+    };
+    let mut doip = DoIP::with_physical(config, MockPhysical::new(None));
+    doip.open()?;
+
+    assert!(matches!(
+        doip.read_frame(),
+        Err(AutomotiveError::InvalidData)
+    ));
+    assert_eq!(doip.state(), LayerState::Open);
+
+    doip.close()?;
+    server.join().unwrap();
+    Ok(())
+}
+
+#[test]
+fn test_doip_closes_on_drop() -> Result<()> {
+    use crate::transport::doip::{DoIP, DoIPConfig};
+    use std::io::Read;
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    let server = std::thread::spawn(move || {
+        let mut stream = accept_doip_routing(&listener);
+        stream
+            .set_read_timeout(Some(std::time::Duration::from_secs(2)))
+            .unwrap();
+        // EOF once the client socket is shut down
+        let mut buf = [0u8; 1];
+        stream.read(&mut buf).unwrap()
+    });
+
+    let config = DoIPConfig {
+        host: String::from("127.0.0.1"),
+        port,
+        timeout_ms: 1000,
+        ..Default::default()
+    };
+    let mut doip = DoIP::with_physical(config, MockPhysical::new(None));
+    doip.open()?;
+    drop(doip);
+
+    assert_eq!(server.join().unwrap(), 0);
+    Ok(())
+}
+
+/// Writes a DoIP message with the given payload type
+fn write_doip_message(stream: &mut std::net::TcpStream, payload_type: u16, payload: &[u8]) {
+    use std::io::Write;
+    let mut message = vec![0x02, 0xFD];
+    message.extend_from_slice(&payload_type.to_be_bytes());
+    message.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    message.extend_from_slice(payload);
+    stream.write_all(&message).unwrap();
+}
+
+#[test]
+fn test_doip_alive_check_between_ack_and_response() -> Result<()> {
+    use crate::transport::doip::{DoIP, DoIPConfig};
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = std::thread::spawn(move || {
+        let mut stream = accept_doip_routing(&listener);
+        let (payload_type, request) = read_doip_message(&mut stream);
+        assert_eq!(payload_type, 0x8001);
+        assert_eq!(request, vec![0x0E, 0x00, 0x0E, 0x80, 0x22, 0xF1, 0x90]);
+
+        write_doip_message(&mut stream, 0x8002, &[0x0E, 0x80, 0x0E, 0x00, 0x00]);
+        write_doip_message(&mut stream, 0x0007, &[]);
+        write_doip_message(
+            &mut stream,
+            0x8001,
+            &[0x0E, 0x80, 0x0E, 0x00, 0x62, 0xF1, 0x90, 0x57],
+        );
+        read_doip_message(&mut stream)
+    });
+
+    let config = DoIPConfig {
+        host: String::from("127.0.0.1"),
+        port,
+        timeout_ms: 1000,
+        ..Default::default()
+    };
+    let mut doip = DoIP::with_physical(config, MockPhysical::new(None));
+    doip.open()?;
+
+    doip.write_frame(&Frame::standard(0, vec![0x22, 0xF1, 0x90]))?;
+    let response = doip.read_frame()?;
+    assert_eq!(response.data, vec![0x62, 0xF1, 0x90, 0x57]);
+
+    // The alive check was answered with the tester address
+    assert_eq!(server.join().unwrap(), (0x0008, vec![0x0E, 0x00]));
+    doip.close()?;
+    Ok(())
+}
+
+#[test]
+fn test_doip_routing_activation_oem_data() -> Result<()> {
+    use crate::transport::doip::{DoIP, DoIPConfig, RoutingActivationResult};
+
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    let server = std::thread::spawn(move || {
+        let success = [
+            0x0E, 0x00, 0x0E, 0x80, 0x10, 0x00, 0x00, 0x00, 0x00, 0xDE, 0xAD, 0xBE, 0xEF,
+        ];
+        let (_stream, request) = accept_doip_routing_with(&listener, &success);
+
+        // Second connection is refused with an unsupported activation type
+        let unsupported = [0x0E, 0x00, 0x0E, 0x80, 0x06, 0x00, 0x00, 0x00, 0x00];
+        let _ = accept_doip_routing_with(&listener, &unsupported);
+        request
+    });
+
+    let config = DoIPConfig {
+        host: String::from("127.0.0.1"),
+        port,
+        timeout_ms: 1000,
+        activation_type: 0xE0,
+        oem_activation_data: Some([0x01, 0x02, 0x03, 0x04]),
+        ..Default::default()
+    };
+    let mut doip = DoIP::with_physical(config, MockPhysical::new(None));
+    doip.open()?;
+    assert_eq!(
+        doip.routing_activation(),
+        Some(&RoutingActivationResult {
+            tester_address: 0x0E00,
+            entity_address: 0x0E80,
+            response_code: 0x10,
+            oem_data: Some([0xDE, 0xAD, 0xBE, 0xEF]),
+        })
+    );
+
+    doip.close()?;
+    match doip.open() {
+        Err(AutomotiveError::DoIPError(message)) => {
+            assert!(message.contains("unsupported routing activation type"))
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+
+    // Source address, activation type, reserved bytes, OEM data
+    assert_eq!(
+        server.join().unwrap(),
+        vec![0x0E, 0x00, 0xE0, 0x00, 0x00, 0x00, 0x00, 0x01, 0x02, 0x03, 0x04]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_lin_sleep_and_wakeup() -> Result<()> {
+    use crate::transport::lin::{Lin, LinConfig, LinState};
+
+    let mock = MockPhysical::new(None);
+    let sent_frames = mock.sent_frames();
+
+    let mut lin = Lin::with_physical(LinConfig::default(), mock);
+    lin.open()?;
+    assert_eq!(lin.state(), LinState::Idle);
+
+    lin.go_to_sleep()?;
+    assert_eq!(lin.state(), LinState::Sleep);
+    {
+        let sent_frames = sent_frames.lock().unwrap();
+        // Break, sync, protected ID 0x3C, sleep command, checksum
+        assert_eq!(sent_frames.len(), 5);
+        assert_eq!(sent_frames[2].data, vec![0x3C]);
+        assert_eq!(
+            sent_frames[3].data,
+            vec![0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]
+        );
+    }
+
+    // Headers are rejected while the cluster is asleep
+    assert!(matches!(
+        lin.send_header(0x10),
+        Err(AutomotiveError::ConditionsNotCorrect)
+    ));
+
+    lin.wakeup()?;
+    assert_eq!(lin.state(), LinState::Idle);
+    assert_eq!(sent_frames.lock().unwrap()[5].data, vec![0x00]);
+
+    lin.send_header(0x10)?;
+
+    Ok(())
+}
+
+#[test]
+fn test_lin_diagnostic_frame_uses_classic_checksum() -> Result<()> {
+    use crate::transport::lin::{ChecksumKind, Lin, LinConfig, LinFrameType};
+
+    let mock = MockPhysical::new(None);
+    let sent_frames = mock.sent_frames();
+
+    let config = LinConfig {
+        frame_type: LinFrameType::Enhanced,
+        ..Default::default()
+    };
+    let mut lin = Lin::with_physical(config, mock);
+    lin.open()?;
+
+    assert_eq!(lin.checksum_kind_for(0x3C), ChecksumKind::Classic);
+    assert_eq!(lin.checksum_kind_for(0x3D), ChecksumKind::Classic);
+    assert_eq!(lin.checksum_kind_for(0x10), ChecksumKind::Enhanced);
+
+    // Classic checksum: inverted carry-wrapped sum of the data bytes only
+    lin.send_response(0x3C, &[0x01, 0x02, 0x03])?;
+    assert_eq!(sent_frames.lock().unwrap()[1].data, vec![!0x06]);
+
+    // Regular frames keep the enhanced checksum, which includes the PID
+    lin.send_response(0x10, &[0x01, 0x02, 0x03])?;
+    assert_eq!(sent_frames.lock().unwrap()[3].data, vec![!0x16]);
+
+    Ok(())
+}
+
+#[test]
+fn test_lin_read_diagnostic_response_classic_checksum() -> Result<()> {
+    use crate::transport::lin::{Lin, LinConfig, LinFrameType};
+
+    let responses = Arc::new(AtomicU32::new(0));
+    let responses_clone = responses.clone();
+    let mock = MockPhysical::new(Some(Box::new(move |_frame: &Frame| {
+        let data = match responses_clone.fetch_add(1, Ordering::SeqCst) {
+            0 => vec![0x01, 0x02, 0x03], // Slave response data
+            1 => vec![],                 // End of data
+            2 => vec![!0x06],            // Classic checksum
+            _ => return Err(AutomotiveError::Timeout),
+        };
+        Ok(Frame::standard(0, data))
+    })));
+
+    let config = LinConfig {
+        frame_type: LinFrameType::Enhanced,
+        ..Default::default()
+    };
+    let mut lin = Lin::with_physical(config, mock);
+    lin.open()?;
+
+    lin.send_header(0x3D)?;
+    assert_eq!(lin.read_response(0x3D, 100)?, vec![0x01, 0x02, 0x03]);
+
+    Ok(())
+}
+
+#[test]
+fn test_isobus_tp_session_aborted_after_t2() -> Result<()> {
+    use crate::j1939::TpTimeouts;
+
+    let config = ISOBUSConfig {
+        name: 0x1234,
+        address_claim_timeout_ms: 0,
+        tp_timeouts: TpTimeouts {
+            t2: 20,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let mut isobus = ISOBUS::new(config)?;
+    isobus.open()?;
+    assert_eq!(isobus.pop_tx_frame().unwrap().id, 0x00EE0080); // Address claim
+
+    // RTS from 0x20: 20 bytes in 3 packets of PGN 0xFECA
+    isobus.process_frame(&Frame::extended(
+        0x1CEC8020,
+        vec![0x10, 0x14, 0x00, 0x03, 0xFF, 0xCA, 0xFE, 0x00],
+    ))?;
+    assert_eq!(isobus.pop_tx_frame().unwrap().data[0], 0x11); // CTS
+    isobus.process_frame(&Frame::extended(0x1CEB8020, vec![0x01; 8]))?;
+
+    // Still within T2
+    isobus.update()?;
+    assert_eq!(isobus.active_sessions(), 1);
+
+    std::thread::sleep(std::time::Duration::from_millis(40));
+    isobus.update()?;
+    assert_eq!(isobus.active_sessions(), 0);
+
+    let abort = isobus.pop_tx_frame().unwrap();
+    assert_eq!(abort.id, 0x00EC2080);
+    assert_eq!(
+        abort.data,
+        vec![0xFF, 0x03, 0xFF, 0xFF, 0xFF, 0xCA, 0xFE, 0x00]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_isobus_upload_object_pool() -> Result<()> {
+    let config = ISOBUSConfig {
+        name: 0x1234,
+        address_claim_timeout_ms: 0,
+        ..Default::default()
+    };
+    let mut isobus = ISOBUS::new(config)?;
+    isobus.open()?;
+    isobus.pop_tx_frame(); // Address claim
+
+    // 20-byte pool plus the function code: 21 bytes in 3 packets
+    let pool: Vec<u8> = (0..20).collect();
+    isobus.upload_object_pool(&pool)?;
+    assert_eq!(
+        isobus.object_pool_upload(),
+        Some(ObjectPoolUpload::Transferring)
+    );
+
+    let rts = isobus.pop_tx_frame().unwrap();
+    assert_eq!(rts.id, 0x00EC2680);
+    assert_eq!(rts.data, vec![0x10, 21, 0x00, 3, 0xFF, 0x00, 0xE7, 0x00]);
+
+    // The VT clears all three packets
+    isobus.process_frame(&Frame::extended(
+        0x1CEC8026,
+        vec![0x11, 3, 1, 0xFF, 0xFF, 0x00, 0xE7, 0x00],
+    ))?;
+    let packets: Vec<Frame> = std::iter::from_fn(|| isobus.pop_tx_frame()).collect();
+    assert_eq!(packets.len(), 3);
+    assert!(packets.iter().all(|packet| packet.id == 0x00EB2680));
+    assert_eq!(packets[0].data, vec![1, 0x11, 0, 1, 2, 3, 4, 5]);
+    assert_eq!(packets[2].data, vec![3, 13, 14, 15, 16, 17, 18, 19]);
+
+    // End of Object Pool follows the acknowledgment
+    isobus.process_frame(&Frame::extended(
+        0x1CEC8026,
+        vec![0x13, 21, 0x00, 3, 0xFF, 0x00, 0xE7, 0x00],
+    ))?;
+    let end_of_pool = isobus.pop_tx_frame().unwrap();
+    assert_eq!(end_of_pool.id, 0x00E72680);
+    assert_eq!(
+        end_of_pool.data,
+        vec![0x12, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF]
+    );
+    assert_eq!(isobus.active_sessions(), 0);
+    assert_eq!(
+        isobus.object_pool_upload(),
+        Some(ObjectPoolUpload::WaitingForResponse)
+    );
+
+    // The VT reports no errors
+    isobus.process_frame(&Frame::extended(
+        0x1CE68026,
+        vec![0x12, 0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF],
+    ))?;
+    assert_eq!(
+        isobus.object_pool_upload(),
+        Some(ObjectPoolUpload::Complete)
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_isobus_dm2_request() {
+    let frame = ISOBUSDiagnosticProtocol::build_dm2_request();
+    assert_eq!((frame.id >> 8) & 0x3FFFF, 0xEAFF);
+    assert_eq!(frame.data, vec![0xCB, 0xFE, 0x00]);
+    assert!(frame.is_extended);
+    assert!(frame.id_is_valid());
+}
+
+#[test]
+fn test_isobus_dm1_round_trip() -> Result<()> {
+    let mut protocol = ISOBUSDiagnosticProtocol::new();
+    protocol.add_dtc(DiagnosticTroubleCode::new(100, 1).with_lamp_status(LampStatus::On));
+    protocol.add_dtc(DiagnosticTroubleCode::new(190, 16).with_lamp_status(LampStatus::On));
+
+    let frame = protocol.update()?.expect("DM1 broadcast");
+    assert_eq!(frame.data.len(), 2 + 2 * 4);
+
+    let (lamp_status, dtcs) = ISOBUSDiagnosticProtocol::parse_dm_response(&frame)?;
+    assert_eq!(lamp_status, LampStatus::On);
+
+    // DTC order follows the protocol's internal map
+    assert_eq!(dtcs.len(), 2);
+    assert!(dtcs.contains(&DiagnosticTroubleCode::new(100, 1).with_lamp_status(LampStatus::On)));
+    assert!(dtcs.contains(&DiagnosticTroubleCode::new(190, 16).with_lamp_status(LampStatus::On)));
+
+    Ok(())
+}
+
+#[test]
+fn test_isobus_dtc_accessors() {
+    let dtc = DiagnosticTroubleCode::new(190, 16)
+        .with_lamp_status(LampStatus::SlowFlash)
+        .with_occurrence_count(12);
+
+    assert_eq!(dtc.spn(), 190);
+    assert_eq!(dtc.fmi(), 16);
+    assert_eq!(dtc.occurrence_count(), 12);
+    assert_eq!(dtc.lamp_status(), LampStatus::SlowFlash);
+    assert!(dtc.is_active());
+
+    // Occurrence count is a 7-bit field
+    assert_eq!(dtc.with_occurrence_count(0xFF).occurrence_count(), 0x7F);
+}
+
+#[test]
+fn test_isobus_dtc_spn_packing() -> Result<()> {
+    // SPN 1234 (0x4D2), FMI 3, occurrence count 1 encoded per J1939-73 method 4
+    let mut protocol = ISOBUSDiagnosticProtocol::new();
+    protocol.add_dtc(DiagnosticTroubleCode::new(1234, 3));
+    let frame = protocol.update()?.expect("DM1 broadcast");
+    assert_eq!(&frame.data[2..], &[0xD2, 0x04, 0x03, 0x01]);
+
+    // Largest SPN (19 bits) with FMI 31 round-trips through the high bits of byte 3
+    let mut protocol = ISOBUSDiagnosticProtocol::new();
+    protocol.add_dtc(DiagnosticTroubleCode::new(524287, 31));
+    let frame = protocol.update()?.expect("DM1 broadcast");
+    assert_eq!(&frame.data[2..], &[0xFF, 0xFF, 0xFF, 0x01]);
+    let (_, dtcs) = ISOBUSDiagnosticProtocol::parse_dm_response(&frame)?;
+    assert_eq!(dtcs, vec![DiagnosticTroubleCode::new(524287, 31)]);
+
+    // Known DM1 record: SPN 100 (engine oil pressure), FMI 1, occurrence count 5
+    let frame = Frame::extended(0x00FECA << 8, vec![0x00, 0xFF, 0x64, 0x00, 0x01, 0x05]);
+    let (_, dtcs) = ISOBUSDiagnosticProtocol::parse_dm_response(&frame)?;
+    assert_eq!(dtcs.len(), 1);
+
+    // Re-encoding the decoded DTC reproduces the original record
+    let mut protocol = ISOBUSDiagnosticProtocol::new();
+    protocol.add_dtc(dtcs[0].clone());
+    let frame = protocol.update()?.expect("DM1 broadcast");
+    assert_eq!(&frame.data[2..], &[0x64, 0x00, 0x01, 0x05]);
+
+    Ok(())
+}
+
+/// Gateway middleware moving frames between two CAN ids
+struct IdRemap {
+    from: u32,
+    to: u32,
+}
+
+impl Middleware for IdRemap {
+    fn on_send(&mut self, frame: &mut Frame) -> Result<()> {
+        if frame.id == self.from {
+            frame.id = self.to;
+        }
+        Ok(())
+    }
+
+    fn on_receive(&mut self, frame: &mut Frame) -> Result<()> {
+        if frame.id == self.to {
+            frame.id = self.from;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn test_instrumented_id_remapping() -> Result<()> {
+    use crate::transport::loopback::LoopbackTransport;
+    use std::sync::Mutex;
+
+    let sent_ids = Arc::new(Mutex::new(Vec::new()));
+    let log = Arc::clone(&sent_ids);
+    let loopback = LoopbackTransport::with_handler(move |frame: &Frame| {
+        log.lock().unwrap().push(frame.id);
+        Ok(frame.clone())
+    });
+    let mut transport = Instrumented::from_transport(loopback).with_middleware(IdRemap {
+        from: 0x7E0,
+        to: 0x6E0,
+    });
+    transport.open()?;
+
+    transport.write_frame(&Frame::standard(0x7E0, vec![0x02, 0x10, 0x03]))?;
+    transport.write_frame(&Frame::standard(0x123, vec![0x01]))?;
+    assert_eq!(*sent_ids.lock().unwrap(), vec![0x6E0, 0x123]);
+
+    // Echoed frames are mapped back on the way in
+    assert_eq!(transport.read_frame()?.id, 0x7E0);
+    assert_eq!(transport.read_frame()?.id, 0x123);
+
+    transport.close()?;
+    Ok(())
 }
@@ -13,8 +13,16 @@ pub type CanId = u32;
 /// The maximum length depends on the protocol:
 /// - Classic CAN: 8 bytes
 /// - CAN-FD: up to 64 bytes
+#[cfg(feature = "std")]
 pub type FrameData = Vec<u8>;
 
+/// Frame data type representing the payload of a CAN frame.
+///
+/// Without `std` the payload is a fixed-capacity buffer sized for the largest
+/// CAN-FD frame (64 bytes).
+#[cfg(not(feature = "std"))]
+pub type FrameData = heapless::Vec<u8, 64>;
+
 /// Timestamp type representing milliseconds since an arbitrary epoch.
 ///
 /// Used for timing and synchronization purposes across the protocol stack.
@@ -23,6 +31,7 @@ pub type Timestamp = u64;
 /// Protocol-specific addressing information, primarily used in higher layer protocols
 /// like J1939.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Address {
     /// Message priority (0-7, with 0 being highest priority)
     pub priority: u8,
@@ -34,11 +43,64 @@ pub struct Address {
     pub destination: u8,
 }
 
+impl Address {
+    /// Decodes the priority, PGN and source of a 29-bit J1939 identifier
+    ///
+    /// The destination is taken from the PGN, see [`Address::destination`].
+    pub fn from_can_id(id: u32) -> Self {
+        let mut address = Self {
+            priority: ((id >> 26) & 0x7) as u8,
+            pgn: (id >> 8) & 0x3FFFF,
+            source: id as u8,
+            destination: 0xFF,
+        };
+        address.destination = address.destination();
+        address
+    }
+
+    /// PDU format (PF) byte of the PGN
+    pub fn pdu_format(&self) -> u8 {
+        (self.pgn >> 8) as u8
+    }
+
+    /// PDU specific (PS) byte of the PGN
+    pub fn pdu_specific(&self) -> u8 {
+        self.pgn as u8
+    }
+
+    /// Returns true for PDU1 (destination-specific) PGNs, whose PF byte is below 240
+    pub fn is_pdu1(&self) -> bool {
+        self.pdu_format() < 0xF0
+    }
+
+    /// Destination encoded in the PGN: the PS byte for PDU1, global (0xFF) for PDU2
+    pub fn destination(&self) -> u8 {
+        if self.is_pdu1() {
+            self.pdu_specific()
+        } else {
+            0xFF
+        }
+    }
+
+    /// PGN of the parameter group: the destination byte cleared for PDU1, the PGN as is for PDU2
+    pub fn group_pgn(&self) -> u32 {
+        if self.is_pdu1() {
+            self.pgn & 0x3FF00
+        } else {
+            self.pgn
+        }
+    }
+}
+
 /// Generic frame structure used across different protocol layers.
 ///
 /// This structure provides a unified representation of CAN frames,
 /// supporting both classic CAN and CAN-FD formats.
-#[derive(Debug, Clone)]
+///
+/// Equality and hashing include the timestamp; use
+/// [`Frame::matches_ignoring_timestamp`] to compare frame contents only.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Frame {
     /// CAN identifier (11-bit or 29-bit)
     pub id: CanId,
@@ -50,20 +112,123 @@ pub struct Frame {
     pub is_extended: bool,
     /// Whether the frame is a CAN-FD frame
     pub is_fd: bool,
+    /// Whether a CAN-FD frame uses bit rate switching for its data phase
+    pub brs: bool,
+}
+
+impl Frame {
+    /// Maximum standard (11-bit) identifier
+    pub const MAX_STANDARD_ID: CanId = 0x7FF;
+    /// Maximum extended (29-bit) identifier
+    pub const MAX_EXTENDED_ID: CanId = 0x1FFF_FFFF;
+
+    /// Creates a classic frame with a standard (11-bit) identifier
+    pub fn standard(id: CanId, data: FrameData) -> Self {
+        Self {
+            id,
+            data,
+            ..Default::default()
+        }
+    }
+
+    /// Creates a classic frame with an extended (29-bit) identifier
+    pub fn extended(id: CanId, data: FrameData) -> Self {
+        Self {
+            id,
+            data,
+            is_extended: true,
+            ..Default::default()
+        }
+    }
+
+    /// Creates a CAN-FD frame with a standard identifier, optionally using bit rate switching
+    pub fn fd(id: CanId, data: FrameData, brs: bool) -> Self {
+        Self {
+            id,
+            data,
+            is_fd: true,
+            brs,
+            ..Default::default()
+        }
+    }
+
+    /// Checks that the identifier fits the 11-bit or 29-bit range given by `is_extended`.
+    pub fn id_is_valid(&self) -> bool {
+        if self.is_extended {
+            self.id <= Self::MAX_EXTENDED_ID
+        } else {
+            self.id <= Self::MAX_STANDARD_ID
+        }
+    }
+
+    /// Returns the CAN-FD data length code of the payload.
+    ///
+    /// `None` if the payload length cannot be encoded without padding.
+    #[cfg(feature = "std")]
+    pub fn dlc(&self) -> Option<u8> {
+        u8::try_from(self.data.len())
+            .ok()
+            .and_then(crate::physical::canfd::len_to_dlc)
+    }
+
+    /// Compares identifier, payload and flags, ignoring the timestamp.
+    pub fn matches_ignoring_timestamp(&self, other: &Frame) -> bool {
+        self.id == other.id
+            && self.data == other.data
+            && self.is_extended == other.is_extended
+            && self.is_fd == other.is_fd
+            && self.brs == other.brs
+    }
 }
 
 impl Default for Frame {
     fn default() -> Self {
         Self {
             id: 0,
-            data: Vec::new(),
+            data: FrameData::new(),
             timestamp: 0,
             is_extended: false,
             is_fd: false,
+            brs: false,
         }
     }
 }
 
+/// Lifecycle state of a protocol layer.
+///
+/// A layer becomes `Errored` after an unrecoverable failure of the layer below;
+/// opening it again resets the stack underneath.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LayerState {
+    Closed,
+    Open,
+    Errored,
+}
+
+impl LayerState {
+    /// Moves an open layer to `Errored` if `result` is an unrecoverable failure
+    #[cfg(feature = "std")]
+    pub(crate) fn observe<T>(&mut self, result: &crate::error::Result<T>) {
+        if let Err(error) = result {
+            if *self == LayerState::Open && error.is_fatal() {
+                *self = LayerState::Errored;
+            }
+        }
+    }
+}
+
+/// Monotonic time source for targets without `std::time`.
+pub trait Clock {
+    /// Returns the current time in milliseconds since an arbitrary epoch.
+    fn now_ms(&self) -> Timestamp;
+}
+
+/// Blocking delay provider for targets without `std::thread`.
+pub trait Delay {
+    /// Blocks for at least `ms` milliseconds.
+    fn delay_ms(&mut self, ms: u32);
+}
+
 /// Configuration trait that must be implemented by all protocol configurations.
 ///
 /// This trait ensures that protocol configurations can be validated before use
@@ -89,9 +254,32 @@ pub trait Port: Send + Sync {
     /// This method will block until a frame is received or a timeout occurs.
     fn receive(&mut self) -> crate::error::Result<Frame>;
 
+    /// Receives a frame or an error frame indication from the CAN interface.
+    ///
+    /// Controllers that report bus errors override this; the default only
+    /// ever returns frames from [`Port::receive`].
+    #[cfg(feature = "std")]
+    fn receive_event(&mut self) -> crate::error::Result<crate::data_link::RxEvent> {
+        self.receive().map(crate::data_link::RxEvent::Frame)
+    }
+
     /// Sets the timeout for receive operations.
     ///
     /// # Parameters
     /// * `timeout_ms` - Timeout in milliseconds. A value of 0 means no timeout.
     fn set_timeout(&mut self, timeout_ms: u32) -> crate::error::Result<()>;
+
+    /// Reconfigures the controller for a new (data-phase) bitrate.
+    ///
+    /// Ports without runtime reconfiguration support can keep the default no-op.
+    fn reconfigure(&mut self, _bitrate: u32, _sample_point: f32) -> crate::error::Result<()> {
+        Ok(())
+    }
+
+    /// Returns the next frame without consuming it.
+    ///
+    /// `Ok(None)` means nothing is pending or the port cannot peek.
+    fn peek(&mut self) -> crate::error::Result<Option<Frame>> {
+        Ok(None)
+    }
 }
@@ -30,6 +30,7 @@ fn test_full_stack_uds() -> Result<(), AutomotiveError> {
                     timestamp: 0,
                     is_extended: false,
                     is_fd: false,
+                    brs: false,
                 })
             }
             SID_SECURITY_ACCESS => {
@@ -40,6 +41,7 @@ fn test_full_stack_uds() -> Result<(), AutomotiveError> {
                         timestamp: 0,
                         is_extended: false,
                         is_fd: false,
+                        brs: false,
                     })
                 } else {
                     Ok(Frame {
@@ -48,6 +50,7 @@ fn test_full_stack_uds() -> Result<(), AutomotiveError> {
                         timestamp: 0,
                         is_extended: false,
                         is_fd: false,
+                        brs: false,
                     })
                 }
             }
@@ -58,6 +61,7 @@ fn test_full_stack_uds() -> Result<(), AutomotiveError> {
                     timestamp: 0,
                     is_extended: false,
                     is_fd: false,
+                    brs: false,
                 })
             }
             _ => Err(AutomotiveError::NotInitialized),
@@ -75,7 +79,7 @@ fn test_full_stack_uds() -> Result<(), AutomotiveError> {
     uds.open()?;
 
     // Test diagnostic session control
-    uds.change_session(UdsSessionType::Programming)?;
+    uds.change_session(UdsSessionType::Programming, false)?;
     assert_eq!(uds.status.session_type, UdsSessionType::Programming);
 
     // Test security access
@@ -109,6 +113,7 @@ fn test_full_stack_obd() -> Result<(), AutomotiveError> {
                             timestamp: 0,
                             is_extended: false,
                             is_fd: false,
+                            brs: false,
                         })
                     }
                     _ => Err(AutomotiveError::NotInitialized),
@@ -122,6 +127,7 @@ fn test_full_stack_obd() -> Result<(), AutomotiveError> {
                     timestamp: 0,
                     is_extended: false,
                     is_fd: false,
+                    brs: false,
                 })
             }
             _ => Err(AutomotiveError::NotInitialized),
@@ -170,7 +176,7 @@ fn test_full_stack_error_handling() -> Result<(), AutomotiveError> {
     uds.open()?;
 
     // Test error handling
-    let result = uds.change_session(UdsSessionType::Programming);
+    let result = uds.change_session(UdsSessionType::Programming, false);
     assert!(result.is_err());
     assert!(matches!(
         result.unwrap_err(),
@@ -193,6 +199,7 @@ fn test_full_stack_multi_layer() -> Result<(), AutomotiveError> {
                     timestamp: 0,
                     is_extended: false,
                     is_fd: false,
+                    brs: false,
                 })
             }
             0x01 => {
@@ -206,6 +213,7 @@ fn test_full_stack_multi_layer() -> Result<(), AutomotiveError> {
                             timestamp: 0,
                             is_extended: false,
                             is_fd: false,
+                            brs: false,
                         })
                     }
                     _ => Err(AutomotiveError::NotInitialized),
@@ -222,7 +230,7 @@ fn test_full_stack_multi_layer() -> Result<(), AutomotiveError> {
     transport.open()?;
     let mut uds = Uds::with_transport(UdsConfig::default(), transport);
     uds.open()?;
-    uds.change_session(UdsSessionType::Programming)?;
+    uds.change_session(UdsSessionType::Programming, false)?;
     assert_eq!(uds.status.session_type, UdsSessionType::Programming);
 
     // Test OBD-II